@@ -0,0 +1,123 @@
+// Puts together the initrd the kernel unpacks into its root file system at boot, see src/fs/initrd.rs.
+// bootloader 0.9 can't load one alongside the kernel, so it gets baked in, the same as the command line:
+// FLAP_INITRD=path/to/archive.tar cargo run
+// Without FLAP_INITRD, whatever's in the initrd directory gets packed into a newc cpio archive.
+// It also makes the scratch disk images the tests write to, see test-args in Cargo.toml.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+const SCRATCH_DISK_SIZE: u64 = 16 * 1024 * 1024;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=FLAP_INITRD");
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("initrd");
+    let archive = match std::env::var("FLAP_INITRD") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            fs::read(&path).unwrap_or_else(|error| panic!("Couldn't read {}: {}", path, error))
+        }
+        Err(_) => {
+            let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("initrd");
+            println!("cargo:rerun-if-changed={}", directory.display());
+            pack(&directory).expect("Couldn't pack the initrd directory")
+        }
+    };
+    fs::write(out, archive).unwrap();
+    make_scratch_disks().expect("Couldn't make the scratch disk images");
+}
+
+// Blank (sparse) images for the disk drivers' tests to write to, instead of the disk the kernel boots from. QEMU is
+// started in the package directory, so they go in target/scratch. Whatever the last run left on them stays there.
+fn make_scratch_disks() -> io::Result<()> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/scratch");
+    fs::create_dir_all(&directory)?;
+    // only there to notice an image being deleted, the images themselves change every test run
+    println!("cargo:rerun-if-changed={}", directory.display());
+    for name in ["ata.img", "ahci.img"] {
+        let image = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(name))?;
+        image.set_len(SCRATCH_DISK_SIZE)?;
+    }
+    Ok(())
+}
+
+fn pack(directory: &Path) -> io::Result<Vec<u8>> {
+    let mut archive = Vec::new();
+    let mut inode = 1;
+    if directory.is_dir() {
+        add_directory(&mut archive, directory, "", &mut inode)?;
+    }
+    add_entry(&mut archive, "TRAILER!!!", 0, 0, &[]);
+    Ok(archive)
+}
+
+// Sorted, so the same directory always makes the same archive
+fn add_directory(
+    archive: &mut Vec<u8>,
+    directory: &Path,
+    prefix: &str,
+    inode: &mut u32,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let metadata = fs::symlink_metadata(&path)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        *inode += 1;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path)?;
+            let target = target.to_string_lossy();
+            add_entry(archive, &name, *inode, 0o120000 | mode, target.as_bytes());
+        } else if metadata.is_dir() {
+            add_entry(archive, &name, *inode, 0o040000 | mode, &[]);
+            add_directory(archive, &path, &format!("{}/", name), inode)?;
+        } else {
+            // hard links in the directory get packed as separate copies
+            add_entry(archive, &name, *inode, 0o100000 | mode, &fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+fn add_entry(archive: &mut Vec<u8>, name: &str, inode: u32, mode: u32, data: &[u8]) {
+    let links = if mode & 0o040000 != 0 { 2 } else { 1 };
+    // magic, then inode, mode, uid, gid, links, mtime, size, device major and minor, rdev major and minor, name size
+    // and checksum
+    let fields = [
+        inode,
+        mode,
+        0,
+        0,
+        links,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad(archive);
+    archive.extend_from_slice(data);
+    pad(archive);
+}
+
+fn pad(archive: &mut Vec<u8>) {
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
@@ -0,0 +1,71 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::acpi::{find_table, read, SdtHeader};
+use crate::memory::address::PhysicalAddress;
+
+// The Multiple APIC Description Table lists every interrupt controller, the local APIC of each CPU included
+// -> ACPI Specification - Section 5.2.12
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+// Entry types, Table 5.21
+const PROCESSOR_LOCAL_APIC: u8 = 0;
+
+// Flags of a Processor Local APIC entry, Table 5.23. A CPU that's neither can't be used at all.
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[repr(C, packed)]
+struct MadtHeader {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+
+#[repr(C, packed)]
+struct EntryHeader {
+    entry_type: u8,
+    length: u8,
+}
+
+// Section 5.2.12.2
+#[repr(C, packed)]
+struct ProcessorLocalApic {
+    header: EntryHeader,
+    processor_id: u8,
+    apic_id: u8,
+    flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApic {
+    // what the ACPI namespace calls the CPU
+    pub processor_id: u8,
+    pub apic_id: u8,
+}
+
+// Every CPU that can be started, the one we're running on included. None if there's no MADT.
+pub fn local_apics() -> Option<Vec<LocalApic>> {
+    let madt = find_table(MADT_SIGNATURE)?;
+    let length = unsafe { read::<SdtHeader>(madt) }.length as u64;
+    let mut local_apics = Vec::new();
+    let mut offset = size_of::<MadtHeader>() as u64;
+    while offset + size_of::<EntryHeader>() as u64 <= length {
+        let address = PhysicalAddress::new(madt.0 + offset);
+        let entry = unsafe { read::<EntryHeader>(address) };
+        if entry.length == 0 {
+            break;
+        }
+        if entry.entry_type == PROCESSOR_LOCAL_APIC {
+            let local_apic = unsafe { read::<ProcessorLocalApic>(address) };
+            if local_apic.flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                local_apics.push(LocalApic {
+                    processor_id: local_apic.processor_id,
+                    apic_id: local_apic.apic_id,
+                });
+            }
+        }
+        offset += entry.length as u64;
+    }
+    Some(local_apics)
+}
@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::Range;
+use core::ptr::read_unaligned;
+
+use crate::memory::address::PhysicalAddress;
+use crate::memory::physical_to_virtual;
+use crate::sync::Once;
+
+pub mod madt;
+
+// The Root System Description Pointer is somewhere in the first KiB of the Extended BIOS Data Area, or else in the
+// BIOS area, always on a 16 byte boundary -> ACPI Specification - Section 5.2.5.1
+// Booting with UEFI would hand it to us instead, but the bootloader doesn't pass it on.
+const EBDA_SEGMENT_POINTER: u64 = 0x40E;
+const EBDA_SEARCH_SIZE: u64 = 1024;
+const BIOS_AREA: Range<u64> = 0xE_0000..0x10_0000;
+const RSDP_ALIGNMENT: u64 = 16;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// the revision 0 RSDP ends at the RSDT address, only ACPI 2.0 and up have the XSDT
+const RSDP_V1_SIZE: usize = 20;
+
+// ACPI Specification - Section 5.2.5.3
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+// Every table starts with this -> ACPI Specification - Section 5.2.6
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+// Where every table the RSDT (or the XSDT) points at starts, empty if there's no ACPI at all
+static TABLES: Once<Vec<PhysicalAddress>> = Once::new();
+
+pub fn init() {
+    let tables = TABLES.call_once(|| find_rsdp().map(read_root_table).unwrap_or_default());
+    if tables.is_empty() {
+        log::warn!("No ACPI tables found");
+    }
+    for &table in tables {
+        let header = unsafe { read::<SdtHeader>(table) };
+        log::debug!(
+            "ACPI table {} at {:#x}",
+            core::str::from_utf8(&header.signature).unwrap_or("????"),
+            table.0
+        );
+    }
+}
+
+// The tables are wherever the firmware left them, which the physical memory mapping covers
+unsafe fn read<T>(address: PhysicalAddress) -> T {
+    read_unaligned(physical_to_virtual(address).as_ptr::<T>())
+}
+
+// All the bytes of a table (checksum included) have to add up to 0
+fn checksum_valid(address: PhysicalAddress, length: usize) -> bool {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(physical_to_virtual(address).as_ptr::<u8>(), length) };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn find_rsdp() -> Option<PhysicalAddress> {
+    let ebda = unsafe { read::<u16>(PhysicalAddress::new(EBDA_SEGMENT_POINTER)) } as u64 * 16;
+    let ebda_area = ebda..ebda + EBDA_SEARCH_SIZE;
+    [ebda_area, BIOS_AREA]
+        .into_iter()
+        .flat_map(|area| area.step_by(RSDP_ALIGNMENT as usize))
+        .map(PhysicalAddress::new)
+        .find(|&address| {
+            let signature = unsafe { read::<[u8; 8]>(address) };
+            &signature == RSDP_SIGNATURE && checksum_valid(address, RSDP_V1_SIZE)
+        })
+}
+
+fn read_root_table(rsdp_address: PhysicalAddress) -> Vec<PhysicalAddress> {
+    let rsdp = unsafe { read::<Rsdp>(rsdp_address) };
+    // the XSDT has 64 bit pointers, the RSDT only 32 bit ones
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (PhysicalAddress::new(rsdp.xsdt_address), size_of::<u64>())
+    } else {
+        (
+            PhysicalAddress::new(rsdp.rsdt_address as u64),
+            size_of::<u32>(),
+        )
+    };
+    let header = unsafe { read::<SdtHeader>(root) };
+    if !checksum_valid(root, header.length as usize) {
+        log::warn!("The ACPI root table at {:#x} is corrupt", root.0);
+        return Vec::new();
+    }
+    let entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+    (0..entries)
+        .map(|entry| {
+            let address =
+                PhysicalAddress::new(root.0 + (size_of::<SdtHeader>() + entry * entry_size) as u64);
+            unsafe {
+                if entry_size == size_of::<u64>() {
+                    PhysicalAddress::new(read::<u64>(address))
+                } else {
+                    PhysicalAddress::new(read::<u32>(address) as u64)
+                }
+            }
+        })
+        .collect()
+}
+
+// The first table with this signature, if it's there and its checksum adds up
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysicalAddress> {
+    TABLES.get()?.iter().copied().find(|&table| {
+        let header = unsafe { read::<SdtHeader>(table) };
+        &header.signature == signature && checksum_valid(table, header.length as usize)
+    })
+}
@@ -0,0 +1,730 @@
+// SATA disks on an AHCI controller, which is what QEMU's q35 machine has instead of IDE. See the Serial ATA AHCI spec
+// (rev 1.3.1) and https://wiki.osdev.org/AHCI.
+// The controller's registers are memory mapped through BAR 5. Each port has a command list of up to 32 slots in
+// memory, every slot pointing at a command table with the command FIS and a PRD table of where the data goes, and a
+// receive area the controller copies the FISes the drive sends back to. Commands are started by setting their slot's
+// bit in PxCI, which the controller clears again when they're done, raising an MSI on AHCI_INTERRUPT.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::block::{self, check_request, BlockDevice, BlockError};
+use crate::interrupts::consts::AHCI_INTERRUPT;
+use crate::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::physical_to_virtual;
+use crate::pci::consts::{AHCI_PROG_IF, MASS_STORAGE_CLASS, SATA_SUBCLASS};
+use crate::pci::{self, Bar};
+use crate::smp::percpu;
+use crate::sync::{Semaphore, SpinLock};
+use crate::task::{self, WaitQueue};
+use crate::time::uptime;
+
+// Generic host control registers
+const CAPABILITIES: usize = 0x00;
+const GLOBAL_HOST_CONTROL: usize = 0x04;
+const INTERRUPT_STATUS: usize = 0x08;
+const PORTS_IMPLEMENTED: usize = 0x0C;
+const CAPABILITIES_2: usize = 0x24;
+const BIOS_HANDOFF: usize = 0x28;
+
+const CAPABILITY_64_BIT: u32 = 1 << 31;
+const CAPABILITY_SLOTS_SHIFT: u32 = 8;
+const CAPABILITY_SLOTS_MASK: u32 = 0x1F;
+const CAPABILITY_2_BIOS_HANDOFF: u32 = 1 << 0;
+const HANDOFF_BIOS_OWNED: u32 = 1 << 0;
+const HANDOFF_OS_OWNED: u32 = 1 << 1;
+
+const HOST_RESET: u32 = 1 << 0;
+const HOST_INTERRUPT_ENABLE: u32 = 1 << 1;
+const HOST_AHCI_ENABLE: u32 = 1 << 31;
+
+// Port registers, offsets from the port's 0x80 bytes, which start at 0x100
+const PORTS_OFFSET: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+const PORT_COMMAND_LIST: usize = 0x00;
+const PORT_COMMAND_LIST_UPPER: usize = 0x04;
+const PORT_FIS: usize = 0x08;
+const PORT_FIS_UPPER: usize = 0x0C;
+const PORT_INTERRUPT_STATUS: usize = 0x10;
+const PORT_INTERRUPT_ENABLE: usize = 0x14;
+const PORT_COMMAND: usize = 0x18;
+const PORT_TASK_FILE: usize = 0x20;
+const PORT_SIGNATURE: usize = 0x24;
+const PORT_SATA_STATUS: usize = 0x28;
+const PORT_SATA_ERROR: usize = 0x30;
+const PORT_COMMAND_ISSUE: usize = 0x38;
+
+const COMMAND_START: u32 = 1 << 0;
+const COMMAND_FIS_RECEIVE: u32 = 1 << 4;
+const COMMAND_FIS_RUNNING: u32 = 1 << 14;
+const COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+// Port interrupt status and enable bits
+const PORT_D2H_REGISTER_FIS: u32 = 1 << 0;
+const PORT_PIO_SETUP_FIS: u32 = 1 << 1;
+const PORT_INTERFACE_FATAL: u32 = 1 << 27;
+const PORT_HOST_DATA_ERROR: u32 = 1 << 28;
+const PORT_HOST_FATAL: u32 = 1 << 29;
+const PORT_TASK_FILE_ERROR: u32 = 1 << 30;
+const PORT_ERRORS: u32 =
+    PORT_INTERFACE_FATAL | PORT_HOST_DATA_ERROR | PORT_HOST_FATAL | PORT_TASK_FILE_ERROR;
+
+// The drive's status register, the low byte of PxTFD
+const STATUS_DATA_REQUEST: u32 = 1 << 3;
+const STATUS_BUSY: u32 = 1 << 7;
+
+// Device detection and interface power management fields of PxSSTS, for a drive that's there and awake
+const SATA_STATUS_PRESENT: u32 = 0x3;
+const SATA_STATUS_ACTIVE: u32 = 0x1;
+
+// What the drive sent in its first D2H register FIS, ATAPI drives use a different one
+const SATA_SIGNATURE: u32 = 0x0000_0101;
+const ATAPI_SIGNATURE: u32 = 0xEB14_0101;
+
+// Serial ATA revision 2.6 - Section 10.3.4, the FIS that carries a command to the drive
+const FIS_REGISTER_H2D: u8 = 0x27;
+const FIS_COMMAND: u8 = 1 << 7;
+const FIS_LBA_MODE: u8 = 1 << 6;
+const FIS_REGISTER_H2D_DWORDS: u32 = 5;
+
+// Command header bits, for the first dword
+const HEADER_WRITE: u32 = 1 << 6;
+const HEADER_PRDT_LENGTH_SHIFT: u32 = 16;
+// The PRD entry's byte count, which is one less than the real one
+const PRD_BYTE_COUNT_MASK: u32 = 0x3F_FFFF;
+
+const IDENTIFY: u8 = 0xEC;
+const READ_DMA_EXT: u8 = 0x25;
+const WRITE_DMA_EXT: u8 = 0x35;
+const FLUSH_CACHE_EXT: u8 = 0xEA;
+
+// Words of the IDENTIFY data
+const IDENTIFY_MODEL: core::ops::Range<usize> = 27..47;
+const IDENTIFY_LBA28_SECTORS: usize = 60;
+const IDENTIFY_COMMAND_SETS: usize = 83;
+const IDENTIFY_LBA48_SECTORS: usize = 100;
+const COMMAND_SET_LBA48: u16 = 1 << 10;
+
+// Where things are in the frame a port's command list is in. The list's 32 headers take 1 KiB, the received FIS area
+// after it has to be 256 byte aligned.
+const COMMAND_HEADER_SIZE: usize = 32;
+const RECEIVED_FIS_OFFSET: u64 = 0x400;
+
+// A command table is the command FIS, the ATAPI command, then the PRD table from 0x80. With one PRD entry per DMA
+// page they're 0x180 bytes, so every slot's fits in one frame, and they stay 128 byte aligned as they have to be.
+const COMMAND_TABLE_PRDT: usize = 0x80;
+const PRD_SIZE: usize = 16;
+const DMA_PAGES: usize = 16;
+const COMMAND_TABLE_SIZE: usize = COMMAND_TABLE_PRDT + DMA_PAGES * PRD_SIZE;
+// Each slot has its own pages to move data through, so only a few of the 32 get used
+const MAX_SLOTS: usize = 4;
+
+const SECTOR_SIZE: usize = 512;
+const MAX_SECTORS: usize = DMA_PAGES * PageSize::NORMAL as usize / SECTOR_SIZE;
+
+// How long the controller gets to start and stop things, the spec says 500 ms for most of them
+const TIMEOUT: Duration = Duration::from_secs(1);
+// Only for ports without interrupts, which get polled
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CONTROLLERS: SpinLock<Vec<Arc<Controller>>> = SpinLock::new(Vec::new());
+
+// Memory mapped registers, of the whole controller or of one port
+#[derive(Debug, Clone, Copy)]
+struct Registers {
+    base: u64,
+}
+
+impl Registers {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base as usize + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base as usize + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn set_bits(&self, offset: usize, bits: u32) {
+        self.write(offset, self.read(offset) | bits);
+    }
+
+    fn clear_bits(&self, offset: usize, bits: u32) {
+        self.write(offset, self.read(offset) & !bits);
+    }
+
+    // Waits for the bits in mask to all be clear, false if they took too long
+    fn wait_clear(&self, offset: usize, mask: u32) -> bool {
+        let deadline = uptime() + TIMEOUT;
+        while self.read(offset) & mask != 0 {
+            if uptime() > deadline {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+}
+
+struct Controller {
+    registers: Registers,
+    // the ports drives were found on, with their numbers
+    ports: Vec<(usize, Arc<Port>)>,
+}
+
+// What the interrupt handler and whoever issues commands share
+#[derive(Debug, Default)]
+struct PortState {
+    // slots nobody's using
+    free: u32,
+    // slots with a command the controller hasn't finished
+    issued: u32,
+    // slots whose command finished with an error, until whoever issued it has seen that
+    failed: u32,
+}
+
+struct Port {
+    registers: Registers,
+    // the command list, with the received FIS area after it
+    command_list: PhysicalFrame,
+    command_tables: PhysicalFrame,
+    // every slot's DMA pages, which are all below 4 GiB if the controller can't address more
+    pages: Vec<Vec<PhysicalFrame>>,
+    slots: Semaphore,
+    state: SpinLock<PortState>,
+    completed: WaitQueue,
+    // false if the controller couldn't do MSI, in which case commands get polled
+    interrupts: bool,
+}
+
+impl Port {
+    fn new(registers: Registers, slots: usize, addressable: u64, interrupts: bool) -> Option<Port> {
+        let zeroed = || {
+            let frame = allocate_below(addressable)?;
+            let page = physical_to_virtual(frame.start_address()).as_mut_ptr::<u8>();
+            unsafe { page.write_bytes(0, PageSize::NORMAL as usize) };
+            Some(frame)
+        };
+        let command_list = zeroed()?;
+        let Some(command_tables) = zeroed() else {
+            unsafe { deallocate_frame(command_list) };
+            return None;
+        };
+        // from here on dropping the port frees whatever it got
+        let mut port = Port {
+            registers,
+            command_list,
+            command_tables,
+            pages: Vec::new(),
+            slots: Semaphore::new(slots),
+            state: SpinLock::new(PortState {
+                free: (1 << slots) - 1,
+                ..PortState::default()
+            }),
+            completed: WaitQueue::new(),
+            interrupts,
+        };
+        for _ in 0..slots {
+            let mut pages = Vec::new();
+            for _ in 0..DMA_PAGES {
+                match allocate_below(addressable) {
+                    Some(frame) => pages.push(frame),
+                    None => {
+                        port.pages.push(pages);
+                        return None;
+                    }
+                }
+            }
+            port.pages.push(pages);
+        }
+        Some(port)
+    }
+
+    // Stops the port's command list and FIS receive engines, which it has to be before its addresses can change
+    fn stop(&self) -> bool {
+        self.registers.clear_bits(PORT_COMMAND, COMMAND_START);
+        if !self
+            .registers
+            .wait_clear(PORT_COMMAND, COMMAND_LIST_RUNNING)
+        {
+            return false;
+        }
+        self.registers.clear_bits(PORT_COMMAND, COMMAND_FIS_RECEIVE);
+        self.registers.wait_clear(PORT_COMMAND, COMMAND_FIS_RUNNING)
+    }
+
+    // Section 10.3.1, points the port at our memory and has it start taking commands
+    fn start(&self) -> bool {
+        if !self.stop() {
+            return false;
+        }
+        let command_list = self.command_list.start_address().0;
+        let fis = command_list + RECEIVED_FIS_OFFSET;
+        self.registers.write(PORT_COMMAND_LIST, command_list as u32);
+        self.registers
+            .write(PORT_COMMAND_LIST_UPPER, (command_list >> 32) as u32);
+        self.registers.write(PORT_FIS, fis as u32);
+        self.registers.write(PORT_FIS_UPPER, (fis >> 32) as u32);
+        self.registers.set_bits(PORT_COMMAND, COMMAND_FIS_RECEIVE);
+        self.restart()
+    }
+
+    // Clears whatever errors the port had and starts processing the command list again, the engines have to be
+    // stopped
+    fn restart(&self) -> bool {
+        self.registers.write(PORT_SATA_ERROR, u32::MAX);
+        self.registers.write(PORT_INTERRUPT_STATUS, u32::MAX);
+        if !self
+            .registers
+            .wait_clear(PORT_TASK_FILE, STATUS_BUSY | STATUS_DATA_REQUEST)
+        {
+            return false;
+        }
+        self.registers.set_bits(PORT_COMMAND, COMMAND_START);
+        true
+    }
+
+    // Finds the commands the controller's done with and wakes up whoever's waiting for them. Called from the interrupt
+    // handler, or in a loop for ports without interrupts.
+    fn complete(&self) {
+        {
+            let mut state = self.state.lock();
+            let status = self.registers.read(PORT_INTERRUPT_STATUS);
+            self.registers.write(PORT_INTERRUPT_STATUS, status);
+            if status & PORT_ERRORS != 0 {
+                // the port stops at the first error, taking everything that was issued with it, Section 6.2.2
+                log::warn!(
+                    "AHCI command failed, port interrupt status {:#x}, task file {:#x}",
+                    status,
+                    self.registers.read(PORT_TASK_FILE)
+                );
+                state.failed |= state.issued;
+                state.issued = 0;
+                self.registers.clear_bits(PORT_COMMAND, COMMAND_START);
+                self.registers
+                    .wait_clear(PORT_COMMAND, COMMAND_LIST_RUNNING);
+                self.restart();
+            } else {
+                state.issued &= self.registers.read(PORT_COMMAND_ISSUE);
+            }
+        }
+        self.completed.wake_all();
+    }
+
+    fn command_header(&self, slot: usize) -> *mut u32 {
+        let list = physical_to_virtual(self.command_list.start_address()).as_mut_ptr::<u8>();
+        unsafe { list.add(slot * COMMAND_HEADER_SIZE) as *mut u32 }
+    }
+
+    fn command_table(&self, slot: usize) -> (u64, *mut u8) {
+        let offset = slot * COMMAND_TABLE_SIZE;
+        let table = physical_to_virtual(self.command_tables.start_address()).as_mut_ptr::<u8>();
+        (
+            self.command_tables.start_address().0 + offset as u64,
+            unsafe { table.add(offset) },
+        )
+    }
+
+    fn page(&self, slot: usize, index: usize) -> *mut u8 {
+        physical_to_virtual(self.pages[slot][index].start_address()).as_mut_ptr()
+    }
+
+    // Runs a 48 bit command on count sectors from lba, with the data going through the slot's pages. data is
+    // what gets written, buffer is where what's read ends up, neither for commands without any.
+    fn execute(
+        &self,
+        command: u8,
+        lba: u64,
+        count: usize,
+        data: Option<&[u8]>,
+        buffer: Option<&mut [u8]>,
+    ) -> Result<(), BlockError> {
+        self.slots.acquire();
+        let slot = {
+            let mut state = self.state.lock();
+            let slot = state.free.trailing_zeros() as usize;
+            state.free &= !(1 << slot);
+            slot
+        };
+        let result = self.run(slot, command, lba, count, data, buffer);
+        self.state.lock().free |= 1 << slot;
+        self.slots.release();
+        result
+    }
+
+    fn run(
+        &self,
+        slot: usize,
+        command: u8,
+        lba: u64,
+        count: usize,
+        data: Option<&[u8]>,
+        buffer: Option<&mut [u8]>,
+    ) -> Result<(), BlockError> {
+        let page_size = PageSize::NORMAL as usize;
+        let length = count * SECTOR_SIZE;
+        if let Some(data) = data {
+            for (index, chunk) in data.chunks(page_size).enumerate() {
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        chunk.as_ptr(),
+                        self.page(slot, index),
+                        chunk.len(),
+                    )
+                };
+            }
+        }
+
+        let (table_address, table) = self.command_table(slot);
+        let mut fis = [0u8; 20];
+        fis[0] = FIS_REGISTER_H2D;
+        fis[1] = FIS_COMMAND;
+        fis[2] = command;
+        fis[4..7].copy_from_slice(&lba.to_le_bytes()[..3]);
+        fis[7] = FIS_LBA_MODE;
+        fis[8..11].copy_from_slice(&lba.to_le_bytes()[3..6]);
+        fis[12..14].copy_from_slice(&(count as u16).to_le_bytes());
+        let prds = length.div_ceil(page_size);
+        unsafe {
+            core::ptr::copy_nonoverlapping(fis.as_ptr(), table, fis.len());
+            let prdt = table.add(COMMAND_TABLE_PRDT) as *mut u32;
+            for index in 0..prds {
+                let address = self.pages[slot][index].start_address().0;
+                let bytes = (length - index * page_size).min(page_size) as u32;
+                let entry = prdt.add(index * PRD_SIZE / 4);
+                entry.write_volatile(address as u32);
+                entry.add(1).write_volatile((address >> 32) as u32);
+                entry.add(2).write_volatile(0);
+                entry
+                    .add(3)
+                    .write_volatile((bytes - 1) & PRD_BYTE_COUNT_MASK);
+            }
+
+            let header = self.command_header(slot);
+            let write = if data.is_some() { HEADER_WRITE } else { 0 };
+            header.write_volatile(
+                FIS_REGISTER_H2D_DWORDS | write | (prds as u32) << HEADER_PRDT_LENGTH_SHIFT,
+            );
+            // bytes transferred, which the controller counts up
+            header.add(1).write_volatile(0);
+            header.add(2).write_volatile(table_address as u32);
+            header.add(3).write_volatile((table_address >> 32) as u32);
+        }
+
+        // the handler works out what's done from what's issued and isn't in PxCI any more, so it mustn't see one
+        // without the other
+        let bit = 1 << slot;
+        {
+            let mut state = self.state.lock();
+            state.issued |= bit;
+            self.registers.write(PORT_COMMAND_ISSUE, bit);
+        }
+        let pending = || self.state.lock().issued & bit != 0;
+        if self.interrupts {
+            self.completed.wait_while(pending);
+        } else {
+            let deadline = uptime() + COMMAND_TIMEOUT;
+            loop {
+                self.complete();
+                if !pending() {
+                    break;
+                }
+                if uptime() > deadline {
+                    // the port has to be stopped to take the command back, which fails the rest too
+                    self.registers.clear_bits(PORT_COMMAND, COMMAND_START);
+                    self.registers
+                        .wait_clear(PORT_COMMAND, COMMAND_LIST_RUNNING);
+                    let mut state = self.state.lock();
+                    state.failed |= state.issued & !bit;
+                    state.issued = 0;
+                    self.restart();
+                    return Err(BlockError::Timeout);
+                }
+                task::yield_now();
+            }
+        }
+        let failed = {
+            let mut state = self.state.lock();
+            let failed = state.failed & bit != 0;
+            state.failed &= !bit;
+            failed
+        };
+        if failed {
+            return Err(BlockError::DeviceError);
+        }
+
+        if let Some(buffer) = buffer {
+            for (index, chunk) in buffer.chunks_mut(page_size).enumerate() {
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        self.page(slot, index),
+                        chunk.as_mut_ptr(),
+                        chunk.len(),
+                    )
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+// Ports only go away if they couldn't be started, the controller has to be done with their memory first
+impl Drop for Port {
+    fn drop(&mut self) {
+        self.stop();
+        unsafe {
+            deallocate_frame(self.command_list);
+            deallocate_frame(self.command_tables);
+            for page in self.pages.iter().flatten() {
+                deallocate_frame(*page);
+            }
+        }
+    }
+}
+
+// A frame the controller can reach, which without 64 bit addressing has to be below 4 GiB
+fn allocate_below(addressable: u64) -> Option<PhysicalFrame> {
+    let frame = allocate_frame()?;
+    if frame.start_address().0 >= addressable {
+        unsafe { deallocate_frame(frame) };
+        return None;
+    }
+    Some(frame)
+}
+
+// A SATA disk on one of the controller's ports. Commands from different threads can be in flight at once, up to one
+// per slot.
+pub struct AhciDisk {
+    name: String,
+    model: String,
+    port: Arc<Port>,
+    block_count: u64,
+}
+
+impl AhciDisk {
+    fn new(name: String, port: Arc<Port>) -> Option<AhciDisk> {
+        let mut data = [0; SECTOR_SIZE];
+        port.execute(IDENTIFY, 0, 1, None, Some(&mut data)).ok()?;
+        let identify: Vec<u16> = data
+            .chunks(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]))
+            .collect();
+        let model = IDENTIFY_MODEL
+            .flat_map(|word| identify[word].to_be_bytes())
+            .map(char::from)
+            .collect::<String>();
+        let model = String::from(model.trim());
+        let block_count = if identify[IDENTIFY_COMMAND_SETS] & COMMAND_SET_LBA48 != 0 {
+            (0..4).fold(0, |count, index| {
+                count | (identify[IDENTIFY_LBA48_SECTORS + index] as u64) << (16 * index)
+            })
+        } else {
+            identify[IDENTIFY_LBA28_SECTORS] as u64
+                | (identify[IDENTIFY_LBA28_SECTORS + 1] as u64) << 16
+        };
+        log::info!(
+            "{}: {} (AHCI{})",
+            name,
+            model,
+            if port.interrupts { "" } else { ", polled" }
+        );
+        Some(AhciDisk {
+            name,
+            model,
+            port,
+            block_count,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    // Whether commands complete with an interrupt, rather than being polled for
+    pub fn uses_interrupts(&self) -> bool {
+        self.port.interrupts
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buffer.len())?;
+        let chunk_size = MAX_SECTORS * SECTOR_SIZE;
+        for (index, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let lba = start + (index * MAX_SECTORS) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            self.port
+                .execute(READ_DMA_EXT, lba, count, None, Some(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, data.len())?;
+        let chunk_size = MAX_SECTORS * SECTOR_SIZE;
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            let lba = start + (index * MAX_SECTORS) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            self.port
+                .execute(WRITE_DMA_EXT, lba, count, Some(chunk), None)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.port.execute(FLUSH_CACHE_EXT, 0, 0, None, None)
+    }
+}
+
+impl core::fmt::Debug for AhciDisk {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AhciDisk")
+            .field("name", &self.name)
+            .field("model", &self.model)
+            .field("block_count", &self.block_count)
+            .finish()
+    }
+}
+
+// Called for AHCI_INTERRUPT. The controller's interrupt status has a bit for every port that wants attention, which
+// has to be cleared after the port's own.
+pub fn handle_interrupt() {
+    let controllers = CONTROLLERS.lock();
+    for controller in controllers.iter() {
+        let pending = controller.registers.read(INTERRUPT_STATUS);
+        if pending == 0 {
+            continue;
+        }
+        for (number, port) in controller.ports.iter() {
+            if pending & (1 << number) != 0 {
+                port.complete();
+            }
+        }
+        controller.registers.write(INTERRUPT_STATUS, pending);
+    }
+}
+
+// Finds every AHCI controller and registers the disks on them as sda, sdb and so on, in the order of their ports
+pub fn init() {
+    let mut disks = 0;
+    let ahci = pci::devices().filter(|device| {
+        device.class == MASS_STORAGE_CLASS
+            && device.subclass == SATA_SUBCLASS
+            && device.prog_if == AHCI_PROG_IF
+    });
+    for device in ahci {
+        let Some(Bar::Memory { address, .. }) = device.bar(5) else {
+            continue;
+        };
+        device.enable_memory_space();
+        device.enable_bus_mastering();
+        let registers = Registers {
+            base: physical_to_virtual(address).0,
+        };
+        let Some(controller) = init_controller(registers) else {
+            log::warn!("Couldn't reset the AHCI controller at {:?}", device.address);
+            continue;
+        };
+        // everything goes to the boot CPU, there's nothing to spread interrupts out yet
+        let interrupts = device.enable_msi(percpu::apic_id(), AHCI_INTERRUPT as u8);
+        let capabilities = registers.read(CAPABILITIES);
+        let slots = (((capabilities >> CAPABILITY_SLOTS_SHIFT) & CAPABILITY_SLOTS_MASK) as usize
+            + 1)
+        .min(MAX_SLOTS);
+        let addressable = match capabilities & CAPABILITY_64_BIT {
+            0 => 1 << 32,
+            _ => u64::MAX,
+        };
+
+        let implemented = registers.read(PORTS_IMPLEMENTED);
+        let mut controller = controller;
+        for number in 0..32 {
+            let port_registers = Registers {
+                base: registers.base + (PORTS_OFFSET + number * PORT_SIZE) as u64,
+            };
+            let present = implemented & (1 << number) != 0 && {
+                let status = port_registers.read(PORT_SATA_STATUS);
+                status & 0xF == SATA_STATUS_PRESENT && (status >> 8) & 0xF == SATA_STATUS_ACTIVE
+            };
+            if !present {
+                continue;
+            }
+            match port_registers.read(PORT_SIGNATURE) {
+                SATA_SIGNATURE => {}
+                ATAPI_SIGNATURE => {
+                    log::warn!("AHCI port {}: ATAPI drives aren't supported", number);
+                    continue;
+                }
+                signature => {
+                    log::warn!("AHCI port {}: unknown signature {:#x}", number, signature);
+                    continue;
+                }
+            }
+            let Some(port) = Port::new(port_registers, slots, addressable, interrupts) else {
+                log::warn!("AHCI port {}: out of memory for its command list", number);
+                continue;
+            };
+            if !port.start() {
+                log::warn!("AHCI port {}: the drive wouldn't start", number);
+                continue;
+            }
+            port_registers.write(
+                PORT_INTERRUPT_ENABLE,
+                PORT_D2H_REGISTER_FIS | PORT_PIO_SETUP_FIS | PORT_ERRORS,
+            );
+            controller.ports.push((number, Arc::new(port)));
+        }
+
+        let ports: Vec<Arc<Port>> = controller
+            .ports
+            .iter()
+            .map(|(_, port)| port.clone())
+            .collect();
+        CONTROLLERS.lock().push(Arc::new(controller));
+        registers.write(INTERRUPT_STATUS, u32::MAX);
+        registers.set_bits(GLOBAL_HOST_CONTROL, HOST_INTERRUPT_ENABLE);
+        for port in ports {
+            let name = alloc::format!("sd{}", (b'a' + disks) as char);
+            if let Some(disk) = AhciDisk::new(name, port) {
+                block::register(Arc::new(disk));
+                disks += 1;
+            }
+        }
+    }
+}
+
+// Section 10.6 and 10.4.3, takes the controller from the firmware if it has it, then resets it into AHCI mode with
+// interrupts off
+fn init_controller(registers: Registers) -> Option<Controller> {
+    if registers.read(CAPABILITIES_2) & CAPABILITY_2_BIOS_HANDOFF != 0 {
+        registers.set_bits(BIOS_HANDOFF, HANDOFF_OS_OWNED);
+        if !registers.wait_clear(BIOS_HANDOFF, HANDOFF_BIOS_OWNED) {
+            log::warn!("The firmware didn't hand over the AHCI controller, taking it anyway");
+        }
+    }
+    registers.set_bits(GLOBAL_HOST_CONTROL, HOST_AHCI_ENABLE);
+    registers.set_bits(GLOBAL_HOST_CONTROL, HOST_RESET);
+    if !registers.wait_clear(GLOBAL_HOST_CONTROL, HOST_RESET) {
+        return None;
+    }
+    registers.write(GLOBAL_HOST_CONTROL, HOST_AHCI_ENABLE);
+    Some(Controller {
+        registers,
+        ports: Vec::new(),
+    })
+}
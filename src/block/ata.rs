@@ -0,0 +1,759 @@
+// Parallel ATA disks and ATAPI CD drives on an IDE controller, which is what QEMU's default i440FX machine has.
+// See the ATA/ATAPI-6 spec (T13 1410D), https://wiki.osdev.org/ATA_PIO_Mode and https://wiki.osdev.org/ATAPI.
+// Disks use bus master DMA when the controller and the drive can do it (Programming Interface for Bus Master IDE
+// Controller, rev 1.0, and https://wiki.osdev.org/ATA/ATAPI_using_DMA), and PIO when they can't.
+// Nothing routes IRQ 14 and 15 anywhere yet, so the drives have their interrupts turned off and get polled.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use crate::block::{self, check_request, BlockDevice, BlockError};
+use crate::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::physical_to_virtual;
+use crate::pci::consts::{IDE_SUBCLASS, MASS_STORAGE_CLASS};
+use crate::pci::{self, Bar, PciDevice};
+use crate::port::Port;
+use crate::sync::Mutex;
+use crate::task;
+use crate::time::uptime;
+
+// Where the channels are when the controller's in compatibility mode
+const PRIMARY_IO_BASE: u16 = 0x1F0;
+const PRIMARY_CONTROL_BASE: u16 = 0x3F6;
+const SECONDARY_IO_BASE: u16 = 0x170;
+const SECONDARY_CONTROL_BASE: u16 = 0x376;
+
+// Programming interface bits of the PCI class code, a channel in native mode has its ports in the BARs
+const PRIMARY_NATIVE: u8 = 1 << 0;
+const SECONDARY_NATIVE: u8 = 1 << 2;
+const BUS_MASTER_CAPABLE: u8 = 1 << 7;
+
+// Command block registers, offsets from the channel's I/O base
+const DATA: u16 = 0;
+const ERROR_FEATURES: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_SELECT: u16 = 6;
+const STATUS_COMMAND: u16 = 7;
+
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_DRIVE_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+
+// Device control register bits
+const CONTROL_INTERRUPTS_DISABLED: u8 = 1 << 1;
+const CONTROL_SOFTWARE_RESET: u8 = 1 << 2;
+
+// Drive select register bits
+const SELECT_ALWAYS_SET: u8 = 0xA0;
+const SELECT_LBA: u8 = 1 << 6;
+
+const IDENTIFY: u8 = 0xEC;
+const IDENTIFY_PACKET: u8 = 0xA1;
+const READ_SECTORS: u8 = 0x20;
+const READ_SECTORS_EXT: u8 = 0x24;
+const WRITE_SECTORS: u8 = 0x30;
+const WRITE_SECTORS_EXT: u8 = 0x34;
+const READ_DMA: u8 = 0xC8;
+const READ_DMA_EXT: u8 = 0x25;
+const WRITE_DMA: u8 = 0xCA;
+const WRITE_DMA_EXT: u8 = 0x35;
+const FLUSH_CACHE: u8 = 0xE7;
+const FLUSH_CACHE_EXT: u8 = 0xEA;
+const PACKET: u8 = 0xA0;
+
+// What's in LBA mid and high after a reset or an IDENTIFY that got aborted, for drives that aren't plain ATA
+const ATAPI_SIGNATURE: (u8, u8) = (0x14, 0xEB);
+
+// SCSI commands ATAPI drives take in their packets, see the SCSI Multimedia Commands spec (MMC)
+const SCSI_READ_CAPACITY: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const PACKET_SIZE: usize = 12;
+
+// Words of the IDENTIFY data
+const IDENTIFY_MODEL: core::ops::Range<usize> = 27..47;
+const IDENTIFY_CAPABILITIES: usize = 49;
+const IDENTIFY_LBA28_SECTORS: usize = 60;
+const IDENTIFY_COMMAND_SETS: usize = 83;
+const IDENTIFY_LBA48_SECTORS: usize = 100;
+const CAPABILITY_DMA: u16 = 1 << 8;
+const CAPABILITY_LBA: u16 = 1 << 9;
+const COMMAND_SET_LBA48: u16 = 1 << 10;
+
+// Bus master registers, offsets from the channel's part of BAR 4. The secondary channel's are 8 bytes after the
+// primary's.
+const BUS_MASTER_COMMAND: u16 = 0;
+const BUS_MASTER_STATUS: u16 = 2;
+const BUS_MASTER_PRDT: u16 = 4;
+const BUS_MASTER_SECONDARY: u16 = 8;
+
+const BUS_MASTER_START: u8 = 1 << 0;
+// the controller writes to memory, so it's set for reads from the disk
+const BUS_MASTER_READ: u8 = 1 << 3;
+const BUS_MASTER_ACTIVE: u8 = 1 << 0;
+const BUS_MASTER_ERROR: u8 = 1 << 1;
+const BUS_MASTER_INTERRUPT: u8 = 1 << 2;
+
+// Marks the last entry of a PRD table
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+
+const SECTOR_SIZE: usize = 512;
+const ATAPI_BLOCK_SIZE: usize = 2048;
+// The most one command moves. Each of a channel's DMA pages is one PRD entry, since a page can't cross a 64 KiB
+// boundary, which an entry isn't allowed to.
+const DMA_PAGES: usize = 16;
+const MAX_SECTORS: usize = DMA_PAGES * PageSize::NORMAL as usize / SECTOR_SIZE;
+
+// Drives are given up to 30 seconds to spin up in the spec, but QEMU's are always ready
+const TIMEOUT: Duration = Duration::from_secs(5);
+const IDENTIFY_TIMEOUT: Duration = Duration::from_millis(100);
+
+// One entry of a Physical Region Descriptor table
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Prd {
+    address: u32,
+    // 0 means 64 KiB
+    byte_count: u16,
+    flags: u16,
+}
+
+// Frames the controller reads from and writes to, which all have to be below 4 GiB
+#[derive(Debug)]
+struct DmaBuffers {
+    port: u16,
+    prdt: PhysicalFrame,
+    pages: Vec<PhysicalFrame>,
+}
+
+impl DmaBuffers {
+    fn new(port: u16) -> Option<DmaBuffers> {
+        let mut buffers = DmaBuffers {
+            port,
+            prdt: allocate_frame()?,
+            pages: Vec::new(),
+        };
+        for _ in 0..DMA_PAGES {
+            buffers.pages.push(allocate_frame()?);
+        }
+        let addressable = |frame: &PhysicalFrame| frame.start_address().0 < (1 << 32);
+        match addressable(&buffers.prdt) && buffers.pages.iter().all(addressable) {
+            true => Some(buffers),
+            false => None,
+        }
+    }
+
+    fn page(&self, index: usize) -> *mut u8 {
+        physical_to_virtual(self.pages[index].start_address()).as_mut_ptr()
+    }
+
+    // Copies from, or to, the pages, in order
+    fn copy_in(&self, data: &[u8]) {
+        for (index, chunk) in data.chunks(PageSize::NORMAL as usize).enumerate() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), self.page(index), chunk.len())
+            };
+        }
+    }
+
+    fn copy_out(&self, buffer: &mut [u8]) {
+        for (index, chunk) in buffer.chunks_mut(PageSize::NORMAL as usize).enumerate() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(self.page(index), chunk.as_mut_ptr(), chunk.len())
+            };
+        }
+    }
+
+    // Points the PRD table at as many pages as it takes to hold length bytes
+    fn fill_prdt(&self, length: usize) {
+        let prdt = physical_to_virtual(self.prdt.start_address()).as_mut_ptr::<Prd>();
+        let page_size = PageSize::NORMAL as usize;
+        let count = length.div_ceil(page_size);
+        for index in 0..count {
+            let prd = Prd {
+                address: self.pages[index].start_address().0 as u32,
+                byte_count: (length - index * page_size).min(page_size) as u16,
+                flags: if index == count - 1 {
+                    PRD_END_OF_TABLE
+                } else {
+                    0
+                },
+            };
+            unsafe { prdt.add(index).write_volatile(prd) };
+        }
+    }
+
+    unsafe fn register(&self, register: u16) -> Port {
+        Port::new(self.port + register)
+    }
+}
+
+impl Drop for DmaBuffers {
+    fn drop(&mut self) {
+        unsafe {
+            deallocate_frame(self.prdt);
+            for page in &self.pages {
+                deallocate_frame(*page);
+            }
+        }
+    }
+}
+
+// The primary or secondary channel of the controller, which the master and slave drive on it share. Only one command
+// can be going on a channel at a time.
+#[derive(Debug)]
+struct Channel {
+    io_base: u16,
+    control_base: u16,
+    dma: Option<DmaBuffers>,
+}
+
+impl Channel {
+    unsafe fn register(&self, register: u16) -> Port {
+        Port::new(self.io_base + register)
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { self.register(STATUS_COMMAND).read_u8() }
+    }
+
+    // Reading it doesn't acknowledge anything, unlike the status register
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.control_base).read_u8() }
+    }
+
+    fn set_control(&self, control: u8) {
+        unsafe { Port::new(self.control_base).write_u8(control) };
+    }
+
+    // Drives take 400ns to put their status up after being selected or sent a command, and each read of the alternate
+    // status register takes at least 100ns
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    fn reset(&self) {
+        self.set_control(CONTROL_SOFTWARE_RESET | CONTROL_INTERRUPTS_DISABLED);
+        crate::time::delay(Duration::from_micros(5));
+        self.set_control(CONTROL_INTERRUPTS_DISABLED);
+        crate::time::delay(Duration::from_millis(2));
+    }
+
+    fn select(&self, drive: u8, bits: u8) {
+        unsafe {
+            self.register(DRIVE_SELECT)
+                .write_u8(SELECT_ALWAYS_SET | drive << 4 | bits)
+        };
+        self.delay();
+    }
+
+    fn command(&self, command: u8) {
+        unsafe { self.register(STATUS_COMMAND).write_u8(command) };
+        self.delay();
+    }
+
+    fn wait_not_busy(&self, timeout: Duration) -> Result<u8, BlockError> {
+        let deadline = uptime() + timeout;
+        loop {
+            let status = self.status();
+            if status & STATUS_BUSY == 0 {
+                return Ok(status);
+            }
+            if uptime() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    // After a command that's finished, to see whether it worked
+    fn check_status(&self) -> Result<(), BlockError> {
+        match self.wait_not_busy(TIMEOUT)? & (STATUS_ERROR | STATUS_DRIVE_FAULT) {
+            0 => Ok(()),
+            _ => Err(BlockError::DeviceError),
+        }
+    }
+
+    // Waits for the drive to be ready to move the next block of data through the data register
+    fn wait_for_data(&self) -> Result<(), BlockError> {
+        let deadline = uptime() + TIMEOUT;
+        loop {
+            let status = self.wait_not_busy(TIMEOUT)?;
+            if status & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 {
+                return Err(BlockError::DeviceError);
+            }
+            if status & STATUS_DATA_REQUEST != 0 {
+                return Ok(());
+            }
+            if uptime() > deadline {
+                return Err(BlockError::Timeout);
+            }
+        }
+    }
+
+    fn read_data(&self, buffer: &mut [u8]) {
+        for word in buffer.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { self.register(DATA).read_u16() }.to_le_bytes());
+        }
+    }
+
+    fn write_data(&self, data: &[u8]) {
+        for word in data.chunks_exact(2) {
+            unsafe {
+                self.register(DATA)
+                    .write_u16(u16::from_le_bytes([word[0], word[1]]))
+            };
+        }
+    }
+
+    // Selects drive and puts the sector number and count in, the high bytes first for LBA48, where each register is
+    // a two deep FIFO. A count of 0 is 256 sectors, or 65536 with LBA48.
+    fn set_address(&self, drive: u8, lba: u64, count: usize, lba48: bool) {
+        let bytes = lba.to_le_bytes();
+        unsafe {
+            if lba48 {
+                self.select(drive, SELECT_LBA);
+                self.register(SECTOR_COUNT).write_u8((count >> 8) as u8);
+                self.register(LBA_LOW).write_u8(bytes[3]);
+                self.register(LBA_MID).write_u8(bytes[4]);
+                self.register(LBA_HIGH).write_u8(bytes[5]);
+            } else {
+                self.select(drive, SELECT_LBA | (bytes[3] & 0x0F));
+            }
+            self.register(SECTOR_COUNT).write_u8(count as u8);
+            self.register(LBA_LOW).write_u8(bytes[0]);
+            self.register(LBA_MID).write_u8(bytes[1]);
+            self.register(LBA_HIGH).write_u8(bytes[2]);
+        }
+    }
+
+    // The 256 words IDENTIFY (or IDENTIFY PACKET) DEVICE returns, if there's a drive there at all
+    fn identify(&self, drive: u8) -> Option<(DriveKind, [u16; 256])> {
+        self.set_address(drive, 0, 0, false);
+        self.command(IDENTIFY);
+        // nothing there, or no channel at all and the bus is floating
+        if matches!(self.status(), 0x00 | 0xFF) {
+            return None;
+        }
+        self.wait_not_busy(IDENTIFY_TIMEOUT).ok()?;
+        let signature = unsafe {
+            (
+                self.register(LBA_MID).read_u8(),
+                self.register(LBA_HIGH).read_u8(),
+            )
+        };
+        let kind = match signature {
+            (0, 0) => DriveKind::Ata,
+            ATAPI_SIGNATURE => {
+                self.command(IDENTIFY_PACKET);
+                DriveKind::Atapi
+            }
+            // SATA drives behind a controller that's pretending to be IDE, among other things
+            _ => return None,
+        };
+        self.wait_for_data().ok()?;
+        let mut data = [0; SECTOR_SIZE];
+        self.read_data(&mut data);
+        let mut words = [0; 256];
+        for (word, bytes) in words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Some((kind, words))
+    }
+
+    fn pio_read(
+        &self,
+        drive: u8,
+        lba: u64,
+        buffer: &mut [u8],
+        lba48: bool,
+    ) -> Result<(), BlockError> {
+        self.set_address(drive, lba, buffer.len() / SECTOR_SIZE, lba48);
+        self.command(if lba48 {
+            READ_SECTORS_EXT
+        } else {
+            READ_SECTORS
+        });
+        for sector in buffer.chunks_exact_mut(SECTOR_SIZE) {
+            self.wait_for_data()?;
+            self.read_data(sector);
+        }
+        self.check_status()
+    }
+
+    fn pio_write(&self, drive: u8, lba: u64, data: &[u8], lba48: bool) -> Result<(), BlockError> {
+        self.set_address(drive, lba, data.len() / SECTOR_SIZE, lba48);
+        self.command(if lba48 {
+            WRITE_SECTORS_EXT
+        } else {
+            WRITE_SECTORS
+        });
+        for sector in data.chunks_exact(SECTOR_SIZE) {
+            self.wait_for_data()?;
+            self.write_data(sector);
+        }
+        self.check_status()
+    }
+
+    // Reads into the DMA pages, or writes whatever's already been copied into them
+    fn dma_transfer(
+        &self,
+        drive: u8,
+        lba: u64,
+        length: usize,
+        write: bool,
+        lba48: bool,
+    ) -> Result<(), BlockError> {
+        let dma = self
+            .dma
+            .as_ref()
+            .expect("DMA on a channel without a bus master");
+        dma.fill_prdt(length);
+        let direction = if write { 0 } else { BUS_MASTER_READ };
+        let command = match (write, lba48) {
+            (false, false) => READ_DMA,
+            (false, true) => READ_DMA_EXT,
+            (true, false) => WRITE_DMA,
+            (true, true) => WRITE_DMA_EXT,
+        };
+        unsafe {
+            dma.register(BUS_MASTER_COMMAND).write_u8(direction);
+            dma.register(BUS_MASTER_PRDT)
+                .write_u32(dma.prdt.start_address().0 as u32);
+            // the error and interrupt bits are cleared by writing 1s to them
+            let status = dma.register(BUS_MASTER_STATUS).read_u8();
+            dma.register(BUS_MASTER_STATUS)
+                .write_u8(status | BUS_MASTER_ERROR | BUS_MASTER_INTERRUPT);
+        }
+        self.set_address(drive, lba, length / SECTOR_SIZE, lba48);
+        self.command(command);
+        unsafe {
+            dma.register(BUS_MASTER_COMMAND)
+                .write_u8(direction | BUS_MASTER_START)
+        };
+
+        let deadline = uptime() + TIMEOUT;
+        let status = loop {
+            let status = unsafe { dma.register(BUS_MASTER_STATUS).read_u8() };
+            if status & (BUS_MASTER_ACTIVE | BUS_MASTER_ERROR) != BUS_MASTER_ACTIVE
+                || uptime() > deadline
+            {
+                break status;
+            }
+            // it's not going anywhere, and nobody else can use the channel in the meantime anyway
+            task::yield_now();
+        };
+        unsafe { dma.register(BUS_MASTER_COMMAND).write_u8(direction) };
+        let result = self.check_status();
+        if status & BUS_MASTER_ERROR != 0 {
+            return Err(BlockError::DeviceError);
+        }
+        if status & BUS_MASTER_ACTIVE != 0 {
+            return Err(BlockError::Timeout);
+        }
+        result
+    }
+
+    // Sends an ATAPI drive a SCSI command, then reads back whatever it answers with, up to the length of buffer
+    fn packet(
+        &self,
+        drive: u8,
+        packet: &[u8; PACKET_SIZE],
+        buffer: &mut [u8],
+    ) -> Result<usize, BlockError> {
+        self.select(drive, 0);
+        unsafe {
+            // PIO, with the most the drive should send before asking for the next DRQ in LBA mid and high
+            self.register(ERROR_FEATURES).write_u8(0);
+            self.register(LBA_MID).write_u8(ATAPI_BLOCK_SIZE as u8);
+            self.register(LBA_HIGH)
+                .write_u8((ATAPI_BLOCK_SIZE >> 8) as u8);
+        }
+        self.command(PACKET);
+        self.wait_for_data()?;
+        self.write_data(packet);
+        let mut read = 0;
+        while read < buffer.len() {
+            self.wait_for_data()?;
+            let length = unsafe {
+                self.register(LBA_MID).read_u8() as usize
+                    | (self.register(LBA_HIGH).read_u8() as usize) << 8
+            };
+            let length = length.min(buffer.len() - read);
+            self.read_data(&mut buffer[read..read + length]);
+            read += length;
+        }
+        self.check_status()?;
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveKind {
+    Ata,
+    Atapi,
+}
+
+pub struct AtaDrive {
+    name: String,
+    model: String,
+    channel: Arc<Mutex<Channel>>,
+    // 0 for the master, 1 for the slave
+    drive: u8,
+    kind: DriveKind,
+    block_count: u64,
+    lba48: bool,
+    dma: AtomicBool,
+}
+
+impl AtaDrive {
+    fn new(name: String, channel: &Arc<Mutex<Channel>>, drive: u8) -> Option<AtaDrive> {
+        let locked = channel.lock();
+        let (kind, identify) = locked.identify(drive)?;
+        let model = IDENTIFY_MODEL
+            .flat_map(|word| identify[word].to_be_bytes())
+            .map(char::from)
+            .collect::<String>();
+        let model = String::from(model.trim());
+        let capabilities = identify[IDENTIFY_CAPABILITIES];
+        let lba48 =
+            kind == DriveKind::Ata && identify[IDENTIFY_COMMAND_SETS] & COMMAND_SET_LBA48 != 0;
+        let block_count = match kind {
+            DriveKind::Ata if capabilities & CAPABILITY_LBA == 0 => {
+                log::warn!(
+                    "{}: {} can only be addressed by cylinder, head and sector",
+                    name,
+                    model
+                );
+                return None;
+            }
+            DriveKind::Ata if lba48 => (0..4).fold(0, |count, index| {
+                count | (identify[IDENTIFY_LBA48_SECTORS + index] as u64) << (16 * index)
+            }),
+            DriveKind::Ata => {
+                identify[IDENTIFY_LBA28_SECTORS] as u64
+                    | (identify[IDENTIFY_LBA28_SECTORS + 1] as u64) << 16
+            }
+            DriveKind::Atapi => read_capacity(&locked, drive).unwrap_or(0),
+        };
+        let dma =
+            kind == DriveKind::Ata && locked.dma.is_some() && capabilities & CAPABILITY_DMA != 0;
+        log::info!(
+            "{}: {} ({:?}{})",
+            name,
+            model,
+            kind,
+            if dma { ", DMA" } else { ", PIO" }
+        );
+        drop(locked);
+        Some(AtaDrive {
+            name,
+            model,
+            channel: channel.clone(),
+            drive,
+            kind,
+            block_count,
+            lba48,
+            dma: AtomicBool::new(dma),
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn kind(&self) -> DriveKind {
+        self.kind
+    }
+
+    pub fn uses_dma(&self) -> bool {
+        self.dma.load(Ordering::Relaxed)
+    }
+
+    // For going back to PIO, DMA can't be turned on for a drive that didn't start out with it
+    pub fn disable_dma(&self) {
+        self.dma.store(false, Ordering::Relaxed);
+    }
+}
+
+// The number of blocks on the disc in an ATAPI drive, None if there isn't one
+fn read_capacity(channel: &Channel, drive: u8) -> Option<u64> {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = SCSI_READ_CAPACITY;
+    // the last block's number and the block size, both big endian
+    let mut capacity = [0; 8];
+    channel.packet(drive, &packet, &mut capacity).ok()?;
+    let last_block = u32::from_be_bytes([capacity[0], capacity[1], capacity[2], capacity[3]]);
+    Some(last_block as u64 + 1)
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        match self.kind {
+            DriveKind::Ata => SECTOR_SIZE,
+            DriveKind::Atapi => ATAPI_BLOCK_SIZE,
+        }
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    // Burning CDs is out of scope
+    fn read_only(&self) -> bool {
+        self.kind == DriveKind::Atapi
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        if self.kind == DriveKind::Atapi && self.block_count == 0 {
+            return Err(BlockError::NoMedium);
+        }
+        check_request(self, start, buffer.len())?;
+        let channel = self.channel.lock();
+        let chunk_size = MAX_SECTORS * SECTOR_SIZE;
+        for (index, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let lba = start + (index * chunk_size / self.block_size()) as u64;
+            match self.kind {
+                DriveKind::Atapi => {
+                    let count = (chunk.len() / ATAPI_BLOCK_SIZE) as u16;
+                    let mut packet = [0; PACKET_SIZE];
+                    packet[0] = SCSI_READ_10;
+                    packet[2..6].copy_from_slice(&(lba as u32).to_be_bytes());
+                    packet[7..9].copy_from_slice(&count.to_be_bytes());
+                    channel.packet(self.drive, &packet, chunk)?;
+                }
+                DriveKind::Ata if self.uses_dma() => {
+                    channel.dma_transfer(self.drive, lba, chunk.len(), false, self.lba48)?;
+                    channel.dma.as_ref().unwrap().copy_out(chunk);
+                }
+                DriveKind::Ata => channel.pio_read(self.drive, lba, chunk, self.lba48)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError> {
+        if self.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, start, data.len())?;
+        let channel = self.channel.lock();
+        let chunk_size = MAX_SECTORS * SECTOR_SIZE;
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            let lba = start + (index * MAX_SECTORS) as u64;
+            if self.uses_dma() {
+                channel.dma.as_ref().unwrap().copy_in(chunk);
+                channel.dma_transfer(self.drive, lba, chunk.len(), true, self.lba48)?;
+            } else {
+                channel.pio_write(self.drive, lba, chunk, self.lba48)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if self.read_only() {
+            return Ok(());
+        }
+        let channel = self.channel.lock();
+        channel.select(self.drive, 0);
+        channel.command(if self.lba48 {
+            FLUSH_CACHE_EXT
+        } else {
+            FLUSH_CACHE
+        });
+        channel.check_status()
+    }
+}
+
+impl core::fmt::Debug for AtaDrive {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AtaDrive")
+            .field("name", &self.name)
+            .field("model", &self.model)
+            .field("drive", &self.drive)
+            .field("kind", &self.kind)
+            .field("block_count", &self.block_count)
+            .finish()
+    }
+}
+
+// Finds the IDE controller and registers every drive on it, as hda and hdb on the primary channel and hdc and hdd on
+// the secondary, the same as Linux
+pub fn init() {
+    let Some(controller) = pci::find_class(MASS_STORAGE_CLASS, IDE_SUBCLASS) else {
+        return;
+    };
+    controller.enable_io_space();
+    let bus_master = match controller.bar(4) {
+        Some(Bar::Io { port, .. }) if controller.prog_if & BUS_MASTER_CAPABLE != 0 => {
+            controller.enable_bus_mastering();
+            Some(port)
+        }
+        _ => None,
+    };
+    let channels = [
+        (PRIMARY_NATIVE, 0, PRIMARY_IO_BASE, PRIMARY_CONTROL_BASE, 0),
+        (
+            SECONDARY_NATIVE,
+            2,
+            SECONDARY_IO_BASE,
+            SECONDARY_CONTROL_BASE,
+            BUS_MASTER_SECONDARY,
+        ),
+    ];
+    for (index, (native, bar, io_base, control_base, bus_master_offset)) in
+        channels.into_iter().enumerate()
+    {
+        let Some((io_base, control_base)) =
+            channel_ports(&controller, native, bar, io_base, control_base)
+        else {
+            continue;
+        };
+        let channel = Channel {
+            io_base,
+            control_base,
+            dma: bus_master.and_then(|port| DmaBuffers::new(port + bus_master_offset)),
+        };
+        channel.reset();
+        let channel = Arc::new(Mutex::new(channel));
+        for drive in 0..2 {
+            let name = alloc::format!("hd{}", (b'a' + (index * 2 + drive) as u8) as char);
+            if let Some(drive) = AtaDrive::new(name, &channel, drive as u8) {
+                block::register(Arc::new(drive));
+            }
+        }
+    }
+}
+
+// Where the command and control registers of a channel are. In native mode the control block BAR points at 4 ports,
+// with the one we want the third.
+fn channel_ports(
+    controller: &PciDevice,
+    native: u8,
+    bar: usize,
+    io_base: u16,
+    control_base: u16,
+) -> Option<(u16, u16)> {
+    if controller.prog_if & native == 0 {
+        return Some((io_base, control_base));
+    }
+    match (controller.bar(bar)?, controller.bar(bar + 1)?) {
+        (Bar::Io { port: io, .. }, Bar::Io { port: control, .. }) => Some((io, control + 2)),
+        _ => None,
+    }
+}
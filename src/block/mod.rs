@@ -0,0 +1,114 @@
+// Disks, and anything else that stores data in fixed size blocks. Drivers find their devices at boot and register
+// them here, under names like Linux's (hda, sdb and so on), for file systems to read and write through BlockDevice.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+
+use crate::fs::FsError;
+use crate::sync::Mutex;
+
+pub mod ahci;
+pub mod ata;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    // past the last block of the device
+    OutOfRange,
+    // the buffer isn't a whole number of blocks
+    BadLength,
+    ReadOnly,
+    // nothing in the drive, for removable media
+    NoMedium,
+    // the device took too long to answer
+    Timeout,
+    // the device said the command failed
+    DeviceError,
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::OutOfRange | BlockError::BadLength => FsError::InvalidArgument,
+            BlockError::ReadOnly => FsError::PermissionDenied,
+            BlockError::NoMedium | BlockError::Timeout | BlockError::DeviceError => FsError::Io,
+        }
+    }
+}
+
+// Reads and writes always cover whole blocks, starting at a block number. Any is so whoever knows what driver a device
+// belongs to can get at the rest of it.
+pub trait BlockDevice: Any + Send + Sync + fmt::Debug {
+    fn name(&self) -> &str;
+
+    // in bytes, 512 for most disks and 2048 for CDs
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    fn read_blocks(&self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    fn write_blocks(&self, start: u64, data: &[u8]) -> Result<(), BlockError>;
+
+    // Makes sure everything written so far is actually stored, rather than in the device's cache
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+// How many blocks a read or write of length bytes from start covers, or why it can't be done
+pub fn check_request(
+    device: &dyn BlockDevice,
+    start: u64,
+    length: usize,
+) -> Result<u64, BlockError> {
+    let block_size = device.block_size();
+    if !length.is_multiple_of(block_size) {
+        return Err(BlockError::BadLength);
+    }
+    let count = (length / block_size) as u64;
+    match start.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+// Looks for disks on every controller there's a driver for
+pub fn init() {
+    ata::init();
+    ahci::init();
+}
+
+pub fn register(device: Arc<dyn BlockDevice>) {
+    log::info!(
+        "{}: {} blocks of {} bytes{}",
+        device.name(),
+        device.block_count(),
+        device.block_size(),
+        if device.read_only() {
+            ", read only"
+        } else {
+            ""
+        }
+    );
+    DEVICES.lock().push(device);
+}
+
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
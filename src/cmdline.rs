@@ -0,0 +1,19 @@
+// bootloader 0.9 has no way of passing a command line to the kernel, so for now it gets baked in at build time:
+// FLAP_CMDLINE="log=debug,flap_os::pci=trace" cargo run
+const DEFAULT_CMDLINE: &str = "";
+
+pub fn get() -> &'static str {
+    option_env!("FLAP_CMDLINE").unwrap_or(DEFAULT_CMDLINE)
+}
+
+// Options are space separated key=value pairs (or bare flags). Returns the value of the last occurrence of key.
+pub fn option(key: &str) -> Option<&'static str> {
+    get()
+        .split_ascii_whitespace()
+        .filter_map(|option| match option.split_once('=') {
+            Some((option_key, value)) if option_key == key => Some(value),
+            None if option == key => Some(""),
+            _ => None,
+        })
+        .next_back()
+}
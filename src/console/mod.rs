@@ -0,0 +1,316 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::{Mutex, RwLock};
+
+use crate::interrupts::without_interrupts;
+
+pub mod ring_buffer;
+
+use ring_buffer::RingBuffer;
+
+const MAX_SINKS: usize = 8;
+pub const RING_BUFFER_SIZE: usize = 16 * 1024;
+
+// Anything print! output can be sent to. Sinks do their own locking, and are always called with interrupts disabled.
+pub trait ConsoleSink: Sync {
+    fn write_str(&self, s: &str);
+
+    // For exception handlers, which might have interrupted whoever is holding the sink's lock.
+    // Has to give up (returning false) rather than spin.
+    fn try_write_str(&self, s: &str) -> bool;
+
+    // Only for the panic path, where whoever is holding the lock is never going to run again
+    unsafe fn force_unlock(&self);
+}
+
+pub struct VgaSink;
+
+impl ConsoleSink for VgaSink {
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        let _ = crate::vga::WRITER.lock().write_str(s);
+    }
+
+    fn try_write_str(&self, s: &str) -> bool {
+        use core::fmt::Write;
+        crate::vga::WRITER
+            .try_lock()
+            .map(|mut writer| writer.write_str(s))
+            .is_some()
+    }
+
+    unsafe fn force_unlock(&self) {
+        crate::vga::WRITER.force_unlock();
+    }
+}
+
+pub struct SerialSink;
+
+impl ConsoleSink for SerialSink {
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        let _ = crate::serial::SERIAL1.lock().write_str(s);
+    }
+
+    // Serial is where exception output matters most (it's what the test harness sees), so it goes around the lock
+    fn try_write_str(&self, s: &str) -> bool {
+        use core::fmt::Write;
+        match crate::serial::SERIAL1.try_lock() {
+            Some(mut serial) => {
+                let _ = serial.write_str(s);
+            }
+            None => crate::serial::_print_bypassing_lock(format_args!("{}", s)),
+        }
+        true
+    }
+
+    unsafe fn force_unlock(&self) {
+        crate::serial::SERIAL1.force_unlock();
+    }
+}
+
+pub struct FramebufferSink;
+
+impl ConsoleSink for FramebufferSink {
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        if let Some(console) = crate::framebuffer::CONSOLE.lock().as_mut() {
+            let _ = console.write_str(s);
+        }
+    }
+
+    fn try_write_str(&self, s: &str) -> bool {
+        use core::fmt::Write;
+        match crate::framebuffer::CONSOLE.try_lock() {
+            Some(mut console) => {
+                if let Some(console) = console.as_mut() {
+                    let _ = console.write_str(s);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe fn force_unlock(&self) {
+        crate::framebuffer::CONSOLE.force_unlock();
+    }
+}
+
+// Keeps the last RING_BUFFER_SIZE bytes of output around so they can be looked at later
+pub struct RingBufferSink;
+
+pub static RING_BUFFER: Mutex<RingBuffer<RING_BUFFER_SIZE>> = Mutex::new(RingBuffer::new());
+
+impl ConsoleSink for RingBufferSink {
+    fn write_str(&self, s: &str) {
+        RING_BUFFER.lock().write(s.as_bytes());
+    }
+
+    fn try_write_str(&self, s: &str) -> bool {
+        RING_BUFFER
+            .try_lock()
+            .map(|mut ring_buffer| ring_buffer.write(s.as_bytes()))
+            .is_some()
+    }
+
+    unsafe fn force_unlock(&self) {
+        RING_BUFFER.force_unlock();
+    }
+}
+
+pub static VGA_SINK: VgaSink = VgaSink;
+pub static SERIAL_SINK: SerialSink = SerialSink;
+pub static FRAMEBUFFER_SINK: FramebufferSink = FramebufferSink;
+pub static RING_BUFFER_SINK: RingBufferSink = RingBufferSink;
+
+struct Registration {
+    name: &'static str,
+    sink: &'static dyn ConsoleSink,
+    enabled: AtomicBool,
+}
+
+impl Registration {
+    const fn new(name: &'static str, sink: &'static dyn ConsoleSink) -> Self {
+        Registration {
+            name,
+            sink,
+            enabled: AtomicBool::new(true),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    AlreadyRegistered,
+    NoFreeSlot,
+    NotFound,
+}
+
+// The VGA text buffer, COM1 and the ring buffer are always there, so they're registered from the start.
+// That way print! works before anything has been initialized.
+// This is a RwLock so that an exception handler can print while the code it interrupted is in the middle of printing.
+static SINKS: RwLock<[Option<Registration>; MAX_SINKS]> = RwLock::new([
+    Some(Registration::new("vga", &VGA_SINK)),
+    Some(Registration::new("serial", &SERIAL_SINK)),
+    Some(Registration::new("ring", &RING_BUFFER_SINK)),
+    None,
+    None,
+    None,
+    None,
+    None,
+]);
+
+pub fn register(name: &'static str, sink: &'static dyn ConsoleSink) -> Result<(), ConsoleError> {
+    without_interrupts(|| register_sink(name, sink))
+}
+
+fn register_sink(name: &'static str, sink: &'static dyn ConsoleSink) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.write();
+    if sinks
+        .iter()
+        .flatten()
+        .any(|registration| registration.name == name)
+    {
+        return Err(ConsoleError::AlreadyRegistered);
+    }
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ConsoleError::NoFreeSlot)?;
+    *slot = Some(Registration::new(name, sink));
+    Ok(())
+}
+
+pub fn unregister(name: &str) -> Result<(), ConsoleError> {
+    without_interrupts(|| {
+        let mut sinks = SINKS.write();
+        let slot = sinks
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(registration) if registration.name == name))
+            .ok_or(ConsoleError::NotFound)?;
+        *slot = None;
+        Ok(())
+    })
+}
+
+pub fn set_enabled(name: &str, enabled: bool) -> Result<(), ConsoleError> {
+    let sinks = SINKS.read();
+    let registration = sinks
+        .iter()
+        .flatten()
+        .find(|registration| registration.name == name)
+        .ok_or(ConsoleError::NotFound)?;
+    registration.enabled.store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn is_enabled(name: &str) -> Option<bool> {
+    SINKS
+        .read()
+        .iter()
+        .flatten()
+        .find(|registration| registration.name == name)
+        .map(|registration| registration.enabled.load(Ordering::Relaxed))
+}
+
+// Hands every piece of formatted output to each enabled sink, so nothing has to be buffered
+struct Fanout<'a> {
+    sinks: &'a [Option<Registration>],
+    // exception handlers can't wait on anyone
+    non_blocking: bool,
+}
+
+impl fmt::Write for Fanout<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for registration in self.sinks.iter().flatten() {
+            if !registration.enabled.load(Ordering::Relaxed) {
+                continue;
+            }
+            if self.non_blocking {
+                // a sink that's busy just misses out on this one
+                let _ = registration.sink.try_write_str(s);
+            } else {
+                registration.sink.write_str(s);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Calls f with everything still in the ring buffer, oldest first
+pub fn dump_ring_buffer(f: impl FnMut(&str)) {
+    without_interrupts(|| RING_BUFFER.lock().for_each_str(f));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    without_interrupts(|| {
+        let sinks = SINKS.read();
+        Fanout {
+            sinks: &*sinks,
+            non_blocking: false,
+        }
+        .write_fmt(args)
+        .unwrap();
+    });
+}
+
+// What exception handlers print with. Never spins on a lock, so it's safe to use even if the exception
+// interrupted someone in the middle of a print!
+#[doc(hidden)]
+pub fn _print_exception(args: fmt::Arguments) {
+    use core::fmt::Write;
+    without_interrupts(|| match SINKS.try_read() {
+        Some(sinks) => {
+            let _ = Fanout {
+                sinks: &*sinks,
+                non_blocking: true,
+            }
+            .write_fmt(args);
+        }
+        // someone is (un)registering a sink, serial is the only thing left we can reach
+        None => crate::serial::_print_bypassing_lock(args),
+    });
+}
+
+// Unlocks every console lock. Only safe once nothing that could be holding them is ever going to run again.
+pub unsafe fn force_unlock() {
+    if SINKS.try_read().is_none() {
+        SINKS.force_write_unlock();
+    }
+    for registration in SINKS.read().iter().flatten() {
+        registration.sink.force_unlock();
+    }
+}
+
+// For panic handlers: whatever we interrupted is never coming back, so the locks can just be broken
+pub fn print_panic(args: fmt::Arguments) {
+    crate::interrupts::disable();
+    unsafe { force_unlock() };
+    _print(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! exception_print {
+    ($($arg:tt)*) => ($crate::console::_print_exception(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! exception_println {
+    () => ($crate::exception_print!("\n"));
+    ($($arg:tt)*) => ($crate::exception_print!("{}\n", format_args!($($arg)*)));
+}
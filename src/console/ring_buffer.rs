@@ -0,0 +1,81 @@
+use core::fmt;
+
+// Fixed size byte ring buffer, once it fills up the oldest bytes get overwritten.
+#[derive(Debug)]
+pub struct RingBuffer<const N: usize> {
+    data: [u8; N],
+    // where the next byte is written
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            data: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        // only the tail end of anything bigger than the whole buffer would survive anyway
+        let bytes = &bytes[bytes.len().saturating_sub(N)..];
+        let first = bytes.len().min(N - self.head);
+        self.data[self.head..self.head + first].copy_from_slice(&bytes[..first]);
+        self.data[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+        self.head = (self.head + bytes.len()) % N;
+        self.len = (self.len + bytes.len()).min(N);
+    }
+
+    // The contents oldest first, split in two where the buffer wraps around
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let start = (self.head + N - self.len) % N;
+        if start + self.len <= N {
+            (&self.data[start..start + self.len], &[])
+        } else {
+            (&self.data[start..], &self.data[..self.head])
+        }
+    }
+
+    // Calls f with the contents as text, oldest first. The oldest character might have been partially
+    // overwritten and a character can straddle the wrap around, anything that isn't valid UTF-8 is skipped.
+    pub fn for_each_str(&self, mut f: impl FnMut(&str)) {
+        let (older, newer) = self.as_slices();
+        for chunk in [older, newer] {
+            for piece in chunk.utf8_chunks() {
+                f(piece.valid());
+            }
+        }
+    }
+}
+
+impl<const N: usize> fmt::Write for RingBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        RingBuffer::new()
+    }
+}
@@ -0,0 +1,127 @@
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count, CpuidResult};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Intel Manual - Volume 2A, CPUID
+// these are only safe to call on newer toolchains
+#[allow(unused_unsafe)]
+#[inline]
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    unsafe { __cpuid(leaf) }
+}
+
+#[allow(unused_unsafe)]
+#[inline]
+pub fn cpuid_count(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    unsafe { __cpuid_count(leaf, sub_leaf) }
+}
+
+// The initial APIC ID, which smp keeps in each CPU's per-CPU data
+pub fn apic_id() -> u8 {
+    (cpuid(0x01).ebx >> 24) as u8
+}
+
+// Model specific registers, Intel Manual - Volume 4
+pub const IA32_APIC_BASE: u32 = 0x1B;
+pub const IA32_EFER: u32 = 0xC000_0080;
+pub const IA32_STAR: u32 = 0xC000_0081;
+pub const IA32_LSTAR: u32 = 0xC000_0082;
+pub const IA32_FMASK: u32 = 0xC000_0084;
+pub const IA32_FS_BASE: u32 = 0xC000_0100;
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+pub const EFER_SYSCALL_ENABLE: u64 = 1 << 0;
+pub const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+#[inline]
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    (high as u64) << 32 | low as u64
+}
+
+#[inline]
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    let (high, low) = ((value >> 32) as u32, value as u32);
+    asm!("wrmsr", in("ecx") msr, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+}
+
+// Lets page table entries use the NO_EXECUTE bit, without this it's reserved and setting it faults
+pub fn enable_no_execute() {
+    unsafe {
+        let efer = read_msr(IA32_EFER);
+        write_msr(IA32_EFER, efer | EFER_NO_EXECUTE_ENABLE);
+    }
+}
+
+// Control registers -> Intel Manual - Section 2.5
+pub const CR0_MONITOR_COPROCESSOR: u64 = 1 << 1;
+pub const CR0_EMULATION: u64 = 1 << 2;
+pub const CR0_TASK_SWITCHED: u64 = 1 << 3;
+pub const CR4_PAGE_GLOBAL_ENABLE: u64 = 1 << 7;
+pub const CR4_OSFXSR: u64 = 1 << 9;
+pub const CR4_OSXMMEXCPT: u64 = 1 << 10;
+pub const CR4_PCID_ENABLE: u64 = 1 << 17;
+
+#[inline]
+pub fn read_cr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline]
+pub unsafe fn write_cr0(value: u64) {
+    asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+// The address that caused the last page fault
+#[inline]
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline]
+pub fn read_cr4() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline]
+pub unsafe fn write_cr4(value: u64) {
+    asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+// The kernel itself is built without SSE, but user programs aren't, and fxsave/fxrstor need this to save the XMM registers.
+// Intel Manual - Section 13.1.3
+pub fn enable_sse() {
+    unsafe {
+        let cr0 = read_cr0() & !(CR0_EMULATION | CR0_TASK_SWITCHED);
+        write_cr0(cr0 | CR0_MONITOR_COPROCESSOR);
+        write_cr4(read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT);
+    }
+}
+
+const CPUID_PCID: u32 = 1 << 17;
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Process-context identifiers tag TLB entries with the address space they came from, so switching address spaces
+// doesn't have to throw them all away -> Intel Manual - Section 4.10.1
+// Can only be turned on while the current PCID is 0, which it always is coming from the bootloader.
+pub fn enable_pcid() -> bool {
+    if cpuid(0x01).ecx & CPUID_PCID == 0 {
+        return false;
+    }
+    unsafe { write_cr4(read_cr4() | CR4_PCID_ENABLE) };
+    PCID_ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+#[inline]
+pub fn pcid_enabled() -> bool {
+    PCID_ENABLED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,114 @@
+// Bochs Graphics Adapter, which is what QEMU gives you with -vga std. See https://wiki.osdev.org/Bochs_VBE_Extensions
+use crate::framebuffer::{FrameBufferInfo, FramebufferError, PixelFormat};
+use crate::memory::address::PhysicalAddress;
+use crate::pci::consts::{BOCHS_VENDOR_ID, BOCHS_VGA_DEVICE_ID};
+use crate::pci::{self, Bar, PciDevice};
+use crate::port::Port;
+
+const VBE_DISPI_IOPORT_INDEX: u16 = 0x01CE;
+const VBE_DISPI_IOPORT_DATA: u16 = 0x01CF;
+
+const VBE_DISPI_INDEX_ID: u16 = 0x00;
+const VBE_DISPI_INDEX_XRES: u16 = 0x01;
+const VBE_DISPI_INDEX_YRES: u16 = 0x02;
+const VBE_DISPI_INDEX_BPP: u16 = 0x03;
+const VBE_DISPI_INDEX_ENABLE: u16 = 0x04;
+const VBE_DISPI_INDEX_VIRT_WIDTH: u16 = 0x06;
+const VBE_DISPI_INDEX_X_OFFSET: u16 = 0x08;
+const VBE_DISPI_INDEX_Y_OFFSET: u16 = 0x09;
+
+const VBE_DISPI_DISABLED: u16 = 0x00;
+const VBE_DISPI_ENABLED: u16 = 0x01;
+const VBE_DISPI_LFB_ENABLED: u16 = 0x40;
+
+// IDs 0xB0C0 through 0xB0C5 are all versions of the interface, we need at least 0xB0C2 for 32 bit color
+const VBE_DISPI_ID_MIN: u16 = 0xB0C2;
+const VBE_DISPI_ID_MAX: u16 = 0xB0C5;
+const VBE_DISPI_MAX_XRES: usize = 2560;
+const VBE_DISPI_MAX_YRES: usize = 1600;
+
+const BITS_PER_PIXEL: u16 = 32;
+
+#[derive(Debug)]
+pub struct BgaDevice {
+    pci_device: PciDevice,
+    framebuffer_address: PhysicalAddress,
+}
+
+impl BgaDevice {
+    pub fn find() -> Option<BgaDevice> {
+        let pci_device = pci::find_device(BOCHS_VENDOR_ID, BOCHS_VGA_DEVICE_ID)?;
+        let version = unsafe { read_register(VBE_DISPI_INDEX_ID) };
+        if !(VBE_DISPI_ID_MIN..=VBE_DISPI_ID_MAX).contains(&version) {
+            return None;
+        }
+        // BAR 0 is the linear framebuffer
+        match pci_device.bar(0)? {
+            Bar::Memory { address, .. } => {
+                pci_device.enable_memory_space();
+                Some(BgaDevice {
+                    pci_device,
+                    framebuffer_address: address,
+                })
+            }
+            Bar::Io { .. } => None,
+        }
+    }
+
+    pub fn pci_device(&self) -> PciDevice {
+        self.pci_device
+    }
+
+    pub fn framebuffer_address(&self) -> PhysicalAddress {
+        self.framebuffer_address
+    }
+
+    pub fn set_mode(
+        &self,
+        width: usize,
+        height: usize,
+    ) -> Result<FrameBufferInfo, FramebufferError> {
+        if width == 0 || height == 0 || width > VBE_DISPI_MAX_XRES || height > VBE_DISPI_MAX_YRES {
+            return Err(FramebufferError::UnsupportedMode);
+        }
+        unsafe {
+            // the mode can only be changed while the display is off
+            write_register(VBE_DISPI_INDEX_ENABLE, VBE_DISPI_DISABLED);
+            write_register(VBE_DISPI_INDEX_XRES, width as u16);
+            write_register(VBE_DISPI_INDEX_YRES, height as u16);
+            write_register(VBE_DISPI_INDEX_BPP, BITS_PER_PIXEL);
+            write_register(VBE_DISPI_INDEX_VIRT_WIDTH, width as u16);
+            write_register(VBE_DISPI_INDEX_X_OFFSET, 0);
+            write_register(VBE_DISPI_INDEX_Y_OFFSET, 0);
+            write_register(
+                VBE_DISPI_INDEX_ENABLE,
+                VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED,
+            );
+
+            // the device silently ignores modes it doesn't like
+            if read_register(VBE_DISPI_INDEX_XRES) as usize != width
+                || read_register(VBE_DISPI_INDEX_YRES) as usize != height
+            {
+                return Err(FramebufferError::UnsupportedMode);
+            }
+        }
+        Ok(FrameBufferInfo {
+            width,
+            height,
+            stride: width,
+            bytes_per_pixel: (BITS_PER_PIXEL / 8) as usize,
+            // 0x00RRGGBB stored little endian
+            pixel_format: PixelFormat::Bgr,
+        })
+    }
+}
+
+unsafe fn read_register(index: u16) -> u16 {
+    Port::new(VBE_DISPI_IOPORT_INDEX).write_u16(index);
+    Port::new(VBE_DISPI_IOPORT_DATA).read_u16()
+}
+
+unsafe fn write_register(index: u16, value: u16) {
+    Port::new(VBE_DISPI_IOPORT_INDEX).write_u16(index);
+    Port::new(VBE_DISPI_IOPORT_DATA).write_u16(value);
+}
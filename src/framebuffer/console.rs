@@ -0,0 +1,267 @@
+use core::fmt;
+
+use crate::framebuffer::font::Font;
+use crate::framebuffer::{FrameBuffer, Rect, Rgb};
+use crate::vga::ansi::{Action, ControlSequence, Parser};
+use crate::vga::{Color, ColorCode, DEFAULT_COLOR_CODE};
+
+const TAB_WIDTH: usize = 8;
+
+// The standard VGA palette, indexed by vga::Color
+const PALETTE: [Rgb; 16] = [
+    Rgb::from_hex(0x000000),
+    Rgb::from_hex(0x0000AA),
+    Rgb::from_hex(0x00AA00),
+    Rgb::from_hex(0x00AAAA),
+    Rgb::from_hex(0xAA0000),
+    Rgb::from_hex(0xAA00AA),
+    Rgb::from_hex(0xAA5500),
+    Rgb::from_hex(0xAAAAAA),
+    Rgb::from_hex(0x555555),
+    Rgb::from_hex(0x5555FF),
+    Rgb::from_hex(0x55FF55),
+    Rgb::from_hex(0x55FFFF),
+    Rgb::from_hex(0xFF5555),
+    Rgb::from_hex(0xFF55FF),
+    Rgb::from_hex(0xFFFF55),
+    Rgb::from_hex(0xFFFFFF),
+];
+
+pub fn color_to_rgb(color: Color) -> Rgb {
+    PALETTE[color as usize]
+}
+
+// Same idea as vga::Writer, just drawing glyphs into a framebuffer instead of poking the text buffer.
+// Colors use the 16 color VGA palette so the same escape sequences look the same on both.
+pub struct FramebufferConsole {
+    framebuffer: FrameBuffer,
+    font: Font,
+    cols: usize,
+    rows: usize,
+    row: usize,
+    // can be equal to cols, in which case the next printed char wraps onto a new line
+    col: usize,
+    color_code: ColorCode,
+    bold: bool,
+    parser: Parser,
+}
+
+impl fmt::Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for char in s.chars() {
+            match self.parser.advance(char) {
+                Action::None => {}
+                Action::Print(char) => self.write_char(char),
+                Action::Execute(byte) => self.execute(byte),
+                Action::Escape(b'c') => {
+                    self.color_code = DEFAULT_COLOR_CODE;
+                    self.clear_screen();
+                }
+                Action::Escape(_) => {}
+                Action::ControlSequence(sequence) => self.control_sequence(&sequence),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FramebufferConsole {
+    pub fn new(framebuffer: FrameBuffer, font: Font) -> Self {
+        let info = framebuffer.info();
+        let mut console = FramebufferConsole {
+            cols: info.width / font.width(),
+            rows: info.height / font.height(),
+            framebuffer,
+            font,
+            row: 0,
+            col: 0,
+            color_code: DEFAULT_COLOR_CODE,
+            bold: false,
+            parser: Parser::new(),
+        };
+        console.clear_screen();
+        console
+    }
+
+    pub fn framebuffer(&mut self) -> &mut FrameBuffer {
+        &mut self.framebuffer
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(background, foreground);
+    }
+
+    pub fn clear_screen(&mut self) {
+        let background = color_to_rgb(self.color_code.background());
+        self.framebuffer.clear(background);
+        self.row = 0;
+        self.col = 0;
+    }
+
+    fn write_char(&mut self, char: char) {
+        if self.col >= self.cols {
+            self.new_line();
+        }
+        self.draw_glyph(self.row, self.col, char);
+        self.col += 1;
+    }
+
+    fn draw_glyph(&mut self, row: usize, col: usize, char: char) {
+        let foreground = color_to_rgb(self.color_code.foreground());
+        let background = color_to_rgb(self.color_code.background());
+        let (width, height) = (self.font.width(), self.font.height());
+        let bytes_per_row = self.font.bytes_per_row();
+        let (origin_x, origin_y) = (col * width, row * height);
+        let glyph = self.font.glyph(char);
+        for y in 0..height {
+            let glyph_row = &glyph[y * bytes_per_row..(y + 1) * bytes_per_row];
+            for x in 0..width {
+                // most significant bit is the leftmost pixel
+                let set = glyph_row[x / 8] & (0x80 >> (x % 8)) != 0;
+                let color = if set { foreground } else { background };
+                self.framebuffer
+                    .write_pixel(origin_x + x, origin_y + y, color);
+            }
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.col = 0,
+            b'\t' => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            0x08 => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn control_sequence(&mut self, sequence: &ControlSequence) {
+        if sequence.private {
+            return;
+        }
+        let n = sequence.param_or(0, 1) as usize;
+        let (row, col) = (self.row, self.col.min(self.cols - 1));
+        match sequence.final_byte {
+            b'A' => self.set_position(row.saturating_sub(n), col),
+            b'B' => self.set_position(row + n, col),
+            b'C' => self.set_position(row, col + n),
+            b'D' => self.set_position(row, col.saturating_sub(n)),
+            b'G' => self.set_position(row, n - 1),
+            b'H' | b'f' => {
+                let row = sequence.param_or(0, 1) as usize - 1;
+                let col = sequence.param_or(1, 1) as usize - 1;
+                self.set_position(row, col);
+            }
+            b'J' => match sequence.params().first().copied().unwrap_or(0) {
+                0 => {
+                    self.clear_cells(row, col, self.cols);
+                    self.clear_rows(row + 1, self.rows);
+                }
+                1 => {
+                    self.clear_rows(0, row);
+                    self.clear_cells(row, 0, col + 1);
+                }
+                _ => self.clear_rows(0, self.rows),
+            },
+            b'K' => match sequence.params().first().copied().unwrap_or(0) {
+                0 => self.clear_cells(row, col, self.cols),
+                1 => self.clear_cells(row, 0, col + 1),
+                _ => self.clear_cells(row, 0, self.cols),
+            },
+            b'm' => self.select_graphic_rendition(sequence.params()),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.color_code = DEFAULT_COLOR_CODE;
+            self.bold = false;
+            return;
+        }
+        for &param in params {
+            let color_code = self.color_code;
+            self.color_code = match param {
+                0 => {
+                    self.bold = false;
+                    DEFAULT_COLOR_CODE
+                }
+                1 => {
+                    self.bold = true;
+                    color_code.with_foreground(color_code.foreground().bright())
+                }
+                22 => {
+                    self.bold = false;
+                    color_code.with_foreground(color_code.foreground().dim())
+                }
+                7 => color_code.inverted(),
+                30..=37 => {
+                    color_code.with_foreground(Color::from_ansi((param - 30) as u8, self.bold))
+                }
+                39 => color_code.with_foreground(DEFAULT_COLOR_CODE.foreground()),
+                40..=47 => color_code.with_background(Color::from_ansi((param - 40) as u8, false)),
+                49 => color_code.with_background(DEFAULT_COLOR_CODE.background()),
+                90..=97 => color_code.with_foreground(Color::from_ansi((param - 90) as u8, true)),
+                100..=107 => {
+                    color_code.with_background(Color::from_ansi((param - 100) as u8, true))
+                }
+                _ => color_code,
+            };
+        }
+    }
+
+    fn new_line(&mut self) {
+        if self.row < self.rows - 1 {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
+        self.col = 0;
+    }
+
+    fn scroll_up(&mut self) {
+        let height = self.font.height();
+        let info = self.framebuffer.info();
+        let visible_height = self.rows * height;
+        self.framebuffer.copy_rect(
+            Rect::new(0, height, info.width, visible_height - height),
+            0,
+            0,
+        );
+        self.clear_rows(self.rows - 1, self.rows);
+    }
+
+    fn clear_rows(&mut self, start: usize, end: usize) {
+        let height = self.font.height();
+        let width = self.framebuffer.info().width;
+        let background = color_to_rgb(self.color_code.background());
+        self.framebuffer.fill_rect(
+            Rect::new(0, start * height, width, end.saturating_sub(start) * height),
+            background,
+        );
+    }
+
+    fn clear_cells(&mut self, row: usize, start: usize, end: usize) {
+        let (width, height) = (self.font.width(), self.font.height());
+        let background = color_to_rgb(self.color_code.background());
+        let end = end.min(self.cols);
+        self.framebuffer.fill_rect(
+            Rect::new(
+                start * width,
+                row * height,
+                end.saturating_sub(start) * width,
+                height,
+            ),
+            background,
+        );
+    }
+}
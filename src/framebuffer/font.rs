@@ -0,0 +1,198 @@
+// PC Screen Font, the format the Linux console uses. See https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html
+// The built in font is Misc-Fixed 8x13 (public domain) padded out to an 8x16 cell, with the latin-1,
+// code page 437, box drawing and greek glyphs kept.
+pub const DEFAULT_FONT: &[u8] = include_bytes!("fonts/fixed8x16.psfu");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    BadMagic,
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnicodeTable {
+    None,
+    Psf1(&'static [u8]),
+    Psf2(&'static [u8]),
+}
+
+pub struct Font {
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    bytes_per_glyph: usize,
+    glyph_count: usize,
+    glyphs: &'static [u8],
+    unicode_table: UnicodeTable,
+    // Walking the unicode table for every character is slow, so the latin-1 range gets looked up once up front
+    latin_1: [u16; 256],
+}
+
+impl Font {
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        let mut font = if data.starts_with(&PSF2_MAGIC) {
+            Font::parse_psf2(data)?
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)?
+        } else {
+            return Err(FontError::BadMagic);
+        };
+        for char in 0..256_u32 {
+            font.latin_1[char as usize] = font
+                .search_unicode_table(char::from_u32(char).unwrap())
+                .unwrap_or(0) as u16;
+        }
+        Ok(font)
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.len() < PSF1_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        let mode = data[2];
+        let height = data[3] as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = PSF1_HEADER_SIZE + glyph_count * height;
+        if data.len() < glyphs_end {
+            return Err(FontError::Truncated);
+        }
+        let unicode_table = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+            UnicodeTable::Psf1(&data[glyphs_end..])
+        } else {
+            UnicodeTable::None
+        };
+        Ok(Font {
+            width: 8,
+            height,
+            bytes_per_row: 1,
+            bytes_per_glyph: height,
+            glyph_count,
+            glyphs: &data[PSF1_HEADER_SIZE..glyphs_end],
+            unicode_table,
+            latin_1: [0; 256],
+        })
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.len() < PSF2_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        let field = |index: usize| {
+            let offset = index * 4;
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ]) as usize
+        };
+        let header_size = field(2);
+        let flags = field(3) as u32;
+        let glyph_count = field(4);
+        let bytes_per_glyph = field(5);
+        let height = field(6);
+        let width = field(7);
+        let glyphs_end = header_size + glyph_count * bytes_per_glyph;
+        if data.len() < glyphs_end {
+            return Err(FontError::Truncated);
+        }
+        let unicode_table = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            UnicodeTable::Psf2(&data[glyphs_end..])
+        } else {
+            UnicodeTable::None
+        };
+        Ok(Font {
+            width,
+            height,
+            bytes_per_row: width.div_ceil(8),
+            bytes_per_glyph,
+            glyph_count,
+            glyphs: &data[header_size..glyphs_end],
+            unicode_table,
+            latin_1: [0; 256],
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        self.bytes_per_row
+    }
+
+    // Glyph 0 is what we fall back to when the font doesn't know about a character
+    pub fn glyph(&self, char: char) -> &[u8] {
+        let index = match char as u32 {
+            0..=0xFF => self.latin_1[char as usize] as usize,
+            _ => self.search_unicode_table(char).unwrap_or(0),
+        };
+        let offset = index * self.bytes_per_glyph;
+        &self.glyphs[offset..offset + self.bytes_per_glyph]
+    }
+
+    fn search_unicode_table(&self, char: char) -> Option<usize> {
+        match self.unicode_table {
+            // without a table glyphs are indexed by code point
+            UnicodeTable::None => Some(char as usize).filter(|&index| index < self.glyph_count),
+            UnicodeTable::Psf1(table) => {
+                let mut glyph = 0;
+                let mut in_sequence = false;
+                for entry in table.chunks_exact(2) {
+                    match u16::from_le_bytes([entry[0], entry[1]]) {
+                        PSF1_SEPARATOR => {
+                            glyph += 1;
+                            in_sequence = false;
+                        }
+                        PSF1_START_SEQUENCE => in_sequence = true,
+                        code_point if !in_sequence && code_point as u32 == char as u32 => {
+                            return Some(glyph)
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }
+            UnicodeTable::Psf2(table) => {
+                let mut encoded = [0; 4];
+                let encoded = char.encode_utf8(&mut encoded).as_bytes();
+                // every glyph's entry is a run of UTF-8 characters, optionally followed by sequences, ended by 0xFF
+                for (glyph, entry) in table.split(|&byte| byte == PSF2_SEPARATOR).enumerate() {
+                    let singles = entry
+                        .split(|&byte| byte == PSF2_START_SEQUENCE)
+                        .next()
+                        .unwrap_or(&[]);
+                    if let Ok(singles) = core::str::from_utf8(singles) {
+                        if singles.chars().any(|single| single == char) {
+                            return Some(glyph);
+                        }
+                    } else if singles
+                        .windows(encoded.len())
+                        .any(|window| window == encoded)
+                    {
+                        return Some(glyph);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
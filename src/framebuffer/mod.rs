@@ -0,0 +1,285 @@
+use core::{fmt, slice};
+
+use spin::Mutex;
+
+use crate::memory::address::VirtualAddress;
+use crate::memory::physical_to_virtual;
+
+pub mod bga;
+pub mod console;
+pub mod font;
+
+use console::FramebufferConsole;
+use font::{Font, FontError};
+
+pub const DEFAULT_WIDTH: usize = 1024;
+pub const DEFAULT_HEIGHT: usize = 768;
+
+// The order the color channels are laid out in memory, lowest address first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    Bgr,
+    // one byte per pixel, only the intensity is kept
+    Grayscale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    pub width: usize,
+    pub height: usize,
+    // in pixels, a row can be wider than what ends up on screen
+    pub stride: usize,
+    pub bytes_per_pixel: usize,
+    pub pixel_format: PixelFormat,
+}
+
+impl FrameBufferInfo {
+    pub fn size_in_bytes(&self) -> usize {
+        self.stride * self.height * self.bytes_per_pixel
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0x00, 0x00, 0x00);
+    pub const WHITE: Rgb = Rgb::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    // 0xRRGGBB
+    pub const fn from_hex(hex: u32) -> Self {
+        Rgb::new((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+    }
+
+    fn intensity(&self) -> u8 {
+        // ITU-R BT.601 luma weights, scaled by 256
+        ((self.r as u32 * 77 + self.g as u32 * 150 + self.b as u32 * 29) >> 8) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn clipped(&self, width: usize, height: usize) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+}
+
+pub struct FrameBuffer {
+    info: FrameBufferInfo,
+    buffer: &'static mut [u8],
+}
+
+impl FrameBuffer {
+    // The caller has to guarantee that the whole buffer is mapped and that nobody else is writing to it
+    pub unsafe fn new(info: FrameBufferInfo, address: VirtualAddress) -> Self {
+        FrameBuffer {
+            info,
+            buffer: slice::from_raw_parts_mut(address.0 as *mut u8, info.size_in_bytes()),
+        }
+    }
+
+    pub fn info(&self) -> FrameBufferInfo {
+        self.info
+    }
+
+    fn encode(&self, color: Rgb) -> [u8; 4] {
+        match self.info.pixel_format {
+            PixelFormat::Rgb => [color.r, color.g, color.b, 0],
+            PixelFormat::Bgr => [color.b, color.g, color.r, 0],
+            PixelFormat::Grayscale => [color.intensity(), 0, 0, 0],
+        }
+    }
+
+    #[inline]
+    fn pixel_offset(&self, x: usize, y: usize) -> usize {
+        (y * self.info.stride + x) * self.info.bytes_per_pixel
+    }
+
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let offset = self.pixel_offset(x, y);
+        let encoded = self.encode(color);
+        self.buffer[offset..offset + bytes_per_pixel].copy_from_slice(&encoded[..bytes_per_pixel]);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Rgb) {
+        let rect = rect.clipped(self.info.width, self.info.height);
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let encoded = self.encode(color);
+        for y in rect.y..rect.y + rect.height {
+            let start = self.pixel_offset(rect.x, y);
+            let row = &mut self.buffer[start..start + rect.width * bytes_per_pixel];
+            for pixel in row.chunks_exact_mut(bytes_per_pixel) {
+                pixel.copy_from_slice(&encoded[..bytes_per_pixel]);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        self.fill_rect(Rect::new(0, 0, self.info.width, self.info.height), color);
+    }
+
+    // Draws a width * (pixels.len() / width) image with its top left corner at (x, y)
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[Rgb]) {
+        if width == 0 {
+            return;
+        }
+        for (row, row_pixels) in pixels.chunks(width).enumerate() {
+            for (col, &pixel) in row_pixels.iter().enumerate() {
+                self.write_pixel(x + col, y + row, pixel);
+            }
+        }
+    }
+
+    // Moves a region of the screen somewhere else, the two are allowed to overlap
+    pub fn copy_rect(&mut self, source: Rect, x: usize, y: usize) {
+        let source = source.clipped(self.info.width, self.info.height);
+        let destination =
+            Rect::new(x, y, source.width, source.height).clipped(self.info.width, self.info.height);
+        let row_bytes = destination.width * self.info.bytes_per_pixel;
+        let rows: &mut dyn Iterator<Item = usize> = if destination.y <= source.y {
+            &mut (0..destination.height)
+        } else {
+            &mut (0..destination.height).rev()
+        };
+        for row in rows {
+            let from = self.pixel_offset(source.x, source.y + row);
+            let to = self.pixel_offset(destination.x, destination.y + row);
+            self.buffer.copy_within(from..from + row_bytes, to);
+        }
+    }
+
+    // Bresenham's line algorithm -> https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
+    pub fn draw_line(&mut self, start: (usize, usize), end: (usize, usize), color: Rgb) {
+        let (mut x, mut y) = (start.0 as isize, start.1 as isize);
+        let (x1, y1) = (end.0 as isize, end.1 as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let step_x = if x < x1 { 1 } else { -1 };
+        let step_y = if y < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.write_pixel(x as usize, y as usize, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled_error = 2 * error;
+            if doubled_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    NoDevice,
+    UnsupportedMode,
+    Font(FontError),
+}
+
+impl From<FontError> for FramebufferError {
+    fn from(error: FontError) -> Self {
+        FramebufferError::Font(error)
+    }
+}
+
+pub static CONSOLE: Mutex<Option<FramebufferConsole>> = Mutex::new(None);
+
+// bootloader 0.9 doesn't hand us a VBE framebuffer, so for now the only place we can get one from is the BGA device.
+// Once this succeeds the VGA text buffer is no longer what's on screen.
+pub fn init(width: usize, height: usize) -> Result<(), FramebufferError> {
+    let device = bga::BgaDevice::find().ok_or(FramebufferError::NoDevice)?;
+    let info = device.set_mode(width, height)?;
+    let framebuffer =
+        unsafe { FrameBuffer::new(info, physical_to_virtual(device.framebuffer_address())) };
+    let font = Font::parse(font::DEFAULT_FONT)?;
+    *CONSOLE.lock() = Some(FramebufferConsole::new(framebuffer, font));
+    // it's fine if this was already done by an earlier call
+    let _ = crate::console::register("framebuffer", &crate::console::FRAMEBUFFER_SINK);
+    Ok(())
+}
+
+// Switches to the framebuffer console at boot if the command line asks for it, with framebuffer on its own for the
+// default mode or framebuffer=800x600 for another. Otherwise the VGA text buffer stays.
+pub fn init_from_cmdline() {
+    let Some(mode) = crate::cmdline::option("framebuffer") else {
+        return;
+    };
+    let (width, height) = match mode {
+        "" => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        mode => match mode
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        {
+            Some(size) => size,
+            None => {
+                log::warn!("Ignoring framebuffer={}, it should be WIDTHxHEIGHT", mode);
+                return;
+            }
+        },
+    };
+    match init(width, height) {
+        Ok(()) => log::info!("Framebuffer console at {}x{}", width, height),
+        Err(error) => log::warn!("Couldn't set up the framebuffer console: {:?}", error),
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.write_fmt(args).unwrap();
+    }
+}
+
+#[macro_export]
+macro_rules! fb_print {
+    ($($arg:tt)*) => ($crate::framebuffer::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! fb_println {
+    () => ($crate::fb_print!("\n"));
+    ($($arg:tt)*) => ($crate::fb_print!("{}\n", format_args!($($arg)*)));
+}
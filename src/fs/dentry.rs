@@ -0,0 +1,222 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::fs::{FsError, Inode, InodeKind};
+use crate::sync::{Mutex, RwLock};
+
+// A name in a directory and the inode it refers to. Once a name has been looked up it stays cached until it's unlinked
+// or its file system is unmounted, so walking the same path again doesn't have to ask the file system. Every dentry
+// keeps its parent alive, which means a path can always be walked back up with .., even out of a directory that's been
+// removed in the meantime.
+// Mounts live here too: a directory that's been mounted on is covered by the root dentry of the file system on top,
+// which points back down at it, see mount.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    // None for the root of a file system
+    parent: Option<Arc<Dentry>>,
+    // Sleeping, since filling it in means asking the file system, which might have to go to a disk. Holding it while
+    // that happens keeps two threads from making two dentries for the same name.
+    children: Mutex<BTreeMap<String, Arc<Dentry>>>,
+    // the root of the file system mounted on this one, if there is one
+    mounted: RwLock<Option<Arc<Dentry>>>,
+    // for the root of a mounted file system, the dentry it's mounted on
+    mountpoint: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    // The root of a file system, mounted on mountpoint unless it's the root of everything
+    pub(super) fn root(inode: Arc<dyn Inode>, mountpoint: Option<Arc<Dentry>>) -> Arc<Dentry> {
+        Arc::new(Dentry {
+            name: mountpoint
+                .as_ref()
+                .map_or_else(|| String::from("/"), |mountpoint| mountpoint.name.clone()),
+            inode,
+            parent: None,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+            mountpoint,
+        })
+    }
+
+    fn child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        Arc::new(Dentry {
+            name: String::from(name),
+            inode,
+            parent: Some(self.clone()),
+            children: Mutex::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+            mountpoint: None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn kind(&self) -> InodeKind {
+        self.inode.metadata().kind
+    }
+
+    // Whatever's mounted on this, and on that, all the way to the top
+    pub(super) fn covering(self: Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self;
+        loop {
+            let mounted = dentry.mounted.read().clone();
+            match mounted {
+                Some(mounted) => dentry = mounted,
+                None => return dentry,
+            }
+        }
+    }
+
+    pub(super) fn set_mounted(&self, root: Option<Arc<Dentry>>) {
+        *self.mounted.write() = root;
+    }
+
+    pub(super) fn mountpoint(&self) -> Option<&Arc<Dentry>> {
+        self.mountpoint.as_ref()
+    }
+
+    fn is_mountpoint(&self) -> bool {
+        self.mounted.read().is_some()
+    }
+
+    // The directory this is in, for .. . The root of a mounted file system is in the directory its mount point is in,
+    // and the root of everything is in itself.
+    pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self.clone();
+        while let Some(mountpoint) = dentry.mountpoint.clone() {
+            dentry = mountpoint;
+        }
+        match dentry.parent.clone() {
+            Some(parent) => parent,
+            None => dentry.covering(),
+        }
+    }
+
+    // The root dentry of the file system this is on
+    pub fn file_system_root(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self.clone();
+        while let Some(parent) = dentry.parent.clone() {
+            dentry = parent;
+        }
+        dentry
+    }
+
+    // Where this is, from the root of everything
+    pub fn path(self: &Arc<Self>) -> String {
+        let mut names = Vec::new();
+        let mut dentry = self.clone();
+        loop {
+            while let Some(mountpoint) = dentry.mountpoint.clone() {
+                dentry = mountpoint;
+            }
+            let Some(parent) = dentry.parent.clone() else {
+                break;
+            };
+            names.push(dentry.name.clone());
+            dentry = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+
+    // The entry called name in this directory, or whatever's mounted on it. Doesn't follow symlinks, see path::resolve.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, FsError> {
+        let mut children = self.children.lock();
+        let child = match children.get(name) {
+            Some(child) => child.clone(),
+            None => {
+                let child = self.child(name, self.inode.lookup(name)?);
+                children.insert(String::from(name), child.clone());
+                child
+            }
+        };
+        drop(children);
+        Ok(child.covering())
+    }
+
+    pub fn create(
+        self: &Arc<Self>,
+        name: &str,
+        kind: InodeKind,
+        mode: u16,
+    ) -> Result<Arc<Dentry>, FsError> {
+        self.insert(name, |directory| directory.create(name, kind, mode))
+    }
+
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Dentry>, FsError> {
+        self.insert(name, |directory| directory.symlink(name, target))
+    }
+
+    pub fn link(
+        self: &Arc<Self>,
+        name: &str,
+        inode: &Arc<dyn Inode>,
+    ) -> Result<Arc<Dentry>, FsError> {
+        self.insert(name, |directory| {
+            directory.link(name, inode)?;
+            Ok(inode.clone())
+        })
+    }
+
+    // Adds the entry make makes to this directory, make is only called if there's nothing called name in the cache
+    fn insert<F>(self: &Arc<Self>, name: &str, make: F) -> Result<Arc<Dentry>, FsError>
+    where
+        F: FnOnce(&dyn Inode) -> Result<Arc<dyn Inode>, FsError>,
+    {
+        let mut children = self.children.lock();
+        if children.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let child = self.child(name, make(&*self.inode)?);
+        children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    // Takes name out of this directory. Whoever still has it open keeps the inode.
+    pub fn unlink(self: &Arc<Self>, name: &str) -> Result<(), FsError> {
+        let mut children = self.children.lock();
+        if children
+            .get(name)
+            .is_some_and(|child| child.is_mountpoint())
+        {
+            return Err(FsError::Busy);
+        }
+        self.inode.unlink(name)?;
+        children.remove(name);
+        Ok(())
+    }
+
+    // Drops everything cached below this, for unmount. Children and parents keep each other alive, so nothing cached on
+    // a file system would ever be freed otherwise.
+    pub(super) fn forget(&self) {
+        let children = core::mem::take(&mut *self.children.lock());
+        for child in children.values() {
+            child.forget();
+        }
+    }
+}
+
+impl fmt::Debug for Dentry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dentry")
+            .field("name", &self.name)
+            .field("inode", &self.inode)
+            .finish()
+    }
+}
@@ -0,0 +1,171 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::time::Duration;
+
+use crate::fs::{Dentry, FsError, InodeKind, Metadata};
+use crate::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+// Something a file descriptor refers to. Each open keeps its own offset, and descriptors that share one File (after a
+// fork, say) share the offset too, the same as on Unix.
+pub trait File: Send + Sync + fmt::Debug {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, FsError>;
+
+    fn write(&self, data: &[u8]) -> Result<usize, FsError>;
+
+    // Returns where it ended up
+    fn seek(&self, _from: SeekFrom) -> Result<u64, FsError> {
+        Err(FsError::NotSeekable)
+    }
+
+    fn metadata(&self) -> Result<Metadata, FsError>;
+}
+
+// How fs::open opens a file, the same things Linux's open flags say
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    // every write goes to the end, wherever the offset is
+    pub append: bool,
+    pub create: bool,
+    // with create, fails if it's already there
+    pub exclusive: bool,
+    pub truncate: bool,
+    // fails if it isn't a directory
+    pub directory: bool,
+    // fails if the last component is a symlink
+    pub no_follow: bool,
+    // permission bits for a file create makes
+    pub mode: u16,
+}
+
+// A file or directory opened through the VFS. Directories can be opened, but not read or written.
+pub struct OpenFile {
+    dentry: Arc<Dentry>,
+    readable: bool,
+    writable: bool,
+    append: bool,
+    // Sleeping, since it's held for as long as the inode takes to read or write, which might mean going to a disk.
+    // Reads and writes through the same open file happen one at a time because of it.
+    offset: Mutex<u64>,
+}
+
+impl OpenFile {
+    pub fn new(dentry: Arc<Dentry>, options: &OpenOptions) -> Self {
+        OpenFile {
+            dentry,
+            readable: options.read,
+            writable: options.write,
+            append: options.append,
+            offset: Mutex::new(0),
+        }
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    fn check_file(&self) -> Result<(), FsError> {
+        match self.dentry.kind() {
+            InodeKind::Directory => Err(FsError::IsADirectory),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("dentry", &self.dentry)
+            .field("readable", &self.readable)
+            .field("writable", &self.writable)
+            .field("append", &self.append)
+            .finish()
+    }
+}
+
+impl File for OpenFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if !self.readable {
+            return Err(FsError::WrongAccessMode);
+        }
+        self.check_file()?;
+        let mut offset = self.offset.lock();
+        let read = self.dentry.inode().read_at(*offset, buffer)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, FsError> {
+        if !self.writable {
+            return Err(FsError::WrongAccessMode);
+        }
+        self.check_file()?;
+        let inode = self.dentry.inode();
+        let mut offset = self.offset.lock();
+        if self.append {
+            *offset = inode.metadata().size;
+        }
+        let written = inode.write_at(*offset, data)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    // Anywhere that isn't negative is fine, past the end included
+    fn seek(&self, from: SeekFrom) -> Result<u64, FsError> {
+        let mut offset = self.offset.lock();
+        let new_offset = match from {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self
+                .dentry
+                .inode()
+                .metadata()
+                .size
+                .checked_add_signed(delta),
+        };
+        *offset = new_offset.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    fn metadata(&self) -> Result<Metadata, FsError> {
+        Ok(self.dentry.inode().metadata())
+    }
+}
+
+// The console as a file, which is what stdin, stdout and stderr start out as. There's no keyboard input yet, so
+// reading always gets the end of the file. Anything written has to be valid UTF-8.
+#[derive(Debug)]
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Ok(0)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, FsError> {
+        let string = core::str::from_utf8(data).map_err(|_| FsError::InvalidArgument)?;
+        crate::print!("{}", string);
+        Ok(data.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata, FsError> {
+        Ok(Metadata {
+            inode: 0,
+            kind: InodeKind::CharDevice,
+            size: 0,
+            links: 1,
+            mode: 0o620,
+            accessed: Duration::ZERO,
+            modified: Duration::ZERO,
+            changed: Duration::ZERO,
+        })
+    }
+}
@@ -0,0 +1,358 @@
+use alloc::format;
+
+use crate::fs::{self, FsError, InodeKind, OpenOptions};
+
+// The archive build.rs put together, either a USTAR tar file or a newc cpio archive
+static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitrdError {
+    // neither a tar file nor a newc cpio archive
+    UnknownFormat,
+    // ends in the middle of an entry
+    Truncated,
+    BadHeader,
+}
+
+// One thing in the archive. Paths are relative to wherever it's being unpacked to.
+#[derive(Debug)]
+struct Entry<'a> {
+    path: &'a str,
+    mode: u16,
+    kind: EntryKind<'a>,
+}
+
+#[derive(Debug)]
+enum EntryKind<'a> {
+    File(&'a [u8]),
+    Directory,
+    Symlink(&'a str),
+    // another name for the path it has, which came earlier in the archive, and what the file should have in it if
+    // that isn't empty
+    HardLink(&'a str, &'a [u8]),
+}
+
+// Unpacks the initrd that's built into the kernel into the root
+pub fn init() {
+    match unpack(INITRD, "/") {
+        Ok(count) => log::info!("Unpacked {} files from the initrd", count),
+        Err(error) => log::error!("Couldn't unpack the initrd: {:?}", error),
+    }
+}
+
+// Unpacks the tar file or cpio archive in archive into the directory at destination, replacing any files that are
+// already there. Returns how many entries it unpacked. Entries that can't be made are skipped with a warning, and so
+// are ones that would end up outside destination, anything wrong with the archive itself stops it where it is. An
+// empty archive is fine and unpacks nothing.
+pub fn unpack(archive: &[u8], destination: &str) -> Result<usize, InitrdError> {
+    let destination = destination.trim_end_matches('/');
+    let mut count = 0;
+    let mut unpack_entry = |entry: Entry| {
+        let path = format!("{}/{}", destination, entry.path);
+        let escapes = |path: &str| path.split('/').any(|component| component == "..");
+        if escapes(entry.path)
+            || matches!(entry.kind, EntryKind::HardLink(existing, _) if escapes(existing))
+        {
+            log::warn!(
+                "Skipping {} in the initrd, it's outside {}",
+                path,
+                destination
+            );
+            return;
+        }
+        match create(&path, destination, &entry) {
+            Ok(()) => count += 1,
+            Err(error) => log::warn!("Couldn't unpack {} from the initrd: {:?}", path, error),
+        }
+    };
+    if archive.iter().all(|&byte| byte == 0) {
+        // nothing, or the two empty blocks a tar file ends with
+    } else if archive.starts_with(b"070701") || archive.starts_with(b"070702") {
+        cpio::parse(archive, &mut unpack_entry)?;
+    } else if archive.get(257..262) == Some(b"ustar") {
+        tar::parse(archive, &mut unpack_entry)?;
+    } else {
+        return Err(InitrdError::UnknownFormat);
+    }
+    Ok(count)
+}
+
+fn create(path: &str, destination: &str, entry: &Entry) -> Result<(), FsError> {
+    create_parents(path, destination)?;
+    match entry.kind {
+        EntryKind::File(data) => {
+            // whatever a symlink there points at isn't ours to write to
+            if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.kind == InodeKind::Symlink)
+            {
+                remove_existing(path)?;
+            }
+            write_file(path, entry.mode, data)
+        }
+        EntryKind::Directory => match fs::create_dir(path, entry.mode) {
+            Err(FsError::AlreadyExists) if fs::metadata(path)?.kind == InodeKind::Directory => {
+                Ok(())
+            }
+            result => result,
+        },
+        EntryKind::Symlink(target) => {
+            remove_existing(path)?;
+            fs::symlink(target, path)
+        }
+        EntryKind::HardLink(existing, data) => {
+            remove_existing(path)?;
+            fs::hard_link(&format!("{}/{}", destination, existing), path)?;
+            match data.is_empty() {
+                true => Ok(()),
+                false => write_file(path, entry.mode, data),
+            }
+        }
+    }
+}
+
+fn write_file(path: &str, mode: u16, data: &[u8]) -> Result<(), FsError> {
+    let options = OpenOptions {
+        write: true,
+        create: true,
+        truncate: true,
+        no_follow: true,
+        mode,
+        ..OpenOptions::default()
+    };
+    let file = fs::open(path, &options)?;
+    let mut written = 0;
+    while written < data.len() {
+        written += file.write(&data[written..])?;
+    }
+    Ok(())
+}
+
+// Archives usually have an entry for every directory before what's in it, but they don't have to. Ones under the
+// destination that are already there have to be real directories, a symlink from the archive could lead anywhere.
+fn create_parents(path: &str, destination: &str) -> Result<(), FsError> {
+    for (slash, _) in path.match_indices('/').skip(1) {
+        let parent = &path[..slash];
+        match fs::create_dir(parent, 0o755) {
+            Ok(()) => {}
+            Err(FsError::AlreadyExists)
+                if slash <= destination.len()
+                    || fs::symlink_metadata(parent)?.kind == InodeKind::Directory => {}
+            Err(FsError::AlreadyExists) => return Err(FsError::NotADirectory),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn remove_existing(path: &str) -> Result<(), FsError> {
+    match fs::remove_file(path) {
+        Ok(()) | Err(FsError::NotFound) => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+// Archives made from . have names starting with ./, and some have absolute ones. Both end up relative to the
+// destination. None for the entry for . itself.
+fn relative(path: &str) -> Option<&str> {
+    let mut path = path.trim_end_matches('/');
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+    match path {
+        "" | "." => None,
+        path => Some(path),
+    }
+}
+
+// POSIX.1-1988 ustar, as described in the GNU tar manual's "Basic Tar Format". Everything is in 512 byte blocks: a
+// header, then the contents padded out to a whole block. Numbers are octal ASCII.
+mod tar {
+    use alloc::borrow::Cow;
+    use alloc::format;
+
+    use super::{relative, Entry, EntryKind, InitrdError};
+
+    const BLOCK_SIZE: usize = 512;
+
+    pub(super) fn parse<F>(archive: &[u8], mut unpack: F) -> Result<(), InitrdError>
+    where
+        F: FnMut(Entry<'_>),
+    {
+        let mut offset = 0;
+        // one empty block is already the end, there should be two
+        while let Some(header) = archive.get(offset..offset + BLOCK_SIZE) {
+            if header.iter().all(|&byte| byte == 0) {
+                return Ok(());
+            }
+            if &header[257..262] != b"ustar" || checksum(header) != number(&header[148..156])? {
+                return Err(InitrdError::BadHeader);
+            }
+            let size = number(&header[124..136])?;
+            let data = archive
+                .get(offset + BLOCK_SIZE..offset + BLOCK_SIZE + size)
+                .ok_or(InitrdError::Truncated)?;
+            offset += BLOCK_SIZE + size.next_multiple_of(BLOCK_SIZE);
+
+            // names too long for the 100 bytes there are get split, with the start of them in the prefix
+            let name = string(&header[0..100])?;
+            let prefix = string(&header[345..500])?;
+            let path = if prefix.is_empty() {
+                Cow::Borrowed(name)
+            } else {
+                Cow::Owned(format!("{}/{}", prefix, name))
+            };
+            let link = string(&header[157..257])?;
+            let kind = match header[156] {
+                b'0' | b'\0' | b'7' => EntryKind::File(data),
+                b'1' => EntryKind::HardLink(relative(link).ok_or(InitrdError::BadHeader)?, data),
+                b'2' => EntryKind::Symlink(link),
+                b'5' => EntryKind::Directory,
+                // devices, FIFOs, and pax and GNU extended headers
+                kind => {
+                    log::warn!(
+                        "Skipping {} in the initrd, it's type {}",
+                        path,
+                        kind as char
+                    );
+                    continue;
+                }
+            };
+            let Some(path) = relative(&path) else {
+                continue;
+            };
+            unpack(Entry {
+                path,
+                mode: number(&header[100..108])? as u16 & 0o7777,
+                kind,
+            });
+        }
+        // no end of archive blocks, which happens if it's been cut short
+        match offset >= archive.len() {
+            true => Ok(()),
+            false => Err(InitrdError::Truncated),
+        }
+    }
+
+    // The sum of every byte in the header, counting the checksum field itself as spaces
+    fn checksum(header: &[u8]) -> usize {
+        header
+            .iter()
+            .enumerate()
+            .map(|(index, &byte)| match index {
+                148..156 => b' ' as usize,
+                _ => byte as usize,
+            })
+            .sum()
+    }
+
+    // Octal, padded with spaces or NULs on either side
+    fn number(field: &[u8]) -> Result<usize, InitrdError> {
+        let digits = core::str::from_utf8(field).map_err(|_| InitrdError::BadHeader)?;
+        let digits = digits.trim_matches(|c| c == ' ' || c == '\0');
+        if digits.is_empty() {
+            return Ok(0);
+        }
+        usize::from_str_radix(digits, 8).map_err(|_| InitrdError::BadHeader)
+    }
+
+    // NUL terminated, unless it takes up the whole field
+    fn string(field: &[u8]) -> Result<&str, InitrdError> {
+        let length = field
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(field.len());
+        core::str::from_utf8(&field[..length]).map_err(|_| InitrdError::BadHeader)
+    }
+}
+
+// The "new" portable format from cpio(5), what Linux's initramfs uses. Each entry is a 110 byte header, the name, and
+// the contents, with the header and name together, and the contents, padded to a multiple of 4 bytes. Numbers are
+// 8 hex digits. A file with more than one name is stored under each of them with the same inode number, only the last
+// one has the contents.
+mod cpio {
+    use alloc::collections::BTreeMap;
+
+    use super::{relative, Entry, EntryKind, InitrdError};
+
+    const HEADER_SIZE: usize = 110;
+    const TRAILER: &str = "TRAILER!!!";
+
+    const TYPE_MASK: usize = 0o170000;
+    const TYPE_FILE: usize = 0o100000;
+    const TYPE_DIRECTORY: usize = 0o040000;
+    const TYPE_SYMLINK: usize = 0o120000;
+
+    pub(super) fn parse<F>(archive: &[u8], mut unpack: F) -> Result<(), InitrdError>
+    where
+        F: FnMut(Entry<'_>),
+    {
+        // the first name each inode with more than one was unpacked as
+        let mut linked: BTreeMap<usize, &str> = BTreeMap::new();
+        let mut offset = 0;
+        loop {
+            let header = archive
+                .get(offset..offset + HEADER_SIZE)
+                .ok_or(InitrdError::Truncated)?;
+            if &header[..6] != b"070701" && &header[..6] != b"070702" {
+                return Err(InitrdError::BadHeader);
+            }
+            let field = |index: usize| number(&header[6 + index * 8..][..8]);
+            let (inode, mode, links, size, name_size) =
+                (field(0)?, field(1)?, field(4)?, field(6)?, field(11)?);
+            let name = archive
+                .get(offset + HEADER_SIZE..offset + HEADER_SIZE + name_size)
+                .ok_or(InitrdError::Truncated)?;
+            let name = match name.split_last() {
+                Some((0, name)) => {
+                    core::str::from_utf8(name).map_err(|_| InitrdError::BadHeader)?
+                }
+                _ => return Err(InitrdError::BadHeader),
+            };
+            let data_start = (offset + HEADER_SIZE + name_size).next_multiple_of(4);
+            let data = archive
+                .get(data_start..data_start + size)
+                .ok_or(InitrdError::Truncated)?;
+            offset = (data_start + size).next_multiple_of(4);
+
+            if name == TRAILER {
+                return Ok(());
+            }
+            let Some(path) = relative(name) else {
+                continue;
+            };
+            let kind = match mode & TYPE_MASK {
+                TYPE_FILE if links > 1 => match linked.get(&inode) {
+                    Some(first) => EntryKind::HardLink(first, data),
+                    None => {
+                        linked.insert(inode, path);
+                        EntryKind::File(data)
+                    }
+                },
+                TYPE_FILE => EntryKind::File(data),
+                TYPE_DIRECTORY => EntryKind::Directory,
+                TYPE_SYMLINK => EntryKind::Symlink(
+                    core::str::from_utf8(data).map_err(|_| InitrdError::BadHeader)?,
+                ),
+                kind => {
+                    log::warn!("Skipping {} in the initrd, it's type {:o}", path, kind);
+                    continue;
+                }
+            };
+            unpack(Entry {
+                path,
+                mode: (mode & 0o7777) as u16,
+                kind,
+            });
+        }
+    }
+
+    fn number(field: &[u8]) -> Result<usize, InitrdError> {
+        let digits = core::str::from_utf8(field).map_err(|_| InitrdError::BadHeader)?;
+        usize::from_str_radix(digits, 16).map_err(|_| InitrdError::BadHeader)
+    }
+}
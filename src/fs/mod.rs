@@ -0,0 +1,311 @@
+// The virtual file system. Every file system plugs in by implementing FileSystem and Inode, and gets mounted somewhere
+// under the root, see mount. Paths are walked through the dentry cache, which only asks a file system about names it
+// hasn't seen yet, see Dentry. What a file descriptor refers to is a File, usually an OpenFile on top of an inode.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+use core::time::Duration;
+
+use crate::syscall::consts::Errno;
+
+pub mod dentry;
+pub mod file;
+pub mod initrd;
+pub mod mount;
+pub mod path;
+pub mod ramfs;
+
+pub use dentry::Dentry;
+pub use file::{Console, File, OpenFile, OpenOptions, SeekFrom};
+pub use mount::{mount, mounts, root, unmount};
+pub use ramfs::RamFs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    NotEmpty,
+    NoSpace,
+    InvalidArgument,
+    // whatever it was, the file system doesn't do it, or not to this inode
+    PermissionDenied,
+    // more symlinks than path::MAX_SYMLINKS in one walk, which is most likely a loop
+    TooManyLinks,
+    NameTooLong,
+    // mounted on, or has something mounted inside it
+    Busy,
+    // a hard link to an inode on another file system
+    CrossDevice,
+    NotSeekable,
+    // reading from a file that was only opened for writing, or the other way around
+    WrongAccessMode,
+    Io,
+    // the kernel heap couldn't fit something the file system needed
+    OutOfMemory,
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => Errno::ENOENT,
+            FsError::AlreadyExists => Errno::EEXIST,
+            FsError::NotADirectory => Errno::ENOTDIR,
+            FsError::IsADirectory => Errno::EISDIR,
+            FsError::NotEmpty => Errno::ENOTEMPTY,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::InvalidArgument => Errno::EINVAL,
+            FsError::PermissionDenied => Errno::EPERM,
+            FsError::TooManyLinks => Errno::ELOOP,
+            FsError::NameTooLong => Errno::ENAMETOOLONG,
+            FsError::Busy => Errno::EBUSY,
+            FsError::CrossDevice => Errno::EXDEV,
+            FsError::NotSeekable => Errno::ESPIPE,
+            FsError::WrongAccessMode => Errno::EBADF,
+            FsError::Io => Errno::EIO,
+            FsError::OutOfMemory => Errno::ENOMEM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+impl InodeKind {
+    // The file type bits of st_mode, the same as Linux's
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            InodeKind::File => 0o100000,
+            InodeKind::Directory => 0o040000,
+            InodeKind::Symlink => 0o120000,
+            InodeKind::CharDevice => 0o020000,
+            InodeKind::BlockDevice => 0o060000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    // unique on its file system
+    pub inode: u64,
+    pub kind: InodeKind,
+    pub size: u64,
+    // how many directory entries refer to it
+    pub links: u64,
+    // permission bits, there are no users to check them against yet
+    pub mode: u16,
+    // since boot, there's no wall clock yet
+    pub accessed: Duration,
+    pub modified: Duration,
+    pub changed: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub kind: InodeKind,
+}
+
+pub trait FileSystem: Send + Sync + fmt::Debug {
+    // Something like "ramfs", for the mount table
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+// A file, directory, symlink or device on some file system. Names are single path components, never . or .., which
+// the VFS takes care of, and it's also made sure a directory operation only gets called on a directory and a file
+// operation on a file. Whatever a file system doesn't implement fails.
+// Any is so a file system can tell its own inodes apart from everyone else's, when it's handed one to link.
+pub trait Inode: Any + Send + Sync + fmt::Debug {
+    fn metadata(&self) -> Metadata;
+
+    // Directories
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    // Has to fail with AlreadyExists if there's something called name already
+    fn create(&self, _name: &str, _kind: InodeKind, _mode: u16) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    // Another name for inode, which is on the same file system and isn't a directory
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    // Removes the entry, whatever kind it is. Directories have to be empty.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    // Files. Reading past the end gets 0 bytes, writing past it fills the gap with zeros.
+
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    // Symlinks
+
+    fn read_link(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+}
+
+// Mounts a ramfs as the root, which is where everything else goes, with whatever's in the initrd in it
+pub fn init() {
+    mount("/", RamFs::new()).expect("Couldn't mount the root file system");
+    initrd::init();
+}
+
+// Opens the file at path, creating it first if options say to
+pub fn open(path: &str, options: &OpenOptions) -> Result<Arc<dyn File>, FsError> {
+    let dentry = match path::resolve(path, !options.no_follow) {
+        Ok(_) if options.create && options.exclusive => return Err(FsError::AlreadyExists),
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if options.create => {
+            let (parent, name) = path::resolve_parent(path)?;
+            parent.create(name, InodeKind::File, options.mode)?
+        }
+        Err(error) => return Err(error),
+    };
+    let kind = dentry.kind();
+    match kind {
+        // only with no_follow, the same as Linux's O_NOFOLLOW
+        InodeKind::Symlink => return Err(FsError::TooManyLinks),
+        InodeKind::Directory if options.write => return Err(FsError::IsADirectory),
+        kind if options.directory && kind != InodeKind::Directory => {
+            return Err(FsError::NotADirectory)
+        }
+        _ => {}
+    }
+    if options.truncate && options.write && kind == InodeKind::File {
+        dentry.inode().truncate(0)?;
+    }
+    Ok(Arc::new(OpenFile::new(dentry, options)))
+}
+
+// Everything in the file at path, which has to fit on the kernel heap
+pub fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    let options = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    let file = open(path, &options)?;
+    // the size is whatever someone wrote or seeked to, it might well not fit on the heap
+    let size = file.metadata()?.size as usize;
+    let mut contents = Vec::new();
+    contents
+        .try_reserve_exact(size)
+        .map_err(|_| FsError::OutOfMemory)?;
+    contents.resize(size, 0);
+    let mut read = 0;
+    while read < contents.len() {
+        match file.read(&mut contents[read..])? {
+            0 => break,
+            bytes => read += bytes,
+        }
+    }
+    contents.truncate(read);
+    Ok(contents)
+}
+
+// Follows a symlink at the end of path, symlink_metadata doesn't
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    Ok(path::resolve(path, true)?.inode().metadata())
+}
+
+pub fn symlink_metadata(path: &str) -> Result<Metadata, FsError> {
+    Ok(path::resolve(path, false)?.inode().metadata())
+}
+
+pub fn create_dir(path: &str, mode: u16) -> Result<(), FsError> {
+    let (parent, name) = path::resolve_parent(path)?;
+    parent.create(name, InodeKind::Directory, mode)?;
+    Ok(())
+}
+
+// Anything but a directory
+pub fn remove_file(path: &str) -> Result<(), FsError> {
+    let (parent, name) = path::resolve_parent(path)?;
+    if parent.lookup(name)?.kind() == InodeKind::Directory {
+        return Err(FsError::IsADirectory);
+    }
+    parent.unlink(name)
+}
+
+// Only an empty directory
+pub fn remove_dir(path: &str) -> Result<(), FsError> {
+    let (parent, name) = path::resolve_parent(path)?;
+    if parent.lookup(name)?.kind() != InodeKind::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    parent.unlink(name)
+}
+
+// Makes link a symlink to target. target isn't looked at until the link is followed, so it doesn't have to exist.
+pub fn symlink(target: &str, link: &str) -> Result<(), FsError> {
+    let (parent, name) = path::resolve_parent(link)?;
+    parent.symlink(name, target)?;
+    Ok(())
+}
+
+// Makes link another name for whatever's at existing. A symlink at the end of existing gets linked to itself.
+pub fn hard_link(existing: &str, link: &str) -> Result<(), FsError> {
+    let existing = path::resolve(existing, false)?;
+    if existing.kind() == InodeKind::Directory {
+        return Err(FsError::PermissionDenied);
+    }
+    let (parent, name) = path::resolve_parent(link)?;
+    if !Arc::ptr_eq(&parent.file_system_root(), &existing.file_system_root()) {
+        return Err(FsError::CrossDevice);
+    }
+    parent.link(name, existing.inode())?;
+    Ok(())
+}
+
+pub fn read_link(path: &str) -> Result<String, FsError> {
+    let dentry = path::resolve(path, false)?;
+    if dentry.kind() != InodeKind::Symlink {
+        return Err(FsError::InvalidArgument);
+    }
+    dentry.inode().read_link()
+}
+
+// Everything in the directory at path, apart from . and ..
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let dentry = path::resolve(path, true)?;
+    if dentry.kind() != InodeKind::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    dentry.inode().entries()
+}
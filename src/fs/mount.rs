@@ -0,0 +1,91 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::{path, Dentry, FileSystem, FsError, InodeKind};
+use crate::sync::{Mutex, RwLock};
+
+#[derive(Debug)]
+struct Mount {
+    // where it was mounted, for mounts
+    path: String,
+    file_system: Arc<dyn FileSystem>,
+    root: Arc<Dentry>,
+}
+
+// Also keeps mount and unmount from running at the same time
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+// The root dentry of the first file system, which everything else is mounted somewhere under
+static ROOT: RwLock<Option<Arc<Dentry>>> = RwLock::new(None);
+
+// Where every absolute path starts, NotFound until something's been mounted on /
+pub fn root() -> Result<Arc<Dentry>, FsError> {
+    let root = ROOT.read().clone();
+    root.map(Dentry::covering).ok_or(FsError::NotFound)
+}
+
+// Mounts file_system on the directory at path, which hides whatever's in there until it's unmounted. The first one has
+// to go on /. Mounting on a directory that's already been mounted on just covers the one that's there.
+pub fn mount(path: &str, file_system: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let mut mounts = MOUNTS.lock();
+    let root = if ROOT.read().is_none() {
+        if path != "/" {
+            return Err(FsError::NotFound);
+        }
+        let root = Dentry::root(file_system.root(), None);
+        *ROOT.write() = Some(root.clone());
+        root
+    } else {
+        let mountpoint = path::resolve(path, true)?;
+        if mountpoint.kind() != InodeKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let root = Dentry::root(file_system.root(), Some(mountpoint.clone()));
+        mountpoint.set_mounted(Some(root.clone()));
+        root
+    };
+    log::info!("Mounted {} on {}", file_system.name(), root.path());
+    mounts.push(Mount {
+        path: root.path(),
+        file_system,
+        root,
+    });
+    Ok(())
+}
+
+// Takes away the file system mounted on path, uncovering whatever was under it. Anything mounted inside it has to go
+// first, and the very first one on / can't go at all. Files that are still open keep working.
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let mut mounts = MOUNTS.lock();
+    let root = path::resolve(path, true)?;
+    let index = mounts
+        .iter()
+        .position(|mount| Arc::ptr_eq(&mount.root, &root))
+        .ok_or(FsError::InvalidArgument)?;
+    let Some(mountpoint) = root.mountpoint() else {
+        return Err(FsError::Busy);
+    };
+    let busy = mounts.iter().any(|mount| {
+        mount
+            .root
+            .mountpoint()
+            .is_some_and(|other| Arc::ptr_eq(&other.file_system_root(), &root))
+    });
+    if busy {
+        return Err(FsError::Busy);
+    }
+    mountpoint.set_mounted(None);
+    let mount = mounts.remove(index);
+    mount.root.forget();
+    log::info!("Unmounted {} from {}", mount.file_system.name(), mount.path);
+    Ok(())
+}
+
+// Where each file system is mounted and what it is, in the order they were mounted
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| (mount.path.clone(), mount.file_system.name()))
+        .collect()
+}
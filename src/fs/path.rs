@@ -0,0 +1,97 @@
+use alloc::sync::Arc;
+
+use crate::fs::{root, Dentry, FsError, InodeKind};
+
+// The same limits as Linux's
+pub const MAX_NAME: usize = 255;
+pub const MAX_PATH: usize = 4096;
+// How many symlinks one walk follows before giving up on it as a loop
+pub const MAX_SYMLINKS: usize = 40;
+
+// There's no working directory yet, so relative paths start at the root too.
+// A symlink at the end of path is followed if follow is set, any others along the way always are. A path ending in a
+// slash has to lead to a directory, so a symlink at the end of it gets followed either way.
+pub fn resolve(path: &str, follow: bool) -> Result<Arc<Dentry>, FsError> {
+    let mut links = 0;
+    walk(root()?, path, follow, &mut links)
+}
+
+// The directory the last component of path is in and the last component itself, for making and removing entries.
+// The last component can't be . or .., and a trailing slash is ignored.
+pub fn resolve_parent(path: &str) -> Result<(Arc<Dentry>, &str), FsError> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    let trimmed = path.trim_end_matches('/');
+    let (directory, name) = match trimmed.rfind('/') {
+        Some(slash) => (&trimmed[..=slash], &trimmed[slash + 1..]),
+        None => ("/", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidArgument);
+    }
+    check_name(name)?;
+    let directory = resolve(directory, true)?;
+    if directory.kind() != InodeKind::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    Ok((directory, name))
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.len() > MAX_NAME {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+// links counts the symlinks followed so far, across every level of the recursion
+fn walk(
+    start: Arc<Dentry>,
+    path: &str,
+    follow: bool,
+    links: &mut usize,
+) -> Result<Arc<Dentry>, FsError> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    if path.len() > MAX_PATH {
+        return Err(FsError::NameTooLong);
+    }
+    let directory_only = path.ends_with('/');
+    let mut dentry = if path.starts_with('/') {
+        root()?
+    } else {
+        start
+    };
+    let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+    while let Some(name) = components.next() {
+        if dentry.kind() != InodeKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        dentry = match name {
+            "." => dentry,
+            ".." => dentry.parent(),
+            name => {
+                check_name(name)?;
+                let child = dentry.lookup(name)?;
+                let last = components.peek().is_none();
+                if child.kind() == InodeKind::Symlink && (!last || follow || directory_only) {
+                    *links += 1;
+                    if *links > MAX_SYMLINKS {
+                        return Err(FsError::TooManyLinks);
+                    }
+                    // a relative target is relative to the directory the symlink is in
+                    let target = child.inode().read_link()?;
+                    walk(dentry, &target, true, links)?
+                } else {
+                    child
+                }
+            }
+        };
+    }
+    if directory_only && dentry.kind() != InodeKind::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    Ok(dentry)
+}
@@ -0,0 +1,385 @@
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::fs::{DirEntry, FileSystem, FsError, Inode, InodeKind, Metadata};
+use crate::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::physical_to_virtual;
+use crate::sync::Mutex;
+use crate::time::uptime;
+
+const PAGE_SIZE: u64 = PageSize::NORMAL as u64;
+
+// A file system that only lives in memory. What the kernel mounts on / at boot, see fs::init.
+// Everything in it goes away once it's been unmounted and nothing has any of its files open anymore.
+#[derive(Debug)]
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Arc<RamFs> {
+        Arc::new(RamFs {
+            root: RamInode::new(Contents::Directory(BTreeMap::new()), 0o755),
+        })
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+// What's in a file goes straight into frames of its own rather than on the kernel heap, which is nowhere near big
+// enough. Only pages that have been written to get one, the holes in between read as zeros.
+#[derive(Debug, Default)]
+struct FileData {
+    size: u64,
+    pages: BTreeMap<u64, PhysicalFrame>,
+}
+
+impl FileData {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> usize {
+        let end = self.size.min(offset.saturating_add(buffer.len() as u64));
+        let mut position = offset;
+        while position < end {
+            let within = position % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - within).min(end - position) as usize;
+            let destination = &mut buffer[(position - offset) as usize..][..chunk];
+            match self.pages.get(&(position / PAGE_SIZE)) {
+                Some(frame) => unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        page_pointer(*frame).add(within as usize),
+                        destination.as_mut_ptr(),
+                        chunk,
+                    )
+                },
+                None => destination.fill(0),
+            }
+            position += chunk as u64;
+        }
+        end.saturating_sub(offset) as usize
+    }
+
+    // Only fails if nothing at all could be written, running out of frames partway through is a short write
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        offset
+            .checked_add(data.len() as u64)
+            .ok_or(FsError::InvalidArgument)?;
+        let mut written = 0;
+        while written < data.len() {
+            let position = offset + written as u64;
+            let within = position % PAGE_SIZE;
+            let chunk = ((PAGE_SIZE - within) as usize).min(data.len() - written);
+            let frame = match self.pages.entry(position / PAGE_SIZE) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => match zeroed_frame() {
+                    Some(frame) => *entry.insert(frame),
+                    None => break,
+                },
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    page_pointer(frame).add(within as usize),
+                    chunk,
+                )
+            };
+            written += chunk;
+        }
+        if written == 0 && !data.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+        self.size = self.size.max(offset + written as u64);
+        Ok(written)
+    }
+
+    fn truncate(&mut self, size: u64) {
+        if size < self.size {
+            for (_, frame) in self.pages.split_off(&size.div_ceil(PAGE_SIZE)) {
+                unsafe { deallocate_frame(frame) };
+            }
+            // whatever's past the end of the last page has to read as zeros if the file grows again
+            if let Some(frame) = self.pages.get(&(size / PAGE_SIZE)) {
+                let within = (size % PAGE_SIZE) as usize;
+                unsafe {
+                    core::ptr::write_bytes(
+                        page_pointer(*frame).add(within),
+                        0,
+                        PAGE_SIZE as usize - within,
+                    )
+                };
+            }
+        }
+        self.size = size;
+    }
+}
+
+impl Drop for FileData {
+    fn drop(&mut self) {
+        for frame in self.pages.values() {
+            unsafe { deallocate_frame(*frame) };
+        }
+    }
+}
+
+fn page_pointer(frame: PhysicalFrame) -> *mut u8 {
+    physical_to_virtual(frame.start_address()).as_mut_ptr::<u8>()
+}
+
+fn zeroed_frame() -> Option<PhysicalFrame> {
+    let frame = allocate_frame()?;
+    unsafe { core::ptr::write_bytes(page_pointer(frame), 0, PAGE_SIZE as usize) };
+    Some(frame)
+}
+
+#[derive(Debug)]
+enum Contents {
+    File(FileData),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+    Symlink(String),
+}
+
+impl Contents {
+    fn kind(&self) -> InodeKind {
+        match self {
+            Contents::File(_) => InodeKind::File,
+            Contents::Directory(_) => InodeKind::Directory,
+            Contents::Symlink(_) => InodeKind::Symlink,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    contents: Contents,
+    mode: u16,
+    // a directory has one for its own name and its ., and one more for every subdirectory's ..
+    links: u64,
+    accessed: Duration,
+    modified: Duration,
+    changed: Duration,
+}
+
+impl State {
+    fn modify(&mut self) {
+        let now = uptime();
+        self.modified = now;
+        self.changed = now;
+    }
+}
+
+pub struct RamInode {
+    number: u64,
+    state: Mutex<State>,
+}
+
+impl RamInode {
+    fn new(contents: Contents, mode: u16) -> Arc<RamInode> {
+        static NEXT_NUMBER: AtomicU64 = AtomicU64::new(1);
+        let now = uptime();
+        Arc::new(RamInode {
+            number: NEXT_NUMBER.fetch_add(1, Ordering::Relaxed),
+            state: Mutex::new(State {
+                links: match contents {
+                    Contents::Directory(_) => 2,
+                    _ => 1,
+                },
+                contents,
+                mode,
+                accessed: now,
+                modified: now,
+                changed: now,
+            }),
+        })
+    }
+
+    // Adds a new entry to this directory, made by make unless there's one called name already
+    fn add_entry<F>(&self, name: &str, make: F) -> Result<Arc<RamInode>, FsError>
+    where
+        F: FnOnce() -> Result<Arc<RamInode>, FsError>,
+    {
+        let mut state = self.state.lock();
+        let Contents::Directory(entries) = &mut state.contents else {
+            return Err(FsError::NotADirectory);
+        };
+        let Entry::Vacant(entry) = entries.entry(String::from(name)) else {
+            return Err(FsError::AlreadyExists);
+        };
+        let inode = entry.insert(make()?).clone();
+        if inode.kind() == InodeKind::Directory {
+            // its ..
+            state.links += 1;
+        }
+        state.modify();
+        Ok(inode)
+    }
+
+    fn kind(&self) -> InodeKind {
+        self.state.lock().contents.kind()
+    }
+}
+
+// The RamInode behind inode, if it is one
+fn ram_inode(inode: &Arc<dyn Inode>) -> Option<Arc<RamInode>> {
+    let inode: Arc<dyn Any + Send + Sync> = inode.clone();
+    inode.downcast().ok()
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let size = match &state.contents {
+            Contents::File(data) => data.size,
+            Contents::Directory(entries) => entries.len() as u64,
+            Contents::Symlink(target) => target.len() as u64,
+        };
+        Metadata {
+            inode: self.number,
+            kind: state.contents.kind(),
+            size,
+            links: state.links,
+            mode: state.mode,
+            accessed: state.accessed,
+            modified: state.modified,
+            changed: state.changed,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let state = self.state.lock();
+        let Contents::Directory(entries) = &state.contents else {
+            return Err(FsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        Ok(inode.clone())
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, mode: u16) -> Result<Arc<dyn Inode>, FsError> {
+        let contents = match kind {
+            InodeKind::File => Contents::File(FileData::default()),
+            InodeKind::Directory => Contents::Directory(BTreeMap::new()),
+            _ => return Err(FsError::PermissionDenied),
+        };
+        Ok(self.add_entry(name, || Ok(RamInode::new(contents, mode)))?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let contents = Contents::Symlink(String::from(target));
+        Ok(self.add_entry(name, || Ok(RamInode::new(contents, 0o777)))?)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        let inode = ram_inode(inode).ok_or(FsError::CrossDevice)?;
+        self.add_entry(name, || {
+            let mut state = inode.state.lock();
+            if state.contents.kind() == InodeKind::Directory {
+                return Err(FsError::PermissionDenied);
+            }
+            state.links += 1;
+            state.changed = uptime();
+            drop(state);
+            Ok(inode.clone())
+        })?;
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let mut state = self.state.lock();
+        let Contents::Directory(entries) = &mut state.contents else {
+            return Err(FsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?.clone();
+        let mut inode_state = inode.state.lock();
+        let directory = match &inode_state.contents {
+            Contents::Directory(entries) if !entries.is_empty() => return Err(FsError::NotEmpty),
+            Contents::Directory(_) => true,
+            _ => false,
+        };
+        entries.remove(name);
+        // an open file keeps its contents until it's closed, and so does a directory, but nothing can go in it
+        inode_state.links = if directory { 0 } else { inode_state.links - 1 };
+        inode_state.changed = uptime();
+        drop(inode_state);
+        if directory {
+            state.links -= 1;
+        }
+        state.modify();
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        let mut state = self.state.lock();
+        state.accessed = uptime();
+        let Contents::Directory(entries) = &state.contents else {
+            return Err(FsError::NotADirectory);
+        };
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                inode: inode.number,
+                kind: inode.kind(),
+            })
+            .collect())
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let mut state = self.state.lock();
+        state.accessed = uptime();
+        let Contents::File(data) = &state.contents else {
+            return Err(FsError::InvalidArgument);
+        };
+        Ok(data.read_at(offset, buffer))
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let mut state = self.state.lock();
+        let Contents::File(file) = &mut state.contents else {
+            return Err(FsError::InvalidArgument);
+        };
+        let written = file.write_at(offset, data)?;
+        state.modify();
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let mut state = self.state.lock();
+        let Contents::File(file) = &mut state.contents else {
+            return Err(FsError::InvalidArgument);
+        };
+        file.truncate(size);
+        state.modify();
+        Ok(())
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        let mut state = self.state.lock();
+        state.accessed = uptime();
+        match &state.contents {
+            Contents::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+impl fmt::Debug for RamInode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RamInode")
+            .field("number", &self.number)
+            .finish()
+    }
+}
@@ -22,3 +22,20 @@ pub const MACHINE_CHECK: usize = 0x12;
 pub const SIMD_FLOATING_POINT_EXCEPTION: usize = 0x13;
 pub const VIRTUALIZATION_EXCEPTION: usize = 0x14;
 // Vectors #21-31 are reserved, and #32-255 are reserved for user defined interrupts
+
+// Legacy system call gate, the only vector ring 3 is allowed to raise itself. See syscall::int80_entry.
+pub const SYSCALL_INTERRUPT: usize = 0x80;
+
+// The legacy PICs get moved up here before they're masked, so a stray IRQ can't pass itself off as an exception.
+// See drivers::pic.
+pub const PIC_1_OFFSET: usize = 0x20;
+pub const PIC_2_OFFSET: usize = 0x28;
+// Spurious PIC interrupts always show up as the lowest priority line of either chip, IRQ 7 or IRQ 15
+pub const PIC_1_SPURIOUS_INTERRUPT: usize = PIC_1_OFFSET + 7;
+pub const PIC_2_SPURIOUS_INTERRUPT: usize = PIC_2_OFFSET + 7;
+
+// Local APIC vectors, see drivers::xapic
+pub const TIMER_INTERRUPT: usize = 0x30;
+// Devices that send MSIs, which go straight to a local APIC, see pci::PciDevice::enable_msi
+pub const AHCI_INTERRUPT: usize = 0x40;
+pub const SPURIOUS_INTERRUPT: usize = 0xFF;
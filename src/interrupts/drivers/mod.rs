@@ -1 +1,2 @@
+pub mod pic;
 pub mod xapic;
@@ -0,0 +1,49 @@
+use crate::interrupts::consts::{PIC_1_OFFSET, PIC_2_OFFSET};
+use crate::port::Port;
+
+// The two chained 8259s -> https://wiki.osdev.org/8259_PIC
+// We use the local APIC instead, so all that's left to do with them is get them out of the way.
+const PIC_1_COMMAND_PORT: u16 = 0x20;
+const PIC_1_DATA_PORT: u16 = 0x21;
+const PIC_2_COMMAND_PORT: u16 = 0xA0;
+const PIC_2_DATA_PORT: u16 = 0xA1;
+// Anything written here takes long enough for the PIC to catch up with the last write
+const POST_CODE_PORT: u16 = 0x80;
+
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4: u8 = 0x01;
+// which line the slave hangs off of, as a bit mask on the master and as a number on the slave
+const ICW3_MASTER_CASCADE: u8 = 0b0000_0100;
+const ICW3_SLAVE_CASCADE: u8 = 2;
+const ICW4_8086: u8 = 0x01;
+const MASK_ALL: u8 = 0xFF;
+
+// The BIOS leaves the master on vectors 0x08-0x0F, right on top of the exceptions, so they get remapped before
+// every line is masked. A masked PIC can still raise spurious interrupts, which is why it still needs vectors.
+pub fn disable() {
+    let pic_1_command = Port::new(PIC_1_COMMAND_PORT);
+    let pic_1_data = Port::new(PIC_1_DATA_PORT);
+    let pic_2_command = Port::new(PIC_2_COMMAND_PORT);
+    let pic_2_data = Port::new(PIC_2_DATA_PORT);
+    let wait = || unsafe { Port::new(POST_CODE_PORT).write_u8(0) };
+    unsafe {
+        pic_1_command.write_u8(ICW1_INIT | ICW1_ICW4);
+        wait();
+        pic_2_command.write_u8(ICW1_INIT | ICW1_ICW4);
+        wait();
+        pic_1_data.write_u8(PIC_1_OFFSET as u8);
+        wait();
+        pic_2_data.write_u8(PIC_2_OFFSET as u8);
+        wait();
+        pic_1_data.write_u8(ICW3_MASTER_CASCADE);
+        wait();
+        pic_2_data.write_u8(ICW3_SLAVE_CASCADE);
+        wait();
+        pic_1_data.write_u8(ICW4_8086);
+        wait();
+        pic_2_data.write_u8(ICW4_8086);
+        wait();
+        pic_1_data.write_u8(MASK_ALL);
+        pic_2_data.write_u8(MASK_ALL);
+    }
+}
@@ -0,0 +1,183 @@
+use core::hint::spin_loop;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::cpu::{cpuid, read_msr, write_msr, IA32_APIC_BASE};
+use crate::interrupts::consts::SPURIOUS_INTERRUPT;
+use crate::memory::address::PhysicalAddress;
+use crate::memory::physical_to_virtual;
+
+// The local APIC in xAPIC mode, where the registers are memory mapped -> Intel Manual - Chapter 10 (APIC)
+// Every CPU sees its own local APIC at the same address.
+const CPUID_APIC: u32 = 1 << 9;
+const APIC_BASE_GLOBAL_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// Register offsets, Intel Manual - Table 10-1
+const ID: usize = 0x020;
+const TASK_PRIORITY: usize = 0x080;
+const END_OF_INTERRUPT: usize = 0x0B0;
+const SPURIOUS_VECTOR: usize = 0x0F0;
+// 8 registers of 32 vectors each, 0x10 apart
+const IN_SERVICE: usize = 0x100;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE_CONFIGURATION: usize = 0x3E0;
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+// Intel Manual - Figure 10-10, the timer counts down at the bus clock divided by this
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const TIMER_CALIBRATION_MS: u64 = 10;
+
+// Interrupt command register, Intel Manual - Figure 10-12. Fixed delivery is 0.
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+const ICR_DESTINATION_SHIFT: u32 = 24;
+
+// zero until init() has run
+static APIC_BASE: AtomicU64 = AtomicU64::new(0);
+// timer ticks per second at TIMER_DIVIDE_BY_16, zero until the timer has been calibrated
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_supported() -> bool {
+    cpuid(0x01).edx & CPUID_APIC != 0
+}
+
+unsafe fn read(register: usize) -> u32 {
+    read_volatile((APIC_BASE.load(Ordering::Relaxed) + register as u64) as *const u32)
+}
+
+unsafe fn write(register: usize, value: u32) {
+    write_volatile(
+        (APIC_BASE.load(Ordering::Relaxed) + register as u64) as *mut u32,
+        value,
+    );
+}
+
+// The APIC registers sit in the MMIO hole below 4GiB, which the bootloader already maps for us
+pub fn init() {
+    assert!(is_supported(), "this CPU doesn't have a local APIC");
+    unsafe {
+        let apic_base = read_msr(IA32_APIC_BASE);
+        write_msr(IA32_APIC_BASE, apic_base | APIC_BASE_GLOBAL_ENABLE);
+        let address = physical_to_virtual(PhysicalAddress::new(apic_base & APIC_BASE_ADDRESS_MASK));
+        APIC_BASE.store(address.0, Ordering::Relaxed);
+
+        // accept every priority, and keep errors from turning into interrupts we have no handler for
+        write(TASK_PRIORITY, 0);
+        write(LVT_ERROR, LVT_MASKED);
+        write(LVT_TIMER, LVT_MASKED);
+        write(
+            SPURIOUS_VECTOR,
+            APIC_SOFTWARE_ENABLE | SPURIOUS_INTERRUPT as u32,
+        );
+    }
+}
+
+pub fn id() -> u8 {
+    (unsafe { read(ID) } >> 24) as u8
+}
+
+// Writing the low half is what sends it, the destination has to be in the high half by then
+unsafe fn send_ipi(apic_id: u8, command: u32) {
+    crate::interrupts::without_interrupts(|| {
+        write(
+            INTERRUPT_COMMAND_HIGH,
+            (apic_id as u32) << ICR_DESTINATION_SHIFT,
+        );
+        write(INTERRUPT_COMMAND_LOW, command);
+        while read(INTERRUPT_COMMAND_LOW) & ICR_SEND_PENDING != 0 {
+            spin_loop();
+        }
+    });
+}
+
+// Resets the CPU to a halted state where it waits for a startup IPI -> Intel Manual - Section 8.4.4
+pub fn send_init(apic_id: u8) {
+    unsafe {
+        send_ipi(
+            apic_id,
+            ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT | ICR_TRIGGER_LEVEL,
+        )
+    };
+}
+
+// Starts a CPU that's waiting after an INIT in real mode at page:0, so page has to be below 1MiB
+pub fn send_startup(apic_id: u8, page: PhysicalAddress) {
+    debug_assert!(page.0.is_multiple_of(0x1000) && page.0 < 0x10_0000);
+    let vector = (page.0 >> 12) as u32;
+    unsafe { send_ipi(apic_id, ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | vector) };
+}
+
+// Gets through even if the CPU has interrupts off, the vector is ignored and it always goes to the NMI handler
+pub fn send_nmi(apic_id: u8) {
+    unsafe { send_ipi(apic_id, ICR_DELIVERY_NMI | ICR_LEVEL_ASSERT) };
+}
+
+// Delivers vector to the CPU like any other interrupt, so it waits for that CPU to have interrupts on.
+// Vectors below 16 aren't allowed.
+pub fn send_fixed(apic_id: u8, vector: u8) {
+    debug_assert!(vector >= 16);
+    unsafe { send_ipi(apic_id, ICR_LEVEL_ASSERT | vector as u32) };
+}
+
+// The vector of the interrupt the CPU is handling right now, or None if nothing that came through the local APIC is
+// in service. Anything that interrupted a handler has a higher priority than it, so that's always the highest one.
+pub fn in_service() -> Option<u8> {
+    (0..8).rev().find_map(|register| {
+        let bits = unsafe { read(IN_SERVICE + register * 0x10) };
+        (bits != 0).then(|| (register * 32 + 31 - bits.leading_zeros() as usize) as u8)
+    })
+}
+
+// Every interrupt that came through the local APIC (except spurious ones) has to be acknowledged,
+// otherwise nothing of the same or lower priority gets through again
+pub fn end_of_interrupt() {
+    unsafe { write(END_OF_INTERRUPT, 0) };
+}
+
+// Counts how far the timer gets while the PIT runs a one-shot, the timer's frequency isn't written down anywhere
+unsafe fn calibrate_timer() -> u64 {
+    write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+    write(LVT_TIMER, LVT_MASKED);
+    write(TIMER_INITIAL_COUNT, u32::MAX);
+    let (start, end) =
+        crate::time::pit_stopwatch(TIMER_CALIBRATION_MS, || read(TIMER_CURRENT_COUNT));
+    write(TIMER_INITIAL_COUNT, 0);
+    (start - end) as u64 * 1000 / TIMER_CALIBRATION_MS
+}
+
+pub fn timer_frequency() -> u64 {
+    TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+// Fires vector frequency times a second until the timer is stopped
+pub fn start_timer(vector: u8, frequency: u64) {
+    unsafe {
+        if timer_frequency() == 0 {
+            TIMER_FREQUENCY.store(calibrate_timer(), Ordering::Relaxed);
+            log::debug!("APIC timer runs at {} Hz", timer_frequency());
+        }
+        let initial_count = (timer_frequency() / frequency).clamp(1, u32::MAX as u64);
+        write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+        write(LVT_TIMER, vector as u32 | LVT_TIMER_PERIODIC);
+        write(TIMER_INITIAL_COUNT, initial_count as u32);
+    }
+}
+
+pub fn stop_timer() {
+    unsafe {
+        write(LVT_TIMER, LVT_MASKED);
+        write(TIMER_INITIAL_COUNT, 0);
+    }
+}
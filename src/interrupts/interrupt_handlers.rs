@@ -1,4 +1,21 @@
-use crate::println;
+use core::arch::naked_asm;
+
+use crate::cpu::read_cr2;
+use crate::exception_println;
+use crate::interrupts::drivers::xapic;
+use crate::memory::address::VirtualAddress;
+use crate::memory::is_user_address;
+use crate::memory::paging::tlb;
+use crate::process::{
+    self, cow, BUS_ERROR_EXIT_STATUS, FLOATING_POINT_EXIT_STATUS, ILLEGAL_INSTRUCTION_EXIT_STATUS,
+    SEGFAULT_EXIT_STATUS, TRAP_EXIT_STATUS,
+};
+use crate::smp::percpu::KernelGs;
+
+// What the page fault error code says about the access -> Intel Manual - Section 4.7
+const PAGE_FAULT_PRESENT: u64 = 1 << 0;
+const PAGE_FAULT_WRITE: u64 = 1 << 1;
+const PAGE_FAULT_USER: u64 = 1 << 2;
 
 #[derive(Debug)]
 #[repr(C)]
@@ -11,59 +28,330 @@ pub struct InterruptStackFrame {
     stack_segment: u64,
 }
 
+impl InterruptStackFrame {
+    // Whether the interrupt arrived in ring 3, going by the RPL of the saved cs
+    pub fn from_user(&self) -> bool {
+        self.segment_selector & 3 == 3
+    }
+}
+
+// Any of these can come from ring 3, and whatever they call might want per-CPU data, so they all start by making sure
+// gs is the kernel's. Apart from page faults they can also hit the kernel right after it's swapped in the user's gs on
+// its way out, so they have to ask the MSR, see KernelGs::enter_paranoid.
+// The ones ring 3 can cause kill its process, with the status the signal Linux sends for them would give, from the
+// kernel they're a bug. They go through interrupt gates, so nothing gets switched to on an IST stack before the
+// process is gone.
+
+// Kills the current process if the exception came from ring 3, otherwise prints it and panics
+fn fault(stack_frame: &InterruptStackFrame, name: &str, error_code: Option<u64>, status: u64) -> ! {
+    if stack_frame.from_user() {
+        // we're never going back to ring 3, so the handler's KernelGs is never dropped and the kernel's gs stays
+        unsafe { crate::usermode::exit_to_kernel(status) };
+    }
+    exception_println!("EXCEPTION: {}", name);
+    if let Some(error_code) = error_code {
+        exception_println!("ERROR CODE: {:#?}", error_code);
+    }
+    exception_println!("{:#?}", stack_frame);
+    panic!();
+}
+
+pub extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "DIVIDE ERROR",
+        None,
+        FLOATING_POINT_EXIT_STATUS,
+    );
+}
+
+// Single stepping, which ring 3 can turn on itself with the trap flag
 pub extern "x86-interrupt" fn debug_exception_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: DEBUG");
-    println!("{:#?}", stack_frame);
+    let _gs = KernelGs::enter_paranoid();
+    if stack_frame.from_user() {
+        fault(&stack_frame, "DEBUG", None, TRAP_EXIT_STATUS);
+    }
+    exception_println!("EXCEPTION: DEBUG");
+    exception_println!("{:#?}", stack_frame);
 }
 
-pub extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame, error_code: u64) {
-    println!("EXCEPTION: NON-MASKABLE HARDWARE INTERRUPT");
-    println!("ERROR CODE: {:#?}", error_code);
-    println!("{:#?}", stack_frame);
+// NMIs don't push an error code
+pub extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    // other CPUs use them to get us to flush our TLB, since they get through with interrupts off
+    if tlb::handle_shootdown() {
+        return;
+    }
+    exception_println!("EXCEPTION: NON-MASKABLE HARDWARE INTERRUPT");
+    exception_println!("{:#?}", stack_frame);
     panic!();
 }
 
+// int3, which ring 3 is allowed to use, see the gate
 pub extern "x86-interrupt" fn breakpoint_exception_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT");
-    println!("{:#?}", stack_frame);
+    let _gs = KernelGs::enter_paranoid();
+    if stack_frame.from_user() {
+        fault(&stack_frame, "BREAKPOINT", None, TRAP_EXIT_STATUS);
+    }
+    exception_println!("EXCEPTION: BREAKPOINT");
+    exception_println!("{:#?}", stack_frame);
+}
+
+pub extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(&stack_frame, "OVERFLOW", None, SEGFAULT_EXIT_STATUS);
+}
+
+pub extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "BOUND RANGE EXCEEDED",
+        None,
+        SEGFAULT_EXIT_STATUS,
+    );
+}
+
+pub extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "INVALID OPCODE",
+        None,
+        ILLEGAL_INSTRUCTION_EXIT_STATUS,
+    );
+}
+
+// Only raised with CR0.TS or CR0.EM set, which we never do
+pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "DEVICE NOT AVAILABLE",
+        None,
+        FLOATING_POINT_EXIT_STATUS,
+    );
 }
 
 pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) -> ! {
-    println!("EXCEPTION: DOUBLE FAULT");
-    println!("ERROR CODE: {:#?}", error_code);
-    println!("{:#?}", stack_frame);
+    let _gs = KernelGs::enter_paranoid();
+    exception_println!("EXCEPTION: DOUBLE FAULT");
+    exception_println!("ERROR CODE: {:#?}", error_code);
+    exception_println!("{:#?}", stack_frame);
     panic!();
 }
 
+pub extern "x86-interrupt" fn invalid_tss_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "INVALID TSS",
+        Some(error_code),
+        SEGFAULT_EXIT_STATUS,
+    );
+}
+
 pub extern "x86-interrupt" fn segment_not_present_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    println!("EXCEPTION: SEGMENT NOT PRESENT");
-    println!("ERROR CODE: {:#?}", error_code);
-    println!("{:#?}", stack_frame);
-    panic!();
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "SEGMENT NOT PRESENT",
+        Some(error_code),
+        SEGFAULT_EXIT_STATUS,
+    );
 }
 
 pub extern "x86-interrupt" fn stack_segment_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    println!("EXCEPTION: STACK SEGMENT FAULT");
-    println!("ERROR CODE: {:#?}", error_code);
-    println!("{:#?}", stack_frame);
-    panic!();
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "STACK SEGMENT FAULT",
+        Some(error_code),
+        SEGFAULT_EXIT_STATUS,
+    );
 }
 
+// Also what privileged instructions like hlt and cli, and int n on a gate ring 3 can't use, cause
 pub extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    println!("EXCEPTION: GENERAL PROTECTION FAULT");
-    println!("ERROR CODE: {:#?}", error_code);
-    println!("{:#?}", stack_frame);
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "GENERAL PROTECTION FAULT",
+        Some(error_code),
+        SEGFAULT_EXIT_STATUS,
+    );
+}
+
+pub extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "X87 FLOATING POINT ERROR",
+        None,
+        FLOATING_POINT_EXIT_STATUS,
+    );
+}
+
+// Only with CR0.AM and the AC flag set, which ring 3 can do to itself
+pub extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "ALIGNMENT CHECK",
+        Some(error_code),
+        BUS_ERROR_EXIT_STATUS,
+    );
+}
+
+// The hardware itself is broken, whatever was running
+pub extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let _gs = KernelGs::enter_paranoid();
+    exception_println!("EXCEPTION: MACHINE CHECK");
+    exception_println!("{:#?}", stack_frame);
+    panic!();
+}
+
+pub extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "SIMD FLOATING POINT EXCEPTION",
+        None,
+        FLOATING_POINT_EXIT_STATUS,
+    );
+}
+
+// Only comes from EPT violations inside a guest, which we never are as far as we know
+pub extern "x86-interrupt" fn virtualization_exception_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    fault(
+        &stack_frame,
+        "VIRTUALIZATION EXCEPTION",
+        None,
+        SEGFAULT_EXIT_STATUS,
+    );
+}
+
+// The first touch of a page in one of the process' areas maps it, and writes to copy-on-write pages get their copy,
+// then the access is tried again. Anything else ring 3 does wrong kills its process, anything the kernel does wrong
+// is a bug.
+// Also an interrupt gate, since a fault from ring 3 still has the user's gs loaded until KernelGs is done.
+// The kernel never touches user memory between a swapgs and going back to ring 3, so cs is enough to go by.
+pub extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = KernelGs::enter(stack_frame.from_user());
+    let address = read_cr2();
+    let write_to_present_page = PAGE_FAULT_PRESENT | PAGE_FAULT_WRITE;
+    if error_code & write_to_present_page == write_to_present_page
+        && cow::handle_write_fault(VirtualAddress::new(address))
+    {
+        return;
+    }
+    if error_code & PAGE_FAULT_PRESENT == 0
+        && is_user_address(VirtualAddress::new(address))
+        && process::current()
+            .address_space()
+            .populate(VirtualAddress::new(address))
+    {
+        return;
+    }
+    if error_code & PAGE_FAULT_USER != 0 {
+        // we're never going back to ring 3, so _gs is never dropped and the kernel's gs stays
+        unsafe { crate::usermode::exit_to_kernel(SEGFAULT_EXIT_STATUS) };
+    }
+    exception_println!("EXCEPTION: PAGE FAULT");
+    exception_println!("ADDRESS: {:#x}", address);
+    exception_println!("ERROR CODE: {:#?}", error_code);
+    exception_println!("{:#?}", stack_frame);
     panic!();
 }
+
+// Spurious interrupts are never acknowledged, there's nothing to do but ignore them.
+// Intel Manual - Section 10.9 for the local APIC, and see drivers::pic for the PICs.
+pub extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+// Every vector nobody has installed a handler for, see idt. Only an interrupt that came through the local APIC gets
+// acknowledged, an int n never did.
+pub extern "x86-interrupt" fn unexpected_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(stack_frame.from_user());
+    match xapic::in_service() {
+        Some(vector) => {
+            exception_println!("INTERRUPT: UNEXPECTED VECTOR {:#x}", vector);
+            xapic::end_of_interrupt();
+        }
+        None => exception_println!("INTERRUPT: UNEXPECTED, NOT FROM THE LOCAL APIC"),
+    }
+}
+
+// The timer can go off in ring 3, and a switch to another thread can happen in the middle of it, so this does its
+// own conditional swapgs like syscall::int80_entry instead of being an x86-interrupt function.
+// Everything the C ABI lets timer_interrupt clobber gets saved, the callee saved registers are its problem.
+#[unsafe(naked)]
+pub unsafe extern "C" fn timer_interrupt_entry() {
+    naked_asm!(
+        "test qword ptr [rsp + 0x08], 3",
+        "jz 2f",
+        "swapgs",
+        "2:",
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        // 5 values from the CPU plus 9 here keeps the stack 16 byte aligned for the call
+        "push r11",
+        "call {handler}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "test qword ptr [rsp + 0x08], 3",
+        "jz 3f",
+        "swapgs",
+        "3:",
+        "iretq",
+        handler = sym timer_interrupt,
+    )
+}
+
+extern "C" fn timer_interrupt() {
+    // acknowledged up front, we might not come back here until after a few other threads have had a go
+    xapic::end_of_interrupt();
+    crate::task::tick();
+}
+
+// Every AHCI controller's commands finish with this, see block::ahci
+pub extern "x86-interrupt" fn ahci_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(stack_frame.from_user());
+    crate::block::ahci::handle_interrupt();
+    xapic::end_of_interrupt();
+}
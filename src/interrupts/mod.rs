@@ -1,3 +1,58 @@
+use core::arch::asm;
+
 pub mod consts;
 pub mod drivers;
 pub mod interrupt_handlers;
+
+// Intel Manual - Section 3.4.3 (EFLAGS register)
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
+
+#[inline]
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags & RFLAGS_INTERRUPT_FLAG != 0
+}
+
+#[inline]
+pub fn enable() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
+
+#[inline]
+pub fn disable() {
+    unsafe {
+        asm!("cli", options(nomem, nostack));
+    }
+}
+
+// For waiting on an interrupt. sti only takes effect after the next instruction, so an interrupt can't slip in
+// between the two and leave us halted with nothing coming to wake us up.
+#[inline]
+pub fn enable_and_hlt() {
+    unsafe {
+        asm!("sti", "hlt", options(nomem, nostack));
+    }
+}
+
+// Anything that takes a lock an interrupt handler might also want has to go through here,
+// otherwise an interrupt arriving while the lock is held deadlocks the kernel.
+#[inline]
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let were_enabled = are_enabled();
+    if were_enabled {
+        disable();
+    }
+    let result = f();
+    if were_enabled {
+        enable();
+    }
+    result
+}
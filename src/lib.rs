@@ -5,41 +5,73 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-use core::arch::asm;
+extern crate alloc;
+
 use core::panic::PanicInfo;
 
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
+use bootloader::BootInfo;
 
+pub mod acpi;
+pub mod block;
+pub mod cmdline;
+pub mod console;
+pub mod cpu;
+pub mod framebuffer;
+pub mod fs;
 pub mod interrupts;
+pub mod logging;
 pub mod memory;
+pub mod pci;
+pub mod port;
+pub mod process;
 pub mod serial;
+pub mod smp;
 pub mod structs;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod time;
+pub mod usermode;
 pub mod vga;
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
 
-pub fn kernel_init() {
-    println!("Loading GDT...");
+pub fn kernel_init(boot_info: &'static BootInfo) {
+    kernel_init_boot_cpu(boot_info);
+    smp::init();
+}
+
+// Everything kernel_init does except starting the other CPUs, so only the boot processor ever runs threads. For tests
+// that care which order threads run in, or how the CPU time gets split between them.
+pub fn kernel_init_boot_cpu(boot_info: &'static BootInfo) {
+    smp::init_boot_cpu();
+    memory::init(boot_info);
+    time::init();
+    logging::init();
+    acpi::init();
+    framebuffer::init_from_cmdline();
+    log::info!("Loading GDT...");
     structs::gdt::init_gdt();
-    println!("...[ok]");
-    println!("Loading IDT...");
+    log::info!("GDT loaded");
+    log::info!("Loading IDT...");
     structs::idt::init_idt();
-    println!("...[ok]");
+    log::info!("IDT loaded");
+    syscall::init();
+    log::info!("System calls enabled");
+    interrupts::drivers::pic::disable();
+    interrupts::drivers::xapic::init();
+    log::info!("Local APIC {} enabled", interrupts::drivers::xapic::id());
+    process::init();
+    task::init();
+    fs::init();
+    interrupts::enable();
+    block::init();
 }
 
-// TODO: This needs some TLC. Write a good port struct with methods that make sense
-#[derive(Debug)]
-#[repr(transparent)]
-pub struct Port(u16);
-
-impl Port {
-    unsafe fn write(&self, value: u32) {
-        let port = self.0;
-        asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
-    }
-}
+pub use port::Port;
 
 // Exit utils
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,8 +83,8 @@ pub enum ExitCode {
 
 pub fn exit(exit_code: ExitCode) {
     unsafe {
-        let port = Port(0xf4);
-        port.write(exit_code as u32);
+        let port = Port::new(0xf4);
+        port.write_u32(exit_code as u32);
     }
 }
 
@@ -81,6 +113,9 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    // the test might have panicked while holding the serial port
+    interrupts::disable();
+    unsafe { serial::SERIAL1.force_unlock() };
     serial_println!("[failed]");
     serial_println!("Panicked at: {}", info);
     exit(ExitCode::Failure);
@@ -90,7 +125,7 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 #[cfg(test)]
 fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
     test_main();
-    loop {}
+    task::exit();
 }
 
 #[cfg(test)]
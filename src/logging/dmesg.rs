@@ -0,0 +1,47 @@
+use core::fmt;
+
+use spin::Mutex;
+
+use crate::console::ring_buffer::RingBuffer;
+use crate::interrupts::without_interrupts;
+
+pub const DMESG_SIZE: usize = 64 * 1024;
+
+// Every log record that made it past the filters, already formatted
+static DMESG: Mutex<RingBuffer<DMESG_SIZE>> = Mutex::new(RingBuffer::new());
+
+pub(super) fn write_fmt(args: fmt::Arguments) {
+    use core::fmt::Write;
+    without_interrupts(|| {
+        let _ = DMESG.lock().write_fmt(args);
+    });
+}
+
+// Calls f with the saved records, oldest first. Once the buffer has wrapped around the oldest record
+// is only partially there, so that one gets skipped.
+pub fn for_each(mut f: impl FnMut(&str)) {
+    without_interrupts(|| {
+        let dmesg = DMESG.lock();
+        let mut skipping = dmesg.len() == dmesg.capacity();
+        dmesg.for_each_str(|mut s| {
+            if skipping {
+                match s.split_once('\n') {
+                    Some((_, rest)) => {
+                        skipping = false;
+                        s = rest;
+                    }
+                    None => return,
+                }
+            }
+            f(s);
+        });
+    });
+}
+
+pub fn dump() {
+    for_each(|s| crate::print!("{}", s));
+}
+
+pub fn clear() {
+    without_interrupts(|| DMESG.lock().clear());
+}
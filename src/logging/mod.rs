@@ -0,0 +1,146 @@
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::cmdline;
+use crate::sync::Once;
+
+pub mod dmesg;
+
+const MAX_DIRECTIVES: usize = 16;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+// A module path and the most verbose level we want to hear from it (and everything under it)
+#[derive(Debug, Clone, Copy)]
+struct Directive {
+    module: &'static str,
+    level: LevelFilter,
+}
+
+#[derive(Debug)]
+struct Filter {
+    default_level: LevelFilter,
+    directives: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+impl Filter {
+    // Same syntax as env_logger, minus the regexes: "info,flap_os::pci=trace,flap_os::vga=off".
+    // A bare level sets the default, anything we can't make sense of is ignored.
+    fn parse(spec: &'static str) -> Filter {
+        let mut filter = Filter {
+            default_level: DEFAULT_LEVEL,
+            directives: [None; MAX_DIRECTIVES],
+        };
+        let mut slots = filter.directives.iter_mut();
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                None => match LevelFilter::from_str(part) {
+                    Ok(level) => filter.default_level = level,
+                    // "log=flap_os::pci" turns everything on for that module
+                    Err(_) => {
+                        if let Some(slot) = slots.next() {
+                            *slot = Some(Directive {
+                                module: part,
+                                level: LevelFilter::Trace,
+                            });
+                        }
+                    }
+                },
+                Some((module, level)) => {
+                    if let (Ok(level), Some(slot)) = (LevelFilter::from_str(level), slots.next()) {
+                        *slot = Some(Directive { module, level });
+                    }
+                }
+            }
+        }
+        filter
+    }
+
+    // The most specific directive wins
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|directive| {
+                target == directive.module
+                    || (target.starts_with(directive.module)
+                        && target[directive.module.len()..].starts_with("::"))
+            })
+            .max_by_key(|directive| directive.module.len())
+            .map_or(self.default_level, |directive| directive.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|directive| directive.level)
+            .fold(self.default_level, Ord::max)
+    }
+}
+
+// [    1.234567] cpu0 INFO  flap_os: Loading GDT...
+struct Line<'a> {
+    record: &'a Record<'a>,
+    uptime: Duration,
+    cpu: usize,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "[{:>5}.{:06}] cpu{} {:<5} {}: {}",
+            self.uptime.as_secs(),
+            self.uptime.subsec_micros(),
+            self.cpu,
+            self.record.level(),
+            self.record.target(),
+            self.record.args()
+        )
+    }
+}
+
+struct KernelLogger {
+    filter: Once<Filter>,
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.filter.get() {
+            Some(filter) => metadata.level() <= filter.level_for(metadata.target()),
+            None => metadata.level() <= DEFAULT_LEVEL,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = Line {
+            record,
+            uptime: crate::time::uptime(),
+            cpu: crate::smp::current_cpu(),
+        };
+        dmesg::write_fmt(format_args!("{}", line));
+        crate::console::_print(format_args!("{}", line));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: KernelLogger = KernelLogger {
+    filter: Once::new(),
+};
+
+// Reads the filters from the log= command line option
+pub fn init() {
+    let filter = LOGGER
+        .filter
+        .call_once(|| Filter::parse(cmdline::option("log").unwrap_or("")));
+    log::set_max_level(filter.max_level());
+    // this only fails if init() gets called twice, in which case the logger is already in place
+    let _ = log::set_logger(&LOGGER);
+}
@@ -13,19 +13,20 @@ use flap_os::memory::paging::page_table::read_cr3;
 use flap_os::println;
 entry_point!(kernel_main);
 
-fn kernel_main(_boot_info: &'static BootInfo) -> ! {
-    flap_os::kernel_init();
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
 
     println!("CR3 value after boot: {:#?}", read_cr3());
     #[cfg(test)]
     test_main();
-    loop {}
+    // nothing left for the boot thread to do, the idle task takes it from here
+    flap_os::task::exit();
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    flap_os::console::print_panic(format_args!("{}\n", info));
     loop {}
 }
 
@@ -24,6 +24,16 @@ impl VirtualAddress {
         }
     }
 
+    #[inline]
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    #[inline]
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
     #[inline]
     pub fn get_page_offset(self) -> u16 {
         (self.0 & 0x0FFF) as u16
@@ -0,0 +1,123 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::PhysicalAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::physical_to_virtual;
+
+const FRAME_SIZE: u64 = PageSize::NORMAL as u64;
+// Real mode can only get at the first MiB
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+// Hands out the usable frames from the bootloader's memory map in order.
+// Frames that get freed go on a linked list that's threaded through the frames themselves
+// (each one holds the address of the next), so there's nothing to size up front.
+pub struct FrameAllocator {
+    memory_map: &'static MemoryMap,
+    region: usize,
+    next: u64,
+    free_list: Option<PhysicalAddress>,
+    free_frames: usize,
+    allocated_frames: usize,
+}
+
+impl FrameAllocator {
+    // The caller has to guarantee that everything the memory map says is usable really is unused
+    pub unsafe fn new(memory_map: &'static MemoryMap) -> Self {
+        FrameAllocator {
+            memory_map,
+            region: 0,
+            next: 0,
+            free_list: None,
+            free_frames: 0,
+            allocated_frames: 0,
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<PhysicalFrame> {
+        let address = match self.free_list {
+            Some(address) => {
+                let next = unsafe { *physical_to_virtual(address).as_ptr::<u64>() };
+                self.free_list = if next == 0 {
+                    None
+                } else {
+                    Some(PhysicalAddress::new(next))
+                };
+                self.free_frames -= 1;
+                address
+            }
+            None => self.next_unused()?,
+        };
+        self.allocated_frames += 1;
+        Some(PhysicalFrame::from_address_aligned(
+            address,
+            PageSize::NORMAL,
+        ))
+    }
+
+    // The frame must have come from allocate() and must not be mapped anywhere anymore
+    pub unsafe fn deallocate(&mut self, frame: PhysicalFrame) {
+        debug_assert!(frame.size() == PageSize::NORMAL);
+        let next = self.free_list.map_or(0, |address| address.0);
+        *physical_to_virtual(frame.start_address()).as_mut_ptr::<u64>() = next;
+        self.free_list = Some(frame.start_address());
+        self.free_frames += 1;
+        self.allocated_frames -= 1;
+    }
+
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated_frames
+    }
+
+    fn next_unused(&mut self) -> Option<PhysicalAddress> {
+        while let Some(region) = self.memory_map.get(self.region) {
+            // physical frame 0 is never handed out, that way 0 can end the free list
+            let start = region.range.start_addr().max(self.next).max(FRAME_SIZE);
+            if region.region_type == MemoryRegionType::Usable && start < region.range.end_addr() {
+                self.next = start + FRAME_SIZE;
+                return Some(PhysicalAddress::new(start));
+            }
+            self.region += 1;
+        }
+        None
+    }
+}
+
+pub static FRAME_ALLOCATOR: Mutex<Option<FrameAllocator>> = Mutex::new(None);
+// A frame in low memory for the application processors to start out in, see smp
+static LOW_FRAME: Mutex<Option<PhysicalFrame>> = Mutex::new(None);
+
+pub(super) fn init(memory_map: &'static MemoryMap) {
+    let mut allocator = unsafe { FrameAllocator::new(memory_map) };
+    // Frames are handed out lowest first, so the low frame has to be set aside before anything else gets a chance
+    // to take it
+    *LOW_FRAME.lock() = match allocator.allocate() {
+        Some(frame) if frame.start_address().0 < LOW_MEMORY_END => Some(frame),
+        Some(frame) => {
+            unsafe { allocator.deallocate(frame) };
+            None
+        }
+        None => None,
+    };
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+}
+
+// The frame below 1MiB that was set aside at boot, if there was one. Only the first caller gets it.
+pub fn take_low_frame() -> Option<PhysicalFrame> {
+    without_interrupts(|| LOW_FRAME.lock().take())
+}
+
+// Page faults will end up allocating frames, so the lock can't be held with interrupts on
+pub fn allocate_frame() -> Option<PhysicalFrame> {
+    without_interrupts(|| FRAME_ALLOCATOR.lock().as_mut()?.allocate())
+}
+
+pub unsafe fn deallocate_frame(frame: PhysicalFrame) {
+    without_interrupts(|| {
+        if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            allocator.deallocate(frame);
+        }
+    })
+}
@@ -0,0 +1,192 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::{self, null_mut};
+
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::mapper::{MapError, Mapper};
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::{KERNEL_HEAP_SIZE, KERNEL_HEAP_START};
+
+// A free block. These live inside the free memory they describe, sorted by address so neighbours can be merged.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+// First fit over a list of free blocks -> https://os.phil-opp.com/allocator-designs/#linked-list-allocator
+// Nothing fancy, but freed memory is merged back together so it doesn't fragment into nothing.
+pub struct Heap {
+    head: *mut FreeBlock,
+    size: usize,
+    used: usize,
+}
+
+// The raw pointers only ever point into the heap, which is only touched through the Mutex
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Heap {
+            head: null_mut(),
+            size: 0,
+            used: 0,
+        }
+    }
+
+    // The caller has to guarantee the memory is mapped, writable and not used for anything else
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.size = size;
+        self.free(start, size);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    // Every block has to be able to hold a FreeBlock once it's freed again
+    fn adjust_layout(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(align_of::<FreeBlock>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        (layout.size().max(size_of::<FreeBlock>()), layout.align())
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Heap::adjust_layout(layout);
+        let mut previous: *mut FreeBlock = null_mut();
+        let mut current = self.head;
+        unsafe {
+            while !current.is_null() {
+                let block_start = current as usize;
+                let block_end = block_start + (*current).size;
+                let start = align_up(block_start, align);
+                // whatever gets cut off the front has to be big enough to stay on the list
+                let start = if start != block_start && start - block_start < size_of::<FreeBlock>()
+                {
+                    align_up(block_start + size_of::<FreeBlock>(), align)
+                } else {
+                    start
+                };
+                let end = start.saturating_add(size);
+                let tail = block_end.saturating_sub(end);
+                if end <= block_end && (tail == 0 || tail >= size_of::<FreeBlock>()) {
+                    let next = (*current).next;
+                    // unlink the block, then put back whatever we didn't use on either side of it
+                    if previous.is_null() {
+                        self.head = next;
+                    } else {
+                        (*previous).next = next;
+                    }
+                    if start != block_start {
+                        self.free(block_start, start - block_start);
+                    }
+                    if tail != 0 {
+                        self.free(end, tail);
+                    }
+                    self.used += size;
+                    return start as *mut u8;
+                }
+                previous = current;
+                current = (*current).next;
+            }
+        }
+        null_mut()
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Heap::adjust_layout(layout);
+        self.used -= size;
+        self.free(ptr as usize, size);
+    }
+
+    // Puts a block back on the list, merging it with the blocks on either side if they touch
+    unsafe fn free(&mut self, start: usize, size: usize) {
+        let mut previous: *mut FreeBlock = null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < start {
+            previous = next;
+            next = (*next).next;
+        }
+        let block = start as *mut FreeBlock;
+        ptr::write(block, FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if previous.is_null() {
+            self.head = block;
+        } else if previous as usize + (*previous).size == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+}
+
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+pub struct LockedHeap(Mutex<Heap>);
+
+impl LockedHeap {
+    pub const fn empty() -> Self {
+        LockedHeap(Mutex::new(Heap::empty()))
+    }
+
+    // (size, used) in bytes
+    pub fn usage(&self) -> (usize, usize) {
+        without_interrupts(|| {
+            let heap = self.0.lock();
+            (heap.size(), heap.used())
+        })
+    }
+}
+
+// Interrupt handlers are allowed to allocate, so the lock is only ever held with interrupts off
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.lock().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.lock().deallocate(ptr, layout))
+    }
+}
+
+#[global_allocator]
+pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+// Maps the whole heap up front, it's small enough that demand paging it isn't worth the trouble
+pub(super) fn init() -> Result<(), MapError> {
+    let mut mapper = unsafe { Mapper::active() };
+    let page_size = PageSize::NORMAL as u64;
+    for offset in (0..KERNEL_HEAP_SIZE).step_by(page_size as usize) {
+        let page = Page::from_address_aligned(
+            VirtualAddress::new(KERNEL_HEAP_START + offset),
+            PageSize::NORMAL,
+        );
+        mapper.map(
+            page,
+            PageTableFlags::WRITABLE | PageTableFlags::GLOBAL | PageTableFlags::NO_EXECUTE,
+        )?;
+    }
+    unsafe {
+        ALLOCATOR
+            .0
+            .lock()
+            .init(KERNEL_HEAP_START as usize, KERNEL_HEAP_SIZE as usize);
+    }
+    Ok(())
+}
@@ -1,2 +1,48 @@
 pub mod address;
+pub mod frame_allocator;
+pub mod heap;
 pub mod paging;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use bootloader::BootInfo;
+
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+
+// The bootloader maps all of physical memory (including the MMIO holes below 4GiB) starting at this address.
+// See the map_physical_memory feature -> https://docs.rs/bootloader/0.9/bootloader/
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// The bootloader puts the kernel, its stack and the physical memory mapping in the first few level 4 entries,
+// so user space gets the rest of the lower half, starting on a level 4 boundary.
+pub const USER_SPACE_START: u64 = 0x0000_4000_0000_0000;
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+// Our own kernel mappings go in the upper half, which the bootloader leaves alone.
+// Each gets its own level 4 entry, so every address space can share them just by copying the entry.
+pub const KERNEL_HEAP_START: u64 = 0xFFFF_8800_0000_0000;
+pub const KERNEL_HEAP_SIZE: u64 = 8 * 1024 * 1024; // 8MiB
+pub const KERNEL_STACKS_START: u64 = 0xFFFF_9000_0000_0000;
+
+pub fn init(boot_info: &'static BootInfo) {
+    PHYSICAL_MEMORY_OFFSET.store(boot_info.physical_memory_offset, Ordering::Relaxed);
+    crate::cpu::enable_no_execute();
+    crate::cpu::enable_pcid();
+    frame_allocator::init(&boot_info.memory_map);
+    heap::init().expect("failed to map the kernel heap");
+}
+
+#[inline]
+pub fn physical_memory_offset() -> u64 {
+    PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed)
+}
+
+#[inline]
+pub fn physical_to_virtual(physical_address: PhysicalAddress) -> VirtualAddress {
+    VirtualAddress::new(physical_address.0 + physical_memory_offset())
+}
+
+#[inline]
+pub fn is_user_address(address: VirtualAddress) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&address.0)
+}
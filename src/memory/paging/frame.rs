@@ -1,7 +1,7 @@
 use crate::memory::address::PhysicalAddress;
 use crate::memory::paging::consts::PageSize;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct PhysicalFrame {
     size: PageSize,
@@ -17,4 +17,14 @@ impl PhysicalFrame {
             offset: aligned_address,
         }
     }
+
+    #[inline]
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.offset
+    }
+
+    #[inline]
+    pub fn size(&self) -> PageSize {
+        self.size
+    }
 }
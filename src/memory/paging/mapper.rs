@@ -0,0 +1,199 @@
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use crate::memory::frame_allocator::allocate_frame;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::{read_cr3, PageTable, PageTableEntry, PageTableFlags};
+use crate::memory::paging::tlb::{FlushBatch, FlushScope};
+use crate::memory::physical_to_virtual;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    FrameAllocationFailed,
+    PageAlreadyMapped,
+    PageNotMapped,
+    // the address is covered by a 2MiB/1GiB page, which we don't split up
+    ParentEntryHugePage,
+}
+
+// Walks and edits a 4 level page table hierarchy through the physical memory mapping the bootloader set up,
+// so it works on any address space, not just the one that's currently loaded.
+// Only ever creates 4KiB mappings. Mappings that go away or change get flushed from every CPU's TLB once the mapper is
+// flushed or dropped, so whatever was unmapped can't be freed before then.
+pub struct Mapper<'a> {
+    level_4_table: PhysicalAddress,
+    flush: FlushBatch<'a>,
+}
+
+impl<'a> Mapper<'a> {
+    // The caller has to guarantee that level_4_table really is a level 4 table, and that nothing else is
+    // changing the same hierarchy at the same time. scope says where changes have to be flushed from.
+    pub unsafe fn new(level_4_table: PhysicalAddress, scope: FlushScope<'a>) -> Self {
+        Mapper {
+            level_4_table,
+            flush: FlushBatch::new(scope),
+        }
+    }
+
+    pub fn level_4_table_address(&self) -> PhysicalAddress {
+        self.level_4_table
+    }
+
+    // Flushes whatever's changed so far, on every CPU that could have it cached
+    pub fn flush(&mut self) {
+        self.flush.flush();
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn table_at(&self, address: PhysicalAddress) -> &mut PageTable {
+        unsafe { &mut *physical_to_virtual(address).as_mut_ptr::<PageTable>() }
+    }
+
+    fn next_table(&self, entry: &PageTableEntry) -> Result<&mut PageTable, MapError> {
+        if !entry.is_present() {
+            return Err(MapError::PageNotMapped);
+        }
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(MapError::ParentEntryHugePage);
+        }
+        Ok(self.table_at(entry.address()))
+    }
+
+    fn next_table_create(
+        &self,
+        entry: &mut PageTableEntry,
+        user_accessible: bool,
+    ) -> Result<&mut PageTable, MapError> {
+        if !entry.is_present() {
+            let frame = allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+            self.table_at(frame.start_address()).zero();
+            entry.set(
+                frame.start_address(),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            );
+        }
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(MapError::ParentEntryHugePage);
+        }
+        // the final entry decides what's actually allowed, the upper levels just must not get in the way
+        if user_accessible && !entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
+            entry.set_flags(entry.flags() | PageTableFlags::USER_ACCESSIBLE);
+        }
+        Ok(self.table_at(entry.address()))
+    }
+
+    fn level_1_entry(&self, page: Page) -> Result<&mut PageTableEntry, MapError> {
+        let address = page.start_address();
+        let level_4_table = self.table_at(self.level_4_table);
+        let level_3_table = self.next_table(&level_4_table[address.get_level_four_pt_index()])?;
+        let level_2_table = self.next_table(&level_3_table[address.get_level_three_pt_index()])?;
+        let level_1_table = self.next_table(&level_2_table[address.get_level_two_pt_index()])?;
+        Ok(&mut level_1_table[address.get_level_one_pt_index()])
+    }
+
+    pub fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysicalFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        debug_assert!(page.size() == PageSize::NORMAL && frame.size() == PageSize::NORMAL);
+        let address = page.start_address();
+        let user_accessible = flags.contains(PageTableFlags::USER_ACCESSIBLE);
+        let level_4_table = self.table_at(self.level_4_table);
+        let level_3_table = self.next_table_create(
+            &mut level_4_table[address.get_level_four_pt_index()],
+            user_accessible,
+        )?;
+        let level_2_table = self.next_table_create(
+            &mut level_3_table[address.get_level_three_pt_index()],
+            user_accessible,
+        )?;
+        let level_1_table = self.next_table_create(
+            &mut level_2_table[address.get_level_two_pt_index()],
+            user_accessible,
+        )?;
+        let entry = &mut level_1_table[address.get_level_one_pt_index()];
+        if !entry.is_unused() {
+            return Err(MapError::PageAlreadyMapped);
+        }
+        entry.set(frame.start_address(), flags | PageTableFlags::PRESENT);
+        Ok(())
+    }
+
+    // Allocates a zeroed frame and maps the page to it
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<PhysicalFrame, MapError> {
+        let frame = allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+        unsafe {
+            physical_to_virtual(frame.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, PageSize::NORMAL as usize);
+        }
+        if let Err(error) = self.map_to(page, frame, flags) {
+            unsafe { crate::memory::frame_allocator::deallocate_frame(frame) };
+            return Err(error);
+        }
+        Ok(frame)
+    }
+
+    // Hands back the frame that was mapped, it's up to the caller whether it gets freed
+    pub fn unmap(&mut self, page: Page) -> Result<PhysicalFrame, MapError> {
+        let entry = self.level_1_entry(page)?;
+        if !entry.is_present() {
+            return Err(MapError::PageNotMapped);
+        }
+        let frame = PhysicalFrame::from_address_aligned(entry.address(), PageSize::NORMAL);
+        entry.clear();
+        self.flush.add(page.start_address());
+        Ok(frame)
+    }
+
+    pub fn update_flags(&mut self, page: Page, flags: PageTableFlags) -> Result<(), MapError> {
+        let entry = self.level_1_entry(page)?;
+        if !entry.is_present() {
+            return Err(MapError::PageNotMapped);
+        }
+        entry.set_flags(flags | PageTableFlags::PRESENT);
+        self.flush.add(page.start_address());
+        Ok(())
+    }
+
+    pub fn flags(&self, page: Page) -> Option<PageTableFlags> {
+        let entry = self.level_1_entry(page).ok()?;
+        entry.is_present().then(|| entry.flags())
+    }
+
+    // Same walk the MMU does, huge pages included -> Intel Manual - Section 4.5.4
+    pub fn translate(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let level_4_table = self.table_at(self.level_4_table);
+        let level_3_entry = &self
+            .next_table(&level_4_table[address.get_level_four_pt_index()])
+            .ok()?[address.get_level_three_pt_index()];
+        if level_3_entry.is_present() && level_3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(PhysicalAddress::new(
+                (level_3_entry.address().0 & !0x3FFF_FFFF) + (address.0 & 0x3FFF_FFFF),
+            ));
+        }
+        let level_2_entry = &self.next_table(level_3_entry).ok()?[address.get_level_two_pt_index()];
+        if level_2_entry.is_present() && level_2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(PhysicalAddress::new(
+                (level_2_entry.address().0 & !0x001F_FFFF) + (address.0 & 0x001F_FFFF),
+            ));
+        }
+        let level_1_entry = &self.next_table(level_2_entry).ok()?[address.get_level_one_pt_index()];
+        if !level_1_entry.is_present() {
+            return None;
+        }
+        Some(PhysicalAddress::new(
+            level_1_entry.address().0 + address.get_page_offset() as u64,
+        ))
+    }
+}
+
+impl Mapper<'static> {
+    // The address space that's loaded in cr3 right now, for changing the kernel half, which is the same in all of them.
+    // Changes get flushed on every CPU.
+    pub unsafe fn active() -> Self {
+        Mapper::new(read_cr3(), FlushScope::Kernel)
+    }
+}
@@ -1,4 +1,6 @@
 pub mod consts;
 pub mod frame;
+pub mod mapper;
 pub mod page;
 pub mod page_table;
+pub mod tlb;
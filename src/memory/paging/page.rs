@@ -1,6 +1,7 @@
 use crate::memory::address::VirtualAddress;
 use crate::memory::paging::consts::PageSize;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct Page {
     size: PageSize,
@@ -15,4 +16,14 @@ impl Page {
             offset: aligned_address,
         }
     }
+
+    #[inline]
+    pub fn start_address(&self) -> VirtualAddress {
+        self.offset
+    }
+
+    #[inline]
+    pub fn size(&self) -> PageSize {
+        self.size
+    }
 }
@@ -1,18 +1,110 @@
 use core::arch::asm;
+use core::ops::{BitAnd, BitOr, BitOrAssign, Index, IndexMut, Not};
 
-use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use crate::memory::address::PhysicalAddress;
 
-const PAGE_TABLE_ENTRIES: usize = 512;
+pub const PAGE_TABLE_ENTRIES: usize = 512;
+
+// bits 12-51 of an entry (and of cr3) hold the physical address, the rest are flags
+const PHYSICAL_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
 
 pub fn read_cr3() -> PhysicalAddress {
     let level_4_table_raw_address: u64;
-    // bits 0-11 are either the
-    let physical_address_mask: u64 = 0x000F_FFFF_FFFF_F000;
+    // bits 0-11 are either the PCID or the PWT/PCD flags depending on CR4.PCIDE, neither of which we care about here
 
     unsafe {
         asm!("mov {}, cr3", out(reg) level_4_table_raw_address, options(nomem, nostack, preserves_flags));
     }
-    PhysicalAddress::new(level_4_table_raw_address & physical_address_mask)
+    PhysicalAddress::new(level_4_table_raw_address & PHYSICAL_ADDRESS_MASK)
+}
+
+// Intel Manual - Section 4.10.4.1: with PCIDs enabled, setting bit 63 keeps the TLB entries tagged with the new PCID
+// instead of flushing them
+const CR3_PCID_MASK: u64 = 0xFFF;
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
+// Switches to another address space. Without PCIDs (see cpu::enable_pcid) pcid is ignored, and every
+// non-global TLB entry gets flushed no matter what flush says.
+// The caller has to guarantee that level_4_table maps the kernel exactly like the current one does.
+pub unsafe fn write_cr3(level_4_table: PhysicalAddress, pcid: u16, flush: bool) {
+    let mut value = level_4_table.0;
+    if crate::cpu::pcid_enabled() {
+        value |= pcid as u64 & CR3_PCID_MASK;
+        if !flush {
+            value |= CR3_NO_FLUSH;
+        }
+    }
+    asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+// Intel Manual - Section 4.5, Table 4-19 and onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PageTableFlags(u64);
+
+impl PageTableFlags {
+    pub const EMPTY: PageTableFlags = PageTableFlags(0);
+    pub const PRESENT: PageTableFlags = PageTableFlags(1 << 0);
+    pub const WRITABLE: PageTableFlags = PageTableFlags(1 << 1);
+    // ring 3 can only touch a page if this is set at every level of the walk
+    pub const USER_ACCESSIBLE: PageTableFlags = PageTableFlags(1 << 2);
+    pub const WRITE_THROUGH: PageTableFlags = PageTableFlags(1 << 3);
+    pub const NO_CACHE: PageTableFlags = PageTableFlags(1 << 4);
+    pub const ACCESSED: PageTableFlags = PageTableFlags(1 << 5);
+    pub const DIRTY: PageTableFlags = PageTableFlags(1 << 6);
+    // only means something in level 2 and 3 entries
+    pub const HUGE_PAGE: PageTableFlags = PageTableFlags(1 << 7);
+    pub const GLOBAL: PageTableFlags = PageTableFlags(1 << 8);
+    // bits 9-11 are ignored by the CPU and ours to use -> Intel Manual - Table 4-19
+    // a read only page that's shared after a fork and gets copied on the first write, see process::cow
+    pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags(1 << 9);
+    // needs EFER.NXE, see cpu::enable_no_execute
+    pub const NO_EXECUTE: PageTableFlags = PageTableFlags(1 << 63);
+
+    #[inline]
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn from_bits_truncate(bits: u64) -> Self {
+        PageTableFlags(bits & !PHYSICAL_ADDRESS_MASK)
+    }
+
+    #[inline]
+    pub const fn contains(self, other: PageTableFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageTableFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        PageTableFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for PageTableFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for PageTableFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        PageTableFlags(self.0 & rhs.0)
+    }
+}
+
+impl Not for PageTableFlags {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        PageTableFlags(!self.0 & !PHYSICAL_ADDRESS_MASK)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -20,9 +112,51 @@ pub fn read_cr3() -> PhysicalAddress {
 pub struct PageTableEntry(u64);
 
 impl PageTableEntry {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         PageTableEntry(0)
     }
+
+    #[inline]
+    pub fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        self.flags().contains(PageTableFlags::PRESENT)
+    }
+
+    #[inline]
+    pub fn flags(&self) -> PageTableFlags {
+        PageTableFlags::from_bits_truncate(self.0)
+    }
+
+    #[inline]
+    pub fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.0 & PHYSICAL_ADDRESS_MASK)
+    }
+
+    #[inline]
+    pub fn set(&mut self, address: PhysicalAddress, flags: PageTableFlags) {
+        debug_assert!(address.0 & !PHYSICAL_ADDRESS_MASK == 0);
+        self.0 = address.0 | flags.bits();
+    }
+
+    #[inline]
+    pub fn set_flags(&mut self, flags: PageTableFlags) {
+        self.0 = (self.0 & PHYSICAL_ADDRESS_MASK) | flags.bits();
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+impl Default for PageTableEntry {
+    fn default() -> Self {
+        PageTableEntry::new()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -30,3 +164,45 @@ impl PageTableEntry {
 pub struct PageTable {
     table: [PageTableEntry; PAGE_TABLE_ENTRIES],
 }
+
+impl PageTable {
+    pub const fn new() -> Self {
+        PageTable {
+            table: [PageTableEntry::new(); PAGE_TABLE_ENTRIES],
+        }
+    }
+
+    pub fn zero(&mut self) {
+        for entry in self.table.iter_mut() {
+            entry.clear();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PageTableEntry> {
+        self.table.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PageTableEntry> {
+        self.table.iter_mut()
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        PageTable::new()
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    fn index(&self, index: usize) -> &PageTableEntry {
+        &self.table[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    fn index_mut(&mut self, index: usize) -> &mut PageTableEntry {
+        &mut self.table[index]
+    }
+}
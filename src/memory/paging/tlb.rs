@@ -0,0 +1,286 @@
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::cpu::{read_cr4, write_cr4, CR4_PAGE_GLOBAL_ENABLE};
+use crate::interrupts::drivers::xapic;
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::smp::{self, current_cpu, percpu, MAX_CPUS};
+use crate::sync::SpinLock;
+
+// Drops whatever translation the TLB has cached for this address, on this CPU only. Global translations go too,
+// but with PCIDs only the current PCID's non-global ones do -> Intel Manual - Section 4.10.4.1
+#[inline]
+pub fn flush(address: VirtualAddress) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) address.0, options(nostack, preserves_flags));
+    }
+}
+
+// Writing cr3 back to itself drops every non-global translation, only for the current PCID if PCIDs are on
+#[inline]
+pub fn flush_all() {
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+// Changing CR4.PGE drops everything, global translations and every PCID's included, so it gets flipped and put back
+pub fn flush_everything() {
+    let cr4 = read_cr4();
+    unsafe {
+        write_cr4(cr4 ^ CR4_PAGE_GLOBAL_ENABLE);
+        write_cr4(cr4);
+    }
+}
+
+// Past this many pages it's cheaper to throw away the whole TLB and let it fill up again than to go one page at a time.
+// Linux settled on the same number.
+const FULL_FLUSH_PAGES: usize = 33;
+// How many separate runs of pages a batch keeps track of, anything past that gets a full flush too
+const MAX_RANGES: usize = 8;
+
+// A bit for each CPU, by number
+const _: () = assert!(MAX_CPUS < 64);
+const ALL_CPUS: u64 = u64::MAX;
+
+fn cpu_bit(cpu: usize) -> u64 {
+    1 << cpu
+}
+
+// Which CPUs might have translations of one address space cached. Other CPUs have to be told when one of its mappings
+// goes away or loses permissions, the ones that have it loaded right away, see shoot_down. The others only get to
+// hear about it once they load it again, since all they can have left is what's tagged with its PCID.
+#[derive(Debug)]
+pub struct AddressSpaceTlb {
+    loaded: AtomicU64,
+    // have to flush its PCID the next time they load it
+    stale: AtomicU64,
+}
+
+impl AddressSpaceTlb {
+    // A PCID that's been handed out before can still have the last owner's translations around, so an address space
+    // that gets one starts out stale everywhere
+    pub const fn new(stale: bool) -> Self {
+        AddressSpaceTlb {
+            loaded: AtomicU64::new(0),
+            stale: AtomicU64::new(if stale { ALL_CPUS } else { 0 }),
+        }
+    }
+
+    // For the CPU that's about to write it to cr3. True if it has to flush its PCID while it's at it.
+    // Counts as loaded first, so a change made after the stale bit is checked can't miss us, see shoot_down.
+    pub fn load(&self) -> bool {
+        let cpu = cpu_bit(current_cpu());
+        self.loaded.fetch_or(cpu, Ordering::SeqCst);
+        self.stale.fetch_and(!cpu, Ordering::SeqCst) & cpu != 0
+    }
+
+    // For the CPU that's just written it to cr3, after load. A shootdown that came in between saw us as loaded and had
+    // us flush, but against whatever cr3 had before, so anything marked stale since load has to be flushed again now
+    // that it's actually in there. True if it does.
+    pub fn finish_load(&self) -> bool {
+        let cpu = cpu_bit(current_cpu());
+        self.stale.fetch_and(!cpu, Ordering::SeqCst) & cpu != 0
+    }
+
+    // For the CPU that's just written something else to cr3
+    pub fn unload(&self) {
+        self.loaded
+            .fetch_and(!cpu_bit(current_cpu()), Ordering::SeqCst);
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst) & cpu_bit(current_cpu()) != 0
+    }
+
+    pub fn is_loaded_anywhere(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst) != 0
+    }
+}
+
+// What a batch of changes has to be flushed from
+#[derive(Debug, Clone, Copy)]
+pub enum FlushScope<'a> {
+    // the kernel half, which every address space shares and which is mapped global, see memory/mod.rs
+    Kernel,
+    AddressSpace(&'a AddressSpaceTlb),
+}
+
+// Pages whose translations have changed, waiting to be flushed everywhere they could be cached. Runs of pages next to
+// each other are kept together, and once there are too many to be worth it the whole TLB gets flushed instead.
+// Whatever was unmapped can only be freed once the batch has been flushed, another CPU could still be using it.
+pub struct FlushBatch<'a> {
+    scope: FlushScope<'a>,
+    // start and end address of each run
+    ranges: [(u64, u64); MAX_RANGES],
+    len: usize,
+    pages: usize,
+    everything: bool,
+}
+
+impl<'a> FlushBatch<'a> {
+    pub fn new(scope: FlushScope<'a>) -> Self {
+        FlushBatch {
+            scope,
+            ranges: [(0, 0); MAX_RANGES],
+            len: 0,
+            pages: 0,
+            everything: false,
+        }
+    }
+
+    pub fn add(&mut self, address: VirtualAddress) {
+        let page_size = PageSize::NORMAL as u64;
+        let start = address.0 & !(page_size - 1);
+        self.pages += 1;
+        if self.pages > FULL_FLUSH_PAGES {
+            self.everything = true;
+        }
+        if self.everything {
+            return;
+        }
+        match self.ranges[..self.len].last_mut() {
+            Some(last) if last.1 == start => last.1 += page_size,
+            Some(last) if last.0 == start + page_size => last.0 = start,
+            _ if self.len == MAX_RANGES => self.everything = true,
+            _ => {
+                self.ranges[self.len] = (start, start + page_size);
+                self.len += 1;
+            }
+        }
+    }
+
+    // For when the page tables themselves change, not just what's in them
+    pub fn add_everything(&mut self) {
+        self.pages += 1;
+        self.everything = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages == 0
+    }
+
+    // Flushes everything added so far, on this CPU and wherever else it's needed, and starts over
+    pub fn flush(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        shoot_down(self);
+        self.len = 0;
+        self.pages = 0;
+        self.everything = false;
+    }
+
+    fn is_kernel(&self) -> bool {
+        matches!(self.scope, FlushScope::Kernel)
+    }
+}
+
+// What every CPU that gets a batch does with it, the one it came from included
+fn flush_local(kernel: bool, everything: bool, ranges: &[(u64, u64)]) {
+    match (everything, kernel) {
+        (true, true) => flush_everything(),
+        (true, false) => flush_all(),
+        (false, _) => {
+            for &(start, end) in ranges {
+                for page in (start..end).step_by(PageSize::NORMAL as usize) {
+                    flush(VirtualAddress::new(page));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FlushBatch<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// The batch being shot down, the CPUs that haven't flushed it yet are in PENDING. Only the CPU holding SHOOTDOWN writes
+// it, and it doesn't let go before everyone's done.
+struct Request {
+    kernel: bool,
+    ranges: [(u64, u64); MAX_RANGES],
+    len: usize,
+    everything: bool,
+}
+
+struct RequestCell(UnsafeCell<Request>);
+
+unsafe impl Sync for RequestCell {}
+
+static SHOOTDOWN: SpinLock<()> = SpinLock::new(());
+static REQUEST: RequestCell = RequestCell(UnsafeCell::new(Request {
+    kernel: false,
+    ranges: [(0, 0); MAX_RANGES],
+    len: 0,
+    everything: false,
+}));
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+// The other CPUs are sent an NMI and flush from the NMI handler, see handle_shootdown. An ordinary interrupt would
+// deadlock as soon as one of them was spinning with interrupts off on a lock we're holding, which page table changes
+// are usually made under. We wait for all of them before going on, since the caller is about to free what it unmapped.
+fn shoot_down(batch: &FlushBatch) {
+    // interrupts stay off until we're done, so we can't end up on another CPU halfway through
+    let _shootdown = SHOOTDOWN.lock();
+    let me = cpu_bit(current_cpu());
+    let online = (1 << smp::cpu_count()) - 1;
+    let (targets, local) = match batch.scope {
+        FlushScope::Kernel => (online & !me, true),
+        FlushScope::AddressSpace(tlb) => {
+            // Marked stale before we look at who has it loaded: a CPU that loads it after that is going to see it,
+            // see AddressSpaceTlb::load. CPUs that have it loaded right now will flush twice, which does no harm.
+            tlb.stale.fetch_or(ALL_CPUS, Ordering::SeqCst);
+            let loaded = tlb.loaded.load(Ordering::SeqCst);
+            (loaded & online & !me, loaded & me != 0)
+        }
+    };
+    let ranges = &batch.ranges[..batch.len];
+    if local {
+        flush_local(batch.is_kernel(), batch.everything, ranges);
+    }
+    if targets == 0 {
+        return;
+    }
+    unsafe {
+        let request = &mut *REQUEST.0.get();
+        request.kernel = batch.is_kernel();
+        request.ranges = batch.ranges;
+        request.len = batch.len;
+        request.everything = batch.everything;
+    }
+    PENDING.store(targets, Ordering::Release);
+    for cpu in (0..MAX_CPUS).filter(|&cpu| targets & cpu_bit(cpu) != 0) {
+        xapic::send_nmi(percpu::apic_id_of(cpu));
+    }
+    while PENDING.load(Ordering::Acquire) != 0 {
+        spin_loop();
+    }
+}
+
+// Called from the NMI handler, with the kernel's gs. Returns false if there wasn't a shootdown waiting for this CPU,
+// in which case the NMI came from somewhere else.
+pub fn handle_shootdown() -> bool {
+    let me = cpu_bit(current_cpu());
+    if PENDING.load(Ordering::Acquire) & me == 0 {
+        return false;
+    }
+    let request = unsafe { &*REQUEST.0.get() };
+    flush_local(
+        request.kernel,
+        request.everything,
+        &request.ranges[..request.len],
+    );
+    PENDING.fetch_and(!me, Ordering::Release);
+    true
+}
@@ -0,0 +1,20 @@
+// See https://pci-ids.ucw.cz/ and https://wiki.osdev.org/PCI#Class_Codes
+
+// Vendor and device IDs
+pub const BOCHS_VENDOR_ID: u16 = 0x1234;
+pub const BOCHS_VGA_DEVICE_ID: u16 = 0x1111;
+
+// Class codes
+pub const MASS_STORAGE_CLASS: u8 = 0x01;
+pub const DISPLAY_CLASS: u8 = 0x03;
+pub const BRIDGE_CLASS: u8 = 0x06;
+
+// Mass storage subclasses
+pub const IDE_SUBCLASS: u8 = 0x01;
+pub const SATA_SUBCLASS: u8 = 0x06;
+
+// SATA programming interfaces
+pub const AHCI_PROG_IF: u8 = 0x01;
+
+// Display subclasses
+pub const VGA_SUBCLASS: u8 = 0x00;
@@ -0,0 +1,303 @@
+use crate::memory::address::PhysicalAddress;
+use crate::port::Port;
+
+pub mod consts;
+
+// Configuration space access mechanism #1 -> https://wiki.osdev.org/PCI#Configuration_Space_Access_Mechanism_.231
+const CONFIG_ADDRESS_PORT: u16 = 0xCF8;
+const CONFIG_DATA_PORT: u16 = 0xCFC;
+
+// Offsets into the (type 0) configuration space header
+const VENDOR_ID_OFFSET: u8 = 0x00;
+const COMMAND_OFFSET: u8 = 0x04;
+const STATUS_OFFSET: u8 = 0x06;
+const CLASS_OFFSET: u8 = 0x08;
+const HEADER_TYPE_OFFSET: u8 = 0x0C;
+const BAR_0_OFFSET: u8 = 0x10;
+const CAPABILITIES_POINTER_OFFSET: u8 = 0x34;
+const INTERRUPT_LINE_OFFSET: u8 = 0x3C;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+// masks the legacy INTx pin, for devices that interrupt through MSI instead
+const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+// PCI Local Bus Specification rev 3.0 - Section 6.8.1 (MSI Capability Structure)
+const MSI_CAPABILITY_ID: u8 = 0x05;
+const MSI_CONTROL_OFFSET: u8 = 0x02;
+const MSI_ADDRESS_OFFSET: u8 = 0x04;
+const MSI_ENABLE: u16 = 1 << 0;
+// the multiple message enable field, which we leave at one message
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+const MSI_64_BIT: u16 = 1 << 7;
+// Messages are writes to the local APIC's address range -> Intel Manual - Section 10.11.1
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+const MSI_DESTINATION_SHIFT: u32 = 12;
+
+const MULTI_FUNCTION_DEVICE: u8 = 0x80;
+const NO_DEVICE: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        debug_assert!(device < 32 && function < 8);
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xFC) as u32
+    }
+
+    pub fn read_config_u32(&self, offset: u8) -> u32 {
+        unsafe {
+            Port::new(CONFIG_ADDRESS_PORT).write_u32(self.config_address(offset));
+            Port::new(CONFIG_DATA_PORT).read_u32()
+        }
+    }
+
+    pub fn read_config_u16(&self, offset: u8) -> u16 {
+        (self.read_config_u32(offset) >> ((offset & 0x02) * 8)) as u16
+    }
+
+    pub fn read_config_u8(&self, offset: u8) -> u8 {
+        (self.read_config_u32(offset) >> ((offset & 0x03) * 8)) as u8
+    }
+
+    pub unsafe fn write_config_u32(&self, offset: u8, value: u32) {
+        Port::new(CONFIG_ADDRESS_PORT).write_u32(self.config_address(offset));
+        Port::new(CONFIG_DATA_PORT).write_u32(value);
+    }
+
+    // Writes only the word at offset, through the half of the data port it's in. Writing the whole dword back would
+    // also write the register next to it, and the status register's bits are cleared by writing 1s to them.
+    pub unsafe fn write_config_u16(&self, offset: u8, value: u16) {
+        Port::new(CONFIG_ADDRESS_PORT).write_u32(self.config_address(offset));
+        Port::new(CONFIG_DATA_PORT + (offset & 0x02) as u16).write_u16(value);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: PhysicalAddress,
+        size: u64,
+        prefetchable: bool,
+    },
+    Io {
+        port: u16,
+        size: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<PciDevice> {
+        let id = address.read_config_u32(VENDOR_ID_OFFSET);
+        let vendor_id = id as u16;
+        if vendor_id == NO_DEVICE {
+            return None;
+        }
+        let class = address.read_config_u32(CLASS_OFFSET);
+        Some(PciDevice {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            header_type: address.read_config_u8(HEADER_TYPE_OFFSET + 2),
+        })
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        self.address.read_config_u8(INTERRUPT_LINE_OFFSET)
+    }
+
+    // Sizing a BAR means writing all 1s to it and seeing which bits stick -> https://wiki.osdev.org/PCI#Address_and_size_of_the_BAR
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        // only general (type 0) devices have 6 BARs
+        debug_assert!(index < 6);
+        let offset = BAR_0_OFFSET + (index as u8) * 4;
+        let value = self.address.read_config_u32(offset);
+        if value & 0x01 == 0x01 {
+            let size = unsafe { self.probe_bar_size(offset, value) } & 0xFFFF_FFFC;
+            if size == 0 {
+                return None;
+            }
+            return Some(Bar::Io {
+                port: (value & 0xFFFC) as u16,
+                size: !size + 1,
+            });
+        }
+        let prefetchable = value & 0x08 == 0x08;
+        match (value >> 1) & 0x03 {
+            // 32 bit
+            0x00 => {
+                let size = unsafe { self.probe_bar_size(offset, value) } & 0xFFFF_FFF0;
+                if size == 0 {
+                    return None;
+                }
+                Some(Bar::Memory {
+                    address: PhysicalAddress::new((value & 0xFFFF_FFF0) as u64),
+                    size: (!size + 1) as u64,
+                    prefetchable,
+                })
+            }
+            // 64 bit, the next BAR holds the upper half
+            0x02 => {
+                let high = self.address.read_config_u32(offset + 4);
+                let size_low = unsafe { self.probe_bar_size(offset, value) } & 0xFFFF_FFF0;
+                let size_high = unsafe { self.probe_bar_size(offset + 4, high) };
+                let size = (size_high as u64) << 32 | size_low as u64;
+                if size == 0 {
+                    return None;
+                }
+                Some(Bar::Memory {
+                    address: PhysicalAddress::new(
+                        (high as u64) << 32 | (value & 0xFFFF_FFF0) as u64,
+                    ),
+                    size: !size + 1,
+                    prefetchable,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    unsafe fn probe_bar_size(&self, offset: u8, original: u32) -> u32 {
+        // decoding has to be off while the BAR holds garbage
+        let command = self.address.read_config_u16(COMMAND_OFFSET);
+        self.address.write_config_u16(
+            COMMAND_OFFSET,
+            command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+        );
+        self.address.write_config_u32(offset, 0xFFFF_FFFF);
+        let size = self.address.read_config_u32(offset);
+        self.address.write_config_u32(offset, original);
+        self.address.write_config_u16(COMMAND_OFFSET, command);
+        size
+    }
+
+    fn set_command_bits(&self, bits: u16) {
+        let command = self.address.read_config_u16(COMMAND_OFFSET);
+        unsafe {
+            self.address
+                .write_config_u16(COMMAND_OFFSET, command | bits)
+        };
+    }
+
+    pub fn enable_io_space(&self) {
+        self.set_command_bits(COMMAND_IO_SPACE);
+    }
+
+    pub fn enable_memory_space(&self) {
+        self.set_command_bits(COMMAND_MEMORY_SPACE);
+    }
+
+    pub fn enable_bus_mastering(&self) {
+        self.set_command_bits(COMMAND_BUS_MASTER);
+    }
+
+    // Walks the capabilities list for the one with id, returns its offset in the configuration space
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        if self.address.read_config_u16(STATUS_OFFSET) & STATUS_CAPABILITIES_LIST == 0 {
+            return None;
+        }
+        let mut offset = self.address.read_config_u8(CAPABILITIES_POINTER_OFFSET) & 0xFC;
+        // a broken list could go round in circles, there's only room for 48 capabilities anyway
+        for _ in 0..48 {
+            if offset == 0 {
+                return None;
+            }
+            if self.address.read_config_u8(offset) == id {
+                return Some(offset);
+            }
+            offset = self.address.read_config_u8(offset + 1) & 0xFC;
+        }
+        None
+    }
+
+    // Has the device send vector to the local APIC apic_id, as a fixed, edge triggered interrupt, instead of raising
+    // its interrupt pin. False if it can't do MSI.
+    pub fn enable_msi(&self, apic_id: u8, vector: u8) -> bool {
+        let Some(msi) = self.capability(MSI_CAPABILITY_ID) else {
+            return false;
+        };
+        let control = self.address.read_config_u16(msi + MSI_CONTROL_OFFSET);
+        // the data register comes after the upper half of the address, if there is one
+        let data_offset = if control & MSI_64_BIT != 0 {
+            0x0C
+        } else {
+            0x08
+        };
+        unsafe {
+            let address = MSI_ADDRESS_BASE | (apic_id as u32) << MSI_DESTINATION_SHIFT;
+            self.address
+                .write_config_u32(msi + MSI_ADDRESS_OFFSET, address);
+            if control & MSI_64_BIT != 0 {
+                self.address
+                    .write_config_u32(msi + MSI_ADDRESS_OFFSET + 4, 0);
+            }
+            self.address
+                .write_config_u16(msi + data_offset, vector as u16);
+            self.address.write_config_u16(
+                msi + MSI_CONTROL_OFFSET,
+                (control & !MSI_MULTIPLE_MESSAGE_ENABLE) | MSI_ENABLE,
+            );
+        }
+        self.set_command_bits(COMMAND_INTERRUPT_DISABLE);
+        true
+    }
+}
+
+// Brute force scan of every bus/device/function. Slow-ish, but we only ever do this during boot.
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255_u8).flat_map(|bus| {
+        (0..32_u8).flat_map(move |device| {
+            (0..8_u8).filter_map(move |function| {
+                if function > 0 {
+                    // functions 1-7 only exist on multi-function devices
+                    let first = PciDevice::probe(PciAddress::new(bus, device, 0))?;
+                    if first.header_type & MULTI_FUNCTION_DEVICE == 0 {
+                        return None;
+                    }
+                }
+                PciDevice::probe(PciAddress::new(bus, device, function))
+            })
+        })
+    })
+}
+
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    devices().find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+pub fn find_class(class: u8, subclass: u8) -> Option<PciDevice> {
+    devices().find(|device| device.class == class && device.subclass == subclass)
+}
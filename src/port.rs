@@ -0,0 +1,50 @@
+use core::arch::asm;
+
+// Intel Manual - Section 19.2 (I/O Port Addressing)
+// Every access is unsafe: writing the wrong value to the wrong port can do just about anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Port(u16);
+
+impl Port {
+    #[inline]
+    pub const fn new(port: u16) -> Self {
+        Port(port)
+    }
+
+    #[inline]
+    pub unsafe fn read_u8(&self) -> u8 {
+        let value: u8;
+        asm!("in al, dx", out("al") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
+        value
+    }
+
+    #[inline]
+    pub unsafe fn write_u8(&self, value: u8) {
+        asm!("out dx, al", in("dx") self.0, in("al") value, options(nomem, nostack, preserves_flags));
+    }
+
+    #[inline]
+    pub unsafe fn read_u16(&self) -> u16 {
+        let value: u16;
+        asm!("in ax, dx", out("ax") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
+        value
+    }
+
+    #[inline]
+    pub unsafe fn write_u16(&self, value: u16) {
+        asm!("out dx, ax", in("dx") self.0, in("ax") value, options(nomem, nostack, preserves_flags));
+    }
+
+    #[inline]
+    pub unsafe fn read_u32(&self) -> u32 {
+        let value: u32;
+        asm!("in eax, dx", out("eax") value, in("dx") self.0, options(nomem, nostack, preserves_flags));
+        value
+    }
+
+    #[inline]
+    pub unsafe fn write_u32(&self, value: u32) {
+        asm!("out dx, eax", in("dx") self.0, in("eax") value, options(nomem, nostack, preserves_flags));
+    }
+}
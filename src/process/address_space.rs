@@ -0,0 +1,397 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use crate::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::paging::mapper::{MapError, Mapper};
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::{
+    read_cr3, write_cr3, PageTable, PageTableFlags, PAGE_TABLE_ENTRIES,
+};
+use crate::memory::paging::tlb::{self, AddressSpaceTlb, FlushBatch, FlushScope};
+use crate::memory::{is_user_address, physical_to_virtual, USER_SPACE_END, USER_SPACE_START};
+use crate::percpu;
+use crate::process::cow::{self, release_frame};
+use crate::process::vma::{Vma, VmaKind, VmaTree};
+
+// Each level 4 entry covers 512GiB. Everything outside of these belongs to the kernel and is the same in every
+// address space, see memory/mod.rs.
+const USER_LEVEL_4_ENTRIES: Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+// PCID 0 is the kernel's. Once the 12 bits run out, the rest share the last one and get flushed whenever they're loaded.
+const KERNEL_PCID: u16 = 0;
+const SHARED_PCID: u16 = 0xFFF;
+
+struct Pcids {
+    next: u16,
+    free: Vec<u16>,
+}
+
+static PCIDS: Mutex<Pcids> = Mutex::new(Pcids {
+    next: KERNEL_PCID + 1,
+    free: Vec::new(),
+});
+
+percpu! {
+    // The address space each CPU has in cr3, null until it first loads one
+    static LOADED: AtomicPtr<AddressSpace> = AtomicPtr::new(core::ptr::null_mut());
+}
+
+fn allocate_pcid() -> Option<u16> {
+    without_interrupts(|| {
+        let mut pcids = PCIDS.lock();
+        pcids.free.pop().or_else(|| {
+            (pcids.next < SHARED_PCID).then(|| {
+                pcids.next += 1;
+                pcids.next - 1
+            })
+        })
+    })
+}
+
+// A level 4 page table and everything under it in the user half, along with the areas of the user half that are
+// allowed to be mapped. Pages in those areas only get mapped once they're touched, see populate.
+#[derive(Debug)]
+pub struct AddressSpace {
+    level_4_table: PhysicalAddress,
+    // The page fault handler looks at this, so it's only locked with interrupts off
+    areas: Mutex<VmaTree>,
+    // None if it has to make do with SHARED_PCID
+    pcid: Option<u16>,
+    // which CPUs might have some of it in their TLBs
+    tlb: AddressSpaceTlb,
+    // the kernel's own address space is never freed
+    owned: bool,
+}
+
+impl AddressSpace {
+    // The address space the bootloader left us in
+    pub fn kernel() -> Self {
+        AddressSpace {
+            level_4_table: read_cr3(),
+            areas: Mutex::new(VmaTree::new()),
+            pcid: Some(KERNEL_PCID),
+            tlb: AddressSpaceTlb::new(false),
+            owned: false,
+        }
+    }
+
+    // Shares the kernel half with kernel, the user half starts out empty
+    pub fn new(kernel: &AddressSpace) -> Result<Self, MapError> {
+        let frame = allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+        let level_4_table = table_at(frame.start_address());
+        let kernel_table = table_at(kernel.level_4_table);
+        level_4_table.zero();
+        for index in (0..PAGE_TABLE_ENTRIES).filter(|index| !USER_LEVEL_4_ENTRIES.contains(index)) {
+            level_4_table[index] = kernel_table[index];
+        }
+        Ok(AddressSpace {
+            level_4_table: frame.start_address(),
+            areas: Mutex::new(VmaTree::new()),
+            pcid: allocate_pcid(),
+            tlb: AddressSpaceTlb::new(true),
+            owned: true,
+        })
+    }
+
+    pub fn level_4_table_address(&self) -> PhysicalAddress {
+        self.level_4_table
+    }
+
+    // Whatever gets unmapped or changed through this is flushed from every CPU that might have it cached once the
+    // mapper is flushed or dropped
+    pub fn mapper(&self) -> Mapper<'_> {
+        unsafe { Mapper::new(self.level_4_table, FlushScope::AddressSpace(&self.tlb)) }
+    }
+
+    // Copies data to address through the physical memory mapping, so it works whether or not we're loaded.
+    // Pages that haven't been touched yet get populated, but every one of them has to be in an area, otherwise nothing
+    // is written past the first one that isn't.
+    pub fn write(&self, address: VirtualAddress, data: &[u8]) -> Result<(), MapError> {
+        let mapper = self.mapper();
+        let page_size = PageSize::NORMAL as u64;
+        let mut written = 0;
+        while written < data.len() {
+            let target = VirtualAddress::new(address.0 + written as u64);
+            if mapper.translate(target).is_none() && !self.populate(target) {
+                return Err(MapError::PageNotMapped);
+            }
+            let physical = mapper.translate(target).ok_or(MapError::PageNotMapped)?;
+            let chunk =
+                (page_size - target.0 % page_size).min((data.len() - written) as u64) as usize;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    physical_to_virtual(physical).as_mut_ptr::<u8>(),
+                    chunk,
+                );
+            }
+            written += chunk;
+        }
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        read_cr3() == self.level_4_table
+    }
+
+    fn with_areas<R>(&self, f: impl FnOnce(&mut VmaTree) -> R) -> R {
+        without_interrupts(|| f(&mut self.areas.lock()))
+    }
+
+    pub fn areas(&self) -> Vec<Vma> {
+        self.with_areas(|areas| areas.iter().copied().collect())
+    }
+
+    pub fn find_area(&self, address: VirtualAddress) -> Option<Vma> {
+        self.with_areas(|areas| areas.find(address).copied())
+    }
+
+    pub fn vma_tree(&self) -> VmaTree {
+        self.with_areas(|areas| areas.clone())
+    }
+
+    // For fork, which shares whatever's mapped in the areas, see cow::share_areas
+    pub(super) fn set_vma_tree(&self, tree: VmaTree) {
+        self.with_areas(|areas| *areas = tree);
+    }
+
+    // Makes size bytes (rounded up to whole pages) at start, which has to be page aligned, part of a new area.
+    // Nothing gets mapped until it's touched.
+    pub fn add_area(
+        &self,
+        start: VirtualAddress,
+        size: u64,
+        flags: PageTableFlags,
+        kind: VmaKind,
+    ) -> Result<Vma, MapError> {
+        let end = user_range_end(start, size).ok_or(MapError::PageNotMapped)?;
+        let vma = Vma {
+            start,
+            end: VirtualAddress::new(end),
+            flags,
+            kind,
+        };
+        self.with_areas(|areas| areas.insert(vma))?;
+        Ok(vma)
+    }
+
+    // Unmaps start..end, which has to be page aligned, and frees whatever was mapped there. Any area that only partly
+    // overlaps it gets cut down to what's left. Anything in the range that isn't in an area is left alone.
+    pub fn remove_areas(&self, start: VirtualAddress, end: VirtualAddress) {
+        let mut mapper = self.mapper();
+        let frames: Vec<PhysicalFrame> = self.with_areas(|areas| {
+            areas
+                .remove(start.0, end.0)
+                .iter()
+                .flat_map(|vma| vma.pages())
+                .filter_map(|page| mapper.unmap(page).ok())
+                .collect()
+        });
+        // another thread of ours could still be using them on another CPU until then
+        mapper.flush();
+        for frame in frames {
+            unsafe { release_frame(frame) };
+        }
+    }
+
+    // Changes what start..end, which has to be page aligned, is mapped with, pages that are already there included.
+    // Fails without changing anything if part of the range isn't in an area.
+    pub fn protect(
+        &self,
+        start: VirtualAddress,
+        end: VirtualAddress,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        let mut mapper = self.mapper();
+        self.with_areas(|areas| {
+            if !areas.is_covered(start.0, end.0) {
+                return Err(MapError::PageNotMapped);
+            }
+            for vma in areas.protect(start.0, end.0, flags) {
+                for page in vma.pages() {
+                    let Some(frame) = mapper.translate(page.start_address()) else {
+                        continue;
+                    };
+                    // without USER_ACCESSIBLE (PROT_NONE) the page keeps its contents but ring 3 can't touch it
+                    mapper.update_flags(page, cow::page_flags(frame, flags))?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    // Maps a zeroed page for address if it's in an area and hasn't been touched yet. Called from the page fault
+    // handler, and before the kernel touches user memory on a process' behalf.
+    pub fn populate(&self, address: VirtualAddress) -> bool {
+        let page = Page::from_address_aligned(
+            address.align_down(PageSize::NORMAL as u64),
+            PageSize::NORMAL,
+        );
+        self.with_areas(|areas| {
+            let Some(vma) = areas.find(address) else {
+                return false;
+            };
+            // another thread could have faulted on the same page on another CPU and got there first
+            vma.flags.contains(PageTableFlags::USER_ACCESSIBLE)
+                && matches!(
+                    self.mapper().map(page, vma.flags),
+                    Ok(_) | Err(MapError::PageAlreadyMapped)
+                )
+        })
+    }
+
+    // Where the heap starts, brk can move its end up and down from there. Called once the executable is loaded.
+    pub fn set_heap_start(&self, start: VirtualAddress) {
+        self.with_areas(|areas| areas.set_heap(start.0, start.0));
+    }
+
+    pub fn brk(&self) -> VirtualAddress {
+        VirtualAddress::new(self.with_areas(|areas| areas.brk()))
+    }
+
+    // Moves the end of the heap to brk, mapping or unmapping whole pages as it crosses them.
+    // Returns where the end of the heap is afterwards, which is where it was if brk was out of bounds.
+    pub fn set_brk(&self, brk: VirtualAddress) -> VirtualAddress {
+        let page_size = PageSize::NORMAL as u64;
+        let (heap_start, old_brk) = self.with_areas(|areas| (areas.heap_start(), areas.brk()));
+        if heap_start == 0 || brk.0 < heap_start || brk.0 > USER_SPACE_END {
+            return VirtualAddress::new(old_brk);
+        }
+        let old_end = old_brk.div_ceil(page_size) * page_size;
+        let new_end = brk.0.div_ceil(page_size) * page_size;
+        if new_end > old_end {
+            let grown = self.add_area(
+                VirtualAddress::new(old_end),
+                new_end - old_end,
+                PageTableFlags::USER_ACCESSIBLE
+                    | PageTableFlags::WRITABLE
+                    | PageTableFlags::NO_EXECUTE,
+                VmaKind::Heap,
+            );
+            if grown.is_err() {
+                return VirtualAddress::new(old_brk);
+            }
+        } else if new_end < old_end {
+            self.remove_areas(VirtualAddress::new(new_end), VirtualAddress::new(old_end));
+        }
+        self.with_areas(|areas| areas.set_heap(heap_start, brk.0));
+        brk
+    }
+
+    // The lowest free size bytes at or above from that end before limit
+    pub fn find_free_area(
+        &self,
+        size: u64,
+        from: VirtualAddress,
+        limit: VirtualAddress,
+    ) -> Option<VirtualAddress> {
+        self.with_areas(|areas| areas.find_free(size, from.0, limit.0))
+            .map(VirtualAddress::new)
+    }
+
+    // Unmaps and frees everything in the user half and forgets all the areas, for exec and exit
+    pub fn clear_user_half(&self) {
+        self.with_areas(VmaTree::clear);
+        let level_4_table = table_at(self.level_4_table);
+        let mut tables = Vec::new();
+        for index in USER_LEVEL_4_ENTRIES {
+            let entry = &mut level_4_table[index];
+            if entry.is_present() {
+                tables.push(entry.address());
+                entry.clear();
+            }
+        }
+        // the page tables are cached too, so they can only go once nobody can be walking them anymore
+        let mut flush = FlushBatch::new(FlushScope::AddressSpace(&self.tlb));
+        flush.add_everything();
+        flush.flush();
+        for table in tables {
+            unsafe { free_table(table, 3) };
+        }
+    }
+
+    // The caller has to make sure the address space outlives its time in cr3
+    pub unsafe fn load(&self) {
+        let flush = self.tlb.load() || self.pcid.is_none();
+        write_cr3(self.level_4_table, self.pcid.unwrap_or(SHARED_PCID), flush);
+        if self.tlb.finish_load() {
+            tlb::flush_all();
+        }
+        let this = self as *const AddressSpace as *mut AddressSpace;
+        let previous = LOADED.get().swap(this, Ordering::Relaxed);
+        if !previous.is_null() && previous != this {
+            // it can't have been dropped, it was still loaded
+            (*previous).tlb.unload();
+        }
+    }
+}
+
+// Where size bytes (rounded up to whole pages) starting at start end, if all of it is in the user half and start is
+// page aligned
+pub fn user_range_end(start: VirtualAddress, size: u64) -> Option<u64> {
+    let page_size = PageSize::NORMAL as u64;
+    let end = start
+        .0
+        .checked_add(size.checked_next_multiple_of(page_size)?)?;
+    (start.0.is_multiple_of(page_size)
+        && is_user_address(start)
+        && end != start.0
+        && end <= USER_SPACE_END)
+        .then_some(end)
+}
+
+#[allow(clippy::mut_from_ref)]
+fn table_at(address: PhysicalAddress) -> &'static mut PageTable {
+    unsafe { &mut *physical_to_virtual(address).as_mut_ptr::<PageTable>() }
+}
+
+// Frees a page table and everything mapped under it. level is the table's own level, 1 for the last one.
+// Frames fork shared with another address space stay around for the other one.
+unsafe fn free_table(address: PhysicalAddress, level: usize) {
+    for entry in table_at(address).iter().filter(|entry| entry.is_present()) {
+        if level > 1 {
+            debug_assert!(!entry.flags().contains(PageTableFlags::HUGE_PAGE));
+            free_table(entry.address(), level - 1);
+        } else {
+            release_frame(PhysicalFrame::from_address_aligned(
+                entry.address(),
+                PageSize::NORMAL,
+            ));
+        }
+    }
+    deallocate_frame(PhysicalFrame::from_address_aligned(
+        address,
+        PageSize::NORMAL,
+    ));
+}
+
+// Everything mapped in the user half belongs to the address space, so it all goes with it
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        assert!(
+            !self.tlb.is_loaded_anywhere(),
+            "an address space was dropped while loaded"
+        );
+        self.clear_user_half();
+        unsafe {
+            deallocate_frame(PhysicalFrame::from_address_aligned(
+                self.level_4_table,
+                PageSize::NORMAL,
+            ))
+        };
+        if let Some(pcid) = self.pcid {
+            without_interrupts(|| PCIDS.lock().free.push(pcid));
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use alloc::collections::BTreeMap;
+
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use crate::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::paging::mapper::MapError;
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::{is_user_address, physical_to_virtual};
+use crate::process::vma::VmaTree;
+use crate::process::{self, AddressSpace};
+
+// Copy-on-write: fork doesn't copy anything, parent and child map the same frames read only instead, with
+// COPY_ON_WRITE set on the ones that were writable. The first write to one of those faults, and whoever made it gets
+// a copy of their own, see handle_write_fault.
+
+// How many address spaces map each frame that's mapped in more than one. A frame that isn't in here has a single
+// owner, which is nearly all of them. The page fault handler uses it, so it's only locked with interrupts off.
+static SHARED_FRAMES: Mutex<BTreeMap<PhysicalAddress, usize>> = Mutex::new(BTreeMap::new());
+
+fn share_frame(frame: PhysicalAddress) {
+    without_interrupts(|| *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1);
+}
+
+fn is_shared(frame: PhysicalAddress) -> bool {
+    without_interrupts(|| SHARED_FRAMES.lock().contains_key(&frame))
+}
+
+// What a page mapped to frame should have in its page table entry for an area mapped with flags. A frame someone
+// else still has can't be writable, it has to wait for its first write to get copied.
+pub fn page_flags(frame: PhysicalAddress, flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE) && is_shared(frame) {
+        (flags & !PageTableFlags::WRITABLE) | PageTableFlags::COPY_ON_WRITE
+    } else {
+        flags
+    }
+}
+
+// Gives up one address space's claim to a user frame, and frees it if that was the last one
+pub unsafe fn release_frame(frame: PhysicalFrame) {
+    let last = without_interrupts(|| {
+        let mut shared_frames = SHARED_FRAMES.lock();
+        match shared_frames.get_mut(&frame.start_address()) {
+            Some(owners) if *owners > 2 => {
+                *owners -= 1;
+                false
+            }
+            Some(_) => {
+                shared_frames.remove(&frame.start_address());
+                false
+            }
+            None => true,
+        }
+    });
+    if last {
+        deallocate_frame(frame);
+    }
+}
+
+// Maps everything that's mapped in parent's areas into child as well, sharing the frames. Writable pages become
+// read only copy-on-write pages in both. Pages nobody has touched yet stay that way, each of them gets its own when
+// it does. parent has to be the active address space.
+pub fn share_areas(
+    parent: &AddressSpace,
+    child: &AddressSpace,
+    areas: &VmaTree,
+) -> Result<(), MapError> {
+    debug_assert!(parent.is_active());
+    let mut parent_mapper = parent.mapper();
+    let mut child_mapper = child.mapper();
+    for page in areas.iter().flat_map(|vma| vma.pages()) {
+        let Some(mut flags) = parent_mapper.flags(page) else {
+            continue;
+        };
+        if flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags & !PageTableFlags::WRITABLE) | PageTableFlags::COPY_ON_WRITE;
+            parent_mapper.update_flags(page, flags)?;
+        }
+        let frame = parent_mapper
+            .translate(page.start_address())
+            .ok_or(MapError::PageNotMapped)?;
+        child_mapper.map_to(
+            page,
+            PhysicalFrame::from_address_aligned(frame, PageSize::NORMAL),
+            flags,
+        )?;
+        share_frame(frame);
+    }
+    Ok(())
+}
+
+// Called for a write to a present page in the user half of the current address space. Returns false if it wasn't a
+// copy-on-write page, in which case the write really wasn't allowed.
+pub fn handle_write_fault(address: VirtualAddress) -> bool {
+    if !is_user_address(address) {
+        return false;
+    }
+    let page = Page::from_address_aligned(
+        VirtualAddress::new(address.0 & !(PageSize::NORMAL as u64 - 1)),
+        PageSize::NORMAL,
+    );
+    let process = process::current();
+    let mut mapper = process.address_space().mapper();
+    let Some(flags) = mapper.flags(page) else {
+        return false;
+    };
+    if !flags.contains(PageTableFlags::COPY_ON_WRITE) {
+        return false;
+    }
+    let flags = (flags & !PageTableFlags::COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    let frame = mapper.translate(page.start_address()).unwrap();
+    if !is_shared(frame) {
+        // everyone else already made their own copy, so the last one left can just have it
+        return mapper.update_flags(page, flags).is_ok();
+    }
+    let Some(copy) = allocate_frame() else {
+        return false;
+    };
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            physical_to_virtual(frame).as_ptr::<u8>(),
+            physical_to_virtual(copy.start_address()).as_mut_ptr::<u8>(),
+            PageSize::NORMAL as usize,
+        );
+    }
+    let old_frame = mapper.unmap(page).unwrap();
+    mapper.map_to(page, copy, flags).unwrap();
+    // other threads of ours could still be reading the old frame through their TLBs
+    mapper.flush();
+    unsafe { release_frame(old_frame) };
+    true
+}
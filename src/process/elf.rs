@@ -0,0 +1,345 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::memory::address::VirtualAddress;
+use crate::memory::is_user_address;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::mapper::MapError;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::USER_SPACE_END;
+use crate::process::{Process, VmaKind};
+
+// Statically linked ELF64 executables. See the System V ABI (https://refspecs.linuxfoundation.org/elf/gabi4+/contents.html)
+// and its x86-64 supplement (https://gitlab.com/x86-psABIs/x86-64-ABI) for the process startup layout.
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+// Auxiliary vector entries, the numbers are the same as Linux's
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+// The stack goes right at the top of the user half and doesn't grow
+pub const USER_STACK_TOP: u64 = USER_SPACE_END;
+pub const USER_STACK_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    BadMagic,
+    Truncated,
+    // not a little endian x86_64 ELF64 executable
+    Unsupported,
+    // a segment that's outside of user space or runs into the stack, is bigger in the file than in memory, or shares
+    // a page with another one
+    BadSegment,
+    // argv, envp and the auxiliary vector don't fit on the stack
+    ArgumentsTooLong,
+    Map(MapError),
+}
+
+impl From<MapError> for ElfError {
+    fn from(error: MapError) -> Self {
+        ElfError::Map(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+}
+
+// A parsed and validated executable, borrowed from the file it came from
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_header_offset: u64,
+    program_headers: Vec<ProgramHeader>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if !data.starts_with(&ELF_MAGIC) {
+            return Err(ElfError::BadMagic);
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[4] != ELFCLASS64
+            || data[5] != ELFDATA2LSB
+            || data[6] != EV_CURRENT
+            || read_u16(data, 16) != ET_EXEC
+            || read_u16(data, 18) != EM_X86_64
+            || read_u16(data, 54) as usize != PROGRAM_HEADER_SIZE
+        {
+            return Err(ElfError::Unsupported);
+        }
+        let entry = read_u64(data, 24);
+        let program_header_offset = read_u64(data, 32);
+        let program_header_count = read_u16(data, 56) as u64;
+        let program_headers_end = program_header_count
+            .checked_mul(PROGRAM_HEADER_SIZE as u64)
+            .and_then(|size| size.checked_add(program_header_offset))
+            .ok_or(ElfError::Truncated)?;
+        if program_headers_end > data.len() as u64 {
+            return Err(ElfError::Truncated);
+        }
+        let program_headers = (0..program_header_count as usize)
+            .map(|index| {
+                let header = &data[program_header_offset as usize + index * PROGRAM_HEADER_SIZE..];
+                ProgramHeader {
+                    kind: read_u32(header, 0),
+                    flags: read_u32(header, 4),
+                    offset: read_u64(header, 8),
+                    virtual_address: read_u64(header, 16),
+                    file_size: read_u64(header, 32),
+                    memory_size: read_u64(header, 40),
+                }
+            })
+            .collect();
+        let elf = Elf {
+            data,
+            entry,
+            program_header_offset,
+            program_headers,
+        };
+        elf.validate()?;
+        Ok(elf)
+    }
+
+    // Everything that could make load fail, short of running out of memory, gets caught here. That way exec can
+    // parse a new program before it throws the old one away.
+    fn validate(&self) -> Result<(), ElfError> {
+        if !is_user_address(self.entry()) {
+            return Err(ElfError::BadSegment);
+        }
+        let mut pages = Vec::new();
+        for segment in self.segments() {
+            self.segment_data(segment)?;
+            let memory_end = segment
+                .virtual_address
+                .checked_add(segment.memory_size)
+                .ok_or(ElfError::BadSegment)?;
+            if segment.file_size > segment.memory_size
+                || !is_user_address(VirtualAddress(segment.virtual_address))
+                || memory_end > USER_STACK_TOP - USER_STACK_SIZE
+            {
+                return Err(ElfError::BadSegment);
+            }
+            pages.extend(segment_pages(segment));
+        }
+        // segments that share a page would need the page mapped with both of their permissions
+        pages.sort_by_key(|range: &Range<u64>| range.start);
+        if pages.windows(2).any(|pair| pair[0].end > pair[1].start) {
+            return Err(ElfError::BadSegment);
+        }
+        Ok(())
+    }
+
+    pub fn entry(&self) -> VirtualAddress {
+        VirtualAddress(self.entry)
+    }
+
+    pub fn program_headers(&self) -> &[ProgramHeader] {
+        &self.program_headers
+    }
+
+    // The part of the file a segment starts out with, the rest of it is zeroed
+    pub fn segment_data(&self, segment: &ProgramHeader) -> Result<&'a [u8], ElfError> {
+        let end = segment
+            .offset
+            .checked_add(segment.file_size)
+            .ok_or(ElfError::Truncated)?;
+        self.data
+            .get(segment.offset as usize..end as usize)
+            .ok_or(ElfError::Truncated)
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.kind == PT_LOAD)
+    }
+
+    // Where the program headers end up once the segments are loaded, for AT_PHDR
+    fn program_headers_address(&self) -> Option<u64> {
+        if let Some(header) = self
+            .program_headers
+            .iter()
+            .find(|header| header.kind == PT_PHDR)
+        {
+            return Some(header.virtual_address);
+        }
+        self.segments()
+            .find(|segment| {
+                (segment.offset..segment.offset + segment.file_size)
+                    .contains(&self.program_header_offset)
+            })
+            .map(|segment| segment.virtual_address + self.program_header_offset - segment.offset)
+    }
+}
+
+// The pages a segment covers, None if it's empty
+fn segment_pages(segment: &ProgramHeader) -> Option<Range<u64>> {
+    let page_size = PageSize::NORMAL as u64;
+    (segment.memory_size > 0).then(|| {
+        let start = segment.virtual_address & !(page_size - 1);
+        let end = (segment.virtual_address + segment.memory_size).div_ceil(page_size) * page_size;
+        start..end
+    })
+}
+
+// Where to start the program once it's loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    pub entry: VirtualAddress,
+    pub stack_pointer: VirtualAddress,
+}
+
+// Gives every PT_LOAD segment of data an area in process with the permissions it asks for, and sets up a stack with
+// argv, envp and the auxiliary vector on it. Only the pages something gets written to are mapped here, the rest of
+// each segment (bss) and of the stack come out zeroed when they're first touched. The heap starts after the last
+// segment. If this fails the process is left half loaded and should be thrown away.
+pub fn load(
+    process: &Process,
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Image, ElfError> {
+    let elf = Elf::parse(data)?;
+    let address_space = process.address_space();
+    let mut heap_start = 0;
+    for segment in elf.segments() {
+        let Some(pages) = segment_pages(segment) else {
+            continue;
+        };
+        let mut flags = PageTableFlags::USER_ACCESSIBLE;
+        if segment.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        address_space.add_area(
+            VirtualAddress::new(pages.start),
+            pages.end - pages.start,
+            flags,
+            VmaKind::File,
+        )?;
+        address_space.write(
+            VirtualAddress::new(segment.virtual_address),
+            elf.segment_data(segment)?,
+        )?;
+        heap_start = heap_start.max(pages.end);
+    }
+    address_space.set_heap_start(VirtualAddress::new(heap_start));
+
+    address_space.add_area(
+        VirtualAddress::new(USER_STACK_TOP - USER_STACK_SIZE),
+        USER_STACK_SIZE,
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        VmaKind::Stack,
+    )?;
+    let stack_pointer = set_up_stack(process, &elf, argv, envp)?;
+    Ok(Image {
+        entry: elf.entry(),
+        stack_pointer,
+    })
+}
+
+// System V ABI x86-64 supplement - Figure 3.9, from the top down: the strings argv and envp point to, padding,
+// the auxiliary vector, envp, argv and argc, with rsp pointing at argc and 16 byte aligned
+fn set_up_stack(
+    process: &Process,
+    elf: &Elf,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<VirtualAddress, ElfError> {
+    // glibc wants 16 bytes to seed its stack protector with. There's no real source of randomness yet.
+    let random = [
+        crate::time::read_tsc().to_le_bytes(),
+        crate::time::read_tsc().to_le_bytes(),
+    ]
+    .concat();
+
+    let mut strings = Vec::new();
+    let mut string_offsets = Vec::new();
+    for string in argv.iter().chain(envp) {
+        string_offsets.push(strings.len() as u64);
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+    }
+    let random_offset = strings.len() as u64;
+    strings.extend_from_slice(&random);
+    let strings_start = (USER_STACK_TOP - strings.len() as u64) & !0xF;
+
+    let mut auxiliary_vector = Vec::new();
+    if let Some(address) = elf.program_headers_address() {
+        auxiliary_vector.extend([AT_PHDR, address]);
+    }
+    auxiliary_vector.extend([
+        AT_PHENT,
+        PROGRAM_HEADER_SIZE as u64,
+        AT_PHNUM,
+        elf.program_headers().len() as u64,
+        AT_PAGESZ,
+        PageSize::NORMAL as u64,
+        AT_ENTRY,
+        elf.entry,
+        AT_RANDOM,
+        strings_start + random_offset,
+        AT_NULL,
+        0,
+    ]);
+
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    let (argv_offsets, envp_offsets) = string_offsets.split_at(argv.len());
+    words.extend(argv_offsets.iter().map(|offset| strings_start + offset));
+    words.push(0);
+    words.extend(envp_offsets.iter().map(|offset| strings_start + offset));
+    words.push(0);
+    words.extend(auxiliary_vector);
+
+    let size = (words.len() * 8) as u64;
+    if strings_start - size < USER_STACK_TOP - USER_STACK_SIZE {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+    let stack_pointer = (strings_start - size) & !0xF;
+    let words: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let address_space = process.address_space();
+    address_space.write(VirtualAddress::new(strings_start), &strings)?;
+    address_space.write(VirtualAddress::new(stack_pointer), &words)?;
+    Ok(VirtualAddress::new(stack_pointer))
+}
@@ -0,0 +1,74 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::{Console, File};
+use crate::syscall::consts::{Errno, STDERR, STDIN, STDOUT};
+
+// The most file descriptors a process can have open at once
+pub const MAX_FILES: usize = 256;
+
+// What a file descriptor refers to. Descriptors copied by fork share it, offset and all.
+pub type FileDescriptor = Arc<dyn File>;
+
+// Indexed by file descriptor number, closed descriptors are None so the numbers of the others don't change
+#[derive(Debug, Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<FileDescriptor>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    // stdin, stdout and stderr all on the console
+    pub fn with_console() -> Self {
+        let mut table = FileTable::new();
+        let console: FileDescriptor = Arc::new(Console);
+        for fd in [STDIN, STDOUT, STDERR] {
+            table
+                .insert_at(fd as usize, console.clone())
+                .expect("the standard file descriptors are always free in a new table");
+        }
+        table
+    }
+
+    pub fn get(&self, fd: u64) -> Result<&FileDescriptor, Errno> {
+        self.files
+            .get(fd as usize)
+            .and_then(|file| file.as_ref())
+            .ok_or(Errno::EBADF)
+    }
+
+    // Takes the lowest free descriptor, like POSIX says open has to
+    pub fn insert(&mut self, file: FileDescriptor) -> Result<u64, Errno> {
+        let fd = self
+            .files
+            .iter()
+            .position(|file| file.is_none())
+            .unwrap_or(self.files.len());
+        self.insert_at(fd, file)?;
+        Ok(fd as u64)
+    }
+
+    fn insert_at(&mut self, fd: usize, file: FileDescriptor) -> Result<(), Errno> {
+        if fd >= MAX_FILES {
+            return Err(Errno::EMFILE);
+        }
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        if self.files[fd].is_some() {
+            return Err(Errno::EBADF);
+        }
+        self.files[fd] = Some(file);
+        Ok(())
+    }
+
+    pub fn close(&mut self, fd: u64) -> Result<FileDescriptor, Errno> {
+        self.files
+            .get_mut(fd as usize)
+            .and_then(|file| file.take())
+            .ok_or(Errno::EBADF)
+    }
+}
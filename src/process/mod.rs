@@ -0,0 +1,324 @@
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::{Mutex, MutexGuard};
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::mapper::MapError;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::sync::Once;
+use crate::syscall::consts::Errno;
+use crate::task::{self, JoinHandle, ThreadId, WaitQueue};
+use crate::usermode::enter_user_mode;
+
+pub mod address_space;
+pub mod cow;
+pub mod elf;
+pub mod file_table;
+pub mod vma;
+
+pub use address_space::AddressSpace;
+pub use elf::{ElfError, Image};
+pub use file_table::{FileDescriptor, FileTable};
+pub use vma::{Vma, VmaKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(u64);
+
+impl ProcessId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub const fn from_u64(id: u64) -> Self {
+        ProcessId(id)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+// What a process killed for touching memory it isn't allowed to exits with, the number a shell shows for SIGSEGV
+pub const SEGFAULT_EXIT_STATUS: u64 = 128 + 11;
+// The same for the other exceptions ring 3 can cause, going by the signal Linux sends for each
+pub const ILLEGAL_INSTRUCTION_EXIT_STATUS: u64 = 128 + 4;
+pub const TRAP_EXIT_STATUS: u64 = 128 + 5;
+pub const BUS_ERROR_EXIT_STATUS: u64 = 128 + 7;
+pub const FLOATING_POINT_EXIT_STATUS: u64 = 128 + 8;
+
+// An address space and everything running in it. Every thread belongs to exactly one process, kernel threads
+// belong to the kernel process, which has the kernel's own address space and nothing in the user half.
+// Once a process exits it hangs around as a zombie, with nothing left but its exit status, until its parent waits
+// for it.
+#[derive(Debug)]
+pub struct Process {
+    id: ProcessId,
+    name: String,
+    address_space: AddressSpace,
+    // Threads take themselves off this when they're dropped, which can happen in the timer interrupt
+    // (see task::finish_switch), so it's only ever locked with interrupts off
+    threads: Mutex<Vec<ThreadId>>,
+    files: Mutex<FileTable>,
+    // The rest are looked at from WaitQueue::wait_while, so they're only locked with interrupts off too.
+    // Nobody but the parent keeps its children alive, the other way around is just a Weak.
+    parent: Mutex<Weak<Process>>,
+    children: Mutex<Vec<Arc<Process>>>,
+    child_exited: WaitQueue,
+    // what the program passed to the exit system call, None until it exits
+    exit_status: Mutex<Option<u64>>,
+}
+
+static KERNEL_PROCESS: Once<Arc<Process>> = Once::new();
+// Orphans get handed to this one, which is expected to wait for them. The kernel process until set_init is called.
+static INIT_PROCESS: Mutex<Option<Arc<Process>>> = Mutex::new(None);
+
+pub fn init() {
+    KERNEL_PROCESS.call_once(|| {
+        Arc::new(Process {
+            id: ProcessId::new(),
+            name: String::from("kernel"),
+            address_space: AddressSpace::kernel(),
+            threads: Mutex::new(Vec::new()),
+            files: Mutex::new(FileTable::with_console()),
+            parent: Mutex::new(Weak::new()),
+            children: Mutex::new(Vec::new()),
+            child_exited: WaitQueue::new(),
+            exit_status: Mutex::new(None),
+        })
+    });
+}
+
+pub fn kernel_process() -> &'static Arc<Process> {
+    KERNEL_PROCESS
+        .get()
+        .expect("process::init hasn't been called")
+}
+
+// The process the running thread belongs to
+pub fn current() -> Arc<Process> {
+    crate::task::current_process()
+}
+
+pub fn set_init(process: &Arc<Process>) {
+    without_interrupts(|| *INIT_PROCESS.lock() = Some(process.clone()));
+}
+
+fn init_process() -> Arc<Process> {
+    without_interrupts(|| INIT_PROCESS.lock().clone()).unwrap_or_else(|| kernel_process().clone())
+}
+
+// Loads an ELF executable into a new child of the current process and starts a thread that runs it in ring 3
+pub fn spawn_program(
+    name: &'static str,
+    executable: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(Arc<Process>, JoinHandle), ElfError> {
+    let process = Process::new(name)?;
+    let image = elf::load(&process, executable, argv, envp)?;
+    current().adopt(&process);
+    let handle = spawn_user_thread(&process, name, move || unsafe {
+        enter_user_mode(image.entry, image.stack_pointer)
+    })
+    .inspect_err(|_| current().disown(&process))?;
+    Ok((process, handle))
+}
+
+// Starts a thread in process that runs user code with run, and takes the whole process down with it once run returns
+// the exit status, see usermode::exit_to_kernel. User processes only ever have the one thread.
+pub fn spawn_user_thread<F>(
+    process: &Arc<Process>,
+    name: &'static str,
+    run: F,
+) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() -> u64 + Send + 'static,
+{
+    task::spawn_in(process, name, move || {
+        let status = run();
+        current().exit(status);
+    })
+}
+
+// Waits for a child of the current process to exit, any of them if pid is None, and reaps it.
+// Returns None straight away instead of waiting if block is false and none of them have exited yet.
+pub fn wait(pid: Option<ProcessId>, block: bool) -> Result<Option<(ProcessId, u64)>, Errno> {
+    let process = current();
+    let mut result = Ok(None);
+    process.child_exited.wait_while(|| {
+        result = process.reap_child(pid);
+        matches!(result, Ok(None)) && block
+    });
+    // the zombie is dropped here, which frees what's left of it
+    result.map(|zombie| zombie.map(|zombie| (zombie.id, zombie.exit_status().unwrap())))
+}
+
+impl Process {
+    // A process with nothing mapped in the user half and no threads yet, see task::spawn_in.
+    // It starts out with stdin, stdout and stderr on the console.
+    pub fn new(name: &str) -> Result<Arc<Process>, MapError> {
+        Ok(Arc::new(Process {
+            id: ProcessId::new(),
+            name: String::from(name),
+            address_space: AddressSpace::new(&kernel_process().address_space)?,
+            threads: Mutex::new(Vec::new()),
+            files: Mutex::new(FileTable::with_console()),
+            parent: Mutex::new(Weak::new()),
+            children: Mutex::new(Vec::new()),
+            child_exited: WaitQueue::new(),
+            exit_status: Mutex::new(None),
+        }))
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address_space(&self) -> &AddressSpace {
+        &self.address_space
+    }
+
+    pub fn threads(&self) -> Vec<ThreadId> {
+        without_interrupts(|| self.threads.lock().clone())
+    }
+
+    pub(crate) fn add_thread(&self, id: ThreadId) {
+        without_interrupts(|| self.threads.lock().push(id));
+    }
+
+    pub(crate) fn remove_thread(&self, id: ThreadId) {
+        without_interrupts(|| self.threads.lock().retain(|&thread| thread != id));
+    }
+
+    pub fn files(&self) -> MutexGuard<'_, FileTable> {
+        self.files.lock()
+    }
+
+    // None until the program has exited
+    pub fn exit_status(&self) -> Option<u64> {
+        without_interrupts(|| *self.exit_status.lock())
+    }
+
+    pub fn parent(&self) -> Option<Arc<Process>> {
+        without_interrupts(|| self.parent.lock().upgrade())
+    }
+
+    pub fn children(&self) -> Vec<Arc<Process>> {
+        without_interrupts(|| self.children.lock().clone())
+    }
+
+    fn adopt(self: &Arc<Self>, child: &Arc<Process>) {
+        without_interrupts(|| {
+            *child.parent.lock() = Arc::downgrade(self);
+            self.children.lock().push(child.clone());
+        });
+    }
+
+    // Forgets about a child that never got to run
+    pub(crate) fn disown(&self, child: &Arc<Process>) {
+        without_interrupts(|| {
+            self.children
+                .lock()
+                .retain(|other| !Arc::ptr_eq(other, child))
+        });
+    }
+
+    // Takes out the first zombie child that matches pid. ECHILD if there isn't even a child that could match.
+    fn reap_child(&self, pid: Option<ProcessId>) -> Result<Option<Arc<Process>>, Errno> {
+        without_interrupts(|| {
+            let mut children = self.children.lock();
+            let mut matching = children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| pid.is_none_or(|pid| child.id == pid))
+                .peekable();
+            if matching.peek().is_none() {
+                return Err(Errno::ECHILD);
+            }
+            let zombie = matching
+                .find(|(_, child)| child.exit_status().is_some())
+                .map(|(index, _)| index);
+            Ok(zombie.map(|index| children.remove(index)))
+        })
+    }
+
+    // A copy of this process for fork, as a child of it. Nothing gets copied until one of them writes to it, see cow.
+    // This has to be the current process.
+    pub fn fork(self: &Arc<Self>) -> Result<Arc<Process>, MapError> {
+        let child = Process::new(&self.name)?;
+        let areas = self.address_space.vma_tree();
+        cow::share_areas(&self.address_space, &child.address_space, &areas)?;
+        child.address_space.set_vma_tree(areas);
+        *child.files() = self.files().clone();
+        self.adopt(&child);
+        Ok(child)
+    }
+
+    // Throws away everything in the user half and loads executable in its place. Once the executable has been
+    // parsed there's no going back, so any error after that leaves the process with nothing to run.
+    // This has to be the current process.
+    pub fn exec(&self, executable: &[u8], argv: &[&str], envp: &[&str]) -> Result<Image, ElfError> {
+        elf::Elf::parse(executable)?;
+        self.address_space.clear_user_half();
+        elf::load(self, executable, argv, envp)
+    }
+
+    // Frees everything the process had and makes it a zombie. Its children go to the init process, and its parent
+    // gets woken up to reap it. Called once the last thread is done with user space, see spawn_user_thread.
+    pub fn exit(self: &Arc<Self>, status: u64) {
+        assert!(
+            !Arc::ptr_eq(self, kernel_process()),
+            "the kernel process can't exit"
+        );
+        *self.files() = FileTable::new();
+        self.address_space.clear_user_half();
+
+        let orphans = without_interrupts(|| core::mem::take(&mut *self.children.lock()));
+        if !orphans.is_empty() {
+            let init = init_process();
+            for orphan in &orphans {
+                init.adopt(orphan);
+            }
+            // some of them might be zombies already
+            init.child_exited.wake_all();
+        }
+
+        without_interrupts(|| *self.exit_status.lock() = Some(status));
+        if let Some(parent) = self.parent() {
+            parent.child_exited.wake_all();
+        }
+    }
+
+    // Maps size bytes (rounded up to whole pages) of zeroed memory at start, which has to be page aligned, straight
+    // away instead of waiting for it to be touched. USER_ACCESSIBLE gets added to flags.
+    pub fn map_region(
+        &self,
+        start: VirtualAddress,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<Vma, MapError> {
+        let vma = self.address_space.add_area(
+            start,
+            size,
+            flags | PageTableFlags::USER_ACCESSIBLE,
+            VmaKind::Anonymous,
+        )?;
+        let mut mapper = self.address_space.mapper();
+        for page in vma.pages() {
+            if let Err(error) = mapper.map(page, vma.flags) {
+                self.address_space.remove_areas(vma.start, vma.end);
+                return Err(error);
+            }
+        }
+        Ok(vma)
+    }
+}
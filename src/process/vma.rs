@@ -0,0 +1,206 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::mapper::MapError;
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::USER_SPACE_START;
+
+// mmap hands out addresses from here up when it gets to pick, well out of the way of the program and its heap at the
+// bottom of the user half and the stack at the top
+pub const MMAP_BASE: u64 = USER_SPACE_START + 0x1000_0000_0000;
+
+// What a virtual memory area was mapped for. Apart from where their first contents come from they all behave the
+// same: nothing is mapped until the first access, then the page fault handler maps a zeroed page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    // mmap with MAP_ANONYMOUS, or anything the kernel maps for a process
+    Anonymous,
+    // A segment of an executable. The pages with something from the file in them are filled in when it's loaded.
+    File,
+    Stack,
+    // grows and shrinks with brk
+    Heap,
+}
+
+// A range of the user half that a process is allowed to touch, start and end are page aligned.
+// flags are what the pages get mapped with. Without USER_ACCESSIBLE it's PROT_NONE: nothing new gets mapped there,
+// and ring 3 can't touch what already is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+    pub flags: PageTableFlags,
+    pub kind: VmaKind,
+}
+
+impl Vma {
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        (self.start.0..self.end.0).contains(&address.0)
+    }
+
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start.0 < end && start < self.end.0
+    }
+
+    pub fn pages(&self) -> impl Iterator<Item = Page> + Clone {
+        (self.start.0..self.end.0)
+            .step_by(PageSize::NORMAL as usize)
+            .map(|address| {
+                Page::from_address_aligned(VirtualAddress::new(address), PageSize::NORMAL)
+            })
+    }
+
+    // The part of this that's in start..end, if there is one
+    fn clamp(&self, start: u64, end: u64) -> Option<Vma> {
+        self.overlaps(start, end).then(|| Vma {
+            start: VirtualAddress::new(self.start.0.max(start)),
+            end: VirtualAddress::new(self.end.0.min(end)),
+            ..*self
+        })
+    }
+}
+
+// Every area mapped in an address space, keyed by where they start. Areas never overlap, and neighbours with the
+// same flags aren't merged, there are never enough of them for it to matter.
+// Also keeps track of the heap brk moves around, which starts right after the executable's last segment.
+#[derive(Debug, Clone)]
+pub struct VmaTree {
+    areas: BTreeMap<u64, Vma>,
+    heap_start: u64,
+    brk: u64,
+}
+
+impl VmaTree {
+    pub const fn new() -> Self {
+        VmaTree {
+            areas: BTreeMap::new(),
+            heap_start: 0,
+            brk: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    pub fn find(&self, address: VirtualAddress) -> Option<&Vma> {
+        self.areas
+            .range(..=address.0)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(address))
+    }
+
+    pub fn is_free(&self, start: u64, end: u64) -> bool {
+        // only the last area that starts before end can reach into the range
+        self.areas
+            .range(..end)
+            .next_back()
+            .is_none_or(|(_, vma)| !vma.overlaps(start, end))
+    }
+
+    // Whether every byte of start..end is in some area
+    pub fn is_covered(&self, start: u64, end: u64) -> bool {
+        let mut covered = start;
+        for vma in self.areas.range(..end).map(|(_, vma)| vma) {
+            if vma.end.0 <= covered {
+                continue;
+            }
+            if vma.start.0 > covered {
+                return false;
+            }
+            covered = vma.end.0;
+        }
+        covered >= end
+    }
+
+    pub fn insert(&mut self, vma: Vma) -> Result<(), MapError> {
+        if !self.is_free(vma.start.0, vma.end.0) {
+            return Err(MapError::PageAlreadyMapped);
+        }
+        self.areas.insert(vma.start.0, vma);
+        Ok(())
+    }
+
+    // Takes start..end out of whatever areas it overlaps, cutting them in two if it has to.
+    // Returns the pieces that were taken out.
+    pub fn remove(&mut self, start: u64, end: u64) -> Vec<Vma> {
+        let overlapping: Vec<Vma> = self
+            .areas
+            .range(..end)
+            .map(|(_, vma)| *vma)
+            .filter(|vma| vma.overlaps(start, end))
+            .collect();
+        let mut removed = Vec::new();
+        for vma in overlapping {
+            self.areas.remove(&vma.start.0);
+            if vma.start.0 < start {
+                self.areas.insert(
+                    vma.start.0,
+                    Vma {
+                        end: VirtualAddress::new(start),
+                        ..vma
+                    },
+                );
+            }
+            if vma.end.0 > end {
+                self.areas.insert(
+                    end,
+                    Vma {
+                        start: VirtualAddress::new(end),
+                        ..vma
+                    },
+                );
+            }
+            removed.extend(vma.clamp(start, end));
+        }
+        removed
+    }
+
+    // Gives everything in start..end new flags, splitting areas at the edges. Returns the pieces that changed.
+    pub fn protect(&mut self, start: u64, end: u64, flags: PageTableFlags) -> Vec<Vma> {
+        let mut changed = self.remove(start, end);
+        for vma in &mut changed {
+            vma.flags = flags;
+            self.areas.insert(vma.start.0, *vma);
+        }
+        changed
+    }
+
+    // The lowest free range of size bytes that starts at or above from and ends at or below limit
+    pub fn find_free(&self, size: u64, from: u64, limit: u64) -> Option<u64> {
+        let mut candidate = from;
+        for vma in self.areas.values() {
+            if vma.end.0 <= candidate {
+                continue;
+            }
+            if vma.start.0 >= candidate.checked_add(size)? {
+                break;
+            }
+            candidate = vma.end.0;
+        }
+        (candidate.checked_add(size)? <= limit).then_some(candidate)
+    }
+
+    pub fn clear(&mut self) {
+        self.areas.clear();
+        self.heap_start = 0;
+        self.brk = 0;
+    }
+
+    pub fn heap_start(&self) -> u64 {
+        self.heap_start
+    }
+
+    pub fn brk(&self) -> u64 {
+        self.brk
+    }
+
+    pub fn set_heap(&mut self, start: u64, brk: u64) {
+        self.heap_start = start;
+        self.brk = brk;
+    }
+}
@@ -1,22 +1,36 @@
-use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
-}
+use crate::interrupts::without_interrupts;
+use crate::sync::Lazy;
+
+const COM1_PORT: u16 = 0x3F8;
+
+pub static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
+    let mut serial_port = unsafe { SerialPort::new(COM1_PORT) };
+    serial_port.init();
+    Mutex::new(serial_port)
+});
 
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1
-        .lock()
-        .write_fmt(args)
-        .expect("Failed printing to serial");
+    without_interrupts(|| {
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("Failed printing to serial");
+    });
+}
+
+// Talks to COM1 without going through SERIAL1's lock. Output can end up interleaved with whoever holds the lock,
+// but that beats not seeing it at all when we're in an exception handler that interrupted them.
+#[doc(hidden)]
+pub fn _print_bypassing_lock(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    // this assumes SERIAL1 has already initialized the UART, QEMU doesn't care either way
+    let mut serial_port = unsafe { SerialPort::new(COM1_PORT) };
+    let _ = serial_port.write_fmt(args);
 }
 
 #[macro_export]
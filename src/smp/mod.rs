@@ -0,0 +1,126 @@
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::acpi;
+use crate::interrupts::drivers::xapic;
+use crate::memory::frame_allocator::take_low_frame;
+use crate::structs::{gdt, idt};
+use crate::sync::SpinLock;
+use crate::task::stack::KernelStack;
+use crate::time::{delay, uptime};
+use crate::{cpu, interrupts, syscall, task};
+
+pub mod percpu;
+mod trampoline;
+
+pub use percpu::current_cpu;
+use trampoline::Trampoline;
+
+// Everything that's kept per CPU has room for this many, any more than that are left halted
+pub const MAX_CPUS: usize = 16;
+
+// The INIT-SIPI-SIPI sequence and how long it has to wait in between -> Intel Manual - Section 8.4.4.1
+const INIT_DELAY: Duration = Duration::from_millis(10);
+const STARTUP_DELAY: Duration = Duration::from_micros(200);
+// how long a CPU gets to show up in the scheduler before we give up on it
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+// The number the next CPU gets, and the kernel stack the trampoline puts it on, which becomes its idle task's
+static STARTUP: SpinLock<Option<(usize, KernelStack)>> = SpinLock::new(None);
+
+// Sets up the boot processor's per-CPU data, before anything gets a chance to look at it
+pub fn init_boot_cpu() {
+    unsafe { percpu::init(0, cpu::apic_id()) };
+}
+
+// How many CPUs have joined the scheduler, the boot processor included
+pub fn cpu_count() -> usize {
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+// Starts every other CPU the MADT lists, one at a time, and has each of them join the scheduler.
+// Has to run after task::init.
+pub fn init() {
+    let Some(local_apics) = acpi::madt::local_apics() else {
+        log::warn!("No MADT, only using the boot processor");
+        return;
+    };
+    let Some(frame) = take_low_frame() else {
+        log::warn!("No memory below 1MiB to start the other CPUs in");
+        return;
+    };
+    let trampoline = match unsafe { Trampoline::install(frame) } {
+        Ok(trampoline) => trampoline,
+        Err(error) => {
+            log::warn!("Failed to map the trampoline: {:?}", error);
+            return;
+        }
+    };
+    let boot_apic_id = percpu::apic_id();
+    for local_apic in local_apics
+        .iter()
+        .filter(|apic| apic.apic_id != boot_apic_id)
+    {
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            log::warn!("Only using the first {} CPUs", MAX_CPUS);
+            break;
+        }
+        if !start(&trampoline, cpu, local_apic.apic_id) {
+            log::warn!("The CPU with APIC ID {} didn't start", local_apic.apic_id);
+            // it could still turn up later, and it'll want the trampoline to be where it was
+            core::mem::forget(trampoline);
+            break;
+        }
+    }
+    log::info!("{} CPUs online", cpu_count());
+}
+
+fn start(trampoline: &Trampoline, cpu: usize, apic_id: u8) -> bool {
+    let Ok(stack) = KernelStack::allocate() else {
+        return false;
+    };
+    trampoline.prepare(ap_entry, stack.top());
+    *STARTUP.lock() = Some((cpu, stack));
+
+    xapic::send_init(apic_id);
+    delay(INIT_DELAY);
+    // the second one is in case the first got lost, a CPU that's already running ignores it
+    for _ in 0..2 {
+        xapic::send_startup(apic_id, trampoline.page());
+        delay(STARTUP_DELAY);
+    }
+    let deadline = uptime() + STARTUP_TIMEOUT;
+    while cpu_count() == cpu {
+        if uptime() > deadline {
+            return false;
+        }
+        spin_loop();
+    }
+    true
+}
+
+// Where the trampoline leaves an application processor, in long mode on the boot processor's page tables, with
+// interrupts off
+extern "C" fn ap_entry() -> ! {
+    let (cpu, stack) = STARTUP
+        .lock()
+        .take()
+        .expect("a CPU started without a stack");
+    unsafe { percpu::init(cpu, cpu::apic_id()) };
+    if cpu::pcid_enabled() {
+        cpu::enable_pcid();
+    }
+    gdt::init_gdt();
+    idt::init_idt();
+    syscall::init();
+    xapic::init();
+    task::init_cpu(stack);
+    log::info!("CPU {} (APIC ID {}) online", cpu, percpu::apic_id());
+    CPUS_ONLINE.fetch_add(1, Ordering::Release);
+    interrupts::enable();
+    task::idle();
+    unreachable!("the idle task returned");
+}
@@ -0,0 +1,206 @@
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::mem::{offset_of, size_of};
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::cpu::{read_msr, write_msr, IA32_GS_BASE, IA32_KERNEL_GS_BASE};
+use crate::smp::MAX_CPUS;
+
+// What gs points at while we're in the kernel, one for each CPU. Ring 3 gets its own gs base, so everywhere we go
+// between the two does a swapgs. The syscall entry stub reads some of this before it has a stack, so the layout is
+// fixed and the asm uses the offsets below.
+#[repr(C)]
+pub(crate) struct CpuData {
+    // gs:0x00, so getting at the rest from Rust is a single mov
+    this: *mut CpuData,
+    cpu: usize,
+    apic_id: u64,
+    // preemption stays off for as long as this isn't 0, see preempt_disable
+    preempt_count: u64,
+    // kept equal to RSP0 in the TSS since syscall doesn't switch stacks on its own
+    pub(crate) kernel_stack: u64,
+    // scratch space for the user rsp while syscall_entry is between stacks
+    pub(crate) user_stack: u64,
+    // for going back with iretq
+    pub(crate) user_code_selector: u64,
+    pub(crate) user_data_selector: u64,
+}
+
+pub(crate) const KERNEL_STACK: usize = offset_of!(CpuData, kernel_stack);
+pub(crate) const USER_STACK: usize = offset_of!(CpuData, user_stack);
+pub(crate) const USER_CODE_SELECTOR: usize = offset_of!(CpuData, user_code_selector);
+pub(crate) const USER_DATA_SELECTOR: usize = offset_of!(CpuData, user_data_selector);
+
+static mut CPU_DATA: [CpuData; MAX_CPUS] = [const {
+    CpuData {
+        this: core::ptr::null_mut(),
+        cpu: 0,
+        apic_id: 0,
+        preempt_count: 0,
+        kernel_stack: 0,
+        user_stack: 0,
+        user_code_selector: 0,
+        user_data_selector: 0,
+    }
+}; MAX_CPUS];
+
+// Points gs at cpu's CpuData. The first thing every CPU does, nothing that uses per-CPU data works before this.
+pub unsafe fn init(cpu: usize, apic_id: u8) {
+    let cpu_data = addr_of_mut!(CPU_DATA[cpu]);
+    (*cpu_data).this = cpu_data;
+    (*cpu_data).cpu = cpu;
+    (*cpu_data).apic_id = apic_id as u64;
+    write_msr(IA32_GS_BASE, cpu_data as u64);
+    write_msr(IA32_KERNEL_GS_BASE, 0);
+}
+
+// This CPU's CpuData. Only its own CPU ever touches it, and only with interrupts off if it's anything an interrupt
+// handler might change too.
+pub(crate) fn cpu_data() -> *mut CpuData {
+    let cpu_data: *mut CpuData;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) cpu_data, options(nostack, preserves_flags, readonly))
+    };
+    cpu_data
+}
+
+// The CPU we're running on, numbered from 0 in the order they were started. A thread that can be preempted could be
+// somewhere else by the time it looks at the answer.
+#[inline]
+pub fn current_cpu() -> usize {
+    let cpu: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{cpu}]",
+            out(reg) cpu,
+            cpu = const offset_of!(CpuData, cpu),
+            options(nostack, preserves_flags, readonly)
+        )
+    };
+    cpu
+}
+
+// The local APIC ID of the CPU we're running on, without going out to the APIC for it
+pub fn apic_id() -> u8 {
+    unsafe { (*cpu_data()).apic_id as u8 }
+}
+
+// The local APIC ID of another CPU, for sending it IPIs. Only meaningful for CPUs that are online.
+pub fn apic_id_of(cpu: usize) -> u8 {
+    unsafe { (*addr_of!(CPU_DATA[cpu])).apic_id as u8 }
+}
+
+// Keeps the timer from switching away from the current thread until the guard is dropped, so whatever it does stays on
+// this CPU. Nests, and interrupts still get handled in the meantime. The thread mustn't block while it's held.
+pub fn preempt_disable() -> PreemptGuard {
+    // a single instruction, so an interrupt can't split it
+    unsafe {
+        asm!(
+            "inc qword ptr gs:[{count}]",
+            count = const offset_of!(CpuData, preempt_count),
+            options(nostack)
+        )
+    };
+    PreemptGuard {
+        _not_send: PhantomData,
+    }
+}
+
+pub fn preemptible() -> bool {
+    unsafe { (*cpu_data()).preempt_count == 0 }
+}
+
+// Has to be dropped on the CPU it was made on, which it is as long as it stays on its thread
+pub struct PreemptGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        unsafe {
+            asm!(
+                "dec qword ptr gs:[{count}]",
+                count = const offset_of!(CpuData, preempt_count),
+                options(nostack)
+            )
+        };
+    }
+}
+
+// Makes sure gs is the kernel's for as long as it's around, for interrupt handlers that can arrive from ring 3 and
+// want to look at per-CPU data. The timer and the system call entries do their own swapgs.
+pub struct KernelGs {
+    swapped: bool,
+}
+
+impl KernelGs {
+    // Whether ring 3 was interrupted tells us which gs we've got, as long as the kernel can't be interrupted between
+    // a swapgs and going back to ring 3, which only holds for maskable interrupts and faults the kernel doesn't cause
+    pub fn enter(from_user: bool) -> Self {
+        if from_user {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+        KernelGs { swapped: from_user }
+    }
+
+    // For NMIs, double faults and the like, which can show up anywhere. The gs base itself is the only reliable way
+    // to tell.
+    pub fn enter_paranoid() -> Self {
+        let gs_base = unsafe { read_msr(IA32_GS_BASE) } as usize;
+        let cpu_data = addr_of!(CPU_DATA) as usize;
+        let is_kernel = (cpu_data..cpu_data + size_of::<[CpuData; MAX_CPUS]>()).contains(&gs_base);
+        KernelGs::enter(!is_kernel)
+    }
+}
+
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.swapped {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+// A variable every CPU has its own copy of, see percpu!. Only the CPU a copy belongs to should be changing it,
+// others can still look.
+pub struct PerCpu<T> {
+    values: [T; MAX_CPUS],
+}
+
+impl<T> PerCpu<T> {
+    pub const fn new(values: [T; MAX_CPUS]) -> Self {
+        PerCpu { values }
+    }
+}
+
+impl<T: Sync> PerCpu<T> {
+    // The copy that belongs to the CPU we're running on
+    pub fn get(&self) -> &T {
+        &self.values[current_cpu()]
+    }
+
+    pub fn of(&self, cpu: usize) -> &T {
+        &self.values[cpu]
+    }
+
+    // Every CPU's copy, whether or not that CPU is online
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.values.iter()
+    }
+}
+
+// Declares per-CPU statics, which all start out with the same value. The initialiser has to be a constant.
+//     percpu! {
+//         static TICKS: AtomicU64 = AtomicU64::new(0);
+//     }
+//     TICKS.get().fetch_add(1, Ordering::Relaxed);
+#[macro_export]
+macro_rules! percpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::smp::percpu::PerCpu<$ty> =
+            $crate::smp::percpu::PerCpu::new([const { $init }; $crate::smp::MAX_CPUS]);
+        $crate::percpu!($($rest)*);
+    };
+    () => {};
+}
@@ -0,0 +1,195 @@
+use core::arch::global_asm;
+use core::ptr::addr_of;
+
+use crate::cpu::{read_cr0, read_cr4, CR4_PCID_ENABLE, EFER_NO_EXECUTE_ENABLE, IA32_EFER};
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use crate::memory::frame_allocator::deallocate_frame;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::paging::mapper::{MapError, Mapper};
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::{read_cr3, PageTableFlags};
+use crate::memory::physical_to_virtual;
+
+// Long mode enable, Intel Manual - Section 2.2.1
+const EFER_LONG_MODE_ENABLE: u64 = 1 << 8;
+// the trampoline's own GDT, just enough to get into long mode with
+const TRAMPOLINE_CODE_SELECTOR: u64 = 0x08;
+const TRAMPOLINE_DATA_SELECTOR: u64 = 0x10;
+
+// Where an application processor starts after the startup IPI: in real mode, with cs set to the page this got
+// copied to and ip to 0. It goes straight from there to long mode, turning on protected mode and paging in the same
+// mov to cr0 -> Intel Manual - Section 9.8.5. Everything it needs to know is filled in at the end of the copy.
+// The 16 bit half only works with offsets from ap_trampoline, and the 64 bit half addresses everything relative to
+// rip, so it runs the same wherever the copy ends up. The page has to be identity mapped for when paging comes on.
+global_asm!(
+    ".global ap_trampoline",
+    ".global ap_trampoline_gdt",
+    ".global ap_trampoline_gdt_pointer",
+    ".global ap_trampoline_cr0",
+    ".global ap_trampoline_cr3",
+    ".global ap_trampoline_cr4",
+    ".global ap_trampoline_stack",
+    ".global ap_trampoline_entry",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "mov ss, ax",
+    // the end of the page is free, the far return below needs a stack
+    "mov sp, 0x1000",
+    "lgdt [AP_TRAMPOLINE_GDT_POINTER]",
+    // the same paging setup the boot processor has, PCIDs can only be turned on once we're in long mode
+    "mov eax, [AP_TRAMPOLINE_CR4]",
+    "mov cr4, eax",
+    "mov eax, [AP_TRAMPOLINE_CR3]",
+    "mov cr3, eax",
+    "mov ecx, {efer}",
+    "rdmsr",
+    "or eax, {efer_bits}",
+    "wrmsr",
+    "mov eax, [AP_TRAMPOLINE_CR0]",
+    "mov cr0, eax",
+    // That's compatibility mode, a far return into the 64 bit code segment does the rest. It wants a linear address.
+    "xor eax, eax",
+    "mov ax, cs",
+    "shl eax, 4",
+    "add eax, offset AP_TRAMPOLINE_64",
+    "mov ebx, {code_selector}",
+    "push ebx",
+    "push eax",
+    // retf with a 32 bit operand size, which LLVM has no mnemonic for
+    ".byte 0x66, 0xcb",
+    ".code64",
+    "ap_trampoline_64:",
+    "mov ax, {data_selector}",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "xor eax, eax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, [rip + ap_trampoline_stack]",
+    "call [rip + ap_trampoline_entry]",
+    "ud2",
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    // 64 bit code and flat data, like the kernel's own
+    ".quad 0x00AF9A000000FFFF",
+    ".quad 0x00CF92000000FFFF",
+    "ap_trampoline_gdt_pointer:",
+    ".short ap_trampoline_gdt_pointer - ap_trampoline_gdt - 1",
+    ".long 0",
+    ".balign 8",
+    "ap_trampoline_cr0:",
+    ".quad 0",
+    "ap_trampoline_cr3:",
+    ".quad 0",
+    "ap_trampoline_cr4:",
+    ".quad 0",
+    "ap_trampoline_stack:",
+    ".quad 0",
+    "ap_trampoline_entry:",
+    ".quad 0",
+    "ap_trampoline_end:",
+    // LLVM won't take the difference of two labels in an operand, but it does take a name for it
+    ".set AP_TRAMPOLINE_64, ap_trampoline_64 - ap_trampoline",
+    ".set AP_TRAMPOLINE_GDT_POINTER, ap_trampoline_gdt_pointer - ap_trampoline",
+    ".set AP_TRAMPOLINE_CR0, ap_trampoline_cr0 - ap_trampoline",
+    ".set AP_TRAMPOLINE_CR3, ap_trampoline_cr3 - ap_trampoline",
+    ".set AP_TRAMPOLINE_CR4, ap_trampoline_cr4 - ap_trampoline",
+    efer = const IA32_EFER,
+    efer_bits = const EFER_LONG_MODE_ENABLE | EFER_NO_EXECUTE_ENABLE,
+    code_selector = const TRAMPOLINE_CODE_SELECTOR,
+    data_selector = const TRAMPOLINE_DATA_SELECTOR,
+);
+
+extern "C" {
+    static ap_trampoline: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdt_pointer: u8;
+    static ap_trampoline_cr0: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_cr4: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_end: u8;
+}
+
+// A copy of the trampoline in low memory. Dropping it unmaps it and frees the frame, so it has to outlive every
+// CPU that's been sent there.
+pub struct Trampoline {
+    frame: PhysicalFrame,
+}
+
+impl Trampoline {
+    // Copies the trampoline into frame, which has to be below 1MiB, and identity maps it in the kernel's address
+    // space. The CPUs it starts get the boot processor's control registers and page tables.
+    pub unsafe fn install(frame: PhysicalFrame) -> Result<Self, MapError> {
+        let page = Page::from_address_aligned(
+            VirtualAddress::new(frame.start_address().0),
+            PageSize::NORMAL,
+        );
+        // the far return pushes onto the end of the page, after paging is on
+        Mapper::active().map_to(page, frame, PageTableFlags::WRITABLE)?;
+        let trampoline = Trampoline { frame };
+        let start = addr_of!(ap_trampoline);
+        let size = addr_of!(ap_trampoline_end) as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, trampoline.field::<u8>(start), size);
+        let gdt = trampoline.linear_address(addr_of!(ap_trampoline_gdt));
+        // the base comes right after the 16 bit limit
+        let gdt_base = trampoline
+            .field::<u8>(addr_of!(ap_trampoline_gdt_pointer))
+            .add(2);
+        (gdt_base as *mut u32).write_unaligned(gdt as u32);
+        *trampoline.field(addr_of!(ap_trampoline_cr0)) = read_cr0();
+        // loaded from real mode, so only the low 32 bits make it
+        let level_4_table = read_cr3();
+        assert!(
+            level_4_table.0 <= u32::MAX as u64,
+            "the kernel's page tables are above 4GiB"
+        );
+        *trampoline.field(addr_of!(ap_trampoline_cr3)) = level_4_table.0;
+        *trampoline.field(addr_of!(ap_trampoline_cr4)) = read_cr4() & !CR4_PCID_ENABLE;
+        Ok(trampoline)
+    }
+
+    pub fn page(&self) -> PhysicalAddress {
+        self.frame.start_address()
+    }
+
+    // Where the next CPU to start goes once it's in long mode, and the stack it gets for it
+    pub fn prepare(&self, entry: extern "C" fn() -> !, stack_top: VirtualAddress) {
+        unsafe {
+            *self.field(addr_of!(ap_trampoline_entry)) = entry as usize as u64;
+            *self.field(addr_of!(ap_trampoline_stack)) = stack_top.0;
+        }
+    }
+
+    // Where a label in the original ends up in the copy, as the CPU sees it before paging
+    fn linear_address(&self, label: *const u8) -> u64 {
+        self.page().0 + (label as u64 - addr_of!(ap_trampoline) as u64)
+    }
+
+    // The same, through the physical memory mapping, for filling things in
+    unsafe fn field<T>(&self, label: *const u8) -> *mut T {
+        physical_to_virtual(PhysicalAddress::new(self.linear_address(label))).as_mut_ptr::<T>()
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        let page = Page::from_address_aligned(VirtualAddress::new(self.page().0), PageSize::NORMAL);
+        unsafe {
+            let mut mapper = Mapper::active();
+            if mapper.unmap(page).is_ok() {
+                mapper.flush();
+                deallocate_frame(self.frame);
+            }
+        }
+    }
+}
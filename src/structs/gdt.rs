@@ -1,10 +1,10 @@
 use core::arch::asm;
 use core::mem::size_of;
 
-use lazy_static::lazy_static;
-
 use crate::memory::address::VirtualAddress;
-use crate::structs::tss::{TaskStateSegment, TSS};
+use crate::percpu;
+use crate::structs::tss::{self, TaskStateSegment};
+use crate::sync::Once;
 
 #[derive(Debug, Clone, Copy)]
 pub struct GlobalDescriptorTable {
@@ -24,9 +24,40 @@ impl GlobalDescriptorTable {
         }
     }
 
-    fn add_entry(&mut self, index: usize, segment_desc: SegmentDescriptor) -> SegmentSelector {
-        self.descriptor_table[index] = segment_desc;
-        SegmentSelector::new(index as u16, segment_desc.get_requested_privilege_level())
+    fn add_entry(&mut self, selector: SegmentSelector, segment_desc: SegmentDescriptor) {
+        debug_assert_eq!(
+            selector.0 & 3,
+            segment_desc.get_requested_privilege_level() as u16
+        );
+        self.descriptor_table[(selector.0 >> 3) as usize] = segment_desc;
+    }
+
+    // Every CPU's GDT is the same apart from the TSS it points at
+    fn with_tss(tss: &'static TaskStateSegment) -> GlobalDescriptorTable {
+        let mut gdt = GlobalDescriptorTable::new();
+        // the null segment descriptor is left alone as the 0th entry of the GDT
+        gdt.add_entry(
+            SELECTORS.kernel_code_segment,
+            SegmentDescriptor::kernel_code_segment_descriptor(),
+        );
+        gdt.add_entry(
+            SELECTORS.kernel_data_segment,
+            SegmentDescriptor::kernel_data_segment_descriptor(),
+        );
+        gdt.add_entry(
+            SELECTORS.user_data_segment,
+            SegmentDescriptor::user_data_segment_descriptor(),
+        );
+        gdt.add_entry(
+            SELECTORS.user_code_segment,
+            SegmentDescriptor::user_code_segment_descriptor(),
+        );
+        let (tss_system_segment_low, tss_system_segment_high) =
+            SegmentDescriptor::tss_system_segment(tss);
+        gdt.add_entry(SELECTORS.tss_system_segment, tss_system_segment_low);
+        // the high half of a system segment takes up the entry after it, nothing ever references it on its own
+        gdt.add_entry(SegmentSelector::new(6, 0), tss_system_segment_high);
+        gdt
     }
 
     fn pointer(&self) -> GdtPointer {
@@ -38,6 +69,30 @@ impl GlobalDescriptorTable {
     pub unsafe fn load_gdt(gdt_ptr: &GdtPointer) {
         asm!("lgdt [{}]", in(reg) gdt_ptr, options(readonly, nostack, preserves_flags))
     }
+
+    // lgdt doesn't touch the segment registers, they keep using whatever descriptors they cached from the
+    // bootloader's GDT until they get reloaded. CS can't be moved into directly, so it goes through a far return.
+    pub unsafe fn reload_segments(code: SegmentSelector, data: SegmentSelector) {
+        asm!(
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) code.0 as u64,
+            data = in(reg) data.0 as u64,
+            tmp = out(reg) _,
+            options(preserves_flags),
+        );
+    }
+
+    // Intel Manual - Section 7.2.4, the TSS descriptor has to be in the GDT that's currently loaded
+    pub unsafe fn load_tss(selector: SegmentSelector) {
+        asm!("ltr {:x}", in(reg) selector.0, options(nostack, preserves_flags));
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -114,7 +169,8 @@ impl SegmentDescriptor {
             limit_low: (size_of::<TaskStateSegment>() - 1) as u16,
             base_low: (tss_ptr.0 & 0x0000_FFFF) as u16,
             base_middle: ((tss_ptr.0 & 0x00FF_0000) >> 16) as u8,
-            access: 0xE9_u8,
+            // present, DPL 0, available 64 bit TSS
+            access: 0x89_u8,
             granularity: 0x00_u8,
             base_high: ((tss_ptr.0 & 0xFF00_0000) >> 24) as u8,
         };
@@ -129,10 +185,10 @@ impl SegmentDescriptor {
         (tss_system_segment_low, tss_system_segment_high)
     }
 
-    // convenience method
+    // convenience method, the DPL of the descriptor is the RPL its selectors get
     pub fn get_requested_privilege_level(&self) -> u8 {
         let rpl_bit_mask: u8 = 0b0110_0000;
-        self.access & rpl_bit_mask
+        (self.access & rpl_bit_mask) >> 5
     }
 }
 
@@ -141,7 +197,7 @@ impl SegmentDescriptor {
 pub struct SegmentSelector(pub u16);
 
 impl SegmentSelector {
-    pub fn new(index: u16, dpl: u8) -> SegmentSelector {
+    pub const fn new(index: u16, dpl: u8) -> SegmentSelector {
         SegmentSelector(index << 3 | dpl as u16)
     }
 }
@@ -154,33 +210,33 @@ pub struct Selectors {
     pub tss_system_segment: SegmentSelector,
 }
 
-lazy_static! {
-    pub static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        // the null segment descriptor is left alone as the 0th entry of the GDT
-        let kernel_code_segment =
-            gdt.add_entry(1, SegmentDescriptor::kernel_code_segment_descriptor());
-        let kernel_data_segment =
-            gdt.add_entry(2, SegmentDescriptor::kernel_data_segment_descriptor());
-        let user_code_segment = gdt.add_entry(3, SegmentDescriptor::user_code_segment_descriptor());
-        let user_data_segment = gdt.add_entry(4, SegmentDescriptor::user_data_segment_descriptor());
-        let (tss_system_segment_low, tss_system_segment_high) =
-            SegmentDescriptor::tss_system_segment(&TSS);
-        let tss_system_segment = gdt.add_entry(5, tss_system_segment_low);
-        let _ = gdt.add_entry(6, tss_system_segment_high); // we shouldn't ever need to reference the high segment of a system segment
-        let selectors = Selectors {
-            kernel_code_segment,
-            kernel_data_segment,
-            user_code_segment,
-            user_data_segment,
-            tss_system_segment,
-        };
-        (gdt, selectors)
-    };
+// The GDTs are all laid out the same, so the selectors are the same on every CPU.
+// sysret finds the user segments relative to STAR[63:48]: SS is the next entry and CS the one after that,
+// so user data has to come right before user code -> Intel Manual - Volume 2B, SYSRET
+pub static SELECTORS: Selectors = Selectors {
+    kernel_code_segment: SegmentSelector::new(1, 0),
+    kernel_data_segment: SegmentSelector::new(2, 0),
+    user_data_segment: SegmentSelector::new(3, 3),
+    user_code_segment: SegmentSelector::new(4, 3),
+    tss_system_segment: SegmentSelector::new(5, 0),
+};
+
+percpu! {
+    // one per CPU, since each one has its own TSS
+    static GDT: Once<GlobalDescriptorTable> = Once::new();
 }
 
+// Loads the GDT of the CPU we're running on, building it the first time
 pub fn init_gdt() {
+    let gdt = GDT
+        .get()
+        .call_once(|| GlobalDescriptorTable::with_tss(tss::init()));
     unsafe {
-        GlobalDescriptorTable::load_gdt(&GDT.0.pointer());
+        GlobalDescriptorTable::load_gdt(&gdt.pointer());
+        GlobalDescriptorTable::reload_segments(
+            SELECTORS.kernel_code_segment,
+            SELECTORS.kernel_data_segment,
+        );
+        GlobalDescriptorTable::load_tss(SELECTORS.tss_system_segment);
     }
 }
@@ -1,13 +1,12 @@
 use core::arch::asm;
 use core::mem::size_of;
 
-use lazy_static::lazy_static;
-
 use crate::interrupts::consts::*;
 use crate::interrupts::interrupt_handlers::*;
 use crate::memory::address::VirtualAddress;
-use crate::structs::gdt::{SegmentSelector, GDT};
+use crate::structs::gdt::{SegmentSelector, SELECTORS};
 use crate::structs::tss::*;
+use crate::sync::Lazy;
 
 #[derive(Debug, Clone)]
 #[repr(C, align(16))]
@@ -55,7 +54,7 @@ impl GateDescriptor {
     fn new(gate_options: GateOptions) -> Self {
         GateDescriptor {
             offset_low: 0,
-            segment: GDT.1.kernel_code_segment,
+            segment: SELECTORS.kernel_code_segment,
             gate_options,
             offset_middle: 0,
             offset_high: 0,
@@ -90,16 +89,23 @@ impl GateOptions {
         self
     }
 
+    // lets ring 3 use the gate with int n, otherwise that's a general protection fault
     fn dpl_3(mut self) -> Self {
-        self.0 &= 0xCFFF;
+        self.0 |= 0x6000;
         self
     }
 
+    // turns the interrupt gate into a trap gate, which doesn't clear IF on the way in
     fn enable_interrupts(mut self) -> Self {
         self.0 |= 0x0100;
         self
     }
 
+    // an interrupt gate, for handlers that can't be interrupted themselves
+    fn interrupt_gate() -> Self {
+        GateOptions::minimal().set_present().dpl_0()
+    }
+
     fn default() -> Self {
         GateOptions::minimal()
             .set_present()
@@ -114,54 +120,89 @@ impl GateOptions {
     }
 }
 
-lazy_static! {
-
-    // This code is an eye-sore, clean this up
-    pub static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        let debug_exception_gate_descriptor =
-            GateDescriptor::new(GateOptions::default().set_stack_index(DEBUG_STACK_TABLE_INDEX))
-                .set_handler_address(VirtualAddress::new(
-                    (debug_exception_handler as usize) as u64,
-                ));
-        let nmi_gate_descriptor =
-            GateDescriptor::new(GateOptions::default().set_stack_index(NMI_STACK_TABLE_INDEX))
-                .set_handler_address(VirtualAddress::new((nmi_handler as usize) as u64));
-        let breakpoint_gate_descriptor = GateDescriptor::new(GateOptions::default())
-            .set_handler_address(VirtualAddress::new(
-                (breakpoint_exception_handler as usize) as u64,
-            ));
-        let segment_not_present_descriptor = GateDescriptor::new(GateOptions::default())
-            .set_handler_address(VirtualAddress::new(
-                (segment_not_present_handler as usize) as u64,
-            ));
-        let double_fault_descriptor = GateDescriptor::new(
-            GateOptions::default().set_stack_index(DOUBLE_FAULT_STACK_TABLE_INDEX),
-        )
-        .set_handler_address(VirtualAddress::new((double_fault_handler as usize) as u64));
-
-        let stack_segment_fault_descriptor = GateDescriptor::new(
-            GateOptions::default().set_stack_index(STACK_SEGMENT_FAULT_STACK_TABLE_INDEX),
-        )
-        .set_handler_address(VirtualAddress::new(
-            (stack_segment_fault_handler as usize) as u64,
-        ));
-        let general_protection_fault_gate_descriptor = GateDescriptor::new(
-            GateOptions::default().set_stack_index(GENERAL_PROTECTION_STACK_TABLE_INDEX),
-        )
+// This code is an eye-sore, clean this up
+pub static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    let gate = |options: GateOptions, handler: usize| {
+        GateDescriptor::new(options).set_handler_address(VirtualAddress::new(handler as u64))
+    };
+    // Anything that shows up on a vector with nothing else installed gets reported and ignored, rather than being a
+    // general protection fault for the missing gate
+    for vector in PIC_1_OFFSET..idt.descriptor_table.len() {
+        idt.descriptor_table[vector] = gate(
+            GateOptions::interrupt_gate(),
+            unexpected_interrupt_handler as usize,
+        );
+    }
+    // Exceptions all go through interrupt gates, see interrupt_handlers
+    let exception = GateOptions::interrupt_gate;
+    idt.descriptor_table[DIVIDE_ERROR] = gate(exception(), divide_error_handler as usize);
+    idt.descriptor_table[DEBUG_EXCEPTION] = gate(
+        exception().set_stack_index(DEBUG_STACK_TABLE_INDEX),
+        debug_exception_handler as usize,
+    );
+    idt.descriptor_table[NMI_INTERRUPT] = gate(
+        exception().set_stack_index(NMI_STACK_TABLE_INDEX),
+        nmi_handler as usize,
+    );
+    // ring 3 can use int3 itself, like int 0x80
+    idt.descriptor_table[BREAKPOINT] =
+        gate(exception().dpl_3(), breakpoint_exception_handler as usize);
+    idt.descriptor_table[OVERFLOW] = gate(exception(), overflow_handler as usize);
+    idt.descriptor_table[BOUND_RANGE_EXCEEDED] =
+        gate(exception(), bound_range_exceeded_handler as usize);
+    idt.descriptor_table[INVALID_OPCODE] = gate(exception(), invalid_opcode_handler as usize);
+    idt.descriptor_table[DEVICE_NOT_AVAILABLE] =
+        gate(exception(), device_not_available_handler as usize);
+    idt.descriptor_table[DOUBLE_FAULT] = gate(
+        exception().set_stack_index(DOUBLE_FAULT_STACK_TABLE_INDEX),
+        double_fault_handler as usize,
+    );
+    idt.descriptor_table[INVALID_TSS] = gate(exception(), invalid_tss_handler as usize);
+    idt.descriptor_table[SEGMENT_NOT_PRESENT] =
+        gate(exception(), segment_not_present_handler as usize);
+    idt.descriptor_table[STACK_SEGMENT_FAULT] = gate(
+        exception().set_stack_index(STACK_SEGMENT_FAULT_STACK_TABLE_INDEX),
+        stack_segment_fault_handler as usize,
+    );
+    idt.descriptor_table[GENERAL_PROTECTION] = gate(
+        exception().set_stack_index(GENERAL_PROTECTION_STACK_TABLE_INDEX),
+        general_protection_fault_handler as usize,
+    );
+    idt.descriptor_table[X87_FLOATING_POINT_ERROR] =
+        gate(exception(), x87_floating_point_handler as usize);
+    idt.descriptor_table[ALIGNMENT_CHECK] = gate(exception(), alignment_check_handler as usize);
+    idt.descriptor_table[MACHINE_CHECK] = gate(
+        exception().set_stack_index(MACHINE_CHECK_STACK_TABLE_INDEX),
+        machine_check_handler as usize,
+    );
+    idt.descriptor_table[SIMD_FLOATING_POINT_EXCEPTION] =
+        gate(exception(), simd_floating_point_handler as usize);
+    idt.descriptor_table[VIRTUALIZATION_EXCEPTION] =
+        gate(exception(), virtualization_exception_handler as usize);
+    let page_fault_descriptor = GateDescriptor::new(GateOptions::interrupt_gate())
+        .set_handler_address(VirtualAddress::new(page_fault_handler as usize as u64));
+    idt.descriptor_table[PAGE_FAULT] = page_fault_descriptor;
+    let syscall_gate_descriptor = GateDescriptor::new(GateOptions::default().dpl_3())
         .set_handler_address(VirtualAddress::new(
-            (general_protection_fault_handler as usize) as u64,
+            crate::syscall::int80_entry as usize as u64,
         ));
-        idt.descriptor_table[DEBUG_EXCEPTION] = debug_exception_gate_descriptor;
-        idt.descriptor_table[NMI_INTERRUPT] = nmi_gate_descriptor;
-        idt.descriptor_table[BREAKPOINT] = breakpoint_gate_descriptor;
-        idt.descriptor_table[SEGMENT_NOT_PRESENT] = segment_not_present_descriptor;
-        idt.descriptor_table[DOUBLE_FAULT] = double_fault_descriptor;
-        idt.descriptor_table[STACK_SEGMENT_FAULT] = stack_segment_fault_descriptor;
-        idt.descriptor_table[GENERAL_PROTECTION] = general_protection_fault_gate_descriptor;
-        idt
-    };
-}
+    idt.descriptor_table[SYSCALL_INTERRUPT] = syscall_gate_descriptor;
+    // the timer switches threads, which has to happen with interrupts off
+    let timer_gate_descriptor = GateDescriptor::new(GateOptions::interrupt_gate())
+        .set_handler_address(VirtualAddress::new(timer_interrupt_entry as usize as u64));
+    idt.descriptor_table[TIMER_INTERRUPT] = timer_gate_descriptor;
+    let ahci_gate_descriptor = GateDescriptor::new(GateOptions::interrupt_gate())
+        .set_handler_address(VirtualAddress::new(ahci_interrupt_handler as usize as u64));
+    idt.descriptor_table[AHCI_INTERRUPT] = ahci_gate_descriptor;
+    let spurious_gate_descriptor = GateDescriptor::new(GateOptions::default()).set_handler_address(
+        VirtualAddress::new((spurious_interrupt_handler as usize) as u64),
+    );
+    idt.descriptor_table[PIC_1_SPURIOUS_INTERRUPT] = spurious_gate_descriptor;
+    idt.descriptor_table[PIC_2_SPURIOUS_INTERRUPT] = spurious_gate_descriptor;
+    idt.descriptor_table[SPURIOUS_INTERRUPT] = spurious_gate_descriptor;
+    idt
+});
 
 pub fn init_idt() {
     unsafe {
@@ -1,11 +1,12 @@
+use core::cell::UnsafeCell;
 use core::mem::size_of;
 
-use lazy_static::lazy_static;
-
 use crate::memory::address::VirtualAddress;
+use crate::percpu;
+use crate::task::stack::KernelStack;
 
-const STACK_SIZE: usize = 1 << 12; // 4KB
-
+// RSP0 is what the CPU switches to when an interrupt arrives in ring 3, it gets replaced by the running task's
+// kernel stack (see set_kernel_stack). RSP2 would only ever be used coming from ring 2, which we don't have.
 pub const PRIVILEGE_LEVEL_ZERO_STACK_TABLE_INDEX: usize = 0x00;
 pub const PRIVILEGE_LEVEL_THREE_STACK_TABLE_INDEX: usize = 0x02;
 
@@ -36,7 +37,7 @@ pub struct TaskStateSegment {
 }
 
 impl TaskStateSegment {
-    pub fn new() -> TaskStateSegment {
+    pub const fn new() -> TaskStateSegment {
         TaskStateSegment {
             reserved_1: 0,
             privilege_stack_table: [VirtualAddress::zero(); 3],
@@ -52,10 +53,10 @@ impl TaskStateSegment {
         VirtualAddress::new(self as *const _ as u64)
     }
 
+    // Every entry gets a stack of its own, otherwise e.g. a double fault in the middle of a general protection fault
+    // tramples all over the stack that was in use
     fn init_stack_table(&mut self, stack_table_index: usize, stack_table_type: StackTableType) {
-        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-        let stack_ptr: u64 = unsafe { &STACK as *const _ as u64 } + STACK_SIZE as u64;
-        let canonical_stack_ptr = VirtualAddress::new(stack_ptr);
+        let canonical_stack_ptr = allocate_stack();
         match stack_table_type {
             StackTableType::Privilege => {
                 debug_assert!(stack_table_index < 3);
@@ -67,24 +68,96 @@ impl TaskStateSegment {
             }
         }
     }
+
+    pub fn privilege_stack(&self, privilege_level: usize) -> VirtualAddress {
+        self.privilege_stack_table[privilege_level]
+    }
+}
+
+// The CPU reads RSP0 out of the TSS on every ring 3 -> ring 0 transition, and it has to change on every task switch,
+// so unlike the GDT and IDT this can't just be immutable once it's built.
+pub struct TaskStateSegmentCell(UnsafeCell<TaskStateSegment>);
+
+// Only ever written with interrupts disabled, on the CPU that owns it
+unsafe impl Sync for TaskStateSegmentCell {}
+
+impl TaskStateSegmentCell {
+    const fn new(tss: TaskStateSegment) -> Self {
+        TaskStateSegmentCell(UnsafeCell::new(tss))
+    }
+
+    pub fn get(&self) -> &TaskStateSegment {
+        unsafe { &*self.0.get() }
+    }
+
+    // Intel Manual - Section 6.12.1: an interrupt or exception in ring 3 switches to this stack before pushing anything
+    pub unsafe fn set_kernel_stack(&self, stack_top: VirtualAddress) {
+        let tss = self.0.get();
+        (*tss).privilege_stack_table[PRIVILEGE_LEVEL_ZERO_STACK_TABLE_INDEX] = stack_top;
+    }
+}
+
+// Exception handlers format whole stack frames with {:#?}, which needs more than 4KB in a debug build, kernel stacks
+// are 16KB. A CPU uses its stacks for as long as it runs, so they're never given back.
+fn allocate_stack() -> VirtualAddress {
+    let stack = KernelStack::allocate().expect("failed to allocate a TSS stack");
+    let top = stack.top();
+    core::mem::forget(stack);
+    top
+}
+
+percpu! {
+    // the CPU marks its own busy when it loads it, so they can't be shared
+    static TSS: TaskStateSegmentCell = TaskStateSegmentCell::new(TaskStateSegment::new());
+}
+
+// Gives this CPU's TSS its stacks and hands it out for the CPU's GDT to point at. Only called once per CPU.
+pub fn init() -> &'static TaskStateSegment {
+    let tss = unsafe { &mut *TSS.get().0.get() };
+    // Intel Software Developer's Manual - section 6.14.5
+    // See https://www.kernel.org/doc/Documentation/x86/kernel-stacks
+    // Privilege Stacks
+    tss.init_stack_table(
+        PRIVILEGE_LEVEL_ZERO_STACK_TABLE_INDEX,
+        StackTableType::Privilege,
+    );
+    tss.init_stack_table(
+        PRIVILEGE_LEVEL_THREE_STACK_TABLE_INDEX,
+        StackTableType::Privilege,
+    );
+
+    // Interrupt Stacks
+    tss.init_stack_table(DEBUG_STACK_TABLE_INDEX, StackTableType::Interrupt);
+    tss.init_stack_table(NMI_STACK_TABLE_INDEX, StackTableType::Interrupt);
+    tss.init_stack_table(DOUBLE_FAULT_STACK_TABLE_INDEX, StackTableType::Interrupt);
+    tss.init_stack_table(
+        STACK_SEGMENT_FAULT_STACK_TABLE_INDEX,
+        StackTableType::Interrupt,
+    );
+    tss.init_stack_table(
+        GENERAL_PROTECTION_STACK_TABLE_INDEX,
+        StackTableType::Interrupt,
+    );
+    tss.init_stack_table(MACHINE_CHECK_STACK_TABLE_INDEX, StackTableType::Interrupt);
+    TSS.get().get()
+}
+
+// The TSS of the CPU we're running on
+pub fn current() -> &'static TaskStateSegmentCell {
+    TSS.get()
+}
+
+// Called on every switch to a task that can drop to ring 3, the stack is that task's own kernel stack.
+// syscall doesn't look at the TSS, so its entry stub gets told separately.
+pub fn set_kernel_stack(stack_top: VirtualAddress) {
+    crate::interrupts::without_interrupts(|| {
+        unsafe { current().set_kernel_stack(stack_top) };
+        crate::syscall::set_kernel_stack(stack_top);
+    });
 }
 
-lazy_static! {
-    pub static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // Intel Software Developer's Manual - section 6.14.5
-        // See https://www.kernel.org/doc/Documentation/x86/kernel-stacks
-        // Privilege Stacks
-        tss.init_stack_table(PRIVILEGE_LEVEL_ZERO_STACK_TABLE_INDEX, StackTableType::Privilege);
-        tss.init_stack_table(PRIVILEGE_LEVEL_THREE_STACK_TABLE_INDEX, StackTableType::Privilege);
-
-        // Interrupt Stacks
-        tss.init_stack_table(DEBUG_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss.init_stack_table(NMI_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss.init_stack_table(DOUBLE_FAULT_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss.init_stack_table(STACK_SEGMENT_FAULT_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss.init_stack_table(GENERAL_PROTECTION_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss.init_stack_table(MACHINE_CHECK_STACK_TABLE_INDEX, StackTableType::Interrupt);
-        tss
-    };
+pub fn kernel_stack() -> VirtualAddress {
+    current()
+        .get()
+        .privilege_stack(PRIVILEGE_LEVEL_ZERO_STACK_TABLE_INDEX)
 }
@@ -0,0 +1,64 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::MutexGuard;
+use crate::task::WaitQueue;
+
+// Lets a thread holding a Mutex sleep until another one says something changed. Like any condition variable it can
+// wake up without anything having changed, so the condition always has to be checked again, see wait_while.
+#[derive(Debug)]
+pub struct Condvar {
+    // Bumped by every notify. A waiter only sleeps while it's the same as when it let go of the mutex, so a notify
+    // that comes in between the two isn't lost.
+    generation: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            generation: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    // Lets go of the mutex, sleeps until notified and takes the mutex again
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+        drop(guard);
+        self.waiters
+            .wait_while(|| self.generation.load(Ordering::Acquire) == generation);
+        mutex.lock()
+    }
+
+    // Waits for as long as condition holds for what the mutex protects
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
@@ -0,0 +1,18 @@
+// Locks and friends. The spinning ones work anywhere, interrupt handlers included. The sleeping ones (Mutex,
+// Semaphore, Condvar) block the thread on a WaitQueue instead, so they're only for threads, and only once task::init
+// has run.
+pub mod condvar;
+pub mod mutex;
+pub mod once;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spinlock;
+pub mod ticket_lock;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use once::{Lazy, Once};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{SpinLock, SpinLockGuard};
+pub use ticket_lock::{TicketLock, TicketLockGuard};
@@ -0,0 +1,100 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+// A value that's set exactly once, by whoever gets to call_once first. Anyone who comes along while that's still
+// running spins until it's done, so calling call_once from inside its own f never returns.
+pub struct Once<T> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU8::new(INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn call_once<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        if self
+            .state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            unsafe { (*self.data.get()).write(f()) };
+            self.state.store(COMPLETE, Ordering::Release);
+        } else {
+            while self.state.load(Ordering::Acquire) == RUNNING {
+                spin_loop();
+            }
+        }
+        unsafe { (*self.data.get()).assume_init_ref() }
+    }
+
+    // None until call_once has finished
+    pub fn get(&self) -> Option<&T> {
+        self.is_completed()
+            .then(|| unsafe { (*self.data.get()).assume_init_ref() })
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Once::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.data.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+// A value that's worked out the first time it's used, for statics that can't be built in a const. What lazy_static
+// does, without the macro.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            once: Once::new(),
+            init,
+        }
+    }
+
+    // Does the work now rather than on first use
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| (this.init)())
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
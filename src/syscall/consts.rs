@@ -0,0 +1,87 @@
+// System call numbers, passed in rax. These index straight into SYSCALL_TABLE.
+pub const SYS_EXIT: usize = 0;
+pub const SYS_WRITE: usize = 1;
+pub const SYS_FORK: usize = 2;
+pub const SYS_EXECVE: usize = 3;
+pub const SYS_WAITPID: usize = 4;
+pub const SYS_GETPID: usize = 5;
+pub const SYS_GETPPID: usize = 6;
+pub const SYS_MMAP: usize = 7;
+pub const SYS_MUNMAP: usize = 8;
+pub const SYS_MPROTECT: usize = 9;
+pub const SYS_BRK: usize = 10;
+pub const SYS_OPEN: usize = 11;
+pub const SYS_READ: usize = 12;
+pub const SYS_CLOSE: usize = 13;
+pub const SYS_LSEEK: usize = 14;
+pub const SYS_STAT: usize = 15;
+
+pub const SYSCALL_COUNT: usize = 64;
+
+// File descriptors every task starts out with
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+// open flags, the same as Linux's. The lowest two bits are the access mode.
+pub const O_ACCESS_MODE: u64 = 0o3;
+pub const O_RDONLY: u64 = 0o0;
+pub const O_WRONLY: u64 = 0o1;
+pub const O_RDWR: u64 = 0o2;
+pub const O_CREAT: u64 = 0o100;
+pub const O_EXCL: u64 = 0o200;
+pub const O_TRUNC: u64 = 0o1000;
+pub const O_APPEND: u64 = 0o2000;
+pub const O_DIRECTORY: u64 = 0o200000;
+pub const O_NOFOLLOW: u64 = 0o400000;
+
+// lseek whence
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+// waitpid options
+pub const WNOHANG: u64 = 1;
+
+// mmap and mprotect protections, and mmap flags
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1 << 0;
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+// Errors come back as the negated error number. The numbers are the same as Linux's so they're easy to look up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+}
@@ -0,0 +1,327 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::fs::{self, InodeKind, Metadata, OpenOptions, SeekFrom};
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::{KERNEL_HEAP_SIZE, USER_SPACE_END, USER_SPACE_START};
+use crate::process::elf::{Elf, USER_STACK_SIZE, USER_STACK_TOP};
+use crate::process::vma::MMAP_BASE;
+use crate::process::{self, ProcessId, VmaKind};
+use crate::syscall::consts::*;
+use crate::syscall::user::{
+    user_pages, user_slice, user_slice_mut, user_string, user_string_array,
+};
+use crate::syscall::{SyscallFrame, SyscallResult};
+use crate::task::context::FpuState;
+use crate::usermode::{exit_to_kernel, return_to_user_mode};
+
+// What a process whose execve failed after its old program was already gone exits with, the same as a shell's
+// "command not found"
+const EXEC_FAILED_EXIT_STATUS: u64 = 127;
+
+// Programs are read onto the kernel heap whole before they're loaded, which leaves room for everything else there
+const MAX_EXECUTABLE_SIZE: u64 = KERNEL_HEAP_SIZE / 4;
+
+// exit(status) -> never returns
+pub fn sys_exit(frame: &mut SyscallFrame) -> SyscallResult {
+    let [status, ..] = frame.arguments();
+    unsafe { exit_to_kernel(status) }
+}
+
+// write(fd, buffer, length) -> bytes written
+// Anything written to the console has to be valid UTF-8
+pub fn sys_write(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buffer, length, ..] = frame.arguments();
+    let file = process::current().files().get(fd)?.clone();
+    let bytes = user_slice(buffer, length)?;
+    Ok(file.write(bytes)? as u64)
+}
+
+// read(fd, buffer, length) -> bytes read, 0 at the end of the file
+pub fn sys_read(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buffer, length, ..] = frame.arguments();
+    let file = process::current().files().get(fd)?.clone();
+    let buffer = user_slice_mut(buffer, length)?;
+    Ok(file.read(buffer)? as u64)
+}
+
+// open(path, flags, mode) -> fd
+// mode is only looked at when O_CREAT makes a new file
+pub fn sys_open(frame: &mut SyscallFrame) -> SyscallResult {
+    let [path, flags, mode, ..] = frame.arguments();
+    let known = O_ACCESS_MODE | O_CREAT | O_EXCL | O_TRUNC | O_APPEND | O_DIRECTORY | O_NOFOLLOW;
+    if flags & !known != 0 || mode > 0o7777 {
+        return Err(Errno::EINVAL);
+    }
+    let (read, write) = match flags & O_ACCESS_MODE {
+        O_RDONLY => (true, false),
+        O_WRONLY => (false, true),
+        O_RDWR => (true, true),
+        _ => return Err(Errno::EINVAL),
+    };
+    let options = OpenOptions {
+        read,
+        write,
+        append: flags & O_APPEND != 0,
+        create: flags & O_CREAT != 0,
+        exclusive: flags & O_EXCL != 0,
+        truncate: flags & O_TRUNC != 0,
+        directory: flags & O_DIRECTORY != 0,
+        no_follow: flags & O_NOFOLLOW != 0,
+        mode: mode as u16,
+    };
+    let file = fs::open(&user_string(path)?, &options)?;
+    process::current().files().insert(file)
+}
+
+// close(fd) -> 0
+pub fn sys_close(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, ..] = frame.arguments();
+    process::current().files().close(fd)?;
+    Ok(0)
+}
+
+// lseek(fd, offset, whence) -> the new offset
+pub fn sys_lseek(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, offset, whence, ..] = frame.arguments();
+    let from = match whence {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+    let file = process::current().files().get(fd)?.clone();
+    Ok(file.seek(from)?)
+}
+
+// struct stat on x86_64 Linux, which is what stat fills in
+#[repr(C)]
+struct Stat {
+    device: u64,
+    inode: u64,
+    links: u64,
+    mode: u32,
+    user: u32,
+    group: u32,
+    _padding: u32,
+    special_device: u64,
+    size: i64,
+    block_size: i64,
+    blocks: i64,
+    // seconds and nanoseconds
+    accessed: [i64; 2],
+    modified: [i64; 2],
+    changed: [i64; 2],
+    _reserved: [i64; 3],
+}
+
+impl From<Metadata> for Stat {
+    fn from(metadata: Metadata) -> Self {
+        let time = |time: core::time::Duration| [time.as_secs() as i64, time.subsec_nanos() as i64];
+        Stat {
+            device: 0,
+            inode: metadata.inode,
+            links: metadata.links,
+            mode: metadata.kind.mode_bits() | metadata.mode as u32,
+            user: 0,
+            group: 0,
+            _padding: 0,
+            special_device: 0,
+            size: metadata.size as i64,
+            block_size: PageSize::NORMAL as i64,
+            // in 512 byte blocks, whatever the block size
+            blocks: metadata.size.div_ceil(512) as i64,
+            accessed: time(metadata.accessed),
+            modified: time(metadata.modified),
+            changed: time(metadata.changed),
+            _reserved: [0; 3],
+        }
+    }
+}
+
+// stat(path, buffer) -> 0
+// Follows a symlink at the end of path, buffer gets a struct stat
+pub fn sys_stat(frame: &mut SyscallFrame) -> SyscallResult {
+    let [path, buffer, ..] = frame.arguments();
+    let stat = Stat::from(fs::metadata(&user_string(path)?)?);
+    let buffer = user_slice_mut(buffer, size_of::<Stat>() as u64)?;
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&stat as *const Stat as *const u8, size_of::<Stat>())
+    };
+    buffer.copy_from_slice(bytes);
+    Ok(0)
+}
+
+// fork() -> the child's pid in the parent, 0 in the child
+// The child carries on from the same place with the same registers, in a copy-on-write copy of the address space
+pub fn sys_fork(frame: &mut SyscallFrame) -> SyscallResult {
+    let parent = process::current();
+    let child = parent.fork().map_err(|_| Errno::ENOMEM)?;
+    let frame = frame.clone();
+    let mut fpu_state = FpuState::new();
+    fpu_state.save();
+    process::spawn_user_thread(&child, "fork", move || unsafe {
+        fpu_state.restore();
+        return_to_user_mode(&frame)
+    })
+    .map_err(|_| {
+        parent.disown(&child);
+        Errno::ENOMEM
+    })?;
+    Ok(child.id().as_u64())
+}
+
+// execve(path, argv, envp) -> doesn't return on success
+// path has to be a regular file with at least one of its execute bits set
+pub fn sys_execve(frame: &mut SyscallFrame) -> SyscallResult {
+    let [path, argv, envp, ..] = frame.arguments();
+    let path = user_string(path)?;
+    let argv = user_string_array(argv)?;
+    let envp = user_string_array(envp)?;
+    let metadata = fs::metadata(&path)?;
+    if metadata.kind != InodeKind::File || metadata.mode & 0o111 == 0 {
+        return Err(Errno::EACCES);
+    }
+    if metadata.size > MAX_EXECUTABLE_SIZE {
+        return Err(Errno::ENOMEM);
+    }
+    let executable = fs::read(&path)?;
+    // the last chance to fail and still have a program to go back to
+    Elf::parse(&executable).map_err(|_| Errno::ENOEXEC)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    match process::current().exec(&executable, &argv, &envp) {
+        Ok(image) => {
+            frame.start_over(image.entry, image.stack_pointer);
+            Ok(0)
+        }
+        Err(_) => unsafe { exit_to_kernel(EXEC_FAILED_EXIT_STATUS) },
+    }
+}
+
+// waitpid(pid, status, options) -> the pid of the child that exited, or 0 with WNOHANG if none have yet
+// pid -1 waits for any child. If status isn't null it gets the exit status in the same format as Linux's.
+pub fn sys_waitpid(frame: &mut SyscallFrame) -> SyscallResult {
+    let [pid, status, options, ..] = frame.arguments();
+    let pid = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(ProcessId::from_u64(pid as u64)),
+        _ => return Err(Errno::EINVAL),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let Some((pid, exit_status)) = process::wait(pid, options & WNOHANG == 0)? else {
+        return Ok(0);
+    };
+    if status != 0 {
+        let wait_status = ((exit_status & 0xFF) << 8) as u32;
+        user_slice_mut(status, 4)?.copy_from_slice(&wait_status.to_le_bytes());
+    }
+    Ok(pid.as_u64())
+}
+
+// getpid() -> pid
+pub fn sys_getpid(_frame: &mut SyscallFrame) -> SyscallResult {
+    Ok(process::current().id().as_u64())
+}
+
+// getppid() -> the parent's pid, 0 if it's the kernel or there isn't one
+pub fn sys_getppid(_frame: &mut SyscallFrame) -> SyscallResult {
+    Ok(process::current()
+        .parent()
+        .map_or(0, |parent| parent.id().as_u64()))
+}
+
+// What pages with protection prot get mapped with. x86 has no way to make a page writable or executable but not
+// readable, so anything but PROT_NONE is readable.
+fn protection_flags(prot: u64) -> Result<PageTableFlags, Errno> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut flags = PageTableFlags::EMPTY;
+    if prot != PROT_NONE {
+        flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+// mmap(address, length, prot, flags, fd, offset) -> where the mapping starts
+// Only private anonymous mappings for now, there are no files to map yet. Without MAP_FIXED address is just a hint,
+// which gets used if it's free. Nothing is actually mapped until it's touched.
+pub fn sys_mmap(frame: &mut SyscallFrame) -> SyscallResult {
+    let [address, length, prot, flags, _fd, _offset] = frame.arguments();
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(Errno::ENODEV);
+    }
+    if flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return Err(Errno::EINVAL);
+    }
+    let protection = protection_flags(prot)?;
+    let process = process::current();
+    let address_space = process.address_space();
+    let start = if flags & MAP_FIXED != 0 {
+        let (start, end) = user_pages(address, length)?;
+        address_space.remove_areas(start, end);
+        start
+    } else {
+        let size = length
+            .checked_next_multiple_of(PageSize::NORMAL as u64)
+            .filter(|&size| size != 0)
+            .ok_or(Errno::EINVAL)?;
+        let limit = VirtualAddress::new(USER_STACK_TOP - USER_STACK_SIZE);
+        user_pages(address, length)
+            .ok()
+            .and_then(|(hint, _)| address_space.find_free_area(size, hint, limit))
+            .or_else(|| address_space.find_free_area(size, VirtualAddress::new(MMAP_BASE), limit))
+            .ok_or(Errno::ENOMEM)?
+    };
+    address_space
+        .add_area(start, length, protection, VmaKind::Anonymous)
+        .map_err(|_| Errno::ENOMEM)?;
+    Ok(start.0)
+}
+
+// munmap(address, length) -> 0
+// Whatever's mapped in the range goes, it's fine if some or all of it wasn't
+pub fn sys_munmap(frame: &mut SyscallFrame) -> SyscallResult {
+    let [address, length, ..] = frame.arguments();
+    let (start, end) = user_pages(address, length)?;
+    process::current().address_space().remove_areas(start, end);
+    Ok(0)
+}
+
+// mprotect(address, length, prot) -> 0
+// Every page in the range has to be mapped
+pub fn sys_mprotect(frame: &mut SyscallFrame) -> SyscallResult {
+    let [address, length, prot, ..] = frame.arguments();
+    let (start, end) = user_pages(address, length)?;
+    let protection = protection_flags(prot)?;
+    process::current()
+        .address_space()
+        .protect(start, end, protection)
+        .map_err(|_| Errno::ENOMEM)?;
+    Ok(0)
+}
+
+// brk(address) -> where the heap ends now
+// Like Linux's, this never fails: asking for somewhere it can't go just gets back where the heap already ends, and
+// so does 0
+pub fn sys_brk(frame: &mut SyscallFrame) -> SyscallResult {
+    let [address, ..] = frame.arguments();
+    let process = process::current();
+    let address_space = process.address_space();
+    if !(USER_SPACE_START..=USER_SPACE_END).contains(&address) {
+        return Ok(address_space.brk().0);
+    }
+    Ok(address_space.set_brk(VirtualAddress::new(address)).0)
+}
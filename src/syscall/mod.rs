@@ -0,0 +1,269 @@
+use core::arch::naked_asm;
+
+use crate::cpu::{
+    read_msr, write_msr, EFER_SYSCALL_ENABLE, IA32_EFER, IA32_FMASK, IA32_LSTAR, IA32_STAR,
+};
+use crate::memory::address::VirtualAddress;
+use crate::smp::percpu::{self, cpu_data};
+use crate::structs::gdt::SELECTORS;
+use crate::structs::tss;
+
+pub mod consts;
+pub mod handlers;
+pub mod user;
+
+use consts::*;
+
+// Calling convention, the same registers Linux uses:
+//   rax                          system call number, see consts.rs
+//   rdi, rsi, rdx, r10, r8, r9   arguments 0-5 (r10 instead of rcx, since syscall overwrites rcx)
+//   rax                          return value, anything from -4095 to -1 is a negated Errno
+//   rcx, r11                     clobbered (the CPU puts the user rip and rflags there)
+// Every other register comes back exactly as it went in.
+
+// RFLAGS bits that get cleared on the way in -> Intel Manual - Section 3.4.3
+const RFLAGS_RESERVED: u64 = 1 << 1;
+const RFLAGS_TRAP_FLAG: u64 = 1 << 8;
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
+const RFLAGS_DIRECTION_FLAG: u64 = 1 << 10;
+const RFLAGS_ALIGNMENT_CHECK: u64 = 1 << 18;
+
+// Everything the entry stub pushes, lowest address first. Apart from rax, rcx and r11 that's the whole user register
+// state, which fork needs to copy. The callee saved registers would be safe in dispatch anyway.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub number: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    // from here on it's laid out like an interrupt stack frame
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl SyscallFrame {
+    pub fn arguments(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+
+    // Makes the system call return to entry on stack_pointer instead, with every other register cleared, for execve
+    pub fn start_over(&mut self, entry: VirtualAddress, stack_pointer: VirtualAddress) {
+        *self = SyscallFrame {
+            number: self.number,
+            r15: 0,
+            r14: 0,
+            r13: 0,
+            r12: 0,
+            rbp: 0,
+            rbx: 0,
+            r9: 0,
+            r8: 0,
+            r10: 0,
+            rdx: 0,
+            rsi: 0,
+            rdi: 0,
+            rip: entry.0,
+            cs: self.cs,
+            rflags: RFLAGS_RESERVED | RFLAGS_INTERRUPT_FLAG,
+            rsp: stack_pointer.0,
+            ss: self.ss,
+        };
+    }
+}
+
+pub type SyscallResult = Result<u64, Errno>;
+pub type SyscallHandler = fn(&mut SyscallFrame) -> SyscallResult;
+
+static SYSCALL_TABLE: [Option<SyscallHandler>; SYSCALL_COUNT] = {
+    let mut table: [Option<SyscallHandler>; SYSCALL_COUNT] = [None; SYSCALL_COUNT];
+    table[SYS_EXIT] = Some(handlers::sys_exit);
+    table[SYS_WRITE] = Some(handlers::sys_write);
+    table[SYS_FORK] = Some(handlers::sys_fork);
+    table[SYS_EXECVE] = Some(handlers::sys_execve);
+    table[SYS_WAITPID] = Some(handlers::sys_waitpid);
+    table[SYS_GETPID] = Some(handlers::sys_getpid);
+    table[SYS_GETPPID] = Some(handlers::sys_getppid);
+    table[SYS_MMAP] = Some(handlers::sys_mmap);
+    table[SYS_MUNMAP] = Some(handlers::sys_munmap);
+    table[SYS_MPROTECT] = Some(handlers::sys_mprotect);
+    table[SYS_BRK] = Some(handlers::sys_brk);
+    table[SYS_OPEN] = Some(handlers::sys_open);
+    table[SYS_READ] = Some(handlers::sys_read);
+    table[SYS_CLOSE] = Some(handlers::sys_close);
+    table[SYS_LSEEK] = Some(handlers::sys_lseek);
+    table[SYS_STAT] = Some(handlers::sys_stat);
+    table
+};
+
+// Whichever way ring 3 got here, this is where the system call actually gets made. The return value goes in rax.
+pub extern "C" fn dispatch(frame: &mut SyscallFrame) -> u64 {
+    let handler = SYSCALL_TABLE.get(frame.number as usize).copied().flatten();
+    let result = match handler {
+        Some(handler) => handler(frame),
+        None => Err(Errno::ENOSYS),
+    };
+    match result {
+        Ok(value) => value,
+        Err(errno) => -(errno as i64) as u64,
+    }
+}
+
+// Intel Manual - Volume 2B, SYSCALL and SYSRET.
+// The CPU only puts the user rip in rcx and rflags in r11, it doesn't touch the stack, so the first thing to do is swapgs
+// to get at this CPU's per-CPU data and move to the kernel stack. The frame pushed here doubles as an iretq frame.
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_data_selector}]",
+        "push qword ptr gs:[{user_stack}]",
+        "push r11",
+        "push qword ptr gs:[{user_code_selector}]",
+        "push rcx",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // 18 pushes, so the stack is still 16 byte aligned for the call
+        "push rax",
+        "mov rdi, rsp",
+        "call {dispatch}",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        // a handler is allowed to change where we go back to, so everything comes out of the frame
+        "mov rcx, [rsp]",
+        "mov r11, [rsp + 0x10]",
+        // sysret with a non-canonical rip faults in ring 0 with the user's rsp already loaded, so anything that isn't a
+        // user address goes back with iretq instead, which faults in ring 3 like it should
+        "bt rcx, 47",
+        "jc 2f",
+        "mov rsp, [rsp + 0x18]",
+        "swapgs",
+        "sysretq",
+        "2:",
+        "swapgs",
+        "iretq",
+        dispatch = sym dispatch,
+        kernel_stack = const percpu::KERNEL_STACK,
+        user_stack = const percpu::USER_STACK,
+        user_code_selector = const percpu::USER_CODE_SELECTOR,
+        user_data_selector = const percpu::USER_DATA_SELECTOR,
+    )
+}
+
+// int 0x80 goes through the same table with the same registers, and leaves rcx and r11 alone on top of that.
+// Slower than syscall, but it's an ordinary trap gate, so it's easy to step through in GDB and works on any CPU.
+// The CPU has already switched to RSP0 and pushed the interrupt stack frame, which makes up the top of a SyscallFrame.
+// It's a trap gate, so interrupts stay however ring 3 had them.
+#[unsafe(naked)]
+pub unsafe extern "C" fn int80_entry() {
+    naked_asm!(
+        // only swapgs if we came from ring 3, the kernel could be using int 0x80 too
+        "test qword ptr [rsp + 0x08], 3",
+        "jz 2f",
+        "swapgs",
+        "2:",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // the CPU aligned the stack before pushing 5 values, plus 13 here keeps it aligned for the call
+        "push rax",
+        "mov rdi, rsp",
+        "call {dispatch}",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "test qword ptr [rsp + 0x08], 3",
+        "jz 3f",
+        "swapgs",
+        "3:",
+        "iretq",
+        dispatch = sym dispatch,
+    )
+}
+
+pub fn set_kernel_stack(stack_top: VirtualAddress) {
+    unsafe { (*cpu_data()).kernel_stack = stack_top.0 };
+}
+
+// Once on every CPU, after percpu::init. While we're in the kernel gs points at this CPU's per-CPU data, and everywhere
+// we go to ring 3 swaps it for the user's.
+pub fn init() {
+    let selectors = &SELECTORS;
+    unsafe {
+        let cpu_data = cpu_data();
+        (*cpu_data).user_code_selector = selectors.user_code_segment.0 as u64;
+        (*cpu_data).user_data_selector = selectors.user_data_segment.0 as u64;
+    }
+    set_kernel_stack(tss::kernel_stack());
+    // syscall loads CS from STAR[47:32] and SS from the entry after it.
+    // sysret loads SS from the entry after STAR[63:48] and CS from the one after that, both with RPL 3.
+    let star = (selectors.kernel_data_segment.0 as u64 | 3) << 48
+        | (selectors.kernel_code_segment.0 as u64) << 32;
+    unsafe {
+        write_msr(IA32_STAR, star);
+        write_msr(IA32_LSTAR, syscall_entry as usize as u64);
+        write_msr(
+            IA32_FMASK,
+            RFLAGS_TRAP_FLAG
+                | RFLAGS_INTERRUPT_FLAG
+                | RFLAGS_DIRECTION_FLAG
+                | RFLAGS_ALIGNMENT_CHECK,
+        );
+        let efer = read_msr(IA32_EFER);
+        write_msr(IA32_EFER, efer | EFER_SYSCALL_ENABLE);
+    }
+}
@@ -0,0 +1,136 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::slice;
+
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::{USER_SPACE_END, USER_SPACE_START};
+use crate::process::{self, cow};
+use crate::syscall::consts::Errno;
+
+// The most a string or a whole argv/envp array can take up, like Linux's ARG_MAX.
+// Both arrays have to fit on the new program's stack together, see process::elf::USER_STACK_SIZE.
+pub const MAX_ARGUMENT_BYTES: usize = 16 * 1024;
+
+// Anything a system call is handed is just a number ring 3 made up. Before the kernel touches it, the whole range has
+// to be in user space and actually mapped for ring 3, otherwise a user could read or scribble over kernel memory.
+// Pages in the process' areas that haven't been touched yet get mapped here, so the kernel never faults on them.
+fn check_user_range(address: u64, length: u64, flags: PageTableFlags) -> Result<(), Errno> {
+    if length == 0 {
+        return Ok(());
+    }
+    let end = address.checked_add(length).ok_or(Errno::EFAULT)?;
+    if address < USER_SPACE_START || end > USER_SPACE_END {
+        return Err(Errno::EFAULT);
+    }
+    let process = process::current();
+    let mapper = process.address_space().mapper();
+    let page_size = PageSize::NORMAL as u64;
+    let mut page_address = address & !(page_size - 1);
+    while page_address < end {
+        let page = Page::from_address_aligned(VirtualAddress::new(page_address), PageSize::NORMAL);
+        let mut page_flags = mapper.flags(page);
+        if page_flags.is_none() && process.address_space().populate(page.start_address()) {
+            page_flags = mapper.flags(page);
+        }
+        match page_flags {
+            Some(page_flags) if page_flags.contains(flags) => {}
+            // fork left it read only until somebody writes to it, which is about to happen
+            Some(page_flags)
+                if flags.contains(PageTableFlags::WRITABLE)
+                    && page_flags.contains(PageTableFlags::COPY_ON_WRITE)
+                    && cow::handle_write_fault(page.start_address()) => {}
+            _ => return Err(Errno::EFAULT),
+        }
+        page_address += page_size;
+    }
+    Ok(())
+}
+
+pub fn user_slice(address: u64, length: u64) -> Result<&'static [u8], Errno> {
+    check_user_range(address, length, PageTableFlags::USER_ACCESSIBLE)?;
+    if length == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length as usize) })
+}
+
+pub fn user_slice_mut(address: u64, length: u64) -> Result<&'static mut [u8], Errno> {
+    check_user_range(
+        address,
+        length,
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+    )?;
+    if length == 0 {
+        return Ok(&mut []);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length as usize) })
+}
+
+// The whole pages length bytes from address cover, for the system calls that work on pages. address has to be page
+// aligned, and none of it can be outside of user space.
+pub fn user_pages(address: u64, length: u64) -> Result<(VirtualAddress, VirtualAddress), Errno> {
+    let page_size = PageSize::NORMAL as u64;
+    let end = length
+        .checked_next_multiple_of(page_size)
+        .and_then(|length| address.checked_add(length))
+        .ok_or(Errno::EINVAL)?;
+    if length == 0
+        || !address.is_multiple_of(page_size)
+        || address < USER_SPACE_START
+        || end > USER_SPACE_END
+    {
+        return Err(Errno::EINVAL);
+    }
+    Ok((VirtualAddress::new(address), VirtualAddress::new(end)))
+}
+
+// Copies a NUL terminated string out of user space. It's read a page at a time, since there's no telling how long it is
+// up front, and the rest of the page it ends in might not be mapped.
+pub fn user_string(address: u64) -> Result<String, Errno> {
+    let page_size = PageSize::NORMAL as u64;
+    let mut bytes = Vec::new();
+    loop {
+        let start = address
+            .checked_add(bytes.len() as u64)
+            .ok_or(Errno::EFAULT)?;
+        let chunk = user_slice(start, page_size - start % page_size)?;
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(length) => {
+                bytes.extend_from_slice(&chunk[..length]);
+                break;
+            }
+            None => bytes.extend_from_slice(chunk),
+        }
+        if bytes.len() > MAX_ARGUMENT_BYTES {
+            return Err(Errno::E2BIG);
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+// Copies a NULL terminated array of strings like argv out of user space. A null array counts as an empty one.
+pub fn user_string_array(address: u64) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if address == 0 {
+        return Ok(strings);
+    }
+    let mut total = 0;
+    loop {
+        let pointer_address = address
+            .checked_add(strings.len() as u64 * 8)
+            .ok_or(Errno::EFAULT)?;
+        let pointer = u64::from_le_bytes(user_slice(pointer_address, 8)?.try_into().unwrap());
+        if pointer == 0 {
+            return Ok(strings);
+        }
+        let string = user_string(pointer)?;
+        total += string.len() + 1 + 8;
+        if total > MAX_ARGUMENT_BYTES {
+            return Err(Errno::E2BIG);
+        }
+        strings.push(string);
+    }
+}
@@ -0,0 +1,108 @@
+use core::arch::{asm, naked_asm};
+
+// Everything the System V ABI says a function has to give back untouched, plus the stack pointer.
+// The instruction pointer doesn't need saving, it's the return address sitting on top of the saved stack.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rsp: u64,
+}
+
+impl Context {
+    // Sets up a stack so that switching to it "returns" into thread_trampoline, which calls entry(argument)
+    pub fn new(stack_top: u64, entry: extern "C" fn(u64) -> !, argument: u64) -> Self {
+        // just the return address, once that's popped the stack is 16 byte aligned for the trampoline's call
+        let rsp = (stack_top & !0x0F) - 8;
+        unsafe { *(rsp as *mut u64) = thread_trampoline as usize as u64 };
+        Context {
+            r12: entry as usize as u64,
+            r13: argument,
+            rsp,
+            ..Default::default()
+        }
+    }
+}
+
+// The x87, MMX and SSE state, in fxsave's format -> Intel Manual - Volume 1, Section 10.5.1
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    // What fninit would leave behind: everything masked, round to nearest
+    pub fn new() -> Self {
+        let mut state = FpuState([0; 512]);
+        // FCW
+        state.0[0..2].copy_from_slice(&0x037F_u16.to_le_bytes());
+        // MXCSR
+        state.0[24..28].copy_from_slice(&0x1F80_u32.to_le_bytes());
+        state
+    }
+
+    // Saves whatever is in the registers right now. Since the kernel doesn't use them, in a system call that's
+    // still the user's state.
+    pub fn save(&mut self) {
+        unsafe {
+            asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags))
+        };
+    }
+
+    pub unsafe fn restore(&self) {
+        asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags));
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        FpuState::new()
+    }
+}
+
+// switch_context(old, new, old_fpu, new_fpu)
+// Saves everything into old and carries on wherever new left off. For the thread that called it, it just looks like
+// an ordinary function call that took a while to come back.
+// Has to be called with interrupts disabled.
+#[unsafe(naked)]
+pub unsafe extern "C" fn switch_context(
+    old: *mut Context,
+    new: *const Context,
+    old_fpu: *mut FpuState,
+    new_fpu: *const FpuState,
+) {
+    naked_asm!(
+        "fxsave64 [rdx]",
+        "mov [rdi + 0x00], rbx",
+        "mov [rdi + 0x08], rbp",
+        "mov [rdi + 0x10], r12",
+        "mov [rdi + 0x18], r13",
+        "mov [rdi + 0x20], r14",
+        "mov [rdi + 0x28], r15",
+        "mov [rdi + 0x30], rsp",
+        "fxrstor64 [rcx]",
+        "mov rbx, [rsi + 0x00]",
+        "mov rbp, [rsi + 0x08]",
+        "mov r12, [rsi + 0x10]",
+        "mov r13, [rsi + 0x18]",
+        "mov r14, [rsi + 0x20]",
+        "mov r15, [rsi + 0x28]",
+        "mov rsp, [rsi + 0x30]",
+        "ret",
+    )
+}
+
+// Where a new thread's first switch_context returns to. Context::new left the entry point in r12 and its argument in r13.
+#[unsafe(naked)]
+unsafe extern "C" fn thread_trampoline() -> ! {
+    naked_asm!(
+        "mov rdi, r13",
+        "call r12",
+        // entry functions never return
+        "ud2",
+    )
+}
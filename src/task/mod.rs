@@ -0,0 +1,410 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::interrupts::consts::TIMER_INTERRUPT;
+use crate::interrupts::drivers::xapic;
+use crate::interrupts::{self, without_interrupts};
+use crate::memory::address::VirtualAddress;
+use crate::memory::paging::mapper::MapError;
+use crate::process::{self, Process};
+use crate::smp::percpu;
+use crate::structs::tss;
+
+pub mod context;
+pub mod policy;
+pub mod scheduler;
+pub mod stack;
+pub mod thread;
+pub mod wait_queue;
+
+use context::switch_context;
+pub use policy::SchedulingClass;
+use scheduler::{Scheduler, SCHEDULER};
+use stack::KernelStack;
+pub use thread::{Thread, ThreadId, ThreadState};
+pub use wait_queue::WaitQueue;
+
+// How often the timer interrupt goes off in Hz, time slices are counted in these ticks
+pub const TICK_RATE: u64 = 1000;
+// Can be changed with timeslice=<ms> on the command line
+const DEFAULT_TIME_SLICE_MS: u64 = 10;
+
+// Turns whatever is running right now into the first thread, so everything else can be switched to and from it,
+// and starts the timer that preempts them
+pub fn init() {
+    crate::cpu::enable_sse();
+    let kernel_process = process::kernel_process();
+    // already in cr3, but it has to know it's loaded here so changes to it get flushed here too
+    unsafe { kernel_process.address_space().load() };
+    let boot_thread = Thread::boot(tss::kernel_stack(), kernel_process.clone());
+    let idle_stack = KernelStack::allocate().expect("failed to allocate the idle task's stack");
+    let mut idle_thread = Thread::new(
+        "idle",
+        idle_stack,
+        Box::new(idle),
+        thread_start,
+        kernel_process.clone(),
+    );
+    // it has to be able to wake up from hlt
+    idle_thread.interrupts_enabled = true;
+    let time_slice = crate::cmdline::option("timeslice")
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_TIME_SLICE_MS);
+    without_interrupts(|| {
+        *SCHEDULER.lock() = Some(Scheduler::new(
+            boot_thread,
+            idle_thread,
+            time_slice_nanoseconds(Duration::from_millis(time_slice)),
+        ))
+    });
+    xapic::start_timer(TIMER_INTERRUPT as u8, TICK_RATE);
+    log::info!("Scheduler started with a {}ms time slice", time_slice);
+}
+
+// Joins the scheduler from a CPU that's just been started, see smp. What it's running becomes its idle task, so all
+// that's left to do after this is to go idle.
+pub(crate) fn init_cpu(stack: KernelStack) {
+    crate::cpu::enable_sse();
+    // see init
+    unsafe { process::kernel_process().address_space().load() };
+    let idle_thread = Thread::idle(stack, process::kernel_process().clone());
+    with_scheduler(|scheduler| scheduler.add_cpu(idle_thread));
+    xapic::start_timer(TIMER_INTERRUPT as u8, TICK_RATE);
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Scheduler) -> R,
+{
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        f(scheduler.as_mut().expect("task::init hasn't been called"))
+    })
+}
+
+// Preemption only ever happens on a timer tick, so anything shorter than one is no different from one
+fn time_slice_nanoseconds(time_slice: Duration) -> u64 {
+    let tick = 1_000_000_000 / TICK_RATE;
+    (time_slice.as_nanos() as u64 / tick).max(1) * tick
+}
+
+// How long a thread gets to run before it's preempted if there's anything else of the same priority to run.
+// The fair class splits it between all of its threads that want to run. Rounded to whole timer ticks.
+pub fn set_time_slice(time_slice: Duration) {
+    with_scheduler(|scheduler| scheduler.time_slice = time_slice_nanoseconds(time_slice));
+}
+
+pub fn time_slice() -> Duration {
+    Duration::from_nanos(with_scheduler(|scheduler| scheduler.time_slice))
+}
+
+#[derive(Debug)]
+pub struct JoinHandle {
+    id: ThreadId,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    // Waits for the thread to exit
+    pub fn join(self) {
+        let id = self.id;
+        // the thread is gone once we've seen it exit, nothing is left for Drop to do
+        core::mem::forget(self);
+        let exit_queue = with_scheduler(|scheduler| {
+            scheduler
+                .threads
+                .get(&id)
+                .map(|thread| thread.exit_queue.clone())
+        });
+        if let Some(exit_queue) = exit_queue {
+            exit_queue.wait_while(|| {
+                with_scheduler(|scheduler| {
+                    scheduler
+                        .threads
+                        .get(&id)
+                        .is_some_and(|thread| thread.state != ThreadState::Exited)
+                })
+            });
+        }
+        // Frees the stack, outside of the scheduler lock. If it's still on its way out on another CPU that's left to
+        // reap instead.
+        let thread = with_scheduler(|scheduler| {
+            let thread = scheduler.threads.get_mut(&id)?;
+            thread.detached = true;
+            scheduler.remove_exited(id)
+        });
+        drop(thread);
+    }
+}
+
+// Nobody is going to join the thread, so it can be cleaned up as soon as it exits
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        let id = self.id;
+        let exited = with_scheduler(|scheduler| {
+            let thread = scheduler.threads.get_mut(&id)?;
+            thread.detached = true;
+            scheduler.remove_exited(id)
+        });
+        drop(exited);
+    }
+}
+
+pub fn spawn<F>(f: F) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_named("thread", f)
+}
+
+pub fn spawn_named<F>(name: &'static str, f: F) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_class(name, SchedulingClass::default(), f)
+}
+
+pub fn spawn_with_class<F>(
+    name: &'static str,
+    class: SchedulingClass,
+    f: F,
+) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_thread(process::kernel_process().clone(), name, class, f)
+}
+
+// Spawns a thread that runs in process's address space, for it to go to ring 3 from
+pub fn spawn_in<F>(process: &Arc<Process>, name: &'static str, f: F) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_thread(process.clone(), name, SchedulingClass::default(), f)
+}
+
+fn spawn_thread<F>(
+    process: Arc<Process>,
+    name: &'static str,
+    class: SchedulingClass,
+    f: F,
+) -> Result<JoinHandle, MapError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let kernel_stack = KernelStack::allocate()?;
+    let mut thread = Thread::new(name, kernel_stack, Box::new(f), thread_start, process);
+    thread.class = class;
+    let id = with_scheduler(|scheduler| scheduler.add(thread));
+    Ok(JoinHandle { id })
+}
+
+// Puts a blocked thread back on the run queue, see Scheduler::wake. Returns false if it wasn't blocked or running.
+// Whatever it was waiting for should be checked again when it runs, WaitQueue::wait_while does.
+pub fn wake(id: ThreadId) -> bool {
+    with_scheduler(|scheduler| scheduler.wake(id))
+}
+
+// Returns false if there's no such thread, or it's the idle task
+pub fn set_scheduling_class(id: ThreadId, class: SchedulingClass) -> bool {
+    with_scheduler(|scheduler| scheduler.set_class(id, class))
+}
+
+// A snapshot of a thread, for debugging
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: &'static str,
+    pub state: ThreadState,
+    pub class: SchedulingClass,
+    pub cpu_time: Duration,
+    pub switches: u64,
+    pub vruntime: u64,
+}
+
+impl ThreadInfo {
+    fn new(thread: &Thread) -> Self {
+        ThreadInfo {
+            id: thread.id,
+            name: thread.name,
+            state: thread.state,
+            class: thread.class,
+            cpu_time: Duration::from_nanos(thread.cpu_time),
+            switches: thread.switches,
+            vruntime: thread.vruntime,
+        }
+    }
+}
+
+pub fn thread_info(id: ThreadId) -> Option<ThreadInfo> {
+    with_scheduler(|scheduler| {
+        scheduler
+            .threads
+            .get(&id)
+            .map(|thread| ThreadInfo::new(thread))
+    })
+}
+
+pub fn threads() -> Vec<ThreadInfo> {
+    with_scheduler(|scheduler| {
+        scheduler
+            .threads
+            .values()
+            .map(|thread| ThreadInfo::new(thread))
+            .collect()
+    })
+}
+
+// Logs every thread and the CPU time it's used, like ps
+pub fn log_threads() {
+    for thread in threads() {
+        log::info!(
+            "{:>4} {:<12} {:<8} {:<28} {:>10?} {:>8} switches",
+            thread.id.as_u64(),
+            thread.name,
+            alloc::format!("{:?}", thread.state),
+            alloc::format!("{:?}", thread.class),
+            thread.cpu_time,
+            thread.switches,
+        );
+    }
+}
+
+pub fn current_id() -> ThreadId {
+    with_scheduler(|scheduler| scheduler.current_id())
+}
+
+pub fn current_process() -> Arc<Process> {
+    with_scheduler(|scheduler| scheduler.current().process.clone())
+}
+
+// Gives up the CPU to the next thread in line. Returns straight away if there's nobody else, or nobody with a claim
+// to the CPU at least as good as ours.
+pub fn yield_now() {
+    without_interrupts(|| schedule(ThreadState::Ready));
+}
+
+// Finishes the current thread, whoever joins it gets woken up
+pub fn exit() -> ! {
+    without_interrupts(|| {
+        // Exited before anyone is woken, so a joiner that gets to run on another CPU before we've switched away
+        // doesn't find us still going and block again with nobody left to wake it
+        let exit_queue = with_scheduler(|scheduler| {
+            let thread = scheduler.current();
+            thread.state = ThreadState::Exited;
+            thread.exit_queue.clone()
+        });
+        exit_queue.wake_all();
+        // our own reference has to go before we do, nothing is ever going to drop it otherwise
+        drop(exit_queue);
+        schedule(ThreadState::Exited);
+    });
+    unreachable!("an exited thread was switched back to");
+}
+
+// From the timer interrupt, with interrupts off. Switches to the next thread once the current one's time is up, unless
+// it's turned preemption off, in which case it carries on and gets switched away from on a later tick.
+pub(crate) fn tick() {
+    if with_scheduler(|scheduler| scheduler.tick()) && percpu::preemptible() {
+        switch_away(ThreadState::Ready, false);
+    }
+}
+
+// Where the current thread's trip to ring 3 keeps the way back, see usermode.
+// Threads are boxed, so this stays valid for as long as the thread does.
+pub(crate) fn user_mode_context() -> *mut u64 {
+    with_scheduler(|scheduler| &mut scheduler.current().user_mode_context as *mut u64)
+}
+
+// Changes the stack the CPU switches to when the current thread comes into the kernel from ring 3,
+// and keeps it that way across switches
+pub(crate) fn set_kernel_stack(stack_top: VirtualAddress) {
+    without_interrupts(|| {
+        with_scheduler(|scheduler| scheduler.current().kernel_stack_top = stack_top);
+        tss::set_kernel_stack(stack_top);
+    });
+}
+
+pub(crate) fn kernel_stack() -> VirtualAddress {
+    with_scheduler(|scheduler| scheduler.current().kernel_stack_top)
+}
+
+// Puts the current thread into state and runs the next one. Comes back once something switches back to us.
+// Has to be called with interrupts off.
+fn schedule(state: ThreadState) {
+    // the next thread would get our preempt count
+    debug_assert!(percpu::preemptible(), "scheduling with preemption off");
+    switch_away(state, true);
+}
+
+// Like schedule, but the current thread didn't give up the CPU on its own. That only makes a difference if it's
+// still Ready, see Scheduler::switch_away.
+fn switch_away(state: ThreadState, yielded: bool) {
+    let mut scheduler = SCHEDULER.lock();
+    let switch = scheduler
+        .as_mut()
+        .expect("task::init hasn't been called")
+        .switch_away(state, yielded);
+    if let Some(switch) = switch {
+        // Stays locked until finish_switch, on the other side. The old thread could already be back on a run queue,
+        // and no other CPU can be allowed to pick it up before switch_context is done saving its context.
+        core::mem::forget(scheduler);
+        tss::set_kernel_stack(switch.kernel_stack_top);
+        unsafe {
+            // the kernel half is the same everywhere, so we can carry on where we are after the switch
+            if let Some(address_space) = switch.address_space {
+                (*address_space).load();
+            }
+            switch_context(
+                switch.old_context,
+                switch.new_context,
+                switch.old_fpu_state,
+                switch.new_fpu_state,
+            );
+        }
+        finish_switch();
+    }
+}
+
+// Runs on the new thread's stack right after every switch, now that the old one is definitely off its stack
+fn finish_switch() {
+    unsafe { SCHEDULER.force_unlock() };
+    let dead = with_scheduler(|scheduler| scheduler.reap());
+    drop(dead);
+}
+
+// Every spawned thread starts here, see Context::new
+extern "C" fn thread_start(_: u64) -> ! {
+    finish_switch();
+    let (entry, interrupts_enabled) = with_scheduler(|scheduler| {
+        let thread = scheduler.current();
+        (thread.entry.take(), thread.interrupts_enabled)
+    });
+    // switch_context always runs with interrupts off, and a new thread has no without_interrupts of its own to undo that
+    if interrupts_enabled {
+        interrupts::enable();
+    }
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+// The idle task. Halts until an interrupt comes along, and gets out of the way as soon as anything else can run.
+// Nothing tells a halted CPU about new threads, so it only notices them on its next timer tick.
+pub(crate) fn idle() {
+    loop {
+        interrupts::disable();
+        if !with_scheduler(|scheduler| scheduler.has_ready()) {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+            yield_now();
+        }
+    }
+}
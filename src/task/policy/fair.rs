@@ -0,0 +1,130 @@
+use alloc::collections::BTreeSet;
+
+use crate::task::policy::{SchedulerPolicy, SchedulingClass};
+use crate::task::thread::{Thread, ThreadId};
+
+// Loosely after Linux's CFS -> https://docs.kernel.org/scheduler/sched-design-CFS.html
+// Every thread's virtual runtime is the CPU time it's had, scaled down by its weight, and whoever has the least runs
+// next. Over time that hands out the CPU in proportion to the weights.
+
+// Linux's sched_prio_to_weight, indexed by nice + 20. Each nice level is worth about 10% of the CPU.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+// Nobody gets preempted after less than this, however many threads there are to share the time slice between
+const MIN_GRANULARITY: u64 = 1_000_000; // 1ms
+
+pub fn weight(thread: &Thread) -> u64 {
+    match thread.class {
+        SchedulingClass::Fair { nice } => {
+            NICE_TO_WEIGHT[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize]
+        }
+        SchedulingClass::RealTime { .. } => {
+            unreachable!("a real-time thread ended up on the fair run queue")
+        }
+    }
+}
+
+pub struct FairPolicy {
+    // (virtual runtime, id, weight), ordered by virtual runtime. The id keeps the entries unique.
+    queue: BTreeSet<(u64, ThreadId, u64)>,
+    // the total weight of everything on the queue
+    queued_weight: u64,
+    // Only ever goes up. Threads that were blocked or are new start from here, otherwise they'd get to
+    // hog the CPU until they caught up with everyone else.
+    min_vruntime: u64,
+}
+
+impl FairPolicy {
+    pub const fn new() -> Self {
+        FairPolicy {
+            queue: BTreeSet::new(),
+            queued_weight: 0,
+            min_vruntime: 0,
+        }
+    }
+
+    fn leftmost(&self) -> Option<u64> {
+        self.queue.first().map(|&(vruntime, _, _)| vruntime)
+    }
+}
+
+impl Default for FairPolicy {
+    fn default() -> Self {
+        FairPolicy::new()
+    }
+}
+
+impl SchedulerPolicy for FairPolicy {
+    fn enqueue(&mut self, thread: &mut Thread) {
+        thread.vruntime = thread.vruntime.max(self.min_vruntime);
+        let weight = weight(thread);
+        self.queue.insert((thread.vruntime, thread.id, weight));
+        self.queued_weight += weight;
+    }
+
+    // Goes to the back of the line: yielding with the least virtual runtime would just get it picked again
+    fn enqueue_yielded(&mut self, thread: &mut Thread) {
+        if let Some(&(rightmost, _, _)) = self.queue.last() {
+            thread.vruntime = thread.vruntime.max(rightmost + 1);
+        }
+        self.enqueue(thread);
+    }
+
+    fn dequeue(&mut self, thread: &Thread) {
+        let weight = weight(thread);
+        if self.queue.remove(&(thread.vruntime, thread.id, weight)) {
+            self.queued_weight -= weight;
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        let (vruntime, id, weight) = self.queue.pop_first()?;
+        self.queued_weight -= weight;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    // Every CPU's virtual runtimes go up at their own pace, so the thread takes how far ahead of this one's minimum it
+    // was along instead
+    fn migrate_out(&mut self, thread: &mut Thread) {
+        thread.vruntime = thread.vruntime.saturating_sub(self.min_vruntime);
+    }
+
+    fn migrate_in(&mut self, thread: &mut Thread) {
+        thread.vruntime += self.min_vruntime;
+    }
+
+    fn charge(&mut self, thread: &mut Thread, ran: u64) {
+        thread.vruntime += ran * NICE_0_WEIGHT / weight(thread);
+        let smallest = self
+            .leftmost()
+            .map_or(thread.vruntime, |leftmost| leftmost.min(thread.vruntime));
+        self.min_vruntime = self.min_vruntime.max(smallest);
+    }
+
+    // The time slice gets split between everything that wants to run, by weight. The running thread makes way once
+    // it's had its share, or once it's that far ahead of whoever is next.
+    fn should_preempt(&self, current: &Thread, ran: u64, time_slice: u64) -> bool {
+        let Some(leftmost) = self.leftmost() else {
+            return false;
+        };
+        let total_weight = self.queued_weight + weight(current);
+        let ideal = (time_slice * weight(current) / total_weight).max(MIN_GRANULARITY);
+        ran >= ideal || current.vruntime > leftmost + ideal
+    }
+}
@@ -0,0 +1,71 @@
+use crate::task::thread::{Thread, ThreadId};
+
+pub mod fair;
+pub mod realtime;
+
+pub use fair::FairPolicy;
+pub use realtime::RealTimePolicy;
+
+// Which policy a thread gets scheduled by. Every real-time thread that's ready runs before any fair one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingClass {
+    // Fixed priority from 0 to RT_MAX_PRIORITY, highest first. Round robin between threads of the same priority.
+    RealTime { priority: u8 },
+    // Shares the CPU by weight, nice goes from -20 (biggest share) to 19 (smallest), like Linux
+    Fair { nice: i8 },
+}
+
+pub const SCHEDULING_CLASSES: usize = 2;
+
+impl SchedulingClass {
+    // Where the class's policy sits in every CPU's policies, which are ordered from the first to run to the last
+    pub fn index(&self) -> usize {
+        match self {
+            SchedulingClass::RealTime { .. } => 0,
+            SchedulingClass::Fair { .. } => 1,
+        }
+    }
+}
+
+impl Default for SchedulingClass {
+    fn default() -> Self {
+        SchedulingClass::Fair { nice: 0 }
+    }
+}
+
+// One scheduling class's run queue on one CPU, and its rules for who goes next. Only ever sees the threads in its own class,
+// the Scheduler takes care of running the classes in order. Times are in nanoseconds.
+pub trait SchedulerPolicy: Send {
+    // The thread is ready to run
+    fn enqueue(&mut self, thread: &mut Thread);
+
+    // Like enqueue, but the thread gave up the CPU on its own, so it should go behind the others in its class
+    fn enqueue_yielded(&mut self, thread: &mut Thread) {
+        self.enqueue(thread);
+    }
+
+    // Takes a thread that's still waiting to run back off the run queue
+    fn dequeue(&mut self, thread: &Thread);
+
+    // Takes the thread that should run next off the run queue
+    fn pick_next(&mut self) -> Option<ThreadId>;
+
+    fn is_empty(&self) -> bool;
+
+    // How many threads are waiting on the run queue
+    fn len(&self) -> usize;
+
+    // The running thread has had the CPU for another ran nanoseconds
+    fn charge(&mut self, _thread: &mut Thread, _ran: u64) {}
+
+    // The thread is moving from this CPU's run queue to another's, see Scheduler::pull. Anything it keeps that only
+    // means something next to the other threads here has to be made relative.
+    fn migrate_out(&mut self, _thread: &mut Thread) {}
+
+    // The other half of migrate_out, on the CPU it's moving to, before it goes on the run queue or gets to run
+    fn migrate_in(&mut self, _thread: &mut Thread) {}
+
+    // Whether the running thread should make way for one of the threads on the run queue, having run for ran since it
+    // was switched to. time_slice is the configured time slice, see task::set_time_slice.
+    fn should_preempt(&self, current: &Thread, ran: u64, time_slice: u64) -> bool;
+}
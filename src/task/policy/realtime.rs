@@ -0,0 +1,80 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::task::policy::{SchedulerPolicy, SchedulingClass};
+use crate::task::thread::{Thread, ThreadId};
+
+pub const RT_MAX_PRIORITY: u8 = 99;
+
+// Like SCHED_RR: the highest priority ready thread always runs, and threads with the same priority take turns
+// a time slice at a time. A real-time thread that never blocks starves everything below it.
+pub struct RealTimePolicy {
+    // one run queue per priority
+    queues: Vec<VecDeque<ThreadId>>,
+    queued: usize,
+}
+
+fn priority(thread: &Thread) -> usize {
+    match thread.class {
+        SchedulingClass::RealTime { priority } => priority.min(RT_MAX_PRIORITY) as usize,
+        SchedulingClass::Fair { .. } => {
+            unreachable!("a fair thread ended up on the real-time run queue")
+        }
+    }
+}
+
+impl RealTimePolicy {
+    pub fn new() -> Self {
+        RealTimePolicy {
+            queues: (0..=RT_MAX_PRIORITY).map(|_| VecDeque::new()).collect(),
+            queued: 0,
+        }
+    }
+
+    fn highest_priority(&self) -> Option<usize> {
+        self.queues.iter().rposition(|queue| !queue.is_empty())
+    }
+}
+
+impl Default for RealTimePolicy {
+    fn default() -> Self {
+        RealTimePolicy::new()
+    }
+}
+
+impl SchedulerPolicy for RealTimePolicy {
+    fn enqueue(&mut self, thread: &mut Thread) {
+        self.queues[priority(thread)].push_back(thread.id);
+        self.queued += 1;
+    }
+
+    fn dequeue(&mut self, thread: &Thread) {
+        let queue = &mut self.queues[priority(thread)];
+        if let Some(position) = queue.iter().position(|&id| id == thread.id) {
+            queue.remove(position);
+            self.queued -= 1;
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        let highest = self.highest_priority()?;
+        self.queued -= 1;
+        self.queues[highest].pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queued == 0
+    }
+
+    fn len(&self) -> usize {
+        self.queued
+    }
+
+    fn should_preempt(&self, current: &Thread, ran: u64, time_slice: u64) -> bool {
+        match self.highest_priority() {
+            Some(highest) if highest > priority(current) => true,
+            Some(highest) if highest == priority(current) => ran >= time_slice,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+
+use spin::Mutex;
+
+use crate::memory::address::VirtualAddress;
+use crate::percpu;
+use crate::process::AddressSpace;
+use crate::smp::current_cpu;
+use crate::task::context::{Context, FpuState};
+use crate::task::policy::{
+    FairPolicy, RealTimePolicy, SchedulerPolicy, SchedulingClass, SCHEDULING_CLASSES,
+};
+use crate::task::thread::{Thread, ThreadId, ThreadState};
+use crate::time::{read_tsc, tsc_to_nanoseconds};
+
+// Everything switch_context needs, pulled out of the thread list so it doesn't have to go looking
+pub(super) struct Switch {
+    pub old_context: *mut Context,
+    pub new_context: *const Context,
+    pub old_fpu_state: *mut FpuState,
+    pub new_fpu_state: *const FpuState,
+    pub kernel_stack_top: VirtualAddress,
+    // only if the new thread is in another process. It stays alive for as long as the thread does.
+    pub address_space: Option<*const AddressSpace>,
+}
+
+// How many timer ticks a busy CPU goes between comparing its run queues with everyone else's, see Scheduler::balance
+const BALANCE_INTERVAL: u32 = 10;
+
+// What the scheduler keeps track of for each CPU
+struct CpuState {
+    current: ThreadId,
+    // runs whenever nothing else can on this CPU, and is never on a run queue itself
+    idle: ThreadId,
+    // TSC readings from when the current thread was switched to, and from when it was last charged for its CPU time
+    switched_in: u64,
+    last_charged: u64,
+    // the threads waiting to run here, one run queue per scheduling class in the order they get to run, see
+    // SchedulingClass::index
+    policies: [Box<dyn SchedulerPolicy>; SCHEDULING_CLASSES],
+    // ticks left until the next balance
+    until_balance: u32,
+}
+
+impl CpuState {
+    fn new(current: ThreadId, idle: ThreadId) -> Self {
+        let now = read_tsc();
+        CpuState {
+            current,
+            idle,
+            switched_in: now,
+            last_charged: now,
+            policies: [Box::new(RealTimePolicy::new()), Box::new(FairPolicy::new())],
+            until_balance: BALANCE_INTERVAL,
+        }
+    }
+
+    // How many threads are waiting to run here
+    fn queued(&self) -> usize {
+        self.policies.iter().map(|policy| policy.len()).sum()
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        self.policies
+            .iter_mut()
+            .find_map(|policy| policy.pick_next())
+    }
+}
+
+// None until the CPU has joined the scheduler. Other CPUs put threads on its run queues and take them off again too,
+// but only ever with SCHEDULER locked, which is what makes sharing it fine.
+struct CpuStateCell(UnsafeCell<Option<CpuState>>);
+
+unsafe impl Sync for CpuStateCell {}
+
+percpu! {
+    static CPU_STATE: CpuStateCell = CpuStateCell(UnsafeCell::new(None));
+}
+
+// A CPU's state, if it's joined. Only with SCHEDULER locked, and nothing else can be holding on to the same CPU's.
+fn cpu_state(cpu: usize) -> Option<&'static mut CpuState> {
+    unsafe { &mut *CPU_STATE.of(cpu).0.get() }.as_mut()
+}
+
+// The state of the CPU we're on, see cpu_state
+fn this_cpu() -> &'static mut CpuState {
+    cpu_state(current_cpu()).expect("this CPU hasn't joined the scheduler")
+}
+
+// Threads are boxed so their contexts stay put while the map changes around them.
+// Every CPU has its own run queues, in CPU_STATE. A thread that's ready goes on the ones of the CPU it last ran on,
+// new threads go to whichever CPU has the least to do, and CPUs that have a lot less to do than another take threads
+// off it, see pull. The thread map is shared, along with the lock.
+pub struct Scheduler {
+    pub(super) threads: BTreeMap<ThreadId, Box<Thread>>,
+    // in nanoseconds
+    pub(super) time_slice: u64,
+}
+
+// Only ever locked with interrupts disabled. A switch keeps it locked until the next thread is running, see
+// task::switch_away, so a thread on a run queue is never still running somewhere else.
+pub(super) static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+impl Scheduler {
+    pub(super) fn new(boot_thread: Box<Thread>, idle_thread: Box<Thread>, time_slice: u64) -> Self {
+        let mut scheduler = Scheduler {
+            threads: BTreeMap::new(),
+            time_slice,
+        };
+        let cpu = CpuState::new(boot_thread.id, idle_thread.id);
+        scheduler.threads.insert(boot_thread.id, boot_thread);
+        scheduler.threads.insert(idle_thread.id, idle_thread);
+        scheduler.set_cpu(cpu);
+        scheduler
+    }
+
+    // For a CPU that's just been started, the thread it's running becomes its idle task
+    pub(super) fn add_cpu(&mut self, idle_thread: Box<Thread>) {
+        self.set_cpu(CpuState::new(idle_thread.id, idle_thread.id));
+        self.threads.insert(idle_thread.id, idle_thread);
+    }
+
+    fn set_cpu(&mut self, cpu: CpuState) {
+        let state = unsafe { &mut *CPU_STATE.get().0.get() };
+        assert!(state.is_none(), "a CPU joined the scheduler twice");
+        *state = Some(cpu);
+    }
+
+    // Every CPU that's joined, with its number
+    fn cpus(&self) -> impl Iterator<Item = (usize, &CpuState)> {
+        CPU_STATE
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, state)| Some((cpu, unsafe { &*state.0.get() }.as_ref()?)))
+    }
+
+    // The thread running on this CPU
+    pub(super) fn current_id(&self) -> ThreadId {
+        this_cpu().current
+    }
+
+    // Whether the thread is running on any CPU
+    fn is_running(&self, id: ThreadId) -> bool {
+        self.cpus().any(|(_, cpu)| cpu.current == id)
+    }
+
+    fn is_idle(&self, id: ThreadId) -> bool {
+        self.cpus().any(|(_, cpu)| cpu.idle == id)
+    }
+
+    // Starts the thread off on the CPU with the least to do, counting what it's running
+    pub(super) fn add(&mut self, mut thread: Box<Thread>) -> ThreadId {
+        thread.cpu = self
+            .cpus()
+            .min_by_key(|(_, cpu)| cpu.queued() + (cpu.current != cpu.idle) as usize)
+            .map(|(cpu, _)| cpu)
+            .expect("no CPU has joined the scheduler");
+        let id = thread.id;
+        self.threads.insert(id, thread);
+        self.enqueue(id, false);
+        id
+    }
+
+    pub(super) fn current(&mut self) -> &mut Thread {
+        let current = self.current_id();
+        self.threads
+            .get_mut(&current)
+            .expect("the current thread isn't in the thread list")
+    }
+
+    // Puts the thread on its CPU's run queue
+    fn enqueue(&mut self, id: ThreadId, yielded: bool) {
+        let thread = self
+            .threads
+            .get_mut(&id)
+            .expect("a thread that isn't in the thread list became ready");
+        let cpu = cpu_state(thread.cpu).expect("a thread belongs to a CPU that hasn't joined");
+        let policy = &mut cpu.policies[thread.class.index()];
+        if yielded {
+            policy.enqueue_yielded(thread);
+        } else {
+            policy.enqueue(thread);
+        }
+    }
+
+    // Whether there's anything waiting to run, on any CPU. This one can always go and take it, see pull.
+    pub(super) fn has_ready(&self) -> bool {
+        self.cpus().any(|(_, cpu)| cpu.queued() > 0)
+    }
+
+    // Takes the thread that would run next off the run queue of whichever other CPU has the most waiting, and moves
+    // it over to cpu. It's up to the caller to run it or put it on cpu's run queue.
+    fn pull(&mut self, to: usize) -> Option<ThreadId> {
+        let from = self
+            .cpus()
+            .filter(|&(cpu, state)| cpu != to && state.queued() > 0)
+            .max_by_key(|(_, state)| state.queued())
+            .map(|(cpu, _)| cpu)?;
+        let source = cpu_state(from)?;
+        let class = source
+            .policies
+            .iter()
+            .position(|policy| !policy.is_empty())?;
+        let id = source.policies[class].pick_next()?;
+        let thread = self
+            .threads
+            .get_mut(&id)
+            .expect("a thread that isn't in the thread list was on a run queue");
+        source.policies[class].migrate_out(thread);
+        cpu_state(to)?.policies[class].migrate_in(thread);
+        thread.cpu = to;
+        Some(id)
+    }
+
+    // For a busy CPU every BALANCE_INTERVAL ticks, idle ones pull as soon as there's anything to. Takes one thread
+    // from the CPU with the most waiting if that's at least two more than here, any closer and moving it would just
+    // turn the difference around.
+    fn balance(&mut self) {
+        let this = current_cpu();
+        let queued = this_cpu().queued();
+        let busiest = self
+            .cpus()
+            .filter(|&(cpu, _)| cpu != this)
+            .map(|(_, state)| state.queued())
+            .max()
+            .unwrap_or(0);
+        if busiest >= queued + 2 {
+            if let Some(id) = self.pull(this) {
+                self.enqueue(id, false);
+            }
+        }
+    }
+
+    // Charges the current thread for the CPU time it's had since the last time
+    fn charge_current(&mut self) {
+        let now = read_tsc();
+        let cpu = this_cpu();
+        let ran = tsc_to_nanoseconds(now - cpu.last_charged);
+        cpu.last_charged = now;
+        let thread = self
+            .threads
+            .get_mut(&cpu.current)
+            .expect("the current thread isn't in the thread list");
+        thread.cpu_time += ran;
+        if cpu.current != cpu.idle {
+            cpu.policies[thread.class.index()].charge(thread, ran);
+        }
+    }
+
+    // Moves the current thread to state and picks the next one to run. A thread that's still Ready goes back on its
+    // run queue, behind the others in its class if it yielded rather than being preempted. With nothing on this CPU's
+    // run queues it takes a thread from another CPU's before it goes idle.
+    // None means the current thread is still the best choice and just carries on.
+    pub(super) fn switch_away(&mut self, state: ThreadState, yielded: bool) -> Option<Switch> {
+        // Woken up before it got as far as blocking, see wake. Only good for the next switch: if that's for anything
+        // else the wakeup is gone, whatever it next waits for gets checked again before it blocks anyway.
+        if core::mem::take(&mut self.current().woken) && state == ThreadState::Blocked {
+            return None;
+        }
+        self.charge_current();
+        let (previous, idle) = (this_cpu().current, this_cpu().idle);
+        if previous == idle {
+            assert_eq!(state, ThreadState::Ready, "the idle task stopped running");
+        } else {
+            self.current().state = state;
+            if state == ThreadState::Ready {
+                self.enqueue(previous, yielded);
+            }
+        }
+        let next = this_cpu()
+            .pick_next()
+            .or_else(|| self.pull(current_cpu()))
+            .unwrap_or(idle);
+        if next == previous {
+            self.current().state = ThreadState::Running;
+            return None;
+        }
+        let cpu = this_cpu();
+        cpu.current = next;
+        cpu.switched_in = read_tsc();
+        let next_thread = self.current();
+        next_thread.state = ThreadState::Running;
+        next_thread.switches += 1;
+        let new_context = &next_thread.context as *const Context;
+        let new_fpu_state = &*next_thread.fpu_state as *const FpuState;
+        let kernel_stack_top = next_thread.kernel_stack_top;
+        let next_process = next_thread.process.clone();
+        let previous_thread = self
+            .threads
+            .get_mut(&previous)
+            .expect("the previous thread isn't in the thread list");
+        let address_space = (!Arc::ptr_eq(&previous_thread.process, &next_process))
+            .then(|| next_process.address_space() as *const AddressSpace);
+        Some(Switch {
+            old_context: &mut previous_thread.context,
+            new_context,
+            old_fpu_state: &mut *previous_thread.fpu_state,
+            new_fpu_state,
+            kernel_stack_top,
+            address_space,
+        })
+    }
+
+    // Called on every timer tick, says whether the current thread should make way for another one.
+    // Anything ready in a class that runs first always gets the CPU, otherwise it's up to the current thread's policy.
+    // The idle task makes way as soon as there's anything else to run, here or on another CPU.
+    pub(super) fn tick(&mut self) -> bool {
+        self.charge_current();
+        let cpu = this_cpu();
+        if cpu.current == cpu.idle {
+            return self.has_ready();
+        }
+        cpu.until_balance -= 1;
+        if cpu.until_balance == 0 {
+            cpu.until_balance = BALANCE_INTERVAL;
+            self.balance();
+        }
+        let cpu = this_cpu();
+        let ran = tsc_to_nanoseconds(read_tsc() - cpu.switched_in);
+        let thread = &self.threads[&cpu.current];
+        let class = thread.class.index();
+        cpu.policies[..class]
+            .iter()
+            .any(|policy| !policy.is_empty())
+            || cpu.policies[class].should_preempt(thread, ran, self.time_slice)
+    }
+
+    // Puts a blocked thread back on its run queue. A thread that's still running might be on its way to blocking on
+    // another CPU, so it gets to carry on instead the next time it tries. Anything else is left alone.
+    pub(super) fn wake(&mut self, id: ThreadId) -> bool {
+        match self.threads.get_mut(&id) {
+            Some(thread) if thread.state == ThreadState::Blocked => {
+                thread.state = ThreadState::Ready;
+                self.enqueue(id, false);
+                true
+            }
+            Some(thread) if thread.state == ThreadState::Running => {
+                thread.woken = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Moves a thread to another class, taking it off its old run queue and putting it on the new one if it's waiting
+    // to run. The idle task always stays the idle task.
+    pub(super) fn set_class(&mut self, id: ThreadId, class: SchedulingClass) -> bool {
+        if self.is_idle(id) {
+            return false;
+        }
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return false;
+        };
+        if thread.state == ThreadState::Ready {
+            let policies = &mut cpu_state(thread.cpu)
+                .expect("a thread belongs to a CPU that hasn't joined")
+                .policies;
+            policies[thread.class.index()].dequeue(thread);
+            thread.class = class;
+            policies[class.index()].enqueue(thread);
+        } else {
+            thread.class = class;
+        }
+        true
+    }
+
+    // Takes out every exited thread nobody is going to join. The caller drops them once the lock is released,
+    // which frees their stacks. Never includes a running thread, since a CPU might still be on its stack.
+    pub(super) fn reap(&mut self) -> Vec<Thread> {
+        let dead: Vec<ThreadId> = self
+            .threads
+            .values()
+            .filter(|thread| {
+                thread.state == ThreadState::Exited
+                    && thread.detached
+                    && !self.is_running(thread.id)
+            })
+            .map(|thread| thread.id)
+            .collect();
+        dead.into_iter()
+            .filter_map(|id| self.threads.remove(&id))
+            .map(|thread| *thread)
+            .collect()
+    }
+
+    // Takes out an exited thread for join
+    pub(super) fn remove_exited(&mut self, id: ThreadId) -> Option<Box<Thread>> {
+        if self.is_running(id) || self.threads.get(&id)?.state != ThreadState::Exited {
+            return None;
+        }
+        self.threads.remove(&id)
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::memory::address::VirtualAddress;
+use crate::memory::frame_allocator::deallocate_frame;
+use crate::memory::paging::consts::PageSize;
+use crate::memory::paging::frame::PhysicalFrame;
+use crate::memory::paging::mapper::{MapError, Mapper};
+use crate::memory::paging::page::Page;
+use crate::memory::paging::page_table::PageTableFlags;
+use crate::memory::KERNEL_STACKS_START;
+
+pub const KERNEL_STACK_PAGES: u64 = 4; // 16KB
+
+// The lowest page of every slot is never mapped, so running off the end of a stack faults instead of
+// quietly scribbling over the stack below it
+const GUARD_PAGES: u64 = 1;
+const SLOT_SIZE: u64 = (KERNEL_STACK_PAGES + GUARD_PAGES) * PageSize::NORMAL as u64;
+
+// Kernel stacks are handed out in fixed size slots, slots that come back get reused first
+struct Slots {
+    next: u64,
+    free: Vec<u64>,
+}
+
+static SLOTS: Mutex<Slots> = Mutex::new(Slots {
+    next: 0,
+    free: Vec::new(),
+});
+
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: u64,
+}
+
+impl KernelStack {
+    pub fn allocate() -> Result<KernelStack, MapError> {
+        let slot = without_interrupts(|| {
+            let mut slots = SLOTS.lock();
+            slots.free.pop().unwrap_or_else(|| {
+                slots.next += 1;
+                slots.next - 1
+            })
+        });
+        let stack = KernelStack { slot };
+        // Stacks are the only kernel mappings that come and go after boot. Holding SLOTS keeps other CPUs from
+        // changing the same page tables at the same time, and interrupts are off so nothing else on this one does.
+        let mapped = without_interrupts(|| {
+            let _slots = SLOTS.lock();
+            let mut mapper = unsafe { Mapper::active() };
+            stack.pages().try_for_each(|page| {
+                mapper
+                    .map(
+                        page,
+                        PageTableFlags::WRITABLE
+                            | PageTableFlags::GLOBAL
+                            | PageTableFlags::NO_EXECUTE,
+                    )
+                    .map(|_| ())
+            })
+        });
+        // dropping the stack unmaps whatever did get mapped
+        mapped.map(|_| stack)
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let bottom = self.bottom().0;
+        (0..KERNEL_STACK_PAGES).map(move |page| {
+            Page::from_address_aligned(
+                VirtualAddress::new(bottom + page * PageSize::NORMAL as u64),
+                PageSize::NORMAL,
+            )
+        })
+    }
+
+    // The lowest usable address, right above the guard page
+    pub fn bottom(&self) -> VirtualAddress {
+        VirtualAddress::new(
+            KERNEL_STACKS_START + self.slot * SLOT_SIZE + GUARD_PAGES * PageSize::NORMAL as u64,
+        )
+    }
+
+    // Stacks grow down, so this is where rsp starts out
+    pub fn top(&self) -> VirtualAddress {
+        VirtualAddress::new(self.bottom().0 + KERNEL_STACK_PAGES * PageSize::NORMAL as u64)
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut slots = SLOTS.lock();
+            let mut mapper = unsafe { Mapper::active() };
+            let frames: Vec<PhysicalFrame> = self
+                .pages()
+                .filter_map(|page| mapper.unmap(page).ok())
+                .collect();
+            // every CPU can have the stack's pages cached, they're global
+            mapper.flush();
+            for frame in frames {
+                unsafe { deallocate_frame(frame) };
+            }
+            slots.free.push(self.slot);
+        });
+    }
+}
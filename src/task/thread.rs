@@ -0,0 +1,169 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::memory::address::VirtualAddress;
+use crate::process::Process;
+use crate::task::context::{Context, FpuState};
+use crate::task::policy::SchedulingClass;
+use crate::task::stack::KernelStack;
+use crate::task::wait_queue::WaitQueue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    pub(super) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    // waiting in the run queue
+    Ready,
+    Running,
+    // waiting on something, not in the run queue until it's woken up
+    Blocked,
+    // finished, waiting for someone to join it (or to be cleaned up if nobody will)
+    Exited,
+}
+
+// The thread control block
+pub struct Thread {
+    pub(super) id: ThreadId,
+    pub(super) name: &'static str,
+    pub(super) process: Arc<Process>,
+    pub(super) state: ThreadState,
+    // The CPU whose run queue it goes on whenever it's ready, the one it last ran on unless it's been moved since, see
+    // Scheduler::pull
+    pub(super) cpu: usize,
+    // woken up while it was still on its way to blocking, so it doesn't, see Scheduler::wake
+    pub(super) woken: bool,
+    pub(super) context: Context,
+    pub(super) fpu_state: Box<FpuState>,
+    // None for the boot thread, which keeps running on the stack the bootloader gave it
+    pub(super) kernel_stack: Option<KernelStack>,
+    // what RSP0 gets set to while this thread runs
+    pub(super) kernel_stack_top: VirtualAddress,
+    // taken out and run the first time the thread is switched to
+    pub(super) entry: Option<Box<dyn FnOnce() + Send>>,
+    // nobody is going to join it, so it can be cleaned up as soon as it exits
+    pub(super) detached: bool,
+    // new threads start out with interrupts however the thread that spawned them had them
+    pub(super) interrupts_enabled: bool,
+    // whoever is joining the thread waits here for it to exit
+    pub(super) exit_queue: Arc<WaitQueue>,
+    // the kernel rsp to go back to when this thread's trip to ring 3 ends, see usermode
+    pub(super) user_mode_context: u64,
+    pub(super) class: SchedulingClass,
+    // CPU time scaled by the thread's weight, only used by the fair policy
+    pub(super) vruntime: u64,
+    // CPU time in nanoseconds, and how many times the thread has been switched to
+    pub(super) cpu_time: u64,
+    pub(super) switches: u64,
+}
+
+impl Thread {
+    // The thread that's already running when the scheduler starts up
+    pub(super) fn boot(kernel_stack_top: VirtualAddress, process: Arc<Process>) -> Box<Thread> {
+        let id = ThreadId::new();
+        process.add_thread(id);
+        Box::new(Thread {
+            id,
+            name: "main",
+            process,
+            state: ThreadState::Running,
+            cpu: crate::smp::current_cpu(),
+            woken: false,
+            context: Context::default(),
+            fpu_state: Box::new(FpuState::new()),
+            kernel_stack: None,
+            kernel_stack_top,
+            entry: None,
+            detached: true,
+            interrupts_enabled: crate::interrupts::are_enabled(),
+            exit_queue: Arc::new(WaitQueue::new()),
+            user_mode_context: 0,
+            class: SchedulingClass::default(),
+            vruntime: 0,
+            cpu_time: 0,
+            switches: 0,
+        })
+    }
+
+    // What a CPU that's just been started is running when it joins the scheduler, which carries on as its idle task
+    pub(super) fn idle(kernel_stack: KernelStack, process: Arc<Process>) -> Box<Thread> {
+        let mut thread = Thread::boot(kernel_stack.top(), process);
+        thread.name = "idle";
+        thread.kernel_stack = Some(kernel_stack);
+        // it has to be able to wake up from hlt
+        thread.interrupts_enabled = true;
+        thread
+    }
+
+    pub(super) fn new(
+        name: &'static str,
+        kernel_stack: KernelStack,
+        entry: Box<dyn FnOnce() + Send>,
+        start: extern "C" fn(u64) -> !,
+        process: Arc<Process>,
+    ) -> Box<Thread> {
+        let kernel_stack_top = kernel_stack.top();
+        let id = ThreadId::new();
+        process.add_thread(id);
+        Box::new(Thread {
+            id,
+            name,
+            process,
+            state: ThreadState::Ready,
+            // picked when it's added, see Scheduler::add
+            cpu: 0,
+            woken: false,
+            context: Context::new(kernel_stack_top.0, start, 0),
+            fpu_state: Box::new(FpuState::new()),
+            kernel_stack: Some(kernel_stack),
+            kernel_stack_top,
+            entry: Some(entry),
+            detached: false,
+            interrupts_enabled: crate::interrupts::are_enabled(),
+            exit_queue: Arc::new(WaitQueue::new()),
+            user_mode_context: 0,
+            class: SchedulingClass::default(),
+            vruntime: 0,
+            cpu_time: 0,
+            switches: 0,
+        })
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+
+    pub fn class(&self) -> SchedulingClass {
+        self.class
+    }
+
+    pub fn process(&self) -> &Arc<Process> {
+        &self.process
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        self.process.remove_thread(self.id);
+    }
+}
@@ -0,0 +1,67 @@
+use alloc::collections::VecDeque;
+
+use spin::Mutex;
+
+use crate::interrupts::without_interrupts;
+use crate::task::{current_id, schedule, with_scheduler, ThreadId, ThreadState};
+
+// Threads blocked until something else says they can go on. Not being on the run queue, they cost nothing while
+// they wait, unlike spinning or yielding in a loop.
+#[derive(Debug)]
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<ThreadId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Blocks the current thread for as long as condition holds. Whoever makes it false has to wake the queue up.
+    // The condition is checked with the queue locked, so whoever wakes it either made the change before we looked,
+    // or finds us on the queue. If that's on another CPU before we've actually blocked, the scheduler remembers, see
+    // Scheduler::wake. condition can't touch the queue itself.
+    pub fn wait_while<F>(&self, mut condition: F)
+    where
+        F: FnMut() -> bool,
+    {
+        loop {
+            let waited = without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if !condition() {
+                    return false;
+                }
+                waiters.push_back(current_id());
+                drop(waiters);
+                schedule(ThreadState::Blocked);
+                true
+            });
+            if !waited {
+                return;
+            }
+        }
+    }
+
+    // Wakes the thread that's been waiting the longest, returns false if nobody was waiting
+    pub fn wake_one(&self) -> bool {
+        let waiter = without_interrupts(|| self.waiters.lock().pop_front());
+        match waiter {
+            Some(id) => with_scheduler(|scheduler| scheduler.wake(id)),
+            None => false,
+        }
+    }
+
+    // Returns how many threads were woken up
+    pub fn wake_all(&self) -> usize {
+        let waiters = without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+        with_scheduler(|scheduler| waiters.into_iter().filter(|&id| scheduler.wake(id)).count())
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        WaitQueue::new()
+    }
+}
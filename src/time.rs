@@ -0,0 +1,97 @@
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::port::Port;
+
+// We use the PIT's channel 2 (the one wired to the PC speaker) as a stopwatch to figure out how fast the TSC ticks.
+// See https://wiki.osdev.org/Programmable_Interval_Timer
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL_2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+const SPEAKER_CONTROL_PORT: u16 = 0x61;
+// channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+const SPEAKER_GATE: u8 = 0x01;
+const SPEAKER_DATA: u8 = 0x02;
+const PIT_CHANNEL_2_OUTPUT: u8 = 0x20;
+const CALIBRATION_MS: u64 = 10;
+
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+// zero until init() has run
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+pub fn read_tsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+pub fn init() {
+    BOOT_TSC.store(read_tsc(), Ordering::Relaxed);
+    let frequency = unsafe { calibrate_tsc() };
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+}
+
+unsafe fn calibrate_tsc() -> u64 {
+    let (start, end) = pit_stopwatch(CALIBRATION_MS, read_tsc);
+    (end - start) * 1000 / CALIBRATION_MS
+}
+
+// Reads something right as a PIT one-shot of ms milliseconds starts and again right as it runs out, for calibrating
+// other timers against. The counter is only 16 bits, so ms can't be much more than 50.
+pub(crate) unsafe fn pit_stopwatch<T, F>(ms: u64, mut read: F) -> (T, T)
+where
+    F: FnMut() -> T,
+{
+    let speaker = Port::new(SPEAKER_CONTROL_PORT);
+    let ticks = PIT_FREQUENCY * ms / 1000;
+    debug_assert!(ticks <= u16::MAX as u64);
+
+    // gate on, speaker off
+    let control = speaker.read_u8();
+    speaker.write_u8((control & !SPEAKER_DATA) | SPEAKER_GATE);
+
+    Port::new(PIT_COMMAND_PORT).write_u8(PIT_CHANNEL_2_ONE_SHOT);
+    Port::new(PIT_CHANNEL_2_PORT).write_u8(ticks as u8);
+    Port::new(PIT_CHANNEL_2_PORT).write_u8((ticks >> 8) as u8);
+
+    let start = read();
+    while speaker.read_u8() & PIT_CHANNEL_2_OUTPUT == 0 {
+        core::hint::spin_loop();
+    }
+    let end = read();
+    speaker.write_u8(control);
+    (start, end)
+}
+
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+pub fn tsc_to_nanoseconds(cycles: u64) -> u64 {
+    let frequency = tsc_frequency();
+    if frequency == 0 {
+        return 0;
+    }
+    (cycles as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+pub fn uptime() -> Duration {
+    let frequency = tsc_frequency();
+    if frequency == 0 {
+        return Duration::ZERO;
+    }
+    let elapsed = read_tsc() - BOOT_TSC.load(Ordering::Relaxed);
+    let seconds = elapsed / frequency;
+    let nanoseconds = (elapsed % frequency) * 1_000_000_000 / frequency;
+    Duration::new(seconds, nanoseconds as u32)
+}
+
+// Spins for at least duration, for hardware that wants some time between two steps. Doesn't wait at all before init.
+pub fn delay(duration: Duration) {
+    let cycles = (duration.as_nanos() * tsc_frequency() as u128 / 1_000_000_000) as u64;
+    let start = read_tsc();
+    while read_tsc() - start < cycles {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,198 @@
+use core::arch::global_asm;
+use core::mem::offset_of;
+
+use crate::interrupts;
+use crate::memory::address::VirtualAddress;
+use crate::structs::gdt::SELECTORS;
+use crate::syscall::SyscallFrame;
+use crate::task;
+
+const RFLAGS_RESERVED: u64 = 1 << 1;
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
+
+// usermode_enter(entry, user_stack, code_selector, data_selector, rflags, context)
+// Intel Manual - Section 6.14.3: iretq pops rip, cs, rflags, rsp and ss, and since the new CS has RPL 3 it
+// drops to ring 3 on the way out.
+// context gets the kernel rsp from just before we drop to ring 3, with the callee saved registers on top of it.
+// Anything coming in from ring 3 gets the stack right below that, so it can't trample the way back.
+// usermode_return(frame, context) does the same as usermode_enter, but with every register taken from a SyscallFrame
+// and rax set to 0.
+// usermode_resume(context, value) picks up where either of them left off, returning value from it.
+global_asm!(
+    ".global usermode_enter",
+    "usermode_enter:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [r9], rsp",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push rcx",
+    "push r8",
+    "mov rdi, [r9]",
+    "call {set_kernel_stack}",
+    "pop r8",
+    "pop rcx",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "push rcx",
+    "push rsi",
+    "push r8",
+    "push rdx",
+    "push rdi",
+    // nothing from the kernel should be left lying around in ring 3
+    "xor eax, eax",
+    "xor ebx, ebx",
+    "xor ecx, ecx",
+    "xor edx, edx",
+    "xor esi, esi",
+    "xor edi, edi",
+    "xor ebp, ebp",
+    "xor r8d, r8d",
+    "xor r9d, r9d",
+    "xor r10d, r10d",
+    "xor r11d, r11d",
+    "xor r12d, r12d",
+    "xor r13d, r13d",
+    "xor r14d, r14d",
+    "xor r15d, r15d",
+    // gs has to be the user's while we're in ring 3, see smp::percpu. iretq puts the interrupt flag back.
+    "cli",
+    "swapgs",
+    "iretq",
+    ".global usermode_return",
+    "usermode_return:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rsi], rsp",
+    "push rdi",
+    "mov rdi, [rsi]",
+    "call {set_kernel_stack}",
+    "pop rdi",
+    "push qword ptr [rdi + {ss}]",
+    "push qword ptr [rdi + {rsp}]",
+    "push qword ptr [rdi + {rflags}]",
+    "push qword ptr [rdi + {cs}]",
+    "push qword ptr [rdi + {rip}]",
+    "xor eax, eax",
+    "xor ecx, ecx",
+    "xor r11d, r11d",
+    "mov rbx, [rdi + {rbx}]",
+    "mov rbp, [rdi + {rbp}]",
+    "mov r12, [rdi + {r12}]",
+    "mov r13, [rdi + {r13}]",
+    "mov r14, [rdi + {r14}]",
+    "mov r15, [rdi + {r15}]",
+    "mov r8, [rdi + {r8}]",
+    "mov r9, [rdi + {r9}]",
+    "mov r10, [rdi + {r10}]",
+    "mov rdx, [rdi + {rdx}]",
+    "mov rsi, [rdi + {rsi}]",
+    "mov rdi, [rdi + {rdi}]",
+    "cli",
+    "swapgs",
+    "iretq",
+    ".global usermode_resume",
+    "usermode_resume:",
+    "mov rsp, rdi",
+    "mov rax, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    set_kernel_stack = sym set_kernel_stack,
+    rbx = const offset_of!(SyscallFrame, rbx),
+    rbp = const offset_of!(SyscallFrame, rbp),
+    r12 = const offset_of!(SyscallFrame, r12),
+    r13 = const offset_of!(SyscallFrame, r13),
+    r14 = const offset_of!(SyscallFrame, r14),
+    r15 = const offset_of!(SyscallFrame, r15),
+    r8 = const offset_of!(SyscallFrame, r8),
+    r9 = const offset_of!(SyscallFrame, r9),
+    r10 = const offset_of!(SyscallFrame, r10),
+    rdx = const offset_of!(SyscallFrame, rdx),
+    rsi = const offset_of!(SyscallFrame, rsi),
+    rdi = const offset_of!(SyscallFrame, rdi),
+    rip = const offset_of!(SyscallFrame, rip),
+    cs = const offset_of!(SyscallFrame, cs),
+    rflags = const offset_of!(SyscallFrame, rflags),
+    rsp = const offset_of!(SyscallFrame, rsp),
+    ss = const offset_of!(SyscallFrame, ss),
+);
+
+extern "C" fn set_kernel_stack(stack_top: u64) {
+    task::set_kernel_stack(VirtualAddress::new(stack_top));
+}
+
+extern "C" {
+    fn usermode_enter(
+        entry: u64,
+        user_stack: u64,
+        code_selector: u64,
+        data_selector: u64,
+        rflags: u64,
+        context: *mut u64,
+    ) -> u64;
+    fn usermode_return(frame: *const SyscallFrame, context: *mut u64) -> u64;
+    fn usermode_resume(context: u64, value: u64) -> !;
+}
+
+// Runs the code at entry in ring 3 on user_stack until it makes an exit system call, and returns the status it exited with.
+// Both have to be mapped USER_ACCESSIBLE. Ring 3 runs with interrupts enabled only if they're enabled right now.
+pub unsafe fn enter_user_mode(entry: VirtualAddress, user_stack: VirtualAddress) -> u64 {
+    let selectors = &SELECTORS;
+    let interrupts_enabled = interrupts::are_enabled();
+    let rflags = if interrupts_enabled {
+        RFLAGS_RESERVED | RFLAGS_INTERRUPT_FLAG
+    } else {
+        RFLAGS_RESERVED
+    };
+    run_in_user_mode(|context| {
+        usermode_enter(
+            entry.0,
+            user_stack.0,
+            selectors.user_code_segment.0 as u64,
+            selectors.user_data_segment.0 as u64,
+            rflags,
+            context,
+        )
+    })
+}
+
+// Like enter_user_mode, but carries on with every register from frame, as if the system call that made it had just
+// returned 0. That's how the child of a fork gets going. Ring 3 gets the interrupt flag from frame.
+pub unsafe fn return_to_user_mode(frame: &SyscallFrame) -> u64 {
+    run_in_user_mode(|context| usermode_return(frame, context))
+}
+
+unsafe fn run_in_user_mode<F>(enter: F) -> u64
+where
+    F: FnOnce(*mut u64) -> u64,
+{
+    let interrupts_enabled = interrupts::are_enabled();
+    let kernel_stack = task::kernel_stack();
+    let status = enter(task::user_mode_context());
+    task::set_kernel_stack(kernel_stack);
+    // the exit system call comes back with interrupts off, since syscall turns them off on the way in
+    if interrupts_enabled {
+        interrupts::enable();
+    }
+    status
+}
+
+// For the exit system call, throws away the system call's stack and returns status from enter_user_mode
+pub unsafe fn exit_to_kernel(status: u64) -> ! {
+    usermode_resume(*task::user_mode_context(), status)
+}
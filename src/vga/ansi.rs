@@ -0,0 +1,173 @@
+// A (very) small subset of the VT100/ANSI escape sequence parser described here -> https://vt100.net/emu/dec_ansi_parser
+// We only care about plain ESC sequences and CSI sequences, which is enough for colors, cursor movement and erasing.
+// OSC/DCS strings and friends are not understood, their bytes just end up being printed.
+// The parser works on chars rather than bytes so that anything outside of ASCII makes it through to the code page mapping.
+
+const ESCAPE: char = '\x1b';
+const CANCEL: char = '\x18';
+const SUBSTITUTE: char = '\x1a';
+const DELETE: char = '\x7f';
+
+pub const MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    ControlSequence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlSequence {
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    // set when the sequence starts with '?' (i.e. DEC private modes like "\x1b[?25l")
+    pub private: bool,
+    pub final_byte: u8,
+}
+
+impl ControlSequence {
+    const fn empty() -> Self {
+        ControlSequence {
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+            final_byte: 0,
+        }
+    }
+
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.param_count]
+    }
+
+    // Omitted parameters and explicit zeroes both mean "use the default" for pretty much every sequence we handle
+    pub fn param_or(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&0) | None => default,
+            Some(&param) => param,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    // a character that should be drawn on the screen
+    Print(char),
+    // C0 control characters (\n, \r, \t, backspace, etc.)
+    Execute(u8),
+    // ESC followed by a single final byte (e.g. "\x1bc" to reset the terminal)
+    Escape(u8),
+    ControlSequence(ControlSequence),
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    sequence: ControlSequence,
+    param_index: usize,
+    has_params: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            sequence: ControlSequence::empty(),
+            param_index: 0,
+            has_params: false,
+        }
+    }
+
+    pub fn advance(&mut self, char: char) -> Action {
+        // These work regardless of what state we're in
+        match char {
+            ESCAPE => {
+                self.state = State::Escape;
+                return Action::None;
+            }
+            CANCEL | SUBSTITUTE => {
+                self.state = State::Ground;
+                return Action::None;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match char {
+                '\0'..='\x1f' => Action::Execute(char as u8),
+                DELETE => Action::None,
+                _ => Action::Print(char),
+            },
+            // escape sequences are pure ASCII, so anything else means we got garbage
+            _ if !char.is_ascii() => {
+                self.state = State::Ground;
+                Action::None
+            }
+            State::Escape => self.advance_escape(char as u8),
+            State::ControlSequence => self.advance_control_sequence(char as u8),
+        }
+    }
+
+    fn advance_escape(&mut self, byte: u8) -> Action {
+        match byte {
+            b'[' => {
+                self.sequence = ControlSequence::empty();
+                self.param_index = 0;
+                self.has_params = false;
+                self.state = State::ControlSequence;
+                Action::None
+            }
+            // C0 controls are still executed in the middle of a sequence
+            0x00..=0x1F => Action::Execute(byte),
+            // intermediate bytes, we don't support any sequences that use them
+            0x20..=0x2F => Action::None,
+            _ => {
+                self.state = State::Ground;
+                Action::Escape(byte)
+            }
+        }
+    }
+
+    fn advance_control_sequence(&mut self, byte: u8) -> Action {
+        match byte {
+            b'0'..=b'9' => {
+                let param = &mut self.sequence.params[self.param_index];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add((byte - b'0') as u16);
+                self.has_params = true;
+                Action::None
+            }
+            b';' => {
+                // anything past MAX_PARAMS gets squashed into the last parameter, which is good enough
+                if self.param_index < MAX_PARAMS - 1 {
+                    self.param_index += 1;
+                }
+                self.has_params = true;
+                Action::None
+            }
+            b'?' => {
+                self.sequence.private = true;
+                Action::None
+            }
+            0x00..=0x1F => Action::Execute(byte),
+            // intermediate bytes and the remaining private markers
+            0x20..=0x3F => Action::None,
+            0x40..=0x7E => {
+                self.state = State::Ground;
+                self.sequence.final_byte = byte;
+                self.sequence.param_count = if self.has_params {
+                    self.param_index + 1
+                } else {
+                    0
+                };
+                Action::ControlSequence(self.sequence)
+            }
+            _ => {
+                self.state = State::Ground;
+                Action::None
+            }
+        }
+    }
+}
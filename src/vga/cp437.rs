@@ -0,0 +1,75 @@
+// Code page 437 is the character set burned into the VGA font ROM. See https://en.wikipedia.org/wiki/Code_page_437
+// Index i of this table is the unicode character drawn by the glyph for byte i.
+// 0x00 is a blank glyph, it doesn't map back to anything.
+pub const CP437_TO_UNICODE: [char; 256] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼', //
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/', //
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?', //
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', //
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_', //
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', //
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂', //
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}', //
+];
+
+// Drawn for anything we can't find a glyph for
+pub const FALLBACK_GLYPH: u8 = 0xFE;
+
+// Characters that don't have their own glyph, but look close enough to one that does
+const LOOKALIKES: [(char, u8); 24] = [
+    ('β', 0xE1),
+    ('μ', 0xE6),
+    ('Ω', 0xEA),
+    ('∑', 0xE4),
+    ('ϕ', 0xED),
+    ('∅', 0xED),
+    ('ø', 0xED),
+    ('∈', 0xEE),
+    ('€', b'E'),
+    ('‘', b'\''),
+    ('’', b'\''),
+    ('‚', b','),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('„', b'"'),
+    ('‐', b'-'),
+    ('‑', b'-'),
+    ('–', b'-'),
+    ('—', b'-'),
+    ('−', b'-'),
+    ('×', b'x'),
+    ('⁄', b'/'),
+    ('…', 0xFA),
+    ('✓', 0xFB),
+];
+
+pub fn from_char(char: char) -> Option<u8> {
+    match char {
+        // the common case
+        ' '..='~' => Some(char as u8),
+        '\0' => None,
+        _ => CP437_TO_UNICODE
+            .iter()
+            .position(|&glyph| glyph == char)
+            .map(|byte| byte as u8)
+            .or_else(|| {
+                LOOKALIKES
+                    .iter()
+                    .find(|&&(lookalike, _)| lookalike == char)
+                    .map(|&(_, byte)| byte)
+            }),
+    }
+}
+
+#[inline]
+pub fn from_char_or_fallback(char: char) -> u8 {
+    from_char(char).unwrap_or(FALLBACK_GLYPH)
+}
@@ -0,0 +1,462 @@
+use core::fmt;
+use spin::Mutex;
+use volatile::Volatile;
+
+use crate::port::Port;
+use crate::sync::Lazy;
+
+pub mod ansi;
+pub mod cp437;
+
+use ansi::{Action, ControlSequence, Parser};
+
+pub const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+const TAB_WIDTH: usize = 8;
+
+// CRT Controller registers, see https://wiki.osdev.org/Text_Mode_Cursor
+const CRTC_ADDRESS_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+const CURSOR_DISABLE: u8 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+impl Color {
+    // only the low nibble is looked at
+    pub fn from_u8(value: u8) -> Color {
+        match value & 0x0F {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::DarkGray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+
+    // ANSI orders its colors black, red, green, yellow, blue, magenta, cyan, white. VGA doesn't.
+    pub fn from_ansi(index: u8, bright: bool) -> Color {
+        const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+        let color = ANSI_TO_VGA[(index & 0x07) as usize];
+        Color::from_u8(if bright { color | 0x08 } else { color })
+    }
+
+    pub fn bright(self) -> Color {
+        Color::from_u8(self as u8 | 0x08)
+    }
+
+    pub fn dim(self) -> Color {
+        Color::from_u8(self as u8 & 0x07)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ColorCode(u8);
+
+impl ColorCode {
+    pub const fn new(background: Color, foreground: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+
+    pub fn foreground(self) -> Color {
+        Color::from_u8(self.0)
+    }
+
+    pub fn background(self) -> Color {
+        Color::from_u8(self.0 >> 4)
+    }
+
+    pub fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode::new(self.background(), foreground)
+    }
+
+    pub fn with_background(self, background: Color) -> ColorCode {
+        ColorCode::new(background, self.foreground())
+    }
+
+    pub fn inverted(self) -> ColorCode {
+        ColorCode::new(self.foreground(), self.background())
+    }
+}
+
+pub const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Color::Black, Color::Cyan);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+struct ScreenChar {
+    char: u8,
+    color: ColorCode,
+}
+
+impl ScreenChar {
+    fn new(char: u8, color: ColorCode) -> Self {
+        ScreenChar { char, color }
+    }
+}
+
+#[repr(transparent)]
+struct Buffer {
+    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+pub struct Writer {
+    row: usize,
+    // can be equal to BUFFER_WIDTH, in which case the next printed char wraps onto a new line
+    col: usize,
+    saved_position: (usize, usize),
+    color_code: ColorCode,
+    default_color_code: ColorCode,
+    // SGR 1, makes the foreground color bright
+    bold: bool,
+    cursor_visible: bool,
+    parser: Parser,
+    buffer: &'static mut Buffer,
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_chars(s);
+        Ok(())
+    }
+}
+
+impl Writer {
+    fn new(color_code: ColorCode) -> Writer {
+        let mut writer = Writer {
+            row: 0,
+            col: 0,
+            saved_position: (0, 0),
+            color_code,
+            default_color_code: color_code,
+            bold: false,
+            cursor_visible: true,
+            parser: Parser::new(),
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        };
+        // the bootloader leaves its own output behind
+        writer.clear_screen();
+        writer.enable_cursor();
+        writer
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row, self.col.min(BUFFER_WIDTH - 1))
+    }
+
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row = row.min(BUFFER_HEIGHT - 1);
+        self.col = col.min(BUFFER_WIDTH - 1);
+        self.update_cursor();
+    }
+
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(background, foreground);
+    }
+
+    pub fn set_default_color_code(&mut self, color_code: ColorCode) {
+        self.default_color_code = color_code;
+    }
+
+    pub fn reset_color(&mut self) {
+        self.color_code = self.default_color_code;
+        self.bold = false;
+    }
+
+    pub fn clear_screen(&mut self) {
+        for y in 0..BUFFER_HEIGHT {
+            self.clear_row(y);
+        }
+        self.row = 0;
+        self.col = 0;
+        self.update_cursor();
+    }
+
+    fn write_chars(&mut self, s: &str) {
+        for char in s.chars() {
+            match self.parser.advance(char) {
+                Action::None => {}
+                Action::Print(char) => self.write_byte(cp437::from_char_or_fallback(char)),
+                Action::Execute(byte) => self.execute(byte),
+                Action::Escape(byte) => self.escape(byte),
+                Action::ControlSequence(sequence) => self.control_sequence(&sequence),
+            }
+        }
+        self.update_cursor();
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if self.col >= BUFFER_WIDTH {
+            self.new_line();
+        }
+        let color = self.color_code;
+        self.buffer.chars[self.row][self.col].write(ScreenChar::new(byte, color));
+        self.col += 1;
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.col = 0,
+            b'\t' => self.tab(),
+            // backspace only moves the cursor, same as a real terminal. Erasing is "\x08 \x08"
+            0x08 => self.col = self.col.min(BUFFER_WIDTH - 1).saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'c' => {
+                self.reset_color();
+                self.clear_screen();
+            }
+            b'7' => self.save_position(),
+            b'8' => self.restore_position(),
+            _ => {}
+        }
+    }
+
+    // See https://en.wikipedia.org/wiki/ANSI_escape_code#CSI_(Control_Sequence_Introducer)_sequences
+    fn control_sequence(&mut self, sequence: &ControlSequence) {
+        let n = sequence.param_or(0, 1) as usize;
+        let (row, col) = self.position();
+        if sequence.private {
+            // we only know how to show and hide the cursor
+            if sequence.param_or(0, 0) == 25 {
+                match sequence.final_byte {
+                    b'h' => self.enable_cursor(),
+                    b'l' => self.disable_cursor(),
+                    _ => {}
+                }
+            }
+            return;
+        }
+        match sequence.final_byte {
+            b'A' => self.set_position(row.saturating_sub(n), col),
+            b'B' => self.set_position(row + n, col),
+            b'C' => self.set_position(row, col + n),
+            b'D' => self.set_position(row, col.saturating_sub(n)),
+            b'E' => self.set_position(row + n, 0),
+            b'F' => self.set_position(row.saturating_sub(n), 0),
+            b'G' => self.set_position(row, n - 1),
+            // positions are 1-based
+            b'H' | b'f' => {
+                let row = sequence.param_or(0, 1) as usize - 1;
+                let col = sequence.param_or(1, 1) as usize - 1;
+                self.set_position(row, col);
+            }
+            b'J' => self.erase_in_display(sequence.params().first().copied().unwrap_or(0)),
+            b'K' => self.erase_in_line(sequence.params().first().copied().unwrap_or(0)),
+            b'm' => self.select_graphic_rendition(sequence.params()),
+            b's' => self.save_position(),
+            b'u' => self.restore_position(),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        // "\x1b[m" is the same as "\x1b[0m"
+        if params.is_empty() {
+            self.reset_color();
+            return;
+        }
+        for &param in params {
+            match param {
+                0 => self.reset_color(),
+                1 => {
+                    self.bold = true;
+                    self.color_code = self
+                        .color_code
+                        .with_foreground(self.color_code.foreground().bright());
+                }
+                22 => {
+                    self.bold = false;
+                    self.color_code = self
+                        .color_code
+                        .with_foreground(self.color_code.foreground().dim());
+                }
+                7 => self.color_code = self.color_code.inverted(),
+                30..=37 => {
+                    let foreground = Color::from_ansi((param - 30) as u8, self.bold);
+                    self.color_code = self.color_code.with_foreground(foreground);
+                }
+                39 => {
+                    let foreground = self.default_color_code.foreground();
+                    self.color_code = self.color_code.with_foreground(foreground);
+                }
+                40..=47 => {
+                    let background = Color::from_ansi((param - 40) as u8, false);
+                    self.color_code = self.color_code.with_background(background);
+                }
+                49 => {
+                    let background = self.default_color_code.background();
+                    self.color_code = self.color_code.with_background(background);
+                }
+                90..=97 => {
+                    let foreground = Color::from_ansi((param - 90) as u8, true);
+                    self.color_code = self.color_code.with_foreground(foreground);
+                }
+                100..=107 => {
+                    let background = Color::from_ansi((param - 100) as u8, true);
+                    self.color_code = self.color_code.with_background(background);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let (row, col) = self.position();
+        match mode {
+            0 => {
+                self.clear_cols(row, col, BUFFER_WIDTH);
+                for y in row + 1..BUFFER_HEIGHT {
+                    self.clear_row(y);
+                }
+            }
+            1 => {
+                for y in 0..row {
+                    self.clear_row(y);
+                }
+                self.clear_cols(row, 0, col + 1);
+            }
+            // 3 is supposed to clear the scrollback too, but we don't have any
+            2 | 3 => {
+                for y in 0..BUFFER_HEIGHT {
+                    self.clear_row(y);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = self.position();
+        match mode {
+            0 => self.clear_cols(row, col, BUFFER_WIDTH),
+            1 => self.clear_cols(row, 0, col + 1),
+            2 => self.clear_row(row),
+            _ => {}
+        }
+    }
+
+    fn tab(&mut self) {
+        let next_stop = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+        self.col = next_stop.min(BUFFER_WIDTH - 1);
+    }
+
+    fn save_position(&mut self) {
+        self.saved_position = self.position();
+    }
+
+    fn restore_position(&mut self) {
+        let (row, col) = self.saved_position;
+        self.set_position(row, col);
+    }
+
+    fn new_line(&mut self) {
+        if self.row < BUFFER_HEIGHT - 1 {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
+        self.col = 0;
+    }
+
+    fn scroll_up(&mut self) {
+        for y in 1..BUFFER_HEIGHT {
+            for x in 0..BUFFER_WIDTH {
+                let char = self.buffer.chars[y][x].read();
+                self.buffer.chars[y - 1][x].write(char);
+            }
+        }
+        self.clear_row(BUFFER_HEIGHT - 1);
+    }
+
+    fn clear_row(&mut self, y: usize) {
+        self.clear_cols(y, 0, BUFFER_WIDTH);
+    }
+
+    fn clear_cols(&mut self, y: usize, start: usize, end: usize) {
+        let whitespace = ScreenChar::new(b' ', self.color_code);
+        for x in start..end.min(BUFFER_WIDTH) {
+            self.buffer.chars[y][x].write(whitespace);
+        }
+    }
+
+    pub fn enable_cursor(&mut self) {
+        // scanlines 14-15, i.e. an underline cursor
+        unsafe {
+            write_crtc_register(CRTC_CURSOR_START, 14);
+            write_crtc_register(CRTC_CURSOR_END, 15);
+        }
+        self.cursor_visible = true;
+        self.update_cursor();
+    }
+
+    pub fn disable_cursor(&mut self) {
+        unsafe { write_crtc_register(CRTC_CURSOR_START, CURSOR_DISABLE) };
+        self.cursor_visible = false;
+    }
+
+    fn update_cursor(&self) {
+        if !self.cursor_visible {
+            return;
+        }
+        let (row, col) = self.position();
+        let offset = (row * BUFFER_WIDTH + col) as u16;
+        unsafe {
+            write_crtc_register(CRTC_CURSOR_LOCATION_LOW, (offset & 0xFF) as u8);
+            write_crtc_register(CRTC_CURSOR_LOCATION_HIGH, (offset >> 8) as u8);
+        }
+    }
+}
+
+unsafe fn write_crtc_register(index: u8, value: u8) {
+    Port::new(CRTC_ADDRESS_PORT).write_u8(index);
+    Port::new(CRTC_DATA_PORT).write_u8(value);
+}
+
+pub static WRITER: Lazy<Mutex<Writer>> = Lazy::new(|| Mutex::new(Writer::new(DEFAULT_COLOR_CODE)));
@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::block::ahci::AhciDisk;
+use flap_os::block::{self, BlockDevice, BlockError};
+use flap_os::task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// The blank 16 MiB image Cargo.toml gives QEMU for tests to write to
+fn scratch_disk() -> Arc<AhciDisk> {
+    let device: Arc<dyn Any + Send + Sync> = block::find("sda").expect("no sda");
+    device.downcast::<AhciDisk>().unwrap()
+}
+
+fn pattern(blocks: usize, seed: usize) -> Vec<u8> {
+    (0..blocks * 512)
+        .map(|index| (index * 7 + seed) as u8)
+        .collect()
+}
+
+#[test_case]
+fn the_disk_is_identified() {
+    let disk = scratch_disk();
+    assert!(disk.uses_interrupts());
+    assert_eq!(disk.block_size(), 512);
+    assert_eq!(disk.block_count(), 16 * 1024 * 1024 / 512);
+}
+
+#[test_case]
+fn writes_read_back() {
+    let disk = scratch_disk();
+    // more than one command's worth, the last of which ends halfway through a page
+    let blocks = 300;
+    let start = disk.block_count() - blocks as u64;
+    for seed in [0, 1] {
+        let written = pattern(blocks, seed);
+        disk.write_blocks(start, &written).unwrap();
+        disk.flush().unwrap();
+        let mut read = vec![0; written.len()];
+        disk.read_blocks(start, &mut read).unwrap();
+        assert!(read == written);
+    }
+}
+
+// More threads than there are command slots, so some have to wait for one
+#[test_case]
+fn commands_from_several_threads_complete() {
+    let threads: Vec<_> = (0..8)
+        .map(|index| {
+            task::spawn(move || {
+                let disk = scratch_disk();
+                let start = index as u64 * 64;
+                for round in 0..4 {
+                    let written = pattern(64, index * 4 + round);
+                    disk.write_blocks(start, &written).unwrap();
+                    let mut read = vec![0; written.len()];
+                    disk.read_blocks(start, &mut read).unwrap();
+                    assert!(read == written);
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for thread in threads {
+        thread.join();
+    }
+}
+
+#[test_case]
+fn bad_requests_are_rejected() {
+    let disk = scratch_disk();
+    let mut buffer = [0; 1024];
+    assert_eq!(
+        disk.read_blocks(disk.block_count() - 1, &mut buffer),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        disk.write_blocks(0, &buffer[..100]),
+        Err(BlockError::BadLength)
+    );
+}
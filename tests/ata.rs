@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::block::ata::{AtaDrive, DriveKind};
+use flap_os::block::{self, BlockDevice, BlockError};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+fn drive(name: &str) -> Arc<AtaDrive> {
+    let device: Arc<dyn Any + Send + Sync> = block::find(name).expect(name);
+    device.downcast::<AtaDrive>().unwrap()
+}
+
+// The disk QEMU boots from, which is the primary master. Only ever read, it has the kernel on it.
+fn boot_disk() -> Arc<AtaDrive> {
+    drive("hda")
+}
+
+// The primary slave, a blank image Cargo.toml gives QEMU for tests to write to
+fn scratch_disk() -> Arc<AtaDrive> {
+    drive("hdb")
+}
+
+#[test_case]
+fn the_boot_sector_is_readable() {
+    let disk = boot_disk();
+    assert_eq!(disk.kind(), DriveKind::Ata);
+    let mut sector = [0; 512];
+    disk.read_blocks(0, &mut sector).unwrap();
+    assert_eq!(&sector[510..], &[0x55, 0xAA]);
+}
+
+// Goes through both DMA and PIO
+#[test_case]
+fn writes_read_back_with_and_without_dma() {
+    let disk = scratch_disk();
+    assert!(disk.uses_dma());
+    assert_eq!(disk.block_count(), 16 * 1024 * 1024 / 512);
+    // enough to take more than one command, the last of which ends halfway through a page
+    let blocks = 300;
+    let start = disk.block_count() - blocks;
+    let pattern: Vec<u8> = (0..blocks as usize * 512)
+        .map(|index| (index * 7) as u8)
+        .collect();
+    disk.write_blocks(start, &pattern).unwrap();
+    disk.flush().unwrap();
+    let mut read = vec![0; pattern.len()];
+    disk.read_blocks(start, &mut read).unwrap();
+    assert!(read == pattern);
+
+    disk.disable_dma();
+    let mut read = vec![0; pattern.len()];
+    disk.read_blocks(start, &mut read).unwrap();
+    assert!(read == pattern);
+    let inverted: Vec<u8> = pattern.iter().map(|byte| !byte).collect();
+    disk.write_blocks(start, &inverted).unwrap();
+    disk.flush().unwrap();
+    disk.read_blocks(start, &mut read).unwrap();
+    assert!(read == inverted);
+}
+
+#[test_case]
+fn bad_requests_are_rejected() {
+    let disk = scratch_disk();
+    let mut buffer = [0; 1024];
+    assert_eq!(
+        disk.read_blocks(disk.block_count() - 1, &mut buffer),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        disk.read_blocks(u64::MAX, &mut buffer),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        disk.write_blocks(0, &buffer[..100]),
+        Err(BlockError::BadLength)
+    );
+}
@@ -0,0 +1,70 @@
+// What the tests that run programs share. Every test file is its own crate and not all of them use everything here.
+#![allow(dead_code)]
+
+use alloc::vec::Vec;
+
+use flap_os::memory::paging::consts::PageSize;
+use flap_os::memory::USER_SPACE_START;
+use flap_os::process;
+
+pub const CODE: u64 = USER_SPACE_START + 0x40_0000;
+pub const DATA: u64 = CODE + 0x10_0000;
+
+// The bytes between two labels of a program in global_asm!
+pub unsafe fn code(start: *const u8, end: *const u8) -> &'static [u8] {
+    core::slice::from_raw_parts(start, end as usize - start as usize)
+}
+
+// A static executable the way a linker would lay it out: the headers, then a code segment at CODE and a data segment
+// at DATA, each starting on its own page in the file. The data segment is data followed by zeros (bss) up to
+// data_memory_size.
+pub fn build_executable_with_data(code: &[u8], data: &[u8], data_memory_size: u64) -> Vec<u8> {
+    let page_size = PageSize::NORMAL as u64;
+    let mut file = Vec::new();
+    file.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(&2_u16.to_le_bytes()); // ET_EXEC
+    file.extend_from_slice(&62_u16.to_le_bytes()); // EM_X86_64
+    file.extend_from_slice(&1_u32.to_le_bytes());
+    file.extend_from_slice(&CODE.to_le_bytes());
+    file.extend_from_slice(&64_u64.to_le_bytes()); // program headers right after this one
+    file.extend_from_slice(&0_u64.to_le_bytes());
+    file.extend_from_slice(&0_u32.to_le_bytes());
+    for size in [64_u16, 56, 2, 64, 0, 0] {
+        file.extend_from_slice(&size.to_le_bytes());
+    }
+    let segments = [
+        // PF_R | PF_X
+        (5_u32, page_size, CODE, code.len() as u64, code.len() as u64),
+        // PF_R | PF_W
+        (6, 2 * page_size, DATA, data.len() as u64, data_memory_size),
+    ];
+    for (flags, offset, address, file_size, memory_size) in segments {
+        file.extend_from_slice(&1_u32.to_le_bytes()); // PT_LOAD
+        file.extend_from_slice(&flags.to_le_bytes());
+        for field in [offset, address, address, file_size, memory_size, page_size] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+    file.resize(page_size as usize, 0);
+    file.extend_from_slice(code);
+    if !data.is_empty() {
+        file.resize(2 * page_size as usize, 0);
+        file.extend_from_slice(data);
+    }
+    file
+}
+
+// With a zeroed, writable page at DATA
+pub fn build_executable(code: &[u8]) -> Vec<u8> {
+    build_executable_with_data(code, &[], PageSize::NORMAL as u64)
+}
+
+// Runs the program and waits for it, returning its exit status
+pub fn run(code: &[u8]) -> u64 {
+    let (process, _) =
+        process::spawn_program("test", &build_executable(code), &["test"], &[]).unwrap();
+    let (pid, status) = process::wait(Some(process.id()), true).unwrap().unwrap();
+    assert_eq!(pid, process.id());
+    status
+}
@@ -0,0 +1,216 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::ptr::addr_of;
+
+use bootloader::{entry_point, BootInfo};
+
+use common::{build_executable_with_data, CODE, DATA};
+
+use flap_os::memory::address::VirtualAddress;
+use flap_os::memory::paging::consts::PageSize;
+use flap_os::memory::paging::page::Page;
+use flap_os::memory::paging::page_table::PageTableFlags;
+use flap_os::process::elf::{self, ElfError};
+use flap_os::process::{self, Process, VmaKind};
+
+// the data segment is 8 bytes in the file and the rest of its two pages are bss
+const DATA_MEMORY_SIZE: u64 = 0x2000;
+const DATA_VALUE: u64 = 0x1122_3344_5566_7788;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// Exits with 0 if the stack, the segments and the auxiliary vector all look the way the loader promises, otherwise
+// with the number of the check that failed. Expects to be run as `test hello` with one environment variable.
+global_asm!(
+    ".global elf_test_program_start",
+    ".global elf_test_program_end",
+    "elf_test_program_start:",
+    "mov r12, 1",
+    "test rsp, 15",
+    "jnz 2f",
+    "mov r12, 2",
+    "cmp qword ptr [rsp], 2",
+    "jne 2f",
+    "mov r12, 3",
+    "cmp qword ptr [rsp + 24], 0",
+    "jne 2f",
+    "cmp qword ptr [rsp + 32], 0",
+    "je 2f",
+    "cmp qword ptr [rsp + 40], 0",
+    "jne 2f",
+    // walk the auxiliary vector, AT_PAGESZ and AT_ENTRY both have to be there
+    "mov r12, 4",
+    "lea rbx, [rsp + 48]",
+    "xor r13, r13",
+    "3:",
+    "mov rax, [rbx]",
+    "test rax, rax",
+    "jz 4f",
+    "cmp rax, 6",
+    "jne 5f",
+    "cmp qword ptr [rbx + 8], 4096",
+    "jne 2f",
+    "inc r13",
+    "5:",
+    "cmp rax, 9",
+    "jne 6f",
+    "lea rcx, [rip + elf_test_program_start]",
+    "cmp [rbx + 8], rcx",
+    "jne 2f",
+    "inc r13",
+    "6:",
+    "add rbx, 16",
+    "jmp 3b",
+    "4:",
+    "cmp r13, 2",
+    "jne 2f",
+    "mov r12, 5",
+    "mov rax, {data}",
+    "mov rcx, {data_value}",
+    "cmp [rax], rcx",
+    "jne 2f",
+    "mov r12, 6",
+    "cmp qword ptr [rax + 0x1800], 0",
+    "jne 2f",
+    "mov [rax + 0x1800], rcx",
+    "mov r12, 7",
+    "mov eax, 1",
+    "mov edi, 1",
+    "mov rsi, [rsp + 16]",
+    "mov edx, 5",
+    "syscall",
+    "cmp rax, 5",
+    "jne 2f",
+    "xor r12, r12",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "elf_test_program_end:",
+    data = const DATA,
+    data_value = const DATA_VALUE,
+);
+
+extern "C" {
+    static elf_test_program_start: u8;
+    static elf_test_program_end: u8;
+}
+
+fn test_program() -> &'static [u8] {
+    unsafe {
+        let start = addr_of!(elf_test_program_start);
+        let end = addr_of!(elf_test_program_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+fn ring_buffer_contains(needle: &[u8]) -> bool {
+    let ring_buffer = flap_os::console::RING_BUFFER.lock();
+    let (older, newer) = ring_buffer.as_slices();
+    older.windows(needle.len()).any(|window| window == needle)
+        || newer.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test_case]
+fn segments_are_mapped_with_their_permissions() {
+    let process = Process::new("test").unwrap();
+    let image = elf::load(
+        &process,
+        &build_executable_with_data(test_program(), &DATA_VALUE.to_le_bytes(), DATA_MEMORY_SIZE),
+        &["test"],
+        &[],
+    )
+    .unwrap();
+    assert_eq!(image.entry, VirtualAddress::new(CODE));
+    assert_eq!(image.stack_pointer.0 % 16, 0);
+    let mapper = process.address_space().mapper();
+    let flags = |address| {
+        mapper
+            .flags(Page::from_address_aligned(
+                VirtualAddress::new(address),
+                PageSize::NORMAL,
+            ))
+            .unwrap()
+    };
+    let code = flags(CODE);
+    assert!(code.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(!code.contains(PageTableFlags::WRITABLE) && !code.contains(PageTableFlags::NO_EXECUTE));
+    let data = flags(DATA);
+    assert!(data.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+    // the bss page only gets mapped once it's touched, but it's part of the segment's area already
+    let bss = VirtualAddress::new(DATA + 0x1000);
+    assert!(mapper.translate(bss).is_none());
+    let area = process.address_space().find_area(bss).unwrap();
+    assert_eq!(area.kind, VmaKind::File);
+    assert!(area.flags.contains(
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+    ));
+    assert_eq!(area.end, VirtualAddress::new(DATA + DATA_MEMORY_SIZE));
+    assert!(process
+        .address_space()
+        .find_area(VirtualAddress::new(DATA + DATA_MEMORY_SIZE))
+        .is_none());
+}
+
+#[test_case]
+fn program_runs_in_ring_3_with_its_arguments() {
+    let (process, handle) = process::spawn_program(
+        "test",
+        &build_executable_with_data(test_program(), &DATA_VALUE.to_le_bytes(), DATA_MEMORY_SIZE),
+        &["test", "hello"],
+        &["PATH=/"],
+    )
+    .unwrap();
+    handle.join();
+    assert_eq!(process.exit_status(), Some(0));
+    assert!(ring_buffer_contains(b"hello"));
+}
+
+#[test_case]
+fn broken_executables_are_refused() {
+    let process = Process::new("test").unwrap();
+    let executable =
+        build_executable_with_data(test_program(), &DATA_VALUE.to_le_bytes(), DATA_MEMORY_SIZE);
+
+    let mut bad_magic = executable.clone();
+    bad_magic[0] = 0;
+    assert_eq!(
+        elf::load(&process, &bad_magic, &[], &[]),
+        Err(ElfError::BadMagic)
+    );
+
+    assert_eq!(
+        elf::load(&process, &executable[..100], &[], &[]),
+        Err(ElfError::Truncated)
+    );
+
+    // the code segment moved into the kernel half
+    let mut kernel_segment = executable.clone();
+    kernel_segment[64 + 16..64 + 24].copy_from_slice(&0xFFFF_8000_0000_0000_u64.to_le_bytes());
+    assert_eq!(
+        elf::load(&process, &kernel_segment, &[], &[]),
+        Err(ElfError::BadSegment)
+    );
+}
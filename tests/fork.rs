@@ -0,0 +1,338 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::ptr::addr_of;
+
+use bootloader::{entry_point, BootInfo};
+
+use common::{build_executable, code, run, DATA};
+
+use flap_os::fs::{self, OpenOptions};
+use flap_os::process::{
+    self, ProcessId, FLOATING_POINT_EXIT_STATUS, ILLEGAL_INSTRUCTION_EXIT_STATUS,
+    SEGFAULT_EXIT_STATUS, TRAP_EXIT_STATUS,
+};
+use flap_os::syscall::consts::Errno;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// System call numbers: exit 0, fork 2, execve 3, waitpid 4, getpid 5, getppid 6.
+// Each program exits with 0 if everything checked out, otherwise with the number of the check that failed.
+global_asm!(
+    // The parent writes 1 to its data page and forks. The child checks it got the parent's registers, parent and
+    // data, writes 2 over the data and exits with 42. The parent waits for it and checks its own data is still 1.
+    ".global fork_test_program_start",
+    ".global fork_test_program_end",
+    "fork_test_program_start:",
+    "mov eax, 5",
+    "syscall",
+    "mov r13, rax",
+    "mov rbx, 0x1234",
+    "mov rax, {data}",
+    "mov qword ptr [rax], 1",
+    "mov eax, 2",
+    "syscall",
+    "test rax, rax",
+    "jz 3f",
+    "mov r12, 1",
+    "js 2f",
+    "mov r14, rax",
+    "mov rdi, r14",
+    "mov rsi, {status}",
+    "xor edx, edx",
+    "mov eax, 4",
+    "syscall",
+    "mov r12, 2",
+    "cmp rax, r14",
+    "jne 2f",
+    "mov r12, 3",
+    "mov rax, {data}",
+    "cmp dword ptr [rax + 8], 0x2A00",
+    "jne 2f",
+    "mov r12, 4",
+    "cmp qword ptr [rax], 1",
+    "jne 2f",
+    "xor r12, r12",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "3:",
+    "mov r12, 10",
+    "cmp rbx, 0x1234",
+    "jne 2b",
+    "mov r12, 11",
+    "mov eax, 6",
+    "syscall",
+    "cmp rax, r13",
+    "jne 2b",
+    "mov r12, 12",
+    "mov rax, {data}",
+    "cmp qword ptr [rax], 1",
+    "jne 2b",
+    "mov qword ptr [rax], 2",
+    "mov r12, 42",
+    "jmp 2b",
+    "fork_test_program_end:",
+    // Tries to execve something that isn't there, then /exit_argc with two arguments
+    ".global exec_test_program_start",
+    ".global exec_test_program_end",
+    "exec_test_program_start:",
+    "lea rdi, [rip + 4f]",
+    "xor esi, esi",
+    "xor edx, edx",
+    "mov eax, 3",
+    "syscall",
+    "mov r12, 1",
+    "cmp rax, -2",
+    "jne 2f",
+    "push 0",
+    "lea rax, [rip + 6f]",
+    "push rax",
+    "lea rax, [rip + 5f]",
+    "push rax",
+    "lea rdi, [rip + 5f]",
+    "mov rsi, rsp",
+    "xor edx, edx",
+    "mov eax, 3",
+    "syscall",
+    "mov r12, 2",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "4:",
+    ".asciz \"/missing\"",
+    "5:",
+    ".asciz \"/exit_argc\"",
+    "6:",
+    ".asciz \"x\"",
+    "exec_test_program_end:",
+    // exits with 40 + argc
+    ".global exit_argc_program_start",
+    ".global exit_argc_program_end",
+    "exit_argc_program_start:",
+    "mov rdi, [rsp]",
+    "add rdi, 40",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "exit_argc_program_end:",
+    // The parent exits straight away, the child waits until it's been handed to the kernel and exits with 7
+    ".global orphan_test_program_start",
+    ".global orphan_test_program_end",
+    "orphan_test_program_start:",
+    "mov eax, 2",
+    "syscall",
+    "test rax, rax",
+    "jz 3f",
+    "xor edi, edi",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "3:",
+    "mov eax, 6",
+    "syscall",
+    "test rax, rax",
+    "jnz 3b",
+    "mov edi, 7",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "orphan_test_program_end:",
+    // writes to its own code
+    ".global segfault_test_program_start",
+    ".global segfault_test_program_end",
+    "segfault_test_program_start:",
+    "lea rax, [rip + segfault_test_program_start]",
+    "mov qword ptr [rax], 0",
+    "xor edi, edi",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "segfault_test_program_end:",
+    // Each of these does something ring 3 isn't allowed to and should never get as far as exiting
+    ".global invalid_opcode_program_start",
+    ".global invalid_opcode_program_end",
+    "invalid_opcode_program_start:",
+    "ud2",
+    "invalid_opcode_program_end:",
+    ".global divide_by_zero_program_start",
+    ".global divide_by_zero_program_end",
+    "divide_by_zero_program_start:",
+    "mov eax, 1",
+    "xor edx, edx",
+    "xor ecx, ecx",
+    "div ecx",
+    "xor edi, edi",
+    "mov eax, 0",
+    "syscall",
+    "divide_by_zero_program_end:",
+    ".global halt_program_start",
+    ".global halt_program_end",
+    "halt_program_start:",
+    "hlt",
+    "xor edi, edi",
+    "mov eax, 0",
+    "syscall",
+    "halt_program_end:",
+    ".global breakpoint_program_start",
+    ".global breakpoint_program_end",
+    "breakpoint_program_start:",
+    "int3",
+    "xor edi, edi",
+    "mov eax, 0",
+    "syscall",
+    "breakpoint_program_end:",
+    data = const DATA,
+    status = const DATA + 8,
+);
+
+extern "C" {
+    static fork_test_program_start: u8;
+    static fork_test_program_end: u8;
+    static exec_test_program_start: u8;
+    static exec_test_program_end: u8;
+    static exit_argc_program_start: u8;
+    static exit_argc_program_end: u8;
+    static orphan_test_program_start: u8;
+    static orphan_test_program_end: u8;
+    static segfault_test_program_start: u8;
+    static segfault_test_program_end: u8;
+    static invalid_opcode_program_start: u8;
+    static invalid_opcode_program_end: u8;
+    static divide_by_zero_program_start: u8;
+    static divide_by_zero_program_end: u8;
+    static halt_program_start: u8;
+    static halt_program_end: u8;
+    static breakpoint_program_start: u8;
+    static breakpoint_program_end: u8;
+}
+
+#[test_case]
+fn forked_child_gets_a_copy_on_write_copy() {
+    let status = run(unsafe {
+        code(
+            addr_of!(fork_test_program_start),
+            addr_of!(fork_test_program_end),
+        )
+    });
+    assert_eq!(status, 0);
+}
+
+#[test_case]
+fn execve_replaces_the_program() {
+    let exit_argc = build_executable(unsafe {
+        code(
+            addr_of!(exit_argc_program_start),
+            addr_of!(exit_argc_program_end),
+        )
+    });
+    let options = OpenOptions {
+        write: true,
+        create: true,
+        mode: 0o755,
+        ..OpenOptions::default()
+    };
+    let file = fs::open("/exit_argc", &options).unwrap();
+    assert_eq!(file.write(&exit_argc), Ok(exit_argc.len()));
+    let status = run(unsafe {
+        code(
+            addr_of!(exec_test_program_start),
+            addr_of!(exec_test_program_end),
+        )
+    });
+    assert_eq!(status, 42);
+}
+
+#[test_case]
+fn orphans_are_handed_to_init() {
+    // nothing called set_init, so that's the kernel process, which is us
+    let status = run(unsafe {
+        code(
+            addr_of!(orphan_test_program_start),
+            addr_of!(orphan_test_program_end),
+        )
+    });
+    assert_eq!(status, 0);
+    let (_, status) = process::wait(None, true).unwrap().unwrap();
+    assert_eq!(status, 7);
+}
+
+#[test_case]
+fn bad_memory_access_kills_the_process() {
+    let status = run(unsafe {
+        code(
+            addr_of!(segfault_test_program_start),
+            addr_of!(segfault_test_program_end),
+        )
+    });
+    assert_eq!(status, SEGFAULT_EXIT_STATUS);
+}
+
+#[test_case]
+fn exceptions_in_ring_3_kill_the_process() {
+    let programs = unsafe {
+        [
+            (
+                code(
+                    addr_of!(invalid_opcode_program_start),
+                    addr_of!(invalid_opcode_program_end),
+                ),
+                ILLEGAL_INSTRUCTION_EXIT_STATUS,
+            ),
+            (
+                code(
+                    addr_of!(divide_by_zero_program_start),
+                    addr_of!(divide_by_zero_program_end),
+                ),
+                FLOATING_POINT_EXIT_STATUS,
+            ),
+            (
+                code(addr_of!(halt_program_start), addr_of!(halt_program_end)),
+                SEGFAULT_EXIT_STATUS,
+            ),
+            (
+                code(
+                    addr_of!(breakpoint_program_start),
+                    addr_of!(breakpoint_program_end),
+                ),
+                TRAP_EXIT_STATUS,
+            ),
+        ]
+    };
+    for (program, expected) in programs {
+        assert_eq!(run(program), expected);
+    }
+}
+
+#[test_case]
+fn waiting_without_children_fails() {
+    assert_eq!(
+        process::wait(Some(ProcessId::from_u64(u64::MAX)), true).err(),
+        Some(Errno::ECHILD)
+    );
+    assert_eq!(process::wait(None, false).err(), Some(Errno::ECHILD));
+}
@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::fs::initrd::{self, InitrdError};
+use flap_os::fs::{self, FsError, InodeKind};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+const PROGRAM: &[u8] = b"\x7fELF pretending to be a program";
+
+// A ustar header for name, with the checksum filled in
+fn tar_header(name: &str, mode: u32, size: usize, kind: u8, link: &str) -> [u8; 512] {
+    let mut header = [0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(format!("{:07o}", mode).as_bytes());
+    header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    header[156] = kind;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    header
+}
+
+fn tar_archive() -> Vec<u8> {
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&tar_header("./", 0o755, 0, b'5', ""));
+    archive.extend_from_slice(&tar_header("./bin/", 0o755, 0, b'5', ""));
+    archive.extend_from_slice(&tar_header("./bin/program", 0o755, PROGRAM.len(), b'0', ""));
+    archive.extend_from_slice(PROGRAM);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+    archive.extend_from_slice(&tar_header("./bin/alias", 0o755, 0, b'1', "./bin/program"));
+    archive.extend_from_slice(&tar_header("./run", 0o777, 0, b'2', "bin/program"));
+    // no entry for etc, it has to be made anyway
+    archive.extend_from_slice(&tar_header("etc/motd", 0o644, 2, b'0', ""));
+    archive.extend_from_slice(b"hi");
+    archive.resize(archive.len().next_multiple_of(512) + 1024, 0);
+    archive
+}
+
+fn cpio_entry(archive: &mut Vec<u8>, name: &str, inode: u32, mode: u32, links: u32, data: &[u8]) {
+    let fields = [
+        inode,
+        mode,
+        0,
+        0,
+        links,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+fn cpio_archive() -> Vec<u8> {
+    let mut archive = Vec::new();
+    cpio_entry(&mut archive, ".", 1, 0o040755, 3, &[]);
+    cpio_entry(&mut archive, "bin", 2, 0o040755, 2, &[]);
+    // only the last name a file has comes with what's in it
+    cpio_entry(&mut archive, "bin/program", 3, 0o100755, 2, &[]);
+    cpio_entry(&mut archive, "bin/alias", 3, 0o100755, 2, PROGRAM);
+    cpio_entry(&mut archive, "run", 4, 0o120777, 1, b"bin/program");
+    cpio_entry(&mut archive, "etc/motd", 5, 0o100644, 1, b"hi");
+    cpio_entry(&mut archive, "TRAILER!!!", 0, 0, 1, &[]);
+    archive
+}
+
+fn check_unpacked(destination: &str) {
+    let path = |name: &str| format!("{}/{}", destination, name);
+    let program = fs::metadata(&path("bin/program")).unwrap();
+    assert_eq!(program.kind, InodeKind::File);
+    assert_eq!(program.mode, 0o755);
+    assert_eq!(program.links, 2);
+    assert_eq!(
+        fs::metadata(&path("bin/alias")).unwrap().inode,
+        program.inode
+    );
+    assert_eq!(fs::read(&path("bin/program")).unwrap(), PROGRAM);
+    assert_eq!(fs::read_link(&path("run")).unwrap(), "bin/program");
+    assert_eq!(fs::read(&path("run")).unwrap(), PROGRAM);
+    assert_eq!(fs::read(&path("etc/motd")).unwrap(), b"hi");
+    assert_eq!(fs::metadata(&path("etc/motd")).unwrap().mode, 0o644);
+}
+
+#[test_case]
+fn tar_files_unpack() {
+    assert_eq!(initrd::unpack(&tar_archive(), "/tar"), Ok(5));
+    check_unpacked("/tar");
+}
+
+#[test_case]
+fn cpio_archives_unpack() {
+    assert_eq!(initrd::unpack(&cpio_archive(), "/cpio"), Ok(5));
+    check_unpacked("/cpio");
+    // unpacking it again replaces what's there
+    assert_eq!(initrd::unpack(&cpio_archive(), "/cpio"), Ok(5));
+    check_unpacked("/cpio");
+}
+
+#[test_case]
+fn broken_archives_are_rejected() {
+    assert_eq!(initrd::unpack(&[], "/broken"), Ok(0));
+    assert_eq!(
+        initrd::unpack(b"not an archive", "/broken"),
+        Err(InitrdError::UnknownFormat)
+    );
+    let cpio = cpio_archive();
+    assert_eq!(
+        initrd::unpack(&cpio[..200], "/broken"),
+        Err(InitrdError::Truncated)
+    );
+    let mut tar = tar_archive();
+    // the program's mode
+    tar[1024 + 100] = b'7';
+    assert_eq!(initrd::unpack(&tar, "/broken"), Err(InitrdError::BadHeader));
+    // whatever came before the broken entry is still there
+    assert_eq!(
+        fs::metadata("/broken/bin").unwrap().kind,
+        InodeKind::Directory
+    );
+    assert_eq!(fs::metadata("/broken/bin/program"), Err(FsError::NotFound));
+}
+
+// Nothing in an archive gets to write outside where it's unpacked, through .. or through its own symlinks
+#[test_case]
+fn entries_stay_inside_the_destination() {
+    fs::create_dir("/outside", 0o755).unwrap();
+    assert_eq!(initrd::unpack(&victim_archive(), "/victim"), Ok(1));
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&tar_header("../escaped", 0o644, 2, b'0', ""));
+    archive.extend_from_slice(b"hi");
+    archive.resize(archive.len().next_multiple_of(512), 0);
+    archive.extend_from_slice(&tar_header("dir", 0o777, 0, b'2', "/outside"));
+    archive.extend_from_slice(&tar_header("dir/file", 0o644, 2, b'0', ""));
+    archive.extend_from_slice(b"hi");
+    archive.resize(archive.len().next_multiple_of(512), 0);
+    archive.extend_from_slice(&tar_header("file", 0o777, 0, b'2', "/victim/file"));
+    archive.extend_from_slice(&tar_header("file", 0o644, 2, b'0', ""));
+    archive.extend_from_slice(b"hi");
+    archive.resize(archive.len().next_multiple_of(512) + 1024, 0);
+
+    // the two symlinks, and the file that replaces the second one
+    assert_eq!(initrd::unpack(&archive, "/unpacked"), Ok(3));
+    assert_eq!(fs::metadata("/escaped"), Err(FsError::NotFound));
+    assert_eq!(fs::metadata("/outside/file"), Err(FsError::NotFound));
+    assert_eq!(fs::read("/victim/file").unwrap(), b"safe");
+    assert_eq!(
+        fs::symlink_metadata("/unpacked/file").unwrap().kind,
+        InodeKind::File
+    );
+    assert_eq!(fs::read("/unpacked/file").unwrap(), b"hi");
+}
+
+fn victim_archive() -> Vec<u8> {
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&tar_header("file", 0o644, 4, b'0', ""));
+    archive.extend_from_slice(b"safe");
+    archive.resize(archive.len().next_multiple_of(512) + 1024, 0);
+    archive
+}
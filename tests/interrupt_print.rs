@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::console::RING_BUFFER;
+use flap_os::interrupts::drivers::xapic;
+use flap_os::interrupts::without_interrupts;
+use flap_os::serial::SERIAL1;
+use flap_os::smp::percpu;
+use flap_os::time::uptime;
+use flap_os::vga::WRITER;
+
+// Nothing is installed on it, so it goes to the handler that reports unexpected interrupts
+const UNUSED_VECTOR: u8 = 0x50;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// With interrupts off, an interrupt handler that found the ring buffer locked would leave its output out of it
+fn ring_buffer_contains(needle: &[u8]) -> bool {
+    without_interrupts(|| {
+        let ring_buffer = RING_BUFFER.lock();
+        let (older, newer) = ring_buffer.as_slices();
+        older.windows(needle.len()).any(|window| window == needle)
+            || newer.windows(needle.len()).any(|window| window == needle)
+    })
+}
+
+// The locks are taken with interrupts off like print! does, so the timer can't switch to another thread that then
+// spins on them. int3 gets through anyway.
+
+// The breakpoint handler prints, so if exception printing waited on WRITER this would never return
+#[test_case]
+fn print_from_exception_while_writer_is_held() {
+    without_interrupts(|| {
+        let _writer = WRITER.lock();
+        unsafe { asm!("int3", options(nomem, nostack)) };
+    });
+    assert!(ring_buffer_contains(b"EXCEPTION: BREAKPOINT"));
+}
+
+#[test_case]
+fn print_from_exception_while_every_sink_is_held() {
+    without_interrupts(|| {
+        let _writer = WRITER.lock();
+        let _serial = SERIAL1.lock();
+        let _ring_buffer = RING_BUFFER.lock();
+        unsafe { asm!("int3", options(nomem, nostack)) };
+    });
+}
+
+// A real interrupt this time, which has to arrive while WRITER is held, so interrupts stay on. Keeping preemption off
+// instead makes sure no other thread gets this CPU in the meantime.
+#[test_case]
+fn print_from_interrupt_while_writer_is_held() {
+    let arrived = {
+        let _preempt = percpu::preempt_disable();
+        let _writer = WRITER.lock();
+        xapic::send_fixed(percpu::apic_id(), UNUSED_VECTOR);
+        let deadline = uptime() + Duration::from_millis(100);
+        let mut arrived = false;
+        while !arrived && uptime() < deadline {
+            arrived = ring_buffer_contains(b"INTERRUPT: UNEXPECTED VECTOR 0x50");
+        }
+        arrived
+    };
+    assert!(arrived, "the interrupt's output never showed up");
+}
+
+#[test_case]
+fn print_still_works_after_exception() {
+    unsafe { asm!("int3", options(nomem, nostack)) };
+    flap_os::println!("printing after a breakpoint");
+    assert!(ring_buffer_contains(b"printing after a breakpoint"));
+}
@@ -0,0 +1,255 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::ptr::addr_of;
+
+use bootloader::{entry_point, BootInfo};
+
+use common::{code, run};
+
+use flap_os::memory::address::VirtualAddress;
+use flap_os::memory::paging::page_table::PageTableFlags;
+use flap_os::memory::USER_SPACE_START;
+use flap_os::process::{Process, VmaKind, SEGFAULT_EXIT_STATUS};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// System call numbers: exit 0, mmap 7, munmap 8, mprotect 9, brk 10.
+// Each program exits with 0 if everything checked out, otherwise with the number of the check that failed.
+global_asm!(
+    // mmaps two pages, checks they come out zeroed, unmaps one of them and moves the heap up and back down
+    ".global memory_test_program_start",
+    ".global memory_test_program_end",
+    "memory_test_program_start:",
+    "xor edi, edi",
+    "mov esi, 0x2000",
+    "mov edx, 3",
+    "mov r10d, 0x22",
+    "mov r8, -1",
+    "xor r9d, r9d",
+    "mov eax, 7",
+    "syscall",
+    "mov r12, 1",
+    "test rax, rax",
+    "js 2f",
+    "mov rbx, rax",
+    "mov r12, 2",
+    "cmp qword ptr [rbx + 0x1000], 0",
+    "jne 2f",
+    "mov qword ptr [rbx + 0x1000], 5",
+    "mov qword ptr [rbx], 5",
+    "lea rdi, [rbx + 0x1000]",
+    "mov esi, 0x1000",
+    "mov eax, 8",
+    "syscall",
+    "mov r12, 3",
+    "test rax, rax",
+    "jnz 2f",
+    // there's nothing left there to change the protection of
+    "lea rdi, [rbx + 0x1000]",
+    "mov esi, 0x1000",
+    "mov edx, 1",
+    "mov eax, 9",
+    "syscall",
+    "mov r12, 4",
+    "cmp rax, -12",
+    "jne 2f",
+    "mov r12, 5",
+    "cmp qword ptr [rbx], 5",
+    "jne 2f",
+    "xor edi, edi",
+    "mov eax, 10",
+    "syscall",
+    "mov r13, rax",
+    "lea rdi, [rax + 0x2000]",
+    "mov eax, 10",
+    "syscall",
+    "mov r12, 6",
+    "lea rcx, [r13 + 0x2000]",
+    "cmp rax, rcx",
+    "jne 2f",
+    "mov qword ptr [r13 + 0x1FF8], 7",
+    "mov rdi, r13",
+    "mov eax, 10",
+    "syscall",
+    "mov r12, 7",
+    "cmp rax, r13",
+    "jne 2f",
+    "xor r12, r12",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "memory_test_program_end:",
+    // mmaps a page, makes it read only and writes to it anyway
+    ".global read_only_test_program_start",
+    ".global read_only_test_program_end",
+    "read_only_test_program_start:",
+    "xor edi, edi",
+    "mov esi, 0x1000",
+    "mov edx, 3",
+    "mov r10d, 0x22",
+    "mov r8, -1",
+    "xor r9d, r9d",
+    "mov eax, 7",
+    "syscall",
+    "mov r12, 1",
+    "test rax, rax",
+    "js 2f",
+    "mov rbx, rax",
+    "mov qword ptr [rbx], 1",
+    "mov rdi, rbx",
+    "mov esi, 0x1000",
+    "mov edx, 1",
+    "mov eax, 9",
+    "syscall",
+    "mov r12, 2",
+    "test rax, rax",
+    "jnz 2f",
+    "mov qword ptr [rbx], 2",
+    "mov r12, 3",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "read_only_test_program_end:",
+);
+
+extern "C" {
+    static memory_test_program_start: u8;
+    static memory_test_program_end: u8;
+    static read_only_test_program_start: u8;
+    static read_only_test_program_end: u8;
+}
+
+fn flags() -> PageTableFlags {
+    PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+}
+
+#[test_case]
+fn pages_are_mapped_when_first_touched() {
+    let process = Process::new("test").unwrap();
+    let address_space = process.address_space();
+    let start = VirtualAddress::new(USER_SPACE_START);
+    address_space
+        .add_area(start, 0x3000, flags(), VmaKind::Anonymous)
+        .unwrap();
+    let mapper = address_space.mapper();
+    assert!(mapper.translate(start).is_none());
+    address_space
+        .write(VirtualAddress::new(USER_SPACE_START + 0x1000), b"hello")
+        .unwrap();
+    assert!(mapper.translate(start).is_none());
+    assert!(mapper
+        .translate(VirtualAddress::new(USER_SPACE_START + 0x1000))
+        .is_some());
+    // nothing outside of an area gets mapped
+    assert!(!address_space.populate(VirtualAddress::new(USER_SPACE_START + 0x3000)));
+}
+
+#[test_case]
+fn unmapping_part_of_an_area_splits_it() {
+    let process = Process::new("test").unwrap();
+    let address_space = process.address_space();
+    let start = VirtualAddress::new(USER_SPACE_START);
+    address_space
+        .add_area(start, 0x3000, flags(), VmaKind::Anonymous)
+        .unwrap();
+    address_space
+        .write(VirtualAddress::new(USER_SPACE_START + 0x1000), b"hello")
+        .unwrap();
+    address_space.remove_areas(
+        VirtualAddress::new(USER_SPACE_START + 0x1000),
+        VirtualAddress::new(USER_SPACE_START + 0x2000),
+    );
+    let areas = address_space.areas();
+    assert_eq!(areas.len(), 2);
+    assert_eq!(areas[0].end, VirtualAddress::new(USER_SPACE_START + 0x1000));
+    assert_eq!(
+        areas[1].start,
+        VirtualAddress::new(USER_SPACE_START + 0x2000)
+    );
+    assert!(address_space
+        .mapper()
+        .translate(VirtualAddress::new(USER_SPACE_START + 0x1000))
+        .is_none());
+    // the hole can be protected no more than it can be touched
+    assert!(address_space
+        .protect(
+            start,
+            VirtualAddress::new(USER_SPACE_START + 0x3000),
+            PageTableFlags::USER_ACCESSIBLE
+        )
+        .is_err());
+    assert_eq!(address_space.areas(), areas);
+}
+
+#[test_case]
+fn brk_moves_the_end_of_the_heap() {
+    let process = Process::new("test").unwrap();
+    let address_space = process.address_space();
+    let heap_start = VirtualAddress::new(USER_SPACE_START + 0x10_0000);
+    address_space.set_heap_start(heap_start);
+    let brk = VirtualAddress::new(heap_start.0 + 0x1800);
+    assert_eq!(address_space.set_brk(brk), brk);
+    let heap = address_space
+        .find_area(VirtualAddress::new(heap_start.0 + 0x1000))
+        .unwrap();
+    assert_eq!(heap.kind, VmaKind::Heap);
+    assert_eq!(heap.end, VirtualAddress::new(heap_start.0 + 0x2000));
+    // below the start of the heap is out of bounds
+    assert_eq!(
+        address_space.set_brk(VirtualAddress::new(heap_start.0 - 1)),
+        brk
+    );
+    let brk = VirtualAddress::new(heap_start.0 + 0x10);
+    assert_eq!(address_space.set_brk(brk), brk);
+    assert!(address_space
+        .find_area(VirtualAddress::new(heap_start.0 + 0x1000))
+        .is_none());
+    assert!(address_space.find_area(heap_start).is_some());
+}
+
+#[test_case]
+fn memory_system_calls_work_from_ring_3() {
+    let status = run(unsafe {
+        code(
+            addr_of!(memory_test_program_start),
+            addr_of!(memory_test_program_end),
+        )
+    });
+    assert_eq!(status, 0);
+}
+
+#[test_case]
+fn writing_to_a_read_only_mapping_kills_the_process() {
+    let status = run(unsafe {
+        code(
+            addr_of!(read_only_test_program_start),
+            addr_of!(read_only_test_program_end),
+        )
+    });
+    assert_eq!(status, SEGFAULT_EXIT_STATUS);
+}
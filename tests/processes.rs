@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use spin::Mutex;
+
+use flap_os::memory::address::{PhysicalAddress, VirtualAddress};
+use flap_os::memory::paging::mapper::Mapper;
+use flap_os::memory::paging::page_table::{read_cr3, PageTableFlags};
+use flap_os::memory::USER_SPACE_START;
+use flap_os::process::{self, Process};
+use flap_os::task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn new_process_gets_its_own_level_4_table() {
+    let process = Process::new("test").unwrap();
+    let kernel = process::kernel_process();
+    assert_ne!(
+        process.address_space().level_4_table_address(),
+        kernel.address_space().level_4_table_address()
+    );
+    // the kernel heap has to look the same from in there
+    let heap_address = VirtualAddress::new(Arc::as_ptr(&process) as u64);
+    let kernel_mapper = kernel.address_space().mapper();
+    assert_eq!(
+        process.address_space().mapper().translate(heap_address),
+        kernel_mapper.translate(heap_address)
+    );
+    assert!(process.threads().is_empty());
+}
+
+#[test_case]
+fn mapped_region_is_only_visible_in_its_process() {
+    let a = Process::new("a").unwrap();
+    let b = Process::new("b").unwrap();
+    let start = VirtualAddress::new(USER_SPACE_START);
+    let region = a
+        .map_region(start, 0x2000, PageTableFlags::WRITABLE)
+        .unwrap();
+    assert!(region.contains(VirtualAddress::new(USER_SPACE_START + 0x1FFF)));
+    assert!(a.address_space().mapper().translate(start).is_some());
+    assert!(b.address_space().mapper().translate(start).is_none());
+    assert!(unsafe { Mapper::active() }.translate(start).is_none());
+    // overlapping regions are refused, the same range in another process is fine
+    assert!(a
+        .map_region(
+            VirtualAddress::new(USER_SPACE_START + 0x1000),
+            0x1000,
+            PageTableFlags::WRITABLE
+        )
+        .is_err());
+    assert!(b
+        .map_region(start, 0x1000, PageTableFlags::WRITABLE)
+        .is_ok());
+}
+
+#[test_case]
+fn threads_run_in_their_process_address_space() {
+    let process = Process::new("test").unwrap();
+    let seen = Arc::new(Mutex::new(None::<PhysicalAddress>));
+    let thread_seen = seen.clone();
+    let thread_process = process.clone();
+    let handle = task::spawn_in(&process, "test", move || {
+        *thread_seen.lock() = Some(read_cr3());
+        assert!(Arc::ptr_eq(&process::current(), &thread_process));
+    })
+    .unwrap();
+    assert_eq!(process.threads(), [handle.id()]);
+    handle.join();
+    assert_eq!(
+        *seen.lock(),
+        Some(process.address_space().level_4_table_address())
+    );
+    assert!(process.threads().is_empty());
+    assert!(process::kernel_process().address_space().is_active());
+}
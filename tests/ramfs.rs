@@ -0,0 +1,249 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::fs::{self, File, FsError, InodeKind, OpenOptions, SeekFrom};
+use flap_os::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+fn create(path: &str) -> Arc<dyn File> {
+    let options = OpenOptions {
+        read: true,
+        write: true,
+        create: true,
+        mode: 0o644,
+        ..OpenOptions::default()
+    };
+    fs::open(path, &options).unwrap()
+}
+
+fn read_all(file: &Arc<dyn File>) -> Vec<u8> {
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = vec![0; file.metadata().unwrap().size as usize + 1];
+    let mut read = 0;
+    loop {
+        match file.read(&mut contents[read..]).unwrap() {
+            0 => break,
+            bytes => read += bytes,
+        }
+    }
+    contents.truncate(read);
+    contents
+}
+
+#[test_case]
+fn the_root_is_a_ramfs() {
+    assert_eq!(fs::mounts()[0].0, "/");
+    assert_eq!(fs::mounts()[0].1, "ramfs");
+    assert_eq!(fs::metadata("/").unwrap().kind, InodeKind::Directory);
+    assert_eq!(fs::unmount("/"), Err(FsError::Busy));
+}
+
+#[test_case]
+fn files_span_pages_and_holes_read_as_zeros() {
+    let file = create("/sparse");
+    assert_eq!(file.write(b"start"), Ok(5));
+    file.seek(SeekFrom::Start(3 * 4096 - 2)).unwrap();
+    assert_eq!(file.write(b"across"), Ok(6));
+    assert_eq!(file.metadata().unwrap().size, 3 * 4096 + 4);
+
+    let contents = read_all(&file);
+    assert_eq!(&contents[..5], b"start");
+    assert!(contents[5..3 * 4096 - 2].iter().all(|&byte| byte == 0));
+    assert_eq!(&contents[3 * 4096 - 2..], b"across");
+
+    // a second open has its own offset but sees the same data
+    let options = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    let again = fs::open("/sparse", &options).unwrap();
+    let mut buffer = [0; 5];
+    assert_eq!(again.read(&mut buffer), Ok(5));
+    assert_eq!(&buffer, b"start");
+    fs::remove_file("/sparse").unwrap();
+    assert_eq!(fs::metadata("/sparse"), Err(FsError::NotFound));
+    // still there for whoever has it open
+    assert_eq!(read_all(&again).len(), 3 * 4096 + 4);
+}
+
+// Its size is all anyone reading it whole has to go on
+#[test_case]
+fn files_too_big_for_the_heap_cant_be_read_whole() {
+    let file = create("/huge");
+    file.seek(SeekFrom::Start(1 << 40)).unwrap();
+    assert_eq!(file.write(b"x"), Ok(1));
+    assert_eq!(fs::read("/huge"), Err(FsError::OutOfMemory));
+    fs::remove_file("/huge").unwrap();
+}
+
+#[test_case]
+fn truncation_shrinks_and_grows() {
+    let file = create("/truncated");
+    file.write(&[0xaa; 5000]).unwrap();
+    let truncate = OpenOptions {
+        write: true,
+        truncate: true,
+        ..OpenOptions::default()
+    };
+    fs::open("/truncated", &truncate).unwrap();
+    assert_eq!(file.metadata().unwrap().size, 0);
+    assert_eq!(read_all(&file), b"");
+
+    file.write(&[0xbb; 5000]).unwrap();
+    fs::path::resolve("/truncated", true)
+        .unwrap()
+        .inode()
+        .truncate(10)
+        .unwrap();
+    fs::path::resolve("/truncated", true)
+        .unwrap()
+        .inode()
+        .truncate(4097)
+        .unwrap();
+    // what was cut off doesn't come back
+    let contents = read_all(&file);
+    assert_eq!(contents.len(), 4097);
+    assert!(contents[..10].iter().all(|&byte| byte == 0xbb));
+    assert!(contents[10..].iter().all(|&byte| byte == 0));
+    fs::remove_file("/truncated").unwrap();
+}
+
+#[test_case]
+fn directories_count_their_links() {
+    fs::create_dir("/dir", 0o755).unwrap();
+    let root_links = fs::metadata("/").unwrap().links;
+    assert_eq!(fs::metadata("/dir").unwrap().links, 2);
+    fs::create_dir("/dir/sub", 0o700).unwrap();
+    assert_eq!(fs::metadata("/dir").unwrap().links, 3);
+    assert_eq!(fs::metadata("/dir/sub").unwrap().mode, 0o700);
+    assert_eq!(
+        fs::create_dir("/dir/sub", 0o755),
+        Err(FsError::AlreadyExists)
+    );
+    create("/dir/sub/file");
+
+    let names: Vec<_> = fs::read_dir("/dir/sub")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(names, ["file"]);
+    assert_eq!(fs::remove_dir("/dir/sub"), Err(FsError::NotEmpty));
+    assert_eq!(fs::remove_dir("/dir/sub/file"), Err(FsError::NotADirectory));
+    assert_eq!(fs::remove_file("/dir/sub"), Err(FsError::IsADirectory));
+    fs::remove_file("/dir/sub/file").unwrap();
+    fs::remove_dir("/dir/sub").unwrap();
+    assert_eq!(fs::metadata("/dir").unwrap().links, 2);
+    fs::remove_dir("/dir").unwrap();
+    assert_eq!(fs::metadata("/").unwrap().links, root_links - 1);
+}
+
+#[test_case]
+fn hard_links_share_an_inode() {
+    let file = create("/original");
+    file.write(b"shared").unwrap();
+    fs::hard_link("/original", "/another").unwrap();
+    let original = fs::metadata("/original").unwrap();
+    assert_eq!(original.inode, fs::metadata("/another").unwrap().inode);
+    assert_eq!(original.links, 2);
+
+    fs::remove_file("/original").unwrap();
+    assert_eq!(fs::metadata("/another").unwrap().links, 1);
+    let options = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    assert_eq!(
+        read_all(&fs::open("/another", &options).unwrap()),
+        b"shared"
+    );
+    assert_eq!(
+        fs::hard_link("/", "/root_link"),
+        Err(FsError::PermissionDenied)
+    );
+    fs::remove_file("/another").unwrap();
+}
+
+#[test_case]
+fn symlinks_point_at_paths() {
+    fs::create_dir("/target", 0o755).unwrap();
+    create("/target/file").write(b"linked").unwrap();
+    fs::symlink("target", "/relative").unwrap();
+    fs::symlink("/target/file", "/absolute").unwrap();
+    fs::symlink("/nowhere", "/dangling").unwrap();
+
+    assert_eq!(fs::read_link("/relative").unwrap(), "target");
+    assert_eq!(
+        fs::metadata("/relative/file").unwrap().inode,
+        fs::metadata("/target/file").unwrap().inode
+    );
+    assert_eq!(fs::metadata("/absolute").unwrap().size, 6);
+    let symlink = fs::symlink_metadata("/absolute").unwrap();
+    assert_eq!(symlink.kind, InodeKind::Symlink);
+    assert_eq!(symlink.size, "/target/file".len() as u64);
+    assert_eq!(fs::metadata("/dangling"), Err(FsError::NotFound));
+    assert_eq!(fs::symlink("x", "/relative"), Err(FsError::AlreadyExists));
+
+    for path in ["/relative", "/absolute", "/dangling", "/target/file"] {
+        fs::remove_file(path).unwrap();
+    }
+    fs::remove_dir("/target").unwrap();
+}
+
+#[test_case]
+fn timestamps_follow_changes() {
+    let file = create("/timed");
+    let created = file.metadata().unwrap();
+    assert_eq!(created.accessed, created.modified);
+    let directory = fs::metadata("/").unwrap();
+    assert!(directory.modified >= created.changed);
+
+    time::delay(Duration::from_millis(1));
+    file.write(b"later").unwrap();
+    let written = file.metadata().unwrap();
+    assert!(written.modified > created.modified);
+    assert_eq!(written.modified, written.changed);
+    assert_eq!(written.accessed, created.accessed);
+
+    time::delay(Duration::from_millis(1));
+    read_all(&file);
+    let read = file.metadata().unwrap();
+    assert!(read.accessed > written.modified);
+    assert_eq!(read.modified, written.modified);
+
+    // linking only changes the inode, not what's in it
+    time::delay(Duration::from_millis(1));
+    fs::hard_link("/timed", "/timed_too").unwrap();
+    let linked = file.metadata().unwrap();
+    assert!(linked.changed > read.accessed);
+    assert_eq!(linked.modified, written.modified);
+    assert!(fs::metadata("/").unwrap().modified >= linked.changed);
+
+    fs::remove_file("/timed").unwrap();
+    fs::remove_file("/timed_too").unwrap();
+}
@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::acpi::madt;
+use flap_os::interrupts::drivers::xapic;
+use flap_os::percpu;
+use flap_os::smp::{self, percpu, MAX_CPUS};
+use flap_os::structs::tss;
+use flap_os::sync::Mutex;
+use flap_os::task;
+use flap_os::time::uptime;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn every_cpu_in_the_madt_is_online() {
+    let local_apics = madt::local_apics().expect("QEMU always has a MADT");
+    assert!(local_apics
+        .iter()
+        .any(|local_apic| local_apic.apic_id == xapic::id()));
+    assert_eq!(smp::cpu_count(), local_apics.len().min(MAX_CPUS));
+    // kernel_init runs on the boot processor
+    assert_eq!(smp::current_cpu(), 0);
+    assert_eq!(percpu::apic_id(), xapic::id());
+}
+
+// Every thread spins until all of them have started, recording where it ran along with the TSS it found there
+#[test_case]
+fn threads_run_on_every_cpu() {
+    let threads = smp::cpu_count() * 2;
+    let started = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::new(spin::Mutex::new(Vec::new()));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let started = started.clone();
+            let seen = seen.clone();
+            task::spawn(move || {
+                started.fetch_add(1, Ordering::SeqCst);
+                while started.load(Ordering::SeqCst) < threads {
+                    core::hint::spin_loop();
+                }
+                let tss = tss::current() as *const _ as usize;
+                seen.lock().push((smp::current_cpu(), tss));
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    let seen = seen.lock();
+    assert_eq!(seen.len(), threads);
+    let mut cpus: Vec<usize> = seen.iter().map(|&(cpu, _)| cpu).collect();
+    cpus.sort();
+    cpus.dedup();
+    if smp::cpu_count() > 1 {
+        assert!(cpus.len() > 1, "everything ran on CPU {}", cpus[0]);
+    }
+    for &(cpu, tss) in seen.iter() {
+        for &(other_cpu, other_tss) in seen.iter() {
+            assert_eq!(cpu == other_cpu, tss == other_tss);
+        }
+    }
+}
+
+// Threads on different CPUs really do run at the same time, so this only adds up if the mutex and the wait queue
+// behind it hold up
+#[test_case]
+fn mutex_holds_up_across_cpus() {
+    let counter = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..smp::cpu_count() * 2)
+        .map(|_| {
+            let counter = counter.clone();
+            task::spawn(move || {
+                for _ in 0..200 {
+                    let mut guard = counter.lock();
+                    let value = *guard;
+                    core::hint::spin_loop();
+                    *guard = value + 1;
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    let threads = handles.len();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*counter.lock(), threads * 200);
+}
+
+// Short threads that exit on another CPU while we're on our way into join. A joiner woken before the thread it's
+// waiting for counts as exited would block again for good.
+#[test_case]
+fn join_returns_for_threads_exiting_on_other_cpus() {
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..500 {
+        let count = count.clone();
+        task::spawn(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap()
+        .join();
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 500);
+}
+
+percpu! {
+    static SPINS: AtomicUsize = AtomicUsize::new(0);
+}
+
+// With preemption off a thread stays on its CPU across timer ticks, even with more threads than CPUs waiting to run.
+// Every spin is counted on the CPU it happened on, and nothing ends up counted on a CPU that isn't online.
+#[test_case]
+fn preempt_disable_keeps_a_thread_on_its_cpu() {
+    let total = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = (0..smp::cpu_count() * 2)
+        .map(|_| {
+            let total = total.clone();
+            task::spawn(move || {
+                let _preempt = percpu::preempt_disable();
+                let cpu = smp::current_cpu();
+                let deadline = uptime() + task::time_slice() * 3;
+                while uptime() < deadline {
+                    assert_eq!(smp::current_cpu(), cpu);
+                    SPINS.get().fetch_add(1, Ordering::Relaxed);
+                    total.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    let spins: usize = SPINS
+        .iter()
+        .map(|spins| spins.load(Ordering::Relaxed))
+        .sum();
+    assert_eq!(spins, total.load(Ordering::Relaxed));
+    for cpu in smp::cpu_count()..MAX_CPUS {
+        assert_eq!(SPINS.of(cpu).load(Ordering::Relaxed), 0);
+    }
+}
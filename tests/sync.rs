@@ -0,0 +1,159 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::interrupts::{self, without_interrupts};
+use flap_os::sync::{Condvar, Lazy, Mutex, Once, RwLock, Semaphore, SpinLock, TicketLock};
+use flap_os::task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn spin_lock_turns_interrupts_off_while_held() {
+    let lock = SpinLock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+        // failing to take it doesn't turn them back on early
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    // and it leaves them off if they already were
+    without_interrupts(|| {
+        drop(lock.lock());
+        assert!(!interrupts::are_enabled());
+    });
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn ticket_lock_counts_who_is_waiting() {
+    let lock = TicketLock::new(());
+    let guard = lock.lock();
+    assert!(lock.is_locked());
+    assert!(lock.try_lock().is_none());
+    assert_eq!(lock.waiting(), 0);
+    drop(guard);
+    assert!(!lock.is_locked());
+    assert!(lock.try_lock().is_some());
+}
+
+#[test_case]
+fn rw_lock_has_many_readers_or_one_writer() {
+    let lock = RwLock::new(1);
+    let first = lock.read();
+    let second = lock.read();
+    assert_eq!(*first + *second, 2);
+    assert_eq!(lock.readers(), 2);
+    assert!(lock.try_write().is_none());
+    drop((first, second));
+    let mut writer = lock.write();
+    *writer = 2;
+    assert!(lock.try_read().is_none());
+    drop(writer);
+    assert_eq!(*lock.read(), 2);
+}
+
+// Each thread yields in the middle of its critical section, so without the mutex they'd trample each other
+#[test_case]
+fn mutex_sleeps_until_it_is_free() {
+    let counter = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let counter = counter.clone();
+            task::spawn(move || {
+                for _ in 0..5 {
+                    let mut guard = counter.lock();
+                    let value = *guard;
+                    task::yield_now();
+                    *guard = value + 1;
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*counter.lock(), 15);
+    assert!(!counter.is_locked());
+}
+
+#[test_case]
+fn semaphore_blocks_until_released() {
+    let semaphore = Arc::new(Semaphore::new(0));
+    let order = Arc::new(spin::Mutex::new(Vec::new()));
+    let thread_semaphore = semaphore.clone();
+    let thread_order = order.clone();
+    let handle = task::spawn(move || {
+        thread_order.lock().push("released");
+        thread_semaphore.release();
+    })
+    .unwrap();
+    semaphore.acquire();
+    order.lock().push("acquired");
+    handle.join();
+    assert_eq!(*order.lock(), ["released", "acquired"]);
+    assert!(!semaphore.try_acquire());
+    semaphore.release();
+    assert_eq!(semaphore.available(), 1);
+}
+
+#[test_case]
+fn condvar_wakes_a_waiter() {
+    let state = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread_state = state.clone();
+    let handle = task::spawn(move || {
+        let (ready, condvar) = &*thread_state;
+        *ready.lock() = true;
+        condvar.notify_all();
+    })
+    .unwrap();
+    let (ready, condvar) = &*state;
+    let guard = condvar.wait_while(ready.lock(), |ready| !*ready);
+    assert!(*guard);
+    drop(guard);
+    handle.join();
+}
+
+static INITIALIZED: AtomicUsize = AtomicUsize::new(0);
+static ANSWER: Lazy<usize> = Lazy::new(|| INITIALIZED.fetch_add(1, Ordering::SeqCst) + 42);
+
+#[test_case]
+fn lazy_is_initialized_once() {
+    assert_eq!(INITIALIZED.load(Ordering::SeqCst), 0);
+    assert_eq!(*ANSWER, 42);
+    assert_eq!(*ANSWER, 42);
+    assert_eq!(INITIALIZED.load(Ordering::SeqCst), 1);
+
+    let once = Once::new();
+    assert!(once.get().is_none());
+    assert_eq!(*once.call_once(|| 1), 1);
+    assert_eq!(*once.call_once(|| 2), 1);
+    assert_eq!(once.get(), Some(&1));
+}
@@ -0,0 +1,273 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use bootloader::{entry_point, BootInfo};
+use spin::Mutex;
+
+use flap_os::interrupts::without_interrupts;
+use flap_os::task::{self, SchedulingClass, ThreadState, WaitQueue};
+use flap_os::time;
+
+entry_point!(main);
+
+// On one CPU, otherwise the other CPUs pick threads up whenever they like and the orders and shares below are
+// anyone's guess
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init_boot_cpu(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn spawned_thread_runs_before_join_returns() {
+    let ran = Arc::new(AtomicBool::new(false));
+    let thread_ran = ran.clone();
+    let handle = task::spawn(move || thread_ran.store(true, Ordering::SeqCst)).unwrap();
+    handle.join();
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+// Spawned with interrupts off so they stay off in the threads too, and the timer can't reorder them
+#[test_case]
+fn yield_now_round_robins() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = without_interrupts(|| {
+        (0..2)
+            .map(|id| {
+                let order = order.clone();
+                task::spawn(move || {
+                    for _ in 0..3 {
+                        order.lock().push(id);
+                        task::yield_now();
+                    }
+                })
+                .unwrap()
+            })
+            .collect()
+    });
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*order.lock(), [0, 1, 0, 1, 0, 1]);
+}
+
+#[test_case]
+fn exit_stops_the_thread() {
+    let after_exit = Arc::new(AtomicBool::new(false));
+    let thread_after_exit = after_exit.clone();
+    task::spawn(move || {
+        if !thread_after_exit.load(Ordering::SeqCst) {
+            task::exit();
+        }
+        thread_after_exit.store(true, Ordering::SeqCst);
+    })
+    .unwrap()
+    .join();
+    assert!(!after_exit.load(Ordering::SeqCst));
+}
+
+// Each thread parks its own value in xmm0 and checks it's still there after the others have had a go
+#[test_case]
+fn fpu_state_survives_a_switch() {
+    let mismatches = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = (1..=3_u64)
+        .map(|value| {
+            let mismatches = mismatches.clone();
+            task::spawn(move || {
+                unsafe { asm!("movq xmm0, {}", in(reg) value) };
+                for _ in 0..3 {
+                    task::yield_now();
+                    let found: u64;
+                    unsafe { asm!("movq {}, xmm0", out(reg) found) };
+                    if found != value {
+                        mismatches.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(mismatches.load(Ordering::SeqCst), 0);
+}
+
+// More threads than there'd be room for if stacks and thread control blocks were never given back
+#[test_case]
+fn finished_threads_are_cleaned_up() {
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..1000 {
+        let count = count.clone();
+        task::spawn(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap()
+        .join();
+    }
+    // dropping the handle instead of joining leaves the cleanup to the scheduler
+    for _ in 0..1000 {
+        let count = count.clone();
+        drop(
+            task::spawn(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap(),
+        );
+        task::yield_now();
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 2000);
+}
+
+// Nobody here ever yields, so the only way the other threads get to count is the timer taking the CPU away
+#[test_case]
+fn busy_threads_get_preempted() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let counters: Vec<_> = (0..2).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+    let handles: Vec<_> = counters
+        .iter()
+        .map(|counter| {
+            let counter = counter.clone();
+            let stop = stop.clone();
+            task::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    while counters
+        .iter()
+        .any(|counter| counter.load(Ordering::SeqCst) == 0)
+    {
+        core::hint::spin_loop();
+    }
+    stop.store(true, Ordering::SeqCst);
+    for handle in handles {
+        handle.join();
+    }
+}
+
+#[test_case]
+fn wait_queue_blocks_until_woken() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    let go = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicBool::new(false));
+    let (thread_go, thread_done) = (go.clone(), done.clone());
+    let handle = task::spawn(move || {
+        QUEUE.wait_while(|| !thread_go.load(Ordering::SeqCst));
+        thread_done.store(true, Ordering::SeqCst);
+    })
+    .unwrap();
+    // lets the thread run until it blocks, after which it's off the run queue
+    task::yield_now();
+    task::yield_now();
+    assert!(!done.load(Ordering::SeqCst));
+    go.store(true, Ordering::SeqCst);
+    assert_eq!(QUEUE.wake_all(), 1);
+    handle.join();
+    assert!(done.load(Ordering::SeqCst));
+}
+
+// A thread woken while it's running that then yields has nothing left of the wakeup by the time it blocks, otherwise
+// the wait would go round once more without anything having changed
+#[test_case]
+fn wakeup_while_running_is_gone_after_a_yield() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    let go = Arc::new(AtomicBool::new(false));
+    let checks = Arc::new(AtomicUsize::new(0));
+    let (thread_go, thread_checks) = (go.clone(), checks.clone());
+    let handle = task::spawn(move || {
+        assert!(task::wake(task::current_id()));
+        task::yield_now();
+        QUEUE.wait_while(|| {
+            thread_checks.fetch_add(1, Ordering::SeqCst);
+            !thread_go.load(Ordering::SeqCst)
+        });
+    })
+    .unwrap();
+    while task::thread_info(handle.id()).unwrap().state != ThreadState::Blocked {
+        task::yield_now();
+    }
+    assert_eq!(checks.load(Ordering::SeqCst), 1);
+    go.store(true, Ordering::SeqCst);
+    assert_eq!(QUEUE.wake_all(), 1);
+    handle.join();
+    assert_eq!(checks.load(Ordering::SeqCst), 2);
+}
+
+// The real-time thread runs first even though it was spawned last
+#[test_case]
+fn real_time_threads_run_before_fair_ones() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let classes = [
+        SchedulingClass::Fair { nice: -20 },
+        SchedulingClass::RealTime { priority: 10 },
+    ];
+    let handles: Vec<_> = without_interrupts(|| {
+        classes
+            .into_iter()
+            .enumerate()
+            .map(|(id, class)| {
+                let order = order.clone();
+                task::spawn_with_class("thread", class, move || order.lock().push(id)).unwrap()
+            })
+            .collect()
+    });
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*order.lock(), [1, 0]);
+}
+
+// Two threads that never block, nice 0 against nice 10. Going by the weights the first should get about 9 times the
+// CPU time, each of them checks how much it got right before it stops.
+#[test_case]
+fn fair_threads_share_the_cpu_by_weight() {
+    let cpu_times = Arc::new(Mutex::new([Duration::ZERO; 2]));
+    let deadline = time::uptime() + Duration::from_millis(300);
+    let handles: Vec<_> = [0, 10]
+        .into_iter()
+        .enumerate()
+        .map(|(index, nice)| {
+            let cpu_times = cpu_times.clone();
+            task::spawn_with_class("busy", SchedulingClass::Fair { nice }, move || {
+                while time::uptime() < deadline {
+                    core::hint::spin_loop();
+                }
+                let info = task::thread_info(task::current_id()).unwrap();
+                cpu_times.lock()[index] = info.cpu_time;
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    let [heavy, light] = *cpu_times.lock();
+    assert!(light > Duration::ZERO);
+    assert!(
+        heavy > light * 3,
+        "nice 0 got {:?}, nice 10 got {:?}",
+        heavy,
+        light
+    );
+}
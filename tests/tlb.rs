@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::memory::address::VirtualAddress;
+use flap_os::memory::frame_allocator::{allocate_frame, deallocate_frame};
+use flap_os::memory::paging::consts::PageSize;
+use flap_os::memory::paging::frame::PhysicalFrame;
+use flap_os::memory::paging::page::Page;
+use flap_os::memory::paging::page_table::PageTableFlags;
+use flap_os::memory::{physical_to_virtual, USER_SPACE_START};
+use flap_os::process;
+use flap_os::smp;
+use flap_os::task;
+
+const PAGE: u64 = USER_SPACE_START + 0x100_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+fn frame_holding(value: u64) -> PhysicalFrame {
+    let frame = allocate_frame().expect("out of frames");
+    unsafe { *physical_to_virtual(frame.start_address()).as_mut_ptr::<u64>() = value };
+    frame
+}
+
+// Readers spread over every CPU read PAGE, so it's cached wherever they ran, then stay off it while it's pointed at a
+// new frame. They're all in the kernel's address space, so nothing reloads cr3 in between, and a translation that
+// didn't get shot down would still read the old frame instead of the new one.
+#[test_case]
+fn remapped_page_is_seen_on_every_cpu() {
+    const ROUNDS: u64 = 20;
+    let page = Page::from_address_aligned(VirtualAddress::new(PAGE), PageSize::NORMAL);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let address_space = process::kernel_process().address_space();
+    address_space
+        .mapper()
+        .map_to(page, frame_holding(0), flags)
+        .unwrap();
+    let readers = smp::cpu_count() * 2;
+    // round * 2 + 1 while the readers are warming up, round * 2 + 2 once the new frame is in
+    let phase = Arc::new(AtomicU64::new(0));
+    let acknowledged = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = (0..readers)
+        .map(|_| {
+            let phase = phase.clone();
+            let acknowledged = acknowledged.clone();
+            task::spawn(move || {
+                for round in 0..ROUNDS {
+                    while phase.load(Ordering::SeqCst) < round * 2 + 1 {
+                        core::hint::spin_loop();
+                    }
+                    let value = unsafe { core::ptr::read_volatile(PAGE as *const u64) };
+                    assert_eq!(value, round, "round {} started with a stale value", round);
+                    acknowledged.fetch_add(1, Ordering::SeqCst);
+                    while phase.load(Ordering::SeqCst) < round * 2 + 2 {
+                        core::hint::spin_loop();
+                    }
+                    let value = unsafe { core::ptr::read_volatile(PAGE as *const u64) };
+                    assert_eq!(
+                        value,
+                        round + 1,
+                        "CPU {} kept a stale translation",
+                        smp::current_cpu()
+                    );
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for round in 0..ROUNDS {
+        phase.store(round * 2 + 1, Ordering::SeqCst);
+        while acknowledged.load(Ordering::SeqCst) < readers * (round as usize + 1) {
+            core::hint::spin_loop();
+        }
+        let mut mapper = address_space.mapper();
+        let old_frame = mapper.unmap(page).unwrap();
+        mapper
+            .map_to(page, frame_holding(round + 1), flags)
+            .unwrap();
+        mapper.flush();
+        unsafe { deallocate_frame(old_frame) };
+        phase.store(round * 2 + 2, Ordering::SeqCst);
+    }
+    for handle in handles {
+        handle.join();
+    }
+    let frame = address_space.mapper().unmap(page).unwrap();
+    unsafe { deallocate_frame(frame) };
+}
+
+// Kernel stacks are global mappings that get unmapped and handed out again all the time. A CPU holding on to an old
+// translation would write into a frame that's since become someone else's stack.
+#[test_case]
+fn kernel_stacks_survive_churn() {
+    for round in 0..20 {
+        let handles: Vec<_> = (0..smp::cpu_count() * 2)
+            .map(|thread| {
+                task::spawn(move || {
+                    let stamp = (round << 32 | thread) as u64;
+                    let mut buffer = [0u64; 512];
+                    for value in buffer.iter_mut() {
+                        *value = stamp;
+                    }
+                    task::yield_now();
+                    for value in buffer.iter() {
+                        assert_eq!(unsafe { core::ptr::read_volatile(value) }, stamp);
+                    }
+                })
+                .unwrap()
+            })
+            .collect();
+        for handle in handles {
+            handle.join();
+        }
+    }
+}
@@ -0,0 +1,226 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::ptr::addr_of;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::memory::address::VirtualAddress;
+use flap_os::memory::paging::consts::PageSize;
+use flap_os::memory::paging::mapper::Mapper;
+use flap_os::memory::paging::page::Page;
+use flap_os::memory::paging::page_table::PageTableFlags;
+use flap_os::memory::{physical_to_virtual, USER_SPACE_START};
+use flap_os::usermode::enter_user_mode;
+
+const USER_CODE: u64 = USER_SPACE_START;
+const SYSCALL_TEST_CODE: u64 = USER_SPACE_START + 0x1000;
+const INT80_TEST_CODE: u64 = USER_SPACE_START + 0x2000;
+const USER_STACK: u64 = USER_SPACE_START + 0x10_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    map_user_stack();
+    unsafe {
+        load_user_program(
+            USER_CODE,
+            addr_of!(user_program_start),
+            addr_of!(user_program_end),
+        );
+        load_user_program(
+            SYSCALL_TEST_CODE,
+            addr_of!(syscall_test_program_start),
+            addr_of!(syscall_test_program_end),
+        );
+        load_user_program(
+            INT80_TEST_CODE,
+            addr_of!(int80_test_program_start),
+            addr_of!(int80_test_program_end),
+        );
+    }
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+// Both are position independent so they can be copied anywhere
+global_asm!(
+    // exits with whatever privilege level it's running at
+    ".global user_program_start",
+    ".global user_program_end",
+    "user_program_start:",
+    "mov rdi, cs",
+    "and rdi, 3",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "user_program_end:",
+    // exits with 0 if every system call behaved, otherwise with the number of the check that failed
+    ".global syscall_test_program_start",
+    ".global syscall_test_program_end",
+    "syscall_test_program_start:",
+    "mov rdi, 0x1234",
+    "mov rbx, 0x5678",
+    "mov eax, 63",
+    "syscall",
+    "mov r12, 1",
+    "cmp rax, -38",
+    "jne 2f",
+    "mov r12, 2",
+    "cmp rdi, 0x1234",
+    "jne 2f",
+    "cmp rbx, 0x5678",
+    "jne 2f",
+    "mov eax, 1",
+    "mov edi, 1",
+    "lea rsi, [rip + 3f]",
+    "mov edx, 18",
+    "syscall",
+    "mov r12, 3",
+    "cmp rax, 18",
+    "jne 2f",
+    // pointing write at kernel memory has to fail
+    "mov eax, 1",
+    "mov edi, 1",
+    "mov rsi, 0xFFFF800000000000",
+    "mov edx, 8",
+    "syscall",
+    "mov r12, 4",
+    "cmp rax, -14",
+    "jne 2f",
+    "xor r12, r12",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "syscall",
+    "ud2",
+    "3:",
+    ".ascii \"hello from ring 3\\n\"",
+    "syscall_test_program_end:",
+    // same idea, through the int 0x80 gate, which unlike syscall leaves rcx alone
+    ".global int80_test_program_start",
+    ".global int80_test_program_end",
+    "int80_test_program_start:",
+    "mov rcx, 0x4242",
+    "mov eax, 1",
+    "mov edi, 1",
+    "lea rsi, [rip + 3f]",
+    "mov edx, 20",
+    "int 0x80",
+    "mov r12, 1",
+    "cmp rax, 20",
+    "jne 2f",
+    "mov r12, 2",
+    "cmp rcx, 0x4242",
+    "jne 2f",
+    "xor r12, r12",
+    "2:",
+    "mov rdi, r12",
+    "mov eax, 0",
+    "int 0x80",
+    "ud2",
+    "3:",
+    ".ascii \"hello from int 0x80\\n\"",
+    "int80_test_program_end:",
+);
+
+extern "C" {
+    static user_program_start: u8;
+    static user_program_end: u8;
+    static syscall_test_program_start: u8;
+    static syscall_test_program_end: u8;
+    static int80_test_program_start: u8;
+    static int80_test_program_end: u8;
+}
+
+fn map_user_stack() {
+    let mut mapper = unsafe { Mapper::active() };
+    let stack_page = Page::from_address_aligned(
+        VirtualAddress::new(USER_STACK - PageSize::NORMAL as u64),
+        PageSize::NORMAL,
+    );
+    mapper
+        .map(
+            stack_page,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .expect("failed to map user stack");
+}
+
+fn ring_buffer_contains(needle: &[u8]) -> bool {
+    let ring_buffer = flap_os::console::RING_BUFFER.lock();
+    let (older, newer) = ring_buffer.as_slices();
+    older.windows(needle.len()).any(|window| window == needle)
+        || newer.windows(needle.len()).any(|window| window == needle)
+}
+
+unsafe fn load_user_program(address: u64, start: *const u8, end: *const u8) {
+    let mut mapper = Mapper::active();
+    let code_page = Page::from_address_aligned(VirtualAddress::new(address), PageSize::NORMAL);
+    let code_frame = mapper
+        .map(code_page, PageTableFlags::USER_ACCESSIBLE)
+        .expect("failed to map user code");
+    core::ptr::copy_nonoverlapping(
+        start,
+        physical_to_virtual(code_frame.start_address()).as_mut_ptr::<u8>(),
+        end as usize - start as usize,
+    );
+}
+
+#[test_case]
+fn ring_3_function_returns_through_exit_system_call() {
+    let privilege_level = unsafe {
+        enter_user_mode(
+            VirtualAddress::new(USER_CODE),
+            VirtualAddress::new(USER_STACK),
+        )
+    };
+    assert_eq!(privilege_level, 3);
+}
+
+#[test_case]
+fn kernel_still_works_after_returning_from_ring_3() {
+    flap_os::println!("back in ring 0");
+    let privilege_level = unsafe {
+        enter_user_mode(
+            VirtualAddress::new(USER_CODE),
+            VirtualAddress::new(USER_STACK),
+        )
+    };
+    assert_eq!(privilege_level, 3);
+}
+
+#[test_case]
+fn system_calls_follow_the_calling_convention() {
+    let failed_check = unsafe {
+        enter_user_mode(
+            VirtualAddress::new(SYSCALL_TEST_CODE),
+            VirtualAddress::new(USER_STACK),
+        )
+    };
+    assert_eq!(failed_check, 0);
+    assert!(ring_buffer_contains(b"hello from ring 3"));
+}
+
+#[test_case]
+fn int_0x80_goes_through_the_same_system_calls() {
+    let failed_check = unsafe {
+        enter_user_mode(
+            VirtualAddress::new(INT80_TEST_CODE),
+            VirtualAddress::new(USER_STACK),
+        )
+    };
+    assert_eq!(failed_check, 0);
+    assert!(ring_buffer_contains(b"hello from int 0x80"));
+}
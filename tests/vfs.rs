@@ -0,0 +1,331 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(flap_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use bootloader::{entry_point, BootInfo};
+
+use flap_os::fs::path;
+use flap_os::fs::{self, FileSystem, FsError, Inode, InodeKind, Metadata, OpenOptions, SeekFrom};
+use flap_os::sync::Once;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    flap_os::kernel_init(boot_info);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    flap_os::test_panic_handler(info)
+}
+
+const MOTD: &[u8] = b"hello from the vfs\n";
+
+// The smallest file system that'll do: a fixed tree that can be read but not changed
+#[derive(Debug)]
+enum Node {
+    Directory(Vec<(&'static str, Arc<TestInode>)>),
+    File(&'static [u8]),
+    Symlink(&'static str),
+}
+
+#[derive(Debug)]
+struct TestInode {
+    number: u64,
+    node: Node,
+}
+
+// every time any TestInode is asked to look a name up
+static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+fn inode(node: Node) -> Arc<TestInode> {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    Arc::new(TestInode {
+        number: NEXT.fetch_add(1, Ordering::Relaxed),
+        node,
+    })
+}
+
+impl Inode for TestInode {
+    fn metadata(&self) -> Metadata {
+        let (kind, size) = match &self.node {
+            Node::Directory(_) => (InodeKind::Directory, 0),
+            Node::File(data) => (InodeKind::File, data.len() as u64),
+            Node::Symlink(target) => (InodeKind::Symlink, target.len() as u64),
+        };
+        Metadata {
+            inode: self.number,
+            kind,
+            size,
+            links: 1,
+            mode: 0o755,
+            accessed: Duration::ZERO,
+            modified: Duration::ZERO,
+            changed: Duration::ZERO,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        LOOKUPS.fetch_add(1, Ordering::Relaxed);
+        let Node::Directory(entries) = &self.node else {
+            return Err(FsError::NotADirectory);
+        };
+        entries
+            .iter()
+            .find(|(entry, _)| *entry == name)
+            .map(|(_, inode)| inode.clone() as Arc<dyn Inode>)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let Node::File(data) = &self.node else {
+            return Err(FsError::InvalidArgument);
+        };
+        let start = (offset as usize).min(data.len());
+        let length = buffer.len().min(data.len() - start);
+        buffer[..length].copy_from_slice(&data[start..start + length]);
+        Ok(length)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        match &self.node {
+            Node::Symlink(target) => Ok(String::from(*target)),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TestFs {
+    root: Arc<TestInode>,
+}
+
+impl FileSystem for TestFs {
+    fn name(&self) -> &'static str {
+        "testfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+fn test_fs() -> Arc<TestFs> {
+    let etc = inode(Node::Directory(vec![("motd", inode(Node::File(MOTD)))]));
+    let mnt = inode(Node::Directory(Vec::new()));
+    let deeper = inode(Node::Directory(Vec::new()));
+    let nested = inode(Node::Directory(vec![("deeper", deeper)]));
+    Arc::new(TestFs {
+        root: inode(Node::Directory(vec![
+            ("etc", etc),
+            ("mnt", mnt),
+            ("nested", nested),
+            ("etc_link", inode(Node::Symlink("etc"))),
+            ("motd", inode(Node::Symlink("/etc/motd"))),
+            ("chain", inode(Node::Symlink("motd"))),
+            ("loop_a", inode(Node::Symlink("loop_b"))),
+            ("loop_b", inode(Node::Symlink("loop_a"))),
+            ("dangling", inode(Node::Symlink("nowhere"))),
+        ])),
+    })
+}
+
+// Every test gets the same root, whichever runs first mounts it on top of the ramfs the kernel boots with
+fn mount_root() {
+    static ROOT: Once<()> = Once::new();
+    ROOT.call_once(|| fs::mount("/", test_fs()).unwrap());
+}
+
+fn inode_number(path: &str) -> u64 {
+    fs::metadata(path).unwrap().inode
+}
+
+fn read_all(path: &str) -> Vec<u8> {
+    let options = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    let file = fs::open(path, &options).unwrap();
+    let mut contents = Vec::new();
+    let mut buffer = [0; 4];
+    loop {
+        match file.read(&mut buffer).unwrap() {
+            0 => return contents,
+            read => contents.extend_from_slice(&buffer[..read]),
+        }
+    }
+}
+
+#[test_case]
+fn dot_and_dot_dot_resolve() {
+    mount_root();
+    let root = inode_number("/");
+    let etc = inode_number("/etc");
+    assert_eq!(inode_number("/etc/."), etc);
+    assert_eq!(inode_number("/etc/.."), root);
+    assert_eq!(inode_number("//etc///./"), etc);
+    assert_eq!(inode_number("/.."), root);
+    assert_eq!(inode_number("/../../etc/../etc"), etc);
+    // relative paths start at the root for now
+    assert_eq!(inode_number("etc"), etc);
+    assert_eq!(fs::metadata("/etc/nothing"), Err(FsError::NotFound));
+    assert_eq!(fs::metadata("/etc/motd/"), Err(FsError::NotADirectory));
+    assert_eq!(fs::metadata("/etc/motd/.."), Err(FsError::NotADirectory));
+    assert_eq!(fs::metadata(""), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn symlinks_are_followed() {
+    mount_root();
+    assert_eq!(inode_number("/etc_link/motd"), inode_number("/etc/motd"));
+    assert_eq!(inode_number("/chain"), inode_number("/etc/motd"));
+    assert_eq!(read_all("/chain"), MOTD);
+    assert_eq!(
+        fs::symlink_metadata("/chain").unwrap().kind,
+        InodeKind::Symlink
+    );
+    assert_eq!(fs::read_link("/chain").unwrap(), "motd");
+    assert_eq!(fs::read_link("/etc"), Err(FsError::InvalidArgument));
+    // a trailing slash follows it even without being asked to
+    assert_eq!(
+        path::resolve("/etc_link/", false).unwrap().kind(),
+        InodeKind::Directory
+    );
+    assert_eq!(fs::metadata("/loop_a"), Err(FsError::TooManyLinks));
+    assert_eq!(fs::metadata("/dangling"), Err(FsError::NotFound));
+    assert_eq!(
+        fs::symlink_metadata("/dangling").unwrap().kind,
+        InodeKind::Symlink
+    );
+}
+
+#[test_case]
+fn open_files_read_and_seek() {
+    mount_root();
+    let read_only = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    let file = fs::open("/etc/motd", &read_only).unwrap();
+    let mut buffer = [0; 5];
+    assert_eq!(file.read(&mut buffer), Ok(5));
+    assert_eq!(&buffer, b"hello");
+    assert_eq!(file.seek(SeekFrom::Current(1)), Ok(6));
+    assert_eq!(file.read(&mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"from");
+    assert_eq!(file.seek(SeekFrom::End(-4)), Ok(MOTD.len() as u64 - 4));
+    assert_eq!(file.read(&mut buffer), Ok(4));
+    assert_eq!(file.read(&mut buffer), Ok(0));
+    assert_eq!(
+        file.seek(SeekFrom::Current(-100)),
+        Err(FsError::InvalidArgument)
+    );
+    assert_eq!(file.write(b"no"), Err(FsError::WrongAccessMode));
+    assert_eq!(file.metadata().unwrap().size, MOTD.len() as u64);
+
+    let directory = fs::open("/etc", &read_only).unwrap();
+    assert_eq!(directory.read(&mut buffer), Err(FsError::IsADirectory));
+    let write = OpenOptions {
+        write: true,
+        ..OpenOptions::default()
+    };
+    assert_eq!(fs::open("/etc", &write).err(), Some(FsError::IsADirectory));
+    let directory_only = OpenOptions {
+        read: true,
+        directory: true,
+        ..OpenOptions::default()
+    };
+    assert_eq!(
+        fs::open("/etc/motd", &directory_only).err(),
+        Some(FsError::NotADirectory)
+    );
+    let no_follow = OpenOptions {
+        read: true,
+        no_follow: true,
+        ..OpenOptions::default()
+    };
+    assert_eq!(
+        fs::open("/chain", &no_follow).err(),
+        Some(FsError::TooManyLinks)
+    );
+    // testfs can't make anything
+    let create = OpenOptions {
+        write: true,
+        create: true,
+        ..OpenOptions::default()
+    };
+    assert_eq!(
+        fs::open("/etc/new", &create).err(),
+        Some(FsError::PermissionDenied)
+    );
+    assert_eq!(
+        fs::create_dir("/etc/new", 0o755),
+        Err(FsError::PermissionDenied)
+    );
+}
+
+#[test_case]
+fn names_are_only_looked_up_once() {
+    mount_root();
+    let first = path::resolve("/nested/deeper", true).unwrap();
+    let lookups = LOOKUPS.load(Ordering::Relaxed);
+    let second = path::resolve("/nested/deeper", true).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(LOOKUPS.load(Ordering::Relaxed), lookups);
+    assert_eq!(first.path(), "/nested/deeper");
+}
+
+#[test_case]
+fn mounts_cover_directories_until_unmounted() {
+    mount_root();
+    let mnt = inode_number("/mnt");
+    assert_eq!(fs::unmount("/mnt"), Err(FsError::InvalidArgument));
+    fs::mount("/mnt", test_fs()).unwrap();
+    assert_ne!(inode_number("/mnt"), mnt);
+    assert_eq!(read_all("/mnt/etc/motd"), MOTD);
+    // .. out of the root of a mounted file system goes up past the mount point
+    assert_eq!(inode_number("/mnt/.."), inode_number("/"));
+    assert_eq!(inode_number("/mnt/etc/../.."), inode_number("/"));
+    // an absolute symlink on it still starts from the real root
+    assert_eq!(inode_number("/mnt/motd"), inode_number("/etc/motd"));
+    assert_eq!(path::resolve("/mnt/etc", true).unwrap().path(), "/mnt/etc");
+
+    fs::mount("/mnt/nested", test_fs()).unwrap();
+    assert_eq!(read_all("/mnt/nested/etc/motd"), MOTD);
+    assert_eq!(fs::metadata("/mnt/nested/deeper"), Err(FsError::NotFound));
+    assert!(fs::mounts()
+        .iter()
+        .any(|(path, name)| path == "/mnt/nested" && *name == "testfs"));
+    assert_eq!(fs::unmount("/mnt"), Err(FsError::Busy));
+
+    // stays open, and readable, while the file system goes away under it
+    let options = OpenOptions {
+        read: true,
+        ..OpenOptions::default()
+    };
+    let file = fs::open("/mnt/nested/etc/motd", &options).unwrap();
+    fs::unmount("/mnt/nested").unwrap();
+    // what's left is the nested directory of the testfs on /mnt, not the root's
+    assert_ne!(
+        inode_number("/mnt/nested/deeper"),
+        inode_number("/nested/deeper")
+    );
+    fs::unmount("/mnt").unwrap();
+    assert_eq!(inode_number("/mnt"), mnt);
+    let mut buffer = [0; 5];
+    assert_eq!(file.read(&mut buffer), Ok(5));
+}